@@ -0,0 +1,62 @@
+/*
+ * C interface to the tea crate, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * See src/ffi.rs for the details.
+ *
+ * Keys are 16 bytes and ivs 8 bytes.  Every function returns TEA_OK or
+ * a negative TEA_ERR_* code.  Output lengths go in as the size of the
+ * output buffer and come back as the number of bytes written; on
+ * TEA_ERR_BUFFER_TOO_SMALL they come back as the size needed, and
+ * nothing else has changed.  Input and output buffers must not
+ * overlap, except in tea_decrypt_buf, which can decrypt in place.
+ */
+
+#ifndef TEA_H
+#define TEA_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TEA_OK 0
+#define TEA_ERR_NULL_POINTER -1
+#define TEA_ERR_BUFFER_TOO_SMALL -2
+#define TEA_ERR_TRUNCATED -3
+#define TEA_ERR_BAD_PADDING -4
+#define TEA_ERR_FINISHED -5
+#define TEA_ERR_OTHER -6
+
+typedef struct TeaEncryptor TeaEncryptor;
+typedef struct TeaDecryptor TeaDecryptor;
+
+/* out needs room for in_len rounded down to a multiple of 8, plus 8. */
+int32_t tea_encrypt_buf(const uint8_t key[16], const uint8_t iv[8], const uint8_t *in, size_t in_len,
+                        uint8_t *out, size_t *out_len);
+/* out needs room for in_len bytes, and which may overlap in. */
+int32_t tea_decrypt_buf(const uint8_t key[16], const uint8_t iv[8], const uint8_t *in, size_t in_len,
+                        uint8_t *out, size_t *out_len);
+
+/* update needs room for in_len + 7 bytes, final for 8.  After final,
+ * both return TEA_ERR_FINISHED. */
+TeaEncryptor *tea_encryptor_new(const uint8_t key[16], const uint8_t iv[8]);
+int32_t tea_encryptor_update(TeaEncryptor *ctx, const uint8_t *in, size_t in_len, uint8_t *out, size_t *out_len);
+int32_t tea_encryptor_final(TeaEncryptor *ctx, uint8_t *out, size_t *out_len);
+void tea_encryptor_free(TeaEncryptor *ctx);
+
+/* update needs room for in_len + 7 bytes, final for 7.  After final
+ * succeeds, both return TEA_ERR_FINISHED. */
+TeaDecryptor *tea_decryptor_new(const uint8_t key[16], const uint8_t iv[8]);
+int32_t tea_decryptor_update(TeaDecryptor *ctx, const uint8_t *in, size_t in_len, uint8_t *out, size_t *out_len);
+int32_t tea_decryptor_final(TeaDecryptor *ctx, uint8_t *out, size_t *out_len);
+void tea_decryptor_free(TeaDecryptor *ctx);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TEA_H */
@@ -0,0 +1,325 @@
+//! Encrypts and decrypts whole buffers at once, for when setting up an
+//! `io::Writer` or `io::Reader` would be overkill.  These produce and
+//! accept the same bytes as `raw()` streaming wrappers with their
+//! default PKCS#7 padding: there's no `Header` in front.
+
+use super::{Key, Block, ByteOrder, Error};
+use cipher::Cipher;
+use ct;
+use mac;
+use mem;
+use padding::pkcs7;
+
+// How long `len` bytes of plaintext are once PKCS#7 padded.
+fn padded_len(len: usize) -> usize {
+    len / 8 * 8 + 8
+}
+
+// How much ciphertext `cbc_decrypt_as` decrypts at a time, keeping a
+// copy to chain with.
+const CBC_BATCH: usize = 512;
+
+// Encrypts `buf`, which must be a multiple of 8 bytes long, in place
+// in CBC mode, chaining on from `prev`.
+pub fn cbc_encrypt(cipher: &Cipher, prev: &mut Block, buf: &mut [u8]) {
+    cbc_encrypt_as(cipher, prev, buf, ByteOrder::Native)
+}
+
+// Decrypts `buf`, which must be a multiple of 8 bytes long, in place
+// in CBC mode, chaining on from `prev`.
+pub fn cbc_decrypt(cipher: &Cipher, prev: &mut Block, buf: &mut [u8]) {
+    cbc_decrypt_as(cipher, prev, buf, ByteOrder::Native)
+}
+
+// Like `cbc_encrypt`, with each block laid out in `order`.  Each block
+// depends on the one before, so this can only go a block at a time.
+pub fn cbc_encrypt_as(cipher: &Cipher, prev: &mut Block, buf: &mut [u8], order: ByteOrder) {
+    for chunk in buf.chunks_mut(8) {
+        let mut block = mem::read_block_as(chunk, order);
+        block[0] ^= prev[0];
+        block[1] ^= prev[1];
+        *prev = cipher.encipher(&block);
+        chunk.clone_from_slice(&mem::write_block_as(prev, order));
+    }
+}
+
+// Like `cbc_decrypt`, with each block laid out in `order`.  Decryption
+// doesn't chain through the cipher, so the blocks go through
+// `decrypt_blocks_as` a batch at a time and are XORed with the
+// ciphertext before them afterwards.
+pub fn cbc_decrypt_as(cipher: &Cipher, prev: &mut Block, buf: &mut [u8], order: ByteOrder) {
+    let mut saved = [0u8; CBC_BATCH];
+    for group in buf.chunks_mut(CBC_BATCH) {
+        let len = group.len();
+        saved[..len].clone_from_slice(group);
+        cipher.decrypt_blocks_as(group, order).ok().unwrap();
+        let first = mem::write_block_as(prev, order);
+        for (i, chunk) in group.chunks_mut(8).enumerate() {
+            let chain = if i == 0 { &first[..] } else { &saved[8 * (i - 1)..8 * i] };
+            for (b, c) in chunk.iter_mut().zip(chain.iter()) {
+                *b ^= *c;
+            }
+        }
+        *prev = mem::read_block_as(&saved[len - 8..len], order);
+    }
+}
+
+/// Encrypts `plaintext` with the given `key` and `iv` (initialization
+/// vector), PKCS#7 padding it out to a whole number of blocks.
+///
+/// # Example:
+/// ```
+/// let ciphertext = tea::encrypt([1, 2, 3, 4], [5, 6], b"Hello, world!");
+/// assert_eq!(ciphertext.len(), 16);
+/// assert_eq!(tea::decrypt([1, 2, 3, 4], [5, 6], &ciphertext).ok().unwrap(), b"Hello, world!");
+/// ```
+pub fn encrypt<K: Into<Key>, I: Into<Block>>(key: K, iv: I, plaintext: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0u8; padded_len(plaintext.len())];
+    encrypt_to(key, iv, plaintext, &mut buf).ok().unwrap();
+    buf
+}
+
+/// Like `encrypt`, but writes the ciphertext to the front of `out`
+/// instead of allocating, and returns its length.  If `out` is too
+/// small, this returns `Error::BufferTooSmall` saying how big it needs
+/// to be, and leaves `out` alone.
+///
+/// # Example:
+/// ```
+/// let mut out = [0u8; 16];
+/// let len = tea::encrypt_to([1, 2, 3, 4], [5, 6], b"Hello, world!", &mut out).ok().unwrap();
+/// assert_eq!(&out[..len], &tea::encrypt([1, 2, 3, 4], [5, 6], b"Hello, world!")[..]);
+/// assert_eq!(tea::encrypt_to([1, 2, 3, 4], [5, 6], b"Hello, world!!!!", &mut out),
+///            Err(tea::Error::BufferTooSmall { needed: 24 }));
+/// ```
+pub fn encrypt_to<K: Into<Key>, I: Into<Block>>(key: K, iv: I, plaintext: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let needed = padded_len(plaintext.len());
+    if out.len() < needed {
+        return Err(Error::BufferTooSmall { needed: needed });
+    }
+    out[..plaintext.len()].clone_from_slice(plaintext);
+    encrypt_padded_in_place(key, iv, out, plaintext.len())
+}
+
+/// Decrypts `ciphertext` made by `encrypt` (or an `io::Writer` with
+/// the default padding) with the given `key` and `iv`, and strips the
+/// padding.
+pub fn decrypt<K: Into<Key>, I: Into<Block>>(key: K, iv: I, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut buf = ciphertext.to_vec();
+    let len = try!(decrypt_padded_in_place(key, iv, &mut buf));
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// Encrypts `buf`, which must be a multiple of 8 bytes long, in place
+/// with the given `key` and `iv`, without any padding.  Nothing is
+/// allocated.
+///
+/// # Example:
+/// ```
+/// let mut buf = *b"16 bytes exactly";
+/// tea::encrypt_in_place([1, 2, 3, 4], [5, 6], &mut buf).ok().unwrap();
+/// tea::decrypt_in_place([1, 2, 3, 4], [5, 6], &mut buf).ok().unwrap();
+/// assert_eq!(&buf, b"16 bytes exactly");
+/// ```
+pub fn encrypt_in_place<K: Into<Key>, I: Into<Block>>(key: K, iv: I, buf: &mut [u8]) -> Result<(), Error> {
+    if buf.len() % 8 != 0 {
+        return Err(Error::Unaligned);
+    }
+    let mut prev = iv.into();
+    cbc_encrypt(&Cipher::new(key), &mut prev, buf);
+    Ok(())
+}
+
+/// Decrypts `buf`, which must be a multiple of 8 bytes long, in place
+/// with the given `key` and `iv`, without looking for any padding.
+pub fn decrypt_in_place<K: Into<Key>, I: Into<Block>>(key: K, iv: I, buf: &mut [u8]) -> Result<(), Error> {
+    if buf.len() % 8 != 0 {
+        return Err(Error::Unaligned);
+    }
+    let mut prev = iv.into();
+    cbc_decrypt(&Cipher::new(key), &mut prev, buf);
+    Ok(())
+}
+
+/// Pads the first `len` bytes of `buf` with PKCS#7 and encrypts them
+/// in place with the given `key` and `iv`, returning the length of the
+/// ciphertext.  The padding goes in the bytes after the plaintext, so
+/// `buf` must have room for up to 8 more; if it doesn't, this returns
+/// `Error::BufferTooSmall` and leaves `buf` alone.
+///
+/// # Example:
+/// ```
+/// let mut buf = *b"Hello, world!\0\0\0";
+/// let len = tea::encrypt_padded_in_place([1, 2, 3, 4], [5, 6], &mut buf, 13).ok().unwrap();
+/// assert_eq!(&buf[..len], &tea::encrypt([1, 2, 3, 4], [5, 6], b"Hello, world!")[..]);
+/// ```
+pub fn encrypt_padded_in_place<K: Into<Key>, I: Into<Block>>(key: K, iv: I, buf: &mut [u8], len: usize) -> Result<usize, Error> {
+    let padded_len = padded_len(len);
+    if buf.len() < padded_len {
+        return Err(Error::BufferTooSmall { needed: padded_len });
+    }
+    let pad_byte = (padded_len - len) as u8;
+    for b in buf[len..padded_len].iter_mut() {
+        *b = pad_byte;
+    }
+    let mut prev = iv.into();
+    cbc_encrypt(&Cipher::new(key), &mut prev, &mut buf[..padded_len]);
+    Ok(padded_len)
+}
+
+/// Decrypts `buf` in place with the given `key` and `iv` and checks
+/// its PKCS#7 padding, returning the length of the plaintext at the
+/// front of `buf`.
+pub fn decrypt_padded_in_place<K: Into<Key>, I: Into<Block>>(key: K, iv: I, buf: &mut [u8]) -> Result<usize, Error> {
+    if buf.is_empty() || buf.len() % 8 != 0 {
+        return Err(Error::Truncated);
+    }
+    let mut prev = iv.into();
+    cbc_decrypt(&Cipher::new(key), &mut prev, buf);
+    match pkcs7::unpad(buf) {
+        Some(plaintext) => Ok(plaintext.len()),
+        None => Err(Error::BadPadding),
+    }
+}
+
+// The iv for convergent encryption of `plaintext`: its CMAC under a
+// key kept apart from the one used to encrypt.
+fn convergent_iv(key: &Key, plaintext: &[u8]) -> Block {
+    Block::from_be_bytes(&mac::cmac(key.derive_subkey(b"tea convergent iv"), plaintext))
+}
+
+/// Encrypts `plaintext` with an iv derived from the plaintext itself,
+/// so the same plaintext under the same key always gives the same
+/// ciphertext.  That's what lets a deduplicating backup store spot
+/// repeated files without decrypting them, but it also lets anyone
+/// who can see the ciphertexts spot them, or confirm a guess at a
+/// plaintext they can encrypt themselves.  Only use it when that's
+/// acceptable; otherwise use `encrypt` with a random iv.
+///
+/// The iv (a CMAC of the plaintext) goes in the first 8 bytes of the
+/// output, followed by the ciphertext as `encrypt` makes it.
+///
+/// # Example:
+/// ```
+/// let a = tea::encrypt_convergent([1, 2, 3, 4], b"Hello, world!");
+/// let b = tea::encrypt_convergent([1, 2, 3, 4], b"Hello, world!");
+/// assert_eq!(a, b);
+/// assert_eq!(tea::decrypt_convergent([1, 2, 3, 4], &a).ok().unwrap(), b"Hello, world!");
+/// ```
+pub fn encrypt_convergent<K: Into<Key>>(key: K, plaintext: &[u8]) -> Vec<u8> {
+    let key = key.into();
+    let iv = convergent_iv(&key, plaintext);
+    let mut out = vec![0u8; 8 + padded_len(plaintext.len())];
+    out[..8].clone_from_slice(&iv.to_be_bytes());
+    encrypt_to(key, iv, plaintext, &mut out[8..]).ok().unwrap();
+    out
+}
+
+/// Decrypts the output of `encrypt_convergent`.  Since the iv has to
+/// match the plaintext, this also notices most tampering, and
+/// returns `Error::BadTag` if they don't match.
+pub fn decrypt_convergent<K: Into<Key>>(key: K, data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < 8 {
+        return Err(Error::Truncated);
+    }
+    let key = key.into();
+    let mut iv = [0u32; 2];
+    for i in 0..8 {
+        iv[i / 4] = (iv[i / 4] << 8) | data[i] as u32;
+    }
+    let plaintext = match decrypt(key, iv, &data[8..]) {
+        Ok(plaintext) => plaintext,
+        Err(Error::BadPadding) => return Err(Error::BadTag),
+        Err(e) => return Err(e),
+    };
+    if !ct::eq(&convergent_iv(&key, &plaintext).to_be_bytes(), &data[..8]) {
+        return Err(Error::BadTag);
+    }
+    Ok(plaintext)
+}
+
+#[test]
+fn it_works() {
+    use std::io::Write;
+    use io::Writer;
+
+    for len in 0..40 {
+        let input: Vec<u8> = (0..len).collect();
+        let ciphertext = encrypt([1, 2, 3, 4], [5, 6], &input);
+        let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).raw();
+        writer.write_all(&input).ok().unwrap();
+        assert_eq!(ciphertext, writer.close().ok().unwrap());
+        assert_eq!(decrypt([1, 2, 3, 4], [5, 6], &ciphertext).ok().unwrap(), input);
+    }
+}
+
+#[test]
+fn errors() {
+    let ciphertext = encrypt([1, 2, 3, 4], [5, 6], b"Hello, world!");
+    assert_eq!(decrypt([1, 2, 3, 4], [5, 6], &ciphertext[..12]), Err(Error::Truncated));
+    assert_eq!(decrypt([1, 2, 3, 4], [5, 6], &[]), Err(Error::Truncated));
+    assert_eq!(decrypt([1, 2, 3, 5], [5, 6], &ciphertext), Err(Error::BadPadding));
+}
+
+#[test]
+fn in_place() {
+    let input: Vec<u8> = (0u8..24).collect();
+    let mut buf = input.clone();
+    encrypt_in_place([1, 2, 3, 4], [5, 6], &mut buf).ok().unwrap();
+    assert!(buf != input);
+    decrypt_in_place([1, 2, 3, 4], [5, 6], &mut buf).ok().unwrap();
+    assert_eq!(buf, input);
+    assert_eq!(encrypt_in_place([1, 2, 3, 4], [5, 6], &mut buf[..20]), Err(Error::Unaligned));
+
+    let mut buf = [0u8; 24];
+    buf[..16].clone_from_slice(&input[..16]);
+    assert_eq!(encrypt_padded_in_place([1, 2, 3, 4], [5, 6], &mut buf[..23], 16),
+               Err(Error::BufferTooSmall { needed: 24 }));
+    assert_eq!(encrypt_padded_in_place([1, 2, 3, 4], [5, 6], &mut buf, 16), Ok(24));
+    assert_eq!(&buf[..], &encrypt([1, 2, 3, 4], [5, 6], &input[..16])[..]);
+    assert_eq!(decrypt_padded_in_place([1, 2, 3, 4], [5, 6], &mut buf), Ok(16));
+    assert_eq!(&buf[..16], &input[..16]);
+}
+
+#[test]
+fn convergent() {
+    let a = encrypt_convergent([1, 2, 3, 4], b"Hello, world!");
+    assert_eq!(a.len(), 24);
+    assert_eq!(a, encrypt_convergent([1, 2, 3, 4], b"Hello, world!"));
+    assert!(a != encrypt_convergent([1, 2, 3, 4], b"Hello, world?"));
+    assert!(a != encrypt_convergent([1, 2, 3, 5], b"Hello, world!"));
+    assert_eq!(decrypt_convergent([1, 2, 3, 4], &a), Ok(b"Hello, world!".to_vec()));
+    for i in 0..a.len() {
+        let mut tampered = a.clone();
+        tampered[i] ^= 1;
+        assert!(decrypt_convergent([1, 2, 3, 4], &tampered).is_err());
+    }
+    assert_eq!(decrypt_convergent([1, 2, 3, 4], &a[..7]), Err(Error::Truncated));
+}
+
+#[test]
+fn cbc_batches() {
+    let cipher = Cipher::new([1, 2, 3, 4]);
+    let input: Vec<u8> = (0..1200).map(|i| (i * 7) as u8).collect();
+    for &order in [ByteOrder::Native, ByteOrder::BigEndian, ByteOrder::LittleEndian].iter() {
+        let mut buf = input.clone();
+        let mut prev = Block::new([5, 6]);
+        cbc_encrypt_as(&cipher, &mut prev, &mut buf, order);
+        let ciphertext = buf.clone();
+
+        // A block at a time, so no batch ever has more than one.
+        let mut one_prev = Block::new([5, 6]);
+        let mut one = ciphertext.clone();
+        for chunk in one.chunks_mut(8) {
+            cbc_decrypt_as(&cipher, &mut one_prev, chunk, order);
+        }
+        assert_eq!(one, input);
+
+        let mut all_prev = Block::new([5, 6]);
+        cbc_decrypt_as(&cipher, &mut all_prev, &mut buf, order);
+        assert_eq!(buf, input);
+        assert_eq!(all_prev, one_prev);
+        assert_eq!(all_prev, mem::read_block_as(&ciphertext[1192..], order));
+    }
+}
@@ -1,61 +1,324 @@
 //! Implements the basic XTEA cipher routines as described in the
 //! paper (http://en.wikipedia.org/wiki/XTEA).  These functions only
-//! deal with a single 64-bit block of data at a time.
+//! deal with a single 64-bit block of data at a time.  `Cipher` does
+//! the same with the key schedule worked out once, for encrypting
+//! lots of blocks under one key.
 
 static NUM_ROUNDS: u32 = 32;
+const DELTA: u32 = 0x9E3779B9;
 
-use super::{Key, Block};
+use std::ptr;
+
+use super::{Key, Block, ByteOrder, Error};
+use mem;
+#[cfg(feature = "simd")]
+use simd;
+
+// Expands `$round!(i)` for each of the 32 rounds in turn.  Written out
+// like this, with no loop, every round's `sum` and key index is a
+// constant, instead of being left to whether the optimizer decides to
+// unroll the loop itself.
+macro_rules! unrolled {
+    ($round:ident) => {
+        $round!(0); $round!(1); $round!(2); $round!(3); $round!(4); $round!(5); $round!(6); $round!(7);
+        $round!(8); $round!(9); $round!(10); $round!(11); $round!(12); $round!(13); $round!(14); $round!(15);
+        $round!(16); $round!(17); $round!(18); $round!(19); $round!(20); $round!(21); $round!(22); $round!(23);
+        $round!(24); $round!(25); $round!(26); $round!(27); $round!(28); $round!(29); $round!(30); $round!(31);
+    }
+}
+
+// The Feistel function, before the key goes in.
+fn mix(v: u32) -> u32 {
+    ((v << 4) ^ (v >> 5)).wrapping_add(v)
+}
+
+// Reverses the bytes of each word in `buf` if `order` isn't the
+// host's, which takes blocks laid out in `order` to the host's layout
+// and back again.
+fn swap_words(buf: &mut [u8], order: ByteOrder) {
+    let swap = match order {
+        ByteOrder::Native => false,
+        ByteOrder::BigEndian => cfg!(target_endian = "little"),
+        ByteOrder::LittleEndian => cfg!(target_endian = "big"),
+    };
+    if swap {
+        for word in buf.chunks_mut(4) {
+            word.reverse();
+        }
+    }
+}
 
 /// Encrypts 64 bits of `input` using the `key`.
 ///
 /// # Example:
 /// ```
-/// use tea::cipher;
+/// use tea::{cipher, Block, Key};
 ///
-/// let key = [5, 6, 7, 8];
-/// let plaintext = [128, 256];
+/// let key = Key::new([5, 6, 7, 8]);
+/// let plaintext = Block::new([128, 256]);
 /// assert!(cipher::encipher(&key, &plaintext) != plaintext);
 /// ```
 pub fn encipher(key: &Key, input: &Block) -> Block {
-    let [mut v0, mut v1] = *input;
-    let delta = 0x9E3779B9;
-    let mut sum: u32 = 0;
-    for _ in 0..NUM_ROUNDS {
-        v0 = v0.wrapping_add((((v1 << 4) ^ (v1 >> 5)).wrapping_add(v1)) ^ (sum.wrapping_add(key[(sum & 3) as usize])));
-        sum = sum.wrapping_add(delta);
-        v1 = v1.wrapping_add((((v0 << 4) ^ (v0 >> 5)).wrapping_add(v0)) ^ (sum.wrapping_add(key[((sum>>11) & 3) as usize])))
+    let [mut v0, mut v1] = input.words();
+    macro_rules! round {
+        ($i:expr) => {{
+            let sum = DELTA.wrapping_mul($i);
+            let next = sum.wrapping_add(DELTA);
+            v0 = v0.wrapping_add(mix(v1) ^ sum.wrapping_add(key[(sum & 3) as usize]));
+            v1 = v1.wrapping_add(mix(v0) ^ next.wrapping_add(key[((next>>11) & 3) as usize]));
+        }}
     }
-    [v0, v1]
+    unrolled!(round);
+    Block::new([v0, v1])
 }
 
 /// Decrypts 64 bits of `input` using the `key`.
 ///
 /// # Example:
 /// ```
-/// use tea::cipher;
+/// use tea::{cipher, Block, Key};
 ///
-/// let key = [5, 6, 7, 8];
-/// let plaintext = [128, 256];
+/// let key = Key::new([5, 6, 7, 8]);
+/// let plaintext = Block::new([128, 256]);
 /// let crypted = cipher::encipher(&key, &plaintext);
 /// assert_eq!(cipher::decipher(&key, &crypted), plaintext);
 /// ```
 pub fn decipher(key: &Key, input: &Block) -> Block {
-    let [mut v0, mut v1] = *input;
-    let delta = 0x9E3779B9;
-    let mut sum = delta.wrapping_mul(NUM_ROUNDS);
-    for _ in 0..NUM_ROUNDS {
-        v1 = v1.wrapping_sub((((v0 << 4) ^ (v0 >> 5)).wrapping_add(v0)) ^ (sum.wrapping_add(key[((sum>>11) & 3) as usize])));
-        sum = sum.wrapping_sub(delta);
-        v0 = v0.wrapping_sub((((v1 << 4) ^ (v1 >> 5)).wrapping_add(v1)) ^ (sum.wrapping_add(key[(sum & 3) as usize])));
+    let [mut v0, mut v1] = input.words();
+    macro_rules! round {
+        ($i:expr) => {{
+            let sum = DELTA.wrapping_mul(NUM_ROUNDS - 1 - $i);
+            let next = sum.wrapping_add(DELTA);
+            v1 = v1.wrapping_sub(mix(v0) ^ next.wrapping_add(key[((next>>11) & 3) as usize]));
+            v0 = v0.wrapping_sub(mix(v1) ^ sum.wrapping_add(key[(sum & 3) as usize]));
+        }}
     }
-    [v0, v1]
+    unrolled!(round);
+    Block::new([v0, v1])
+}
+
+/// XTEA with the key schedule (the sum and key word that go into each
+/// half-round) worked out once up front, for bulk encryption under one
+/// key.  `encrypt_blocks` and `decrypt_blocks` run over a whole buffer
+/// of independent blocks, with no per-block function call or key
+/// lookup, which is much faster than calling `encipher` in a loop.
+/// With the `simd` feature, they go four blocks at a time through a
+/// `core::simd` kernel, and only the last few blocks of a buffer take
+/// the scalar path; on a CPU without SSE2 or NEON, it's all scalar.
+/// The `io` streams, the buffer functions and CTR mode all run
+/// through a `Cipher`.  The schedule is wiped when the `Cipher` is
+/// dropped.
+///
+/// # Example:
+/// ```
+/// use tea::{cipher, Block, Key};
+/// use tea::cipher::Cipher;
+///
+/// let key = Key::new([5, 6, 7, 8]);
+/// let xtea = Cipher::new(key);
+/// let plaintext = Block::new([128, 256]);
+/// assert_eq!(xtea.encipher(&plaintext), cipher::encipher(&key, &plaintext));
+///
+/// let mut buf = [7u8; 4096];
+/// xtea.encrypt_blocks(&mut buf).ok().unwrap();
+/// xtea.decrypt_blocks(&mut buf).ok().unwrap();
+/// assert_eq!(&buf[..], &[7u8; 4096][..]);
+/// ```
+pub struct Cipher {
+    // Round `i` adds `schedule[2 * i]` into the first half-round and
+    // `schedule[2 * i + 1]` into the second.
+    schedule: [u32; 64],
+}
+
+impl Cipher {
+
+    /// Works out the key schedule for `key`.
+    pub fn new<K: Into<Key>>(key: K) -> Cipher {
+        let key = key.into();
+        let mut schedule = [0u32; 64];
+        let mut sum: u32 = 0;
+        for i in 0..NUM_ROUNDS as usize {
+            schedule[2 * i] = sum.wrapping_add(key[(sum & 3) as usize]);
+            sum = sum.wrapping_add(DELTA);
+            schedule[2 * i + 1] = sum.wrapping_add(key[((sum>>11) & 3) as usize]);
+        }
+        Cipher{ schedule: schedule }
+    }
+
+    // The schedule, so the `simd` kernel can be tested on its own.
+    #[cfg(all(test, feature = "simd"))]
+    pub fn schedule(&self) -> &[u32; 64] {
+        &self.schedule
+    }
+
+    /// Encrypts one block, like `encipher`.
+    pub fn encipher(&self, input: &Block) -> Block {
+        let [mut v0, mut v1] = input.words();
+        let schedule = &self.schedule;
+        macro_rules! round {
+            ($i:expr) => {{
+                v0 = v0.wrapping_add(mix(v1) ^ schedule[2 * $i]);
+                v1 = v1.wrapping_add(mix(v0) ^ schedule[2 * $i + 1]);
+            }}
+        }
+        unrolled!(round);
+        Block::new([v0, v1])
+    }
+
+    /// Decrypts one block, like `decipher`.
+    pub fn decipher(&self, input: &Block) -> Block {
+        let [mut v0, mut v1] = input.words();
+        let schedule = &self.schedule;
+        macro_rules! round {
+            ($i:expr) => {{
+                v1 = v1.wrapping_sub(mix(v0) ^ schedule[63 - 2 * $i]);
+                v0 = v0.wrapping_sub(mix(v1) ^ schedule[62 - 2 * $i]);
+            }}
+        }
+        unrolled!(round);
+        Block::new([v0, v1])
+    }
+
+    /// Encrypts each 8-byte block of `buf` in place on its own, with
+    /// no chaining, reading and writing blocks in the host's byte
+    /// order like `encrypt_in_place`.  Returns `Error::Unaligned` if
+    /// `buf` isn't a multiple of 8 bytes long.  Identical blocks
+    /// encrypt identically, so this is a building block for modes,
+    /// not a way to encrypt data by itself.
+    pub fn encrypt_blocks(&self, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() % 8 != 0 {
+            return Err(Error::Unaligned);
+        }
+        #[cfg(feature = "simd")]
+        let buf = {
+            let done = simd::encrypt(&self.schedule, buf);
+            &mut buf[done..]
+        };
+        for chunk in buf.chunks_mut(8) {
+            let block = self.encipher(&mem::read_block(chunk));
+            chunk.clone_from_slice(mem::write_block(&block));
+        }
+        Ok(())
+    }
+
+    /// Decrypts each 8-byte block of `buf` in place, undoing
+    /// `encrypt_blocks`.
+    pub fn decrypt_blocks(&self, buf: &mut [u8]) -> Result<(), Error> {
+        if buf.len() % 8 != 0 {
+            return Err(Error::Unaligned);
+        }
+        #[cfg(feature = "simd")]
+        let buf = {
+            let done = simd::decrypt(&self.schedule, buf);
+            &mut buf[done..]
+        };
+        for chunk in buf.chunks_mut(8) {
+            let block = self.decipher(&mem::read_block(chunk));
+            chunk.clone_from_slice(mem::write_block(&block));
+        }
+        Ok(())
+    }
+
+    /// Like `encrypt_blocks`, but with each block laid out in `order`
+    /// instead of the host's byte order.
+    pub fn encrypt_blocks_as(&self, buf: &mut [u8], order: ByteOrder) -> Result<(), Error> {
+        if buf.len() % 8 != 0 {
+            return Err(Error::Unaligned);
+        }
+        swap_words(buf, order);
+        try!(self.encrypt_blocks(buf));
+        swap_words(buf, order);
+        Ok(())
+    }
+
+    /// Like `decrypt_blocks`, but with each block laid out in `order`.
+    pub fn decrypt_blocks_as(&self, buf: &mut [u8], order: ByteOrder) -> Result<(), Error> {
+        if buf.len() % 8 != 0 {
+            return Err(Error::Unaligned);
+        }
+        swap_words(buf, order);
+        try!(self.decrypt_blocks(buf));
+        swap_words(buf, order);
+        Ok(())
+    }
+
+}
+
+impl Drop for Cipher {
+
+    fn drop(&mut self) {
+        for word in self.schedule.iter_mut() {
+            unsafe { ptr::write_volatile(word, 0) };
+        }
+    }
+
 }
 
 #[test]
 fn it_works() {
-    let key: Key = [10, 20, 30, 42];
-    let plaintext: Block = [300, 400];
+    let key = Key::new([10, 20, 30, 42]);
+    let plaintext = Block::new([300, 400]);
     let ciphertext = encipher(&key, &plaintext);
     assert!(plaintext != ciphertext);
     assert_eq!(plaintext, decipher(&key, &ciphertext));
 }
+
+#[test]
+fn unrolled() {
+    // The rounds as the paper writes them, one at a time in a loop.
+    fn reference(key: &Key, input: &Block) -> Block {
+        let [mut v0, mut v1] = input.words();
+        let mut sum: u32 = 0;
+        for _ in 0..NUM_ROUNDS {
+            v0 = v0.wrapping_add((((v1 << 4) ^ (v1 >> 5)).wrapping_add(v1)) ^ (sum.wrapping_add(key[(sum & 3) as usize])));
+            sum = sum.wrapping_add(DELTA);
+            v1 = v1.wrapping_add((((v0 << 4) ^ (v0 >> 5)).wrapping_add(v0)) ^ (sum.wrapping_add(key[((sum>>11) & 3) as usize])));
+        }
+        Block::new([v0, v1])
+    }
+
+    for i in 0..100u64 {
+        let key = Key::new([i as u32, (i * 7) as u32, !(i as u32), 0x9E3779B9]);
+        let block = Block::from(i.wrapping_mul(0x9E3779B97F4A7C15));
+        assert_eq!(encipher(&key, &block), reference(&key, &block));
+        assert_eq!(decipher(&key, &reference(&key, &block)), block);
+    }
+}
+
+#[test]
+fn schedule() {
+    let key = Key::new([10, 20, 30, 42]);
+    let xtea = Cipher::new(key);
+    for i in 0..100u64 {
+        let block = Block::from(i.wrapping_mul(0x9E3779B97F4A7C15));
+        assert_eq!(xtea.encipher(&block), encipher(&key, &block));
+        assert_eq!(xtea.decipher(&block), decipher(&key, &block));
+    }
+
+    let mut buf: Vec<u8> = (0..64).collect();
+    xtea.encrypt_blocks(&mut buf).ok().unwrap();
+    for (i, chunk) in buf.chunks(8).enumerate() {
+        let plain: Vec<u8> = (8 * i as u8..8 * i as u8 + 8).collect();
+        assert_eq!(mem::read_block(chunk), encipher(&key, &mem::read_block(&plain)));
+    }
+    xtea.decrypt_blocks(&mut buf).ok().unwrap();
+    assert_eq!(buf, (0..64).collect::<Vec<u8>>());
+    assert_eq!(xtea.encrypt_blocks(&mut [0; 7]), Err(Error::Unaligned));
+    assert_eq!(xtea.decrypt_blocks(&mut [0; 9]), Err(Error::Unaligned));
+}
+
+#[test]
+fn byte_order() {
+    let xtea = Cipher::new(Key::new([10, 20, 30, 42]));
+    for &order in [ByteOrder::Native, ByteOrder::BigEndian, ByteOrder::LittleEndian].iter() {
+        let mut buf: Vec<u8> = (0..72).collect();
+        xtea.encrypt_blocks_as(&mut buf, order).ok().unwrap();
+        for (i, chunk) in buf.chunks(8).enumerate() {
+            let plain: Vec<u8> = (8 * i as u8..8 * i as u8 + 8).collect();
+            let expected = xtea.encipher(&mem::read_block_as(&plain, order));
+            assert_eq!(mem::read_block_as(chunk, order), expected);
+        }
+        xtea.decrypt_blocks_as(&mut buf, order).ok().unwrap();
+        assert_eq!(buf, (0..72).collect::<Vec<u8>>());
+    }
+}
@@ -0,0 +1,46 @@
+//! CRC-32 (the IEEE polynomial, as in zlib and Ethernet), for catching
+//! accidental corruption.  It's not a MAC: anyone can forge it.
+
+// The reflected IEEE 802.3 polynomial.
+const POLY: u32 = 0xedb88320;
+
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+
+    pub fn new() -> Crc32 {
+        Crc32{ state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.state ^= b as u32;
+            for _ in 0..8 {
+                self.state = if self.state & 1 == 1 { (self.state >> 1) ^ POLY } else { self.state >> 1 };
+            }
+        }
+    }
+
+    pub fn finalize(&self) -> u32 {
+        !self.state
+    }
+
+    // The checksum as it goes on the wire, big-endian.
+    pub fn to_be_bytes(&self) -> [u8; 4] {
+        let n = self.finalize();
+        [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let mut crc = Crc32::new();
+    assert_eq!(crc.finalize(), 0);
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finalize(), 0xcbf43926);
+    assert_eq!(crc.to_be_bytes(), [0xcb, 0xf4, 0x39, 0x26]);
+}
@@ -0,0 +1,97 @@
+//! Keeps keys in the platform's credential store (the macOS Keychain,
+//! the Windows Credential Manager, or the Linux kernel keyring), so
+//! desktop apps never have to write a raw key to disk.
+
+use std::error;
+use std::fmt;
+
+use keyring;
+
+use super::Key;
+use mem;
+
+/// The ways loading or storing a key in the credential store can fail.
+#[derive(Debug)]
+pub enum KeyringError {
+    /// The credential store failed, or has no entry for this service
+    /// and account.
+    Store(keyring::Error),
+    /// The entry holds this many bytes instead of a 16-byte key.
+    WrongLength(usize),
+}
+
+impl fmt::Display for KeyringError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyringError::Store(ref e) => write!(f, "credential store failed: {}", e),
+            KeyringError::WrongLength(len) => write!(f, "credential holds {} bytes, not a 16-byte key", len),
+        }
+    }
+
+}
+
+impl error::Error for KeyringError {
+
+    fn description(&self) -> &str {
+        match *self {
+            KeyringError::Store(_) => "credential store failed",
+            KeyringError::WrongLength(_) => "credential is the wrong length",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            KeyringError::Store(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+}
+
+impl Key {
+
+    /// Loads the key stored under `service` and `account` in the
+    /// platform credential store by `store_in_keyring`.  Needs the
+    /// `keyring` feature.
+    ///
+    /// # Example:
+    /// ```.ignore
+    /// let key = tea::Key::from_keyring("my-app", "alice").ok().unwrap();
+    /// ```
+    pub fn from_keyring(service: &str, account: &str) -> Result<Key, KeyringError> {
+        let entry = try!(keyring::Entry::new(service, account).map_err(KeyringError::Store));
+        let mut secret = try!(entry.get_secret().map_err(KeyringError::Store));
+        if secret.len() != 16 {
+            mem::wipe(&mut secret);
+            return Err(KeyringError::WrongLength(secret.len()));
+        }
+        let mut bytes = [0u8; 16];
+        bytes.clone_from_slice(&secret);
+        let key = Key::from_bytes(&bytes);
+        mem::wipe(&mut bytes);
+        mem::wipe(&mut secret);
+        Ok(key)
+    }
+
+    /// Stores this key under `service` and `account` in the platform
+    /// credential store, as the 16 bytes `from_bytes` takes, replacing
+    /// anything already there.  Needs the `keyring` feature.
+    pub fn store_in_keyring(&self, service: &str, account: &str) -> Result<(), KeyringError> {
+        let entry = try!(keyring::Entry::new(service, account).map_err(KeyringError::Store));
+        let mut bytes = self.to_bytes();
+        let result = entry.set_secret(&bytes).map_err(KeyringError::Store);
+        mem::wipe(&mut bytes);
+        result
+    }
+
+}
+
+#[test]
+fn missing() {
+    keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+    match Key::from_keyring("tea-keyring-test", "nobody") {
+        Err(KeyringError::Store(keyring::Error::NoEntry)) => {},
+        other => panic!("expected NoEntry, got {:?}", other),
+    }
+}
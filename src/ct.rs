@@ -0,0 +1,41 @@
+//! Constant-time helpers, for checking secrets like MAC tags without
+//! the time taken giving away how close a guess was.
+
+use std::ptr;
+
+/// Returns whether `a` and `b` hold the same bytes.  Every byte is
+/// examined no matter where the first difference is, so the time taken
+/// depends only on the lengths, which aren't treated as secret.  Use
+/// this rather than `==` whenever checking a tag.
+///
+/// # Example:
+/// ```
+/// let tag = tea::mac::cmac([1, 2, 3, 4], b"Hello, world!");
+/// assert!(tea::ct::eq(&tag, &tea::mac::cmac([1, 2, 3, 4], b"Hello, world!")));
+/// assert!(!tea::ct::eq(&tag, &tea::mac::cmac([1, 2, 3, 4], b"Hello, world?")));
+/// assert!(!tea::ct::eq(&tag, &tag[..7]));
+/// ```
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    // Read the result back in a way the compiler can't see through, so
+    // it can't turn the loop into one that stops early.
+    unsafe { ptr::read_volatile(&diff) == 0 }
+}
+
+#[test]
+fn it_works() {
+    assert!(eq(b"", b""));
+    assert!(eq(b"abcdefgh", b"abcdefgh"));
+    for i in 0..8 {
+        let mut other = *b"abcdefgh";
+        other[i] ^= 0x80;
+        assert!(!eq(b"abcdefgh", &other));
+    }
+    assert!(!eq(b"abcdefgh", b"abcdefg"));
+}
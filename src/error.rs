@@ -0,0 +1,65 @@
+//! The error type for the whole-buffer functions and key constructors
+//! at the top of the crate.  The streaming wrappers in `io` report
+//! `std::io::Error`s instead.
+
+use std::error;
+use std::fmt;
+
+/// Why a buffer couldn't be encrypted or decrypted, or a key couldn't
+/// be made.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The ciphertext isn't a non-zero multiple of 8 bytes long, so
+    /// it can't be a complete padded message.
+    Truncated,
+    /// The final block doesn't end in valid padding, which most
+    /// likely means the key or iv is wrong.
+    BadPadding,
+    /// Data to be encrypted or decrypted without padding isn't a
+    /// multiple of 8 bytes long.
+    Unaligned,
+    /// The buffer given to hold the output is too small; it needs to
+    /// be at least `needed` bytes long.
+    BufferTooSmall {
+        /// How many bytes the buffer has to hold.
+        needed: usize,
+    },
+    /// Key material isn't the right length or format.
+    InvalidKey,
+    /// An iv isn't the right length.
+    InvalidIv,
+    /// An integrity check failed: the data has been tampered with or
+    /// corrupted, or the key is wrong.
+    BadTag,
+    /// A stream fed a piece at a time has already been finished, so
+    /// nothing more can go through it.
+    Finished,
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BufferTooSmall { needed } => write!(f, "buffer too small, {} bytes needed", needed),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+
+}
+
+impl error::Error for Error {
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::Truncated => "truncated ciphertext",
+            Error::BadPadding => "bad padding",
+            Error::Unaligned => "not a multiple of the block size",
+            Error::BufferTooSmall { .. } => "buffer too small",
+            Error::InvalidKey => "invalid key",
+            Error::InvalidIv => "invalid iv",
+            Error::BadTag => "integrity check failed",
+            Error::Finished => "stream already finished",
+        }
+    }
+
+}
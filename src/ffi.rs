@@ -0,0 +1,374 @@
+//! A C interface to CBC encryption with PKCS#7 padding, for C and C++
+//! code that would otherwise vendor the reference source.  Only built
+//! with the `ffi` feature; `include/tea.h` declares everything here.
+//! Build the shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! Keys are 16 bytes and ivs 8 bytes, read big-endian as by
+//! `Key::from_bytes` and `Block::from_be_bytes`.  Every function
+//! returns `TEA_OK` or one of the negative `TEA_ERR_*` codes.  Output
+//! lengths are passed in holding the size of the output buffer and
+//! come back holding the number of bytes written; if the buffer is too
+//! small, the function returns `TEA_ERR_BUFFER_TOO_SMALL`, sets the
+//! length to how big it needs to be, and changes nothing else, so it
+//! can simply be called again.  Input and output buffers must not
+//! overlap, except in `tea_decrypt_buf`, which can decrypt in place.
+//!
+//! The buffer functions produce and accept the same bytes as
+//! `tea::encrypt` and `tea::decrypt`, and a stream made with the
+//! `tea_encryptor_*` functions is the same as one made in one go.
+
+use std::ptr;
+use std::slice;
+
+use super::{Block, Error, Key};
+use buffer;
+use incremental::{Decryptor, Encryptor};
+use mem;
+
+/// Success.
+pub const TEA_OK: i32 = 0;
+/// A pointer that mustn't be null was.
+pub const TEA_ERR_NULL_POINTER: i32 = -1;
+/// The output buffer is too small; the length has been set to the size
+/// needed.
+pub const TEA_ERR_BUFFER_TOO_SMALL: i32 = -2;
+/// The ciphertext isn't a non-zero multiple of 8 bytes long.
+pub const TEA_ERR_TRUNCATED: i32 = -3;
+/// The final block doesn't end in valid padding: most likely the key
+/// or iv is wrong.
+pub const TEA_ERR_BAD_PADDING: i32 = -4;
+/// The stream has already been finished with `tea_encryptor_final` or
+/// `tea_decryptor_final`.
+pub const TEA_ERR_FINISHED: i32 = -5;
+/// Anything else went wrong.
+pub const TEA_ERR_OTHER: i32 = -6;
+
+fn error_code(err: Error, out_len: &mut usize) -> i32 {
+    match err {
+        Error::BufferTooSmall { needed } => {
+            *out_len = needed;
+            TEA_ERR_BUFFER_TOO_SMALL
+        },
+        Error::Truncated => TEA_ERR_TRUNCATED,
+        Error::BadPadding => TEA_ERR_BAD_PADDING,
+        Error::Finished => TEA_ERR_FINISHED,
+        _ => TEA_ERR_OTHER,
+    }
+}
+
+// Sets `out_len` to how much a stream function wrote.
+fn written(result: Result<usize, Error>, out_len: &mut usize) -> i32 {
+    match result {
+        Ok(len) => {
+            *out_len = len;
+            TEA_OK
+        },
+        Err(err) => error_code(err, out_len),
+    }
+}
+
+unsafe fn read_key(key: *const u8) -> Key {
+    let mut bytes = [0u8; 16];
+    bytes.clone_from_slice(slice::from_raw_parts(key, 16));
+    let key = Key::from_bytes(&bytes);
+    mem::wipe(&mut bytes);
+    key
+}
+
+unsafe fn read_iv(iv: *const u8) -> Block {
+    let mut bytes = [0u8; 8];
+    bytes.clone_from_slice(slice::from_raw_parts(iv, 8));
+    Block::from_be_bytes(&bytes)
+}
+
+// A null pointer is fine for an empty buffer.
+unsafe fn input<'a>(buf: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        Some(&[])
+    } else if buf.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(buf, len))
+    }
+}
+
+unsafe fn output<'a>(buf: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    if len == 0 {
+        Some(&mut [])
+    } else if buf.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts_mut(buf, len))
+    }
+}
+
+/// Encrypts the `in_len` bytes at `in` into `out`, which must have room
+/// for `in_len` rounded down to a multiple of 8, plus 8.
+#[no_mangle]
+pub unsafe extern "C" fn tea_encrypt_buf(key: *const u8, iv: *const u8, in_: *const u8, in_len: usize,
+                                         out: *mut u8, out_len: *mut usize) -> i32 {
+    if key.is_null() || iv.is_null() || out_len.is_null() {
+        return TEA_ERR_NULL_POINTER;
+    }
+    let (plaintext, out) = match (input(in_, in_len), output(out, *out_len)) {
+        (Some(plaintext), Some(out)) => (plaintext, out),
+        _ => return TEA_ERR_NULL_POINTER,
+    };
+    match buffer::encrypt_to(read_key(key), read_iv(iv), plaintext, out) {
+        Ok(len) => {
+            *out_len = len;
+            TEA_OK
+        },
+        Err(err) => error_code(err, &mut *out_len),
+    }
+}
+
+/// Decrypts the `in_len` bytes at `in` and checks the padding, writing
+/// the plaintext to `out`, which must have room for `in_len` bytes
+/// (the plaintext is shorter, but it's decrypted in place there).
+/// `in` and `out` may overlap, or be the same buffer.
+#[no_mangle]
+pub unsafe extern "C" fn tea_decrypt_buf(key: *const u8, iv: *const u8, in_: *const u8, in_len: usize,
+                                         out: *mut u8, out_len: *mut usize) -> i32 {
+    if key.is_null() || iv.is_null() || out_len.is_null() {
+        return TEA_ERR_NULL_POINTER;
+    }
+    if (in_.is_null() && in_len != 0) || (out.is_null() && *out_len != 0) {
+        return TEA_ERR_NULL_POINTER;
+    }
+    if *out_len < in_len {
+        return error_code(Error::BufferTooSmall { needed: in_len }, &mut *out_len);
+    }
+    // Only `out` is ever borrowed, so the buffers can overlap: the
+    // ciphertext is moved there first and decrypted in place.
+    if in_len != 0 {
+        ptr::copy(in_, out, in_len);
+    }
+    let out = match output(out, in_len) {
+        Some(out) => out,
+        None => return TEA_ERR_NULL_POINTER,
+    };
+    match buffer::decrypt_padded_in_place(read_key(key), read_iv(iv), out) {
+        Ok(len) => {
+            mem::wipe(&mut out[len..]);
+            *out_len = len;
+            TEA_OK
+        },
+        Err(err) => {
+            mem::wipe(out);
+            error_code(err, &mut *out_len)
+        },
+    }
+}
+
+/// A stream being encrypted a piece at a time.  Opaque to C.
+pub struct TeaEncryptor(Encryptor);
+
+/// A stream being decrypted a piece at a time.  Opaque to C.
+pub struct TeaDecryptor(Decryptor);
+
+/// Starts encrypting a stream, returning null if `key` or `iv` is.
+/// Free it with `tea_encryptor_free`.
+#[no_mangle]
+pub unsafe extern "C" fn tea_encryptor_new(key: *const u8, iv: *const u8) -> *mut TeaEncryptor {
+    if key.is_null() || iv.is_null() {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(TeaEncryptor(Encryptor::new(read_key(key), read_iv(iv)))))
+}
+
+/// Encrypts the `in_len` bytes at `in`, writing every whole block of
+/// ciphertext that's ready to `out`.  Up to 7 bytes wait for the next
+/// call, so `out` needs room for `in_len` + 7 at most.
+#[no_mangle]
+pub unsafe extern "C" fn tea_encryptor_update(ctx: *mut TeaEncryptor, in_: *const u8, in_len: usize,
+                                              out: *mut u8, out_len: *mut usize) -> i32 {
+    if ctx.is_null() || out_len.is_null() {
+        return TEA_ERR_NULL_POINTER;
+    }
+    let (plaintext, out) = match (input(in_, in_len), output(out, *out_len)) {
+        (Some(plaintext), Some(out)) => (plaintext, out),
+        _ => return TEA_ERR_NULL_POINTER,
+    };
+    written((*ctx).0.update(plaintext, out), &mut *out_len)
+}
+
+/// Pads and encrypts what's left of the stream, which is always one
+/// block: `out` needs room for 8 bytes.  Nothing more can be
+/// encrypted afterwards: calling this or `tea_encryptor_update` again
+/// returns `TEA_ERR_FINISHED`.
+#[no_mangle]
+pub unsafe extern "C" fn tea_encryptor_final(ctx: *mut TeaEncryptor, out: *mut u8, out_len: *mut usize) -> i32 {
+    if ctx.is_null() || out_len.is_null() {
+        return TEA_ERR_NULL_POINTER;
+    }
+    let out = match output(out, *out_len) {
+        Some(out) => out,
+        None => return TEA_ERR_NULL_POINTER,
+    };
+    written((*ctx).0.finish(out), &mut *out_len)
+}
+
+/// Frees an encryptor.  Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn tea_encryptor_free(ctx: *mut TeaEncryptor) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+/// Starts decrypting a stream, returning null if `key` or `iv` is.
+/// Free it with `tea_decryptor_free`.
+#[no_mangle]
+pub unsafe extern "C" fn tea_decryptor_new(key: *const u8, iv: *const u8) -> *mut TeaDecryptor {
+    if key.is_null() || iv.is_null() {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(TeaDecryptor(Decryptor::new(read_key(key), read_iv(iv)))))
+}
+
+/// Decrypts the `in_len` bytes at `in`, writing the plaintext that's
+/// ready to `out`.  The last block is held back until
+/// `tea_decryptor_final`, since it holds the padding, so `out` needs
+/// room for `in_len` + 7 at most.
+#[no_mangle]
+pub unsafe extern "C" fn tea_decryptor_update(ctx: *mut TeaDecryptor, in_: *const u8, in_len: usize,
+                                              out: *mut u8, out_len: *mut usize) -> i32 {
+    if ctx.is_null() || out_len.is_null() {
+        return TEA_ERR_NULL_POINTER;
+    }
+    let (ciphertext, out) = match (input(in_, in_len), output(out, *out_len)) {
+        (Some(ciphertext), Some(out)) => (ciphertext, out),
+        _ => return TEA_ERR_NULL_POINTER,
+    };
+    written((*ctx).0.update(ciphertext, out), &mut *out_len)
+}
+
+/// Decrypts the last block and checks the padding, writing the last
+/// few bytes of plaintext (7 at most) to `out`.  Once it has
+/// succeeded, calling this or `tea_decryptor_update` again returns
+/// `TEA_ERR_FINISHED`.
+#[no_mangle]
+pub unsafe extern "C" fn tea_decryptor_final(ctx: *mut TeaDecryptor, out: *mut u8, out_len: *mut usize) -> i32 {
+    if ctx.is_null() || out_len.is_null() {
+        return TEA_ERR_NULL_POINTER;
+    }
+    let out = match output(out, *out_len) {
+        Some(out) => out,
+        None => return TEA_ERR_NULL_POINTER,
+    };
+    written((*ctx).0.finish(out), &mut *out_len)
+}
+
+/// Frees a decryptor.  Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn tea_decryptor_free(ctx: *mut TeaDecryptor) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+#[test]
+fn buffers() {
+    let key = [7u8; 16];
+    let iv = [0u8, 0, 0, 5, 0, 0, 0, 6];
+    let mut out = [0u8; 16];
+    let mut out_len = 8;
+    unsafe {
+        assert_eq!(tea_encrypt_buf(key.as_ptr(), iv.as_ptr(), b"Hello, world!".as_ptr(), 13, out.as_mut_ptr(), &mut out_len),
+                   TEA_ERR_BUFFER_TOO_SMALL);
+        assert_eq!(out_len, 16);
+        assert_eq!(tea_encrypt_buf(key.as_ptr(), iv.as_ptr(), b"Hello, world!".as_ptr(), 13, out.as_mut_ptr(), &mut out_len),
+                   TEA_OK);
+    }
+    assert_eq!(&out[..out_len], &buffer::encrypt(Key::from_bytes(&key), [5, 6], b"Hello, world!")[..]);
+
+    let mut plain = [0u8; 16];
+    let mut plain_len = 16;
+    unsafe {
+        assert_eq!(tea_decrypt_buf(key.as_ptr(), iv.as_ptr(), out.as_ptr(), 16, plain.as_mut_ptr(), &mut plain_len), TEA_OK);
+        assert_eq!(&plain[..plain_len], b"Hello, world!");
+        plain_len = 16;
+        assert_eq!(tea_decrypt_buf(key.as_ptr(), iv.as_ptr(), out.as_ptr(), 15, plain.as_mut_ptr(), &mut plain_len),
+                   TEA_ERR_TRUNCATED);
+        assert_eq!(tea_decrypt_buf(ptr::null(), iv.as_ptr(), out.as_ptr(), 16, plain.as_mut_ptr(), &mut plain_len),
+                   TEA_ERR_NULL_POINTER);
+
+        // In place.
+        plain_len = 16;
+        let buf = out.as_mut_ptr();
+        assert_eq!(tea_decrypt_buf(key.as_ptr(), iv.as_ptr(), buf, 16, buf, &mut plain_len), TEA_OK);
+        assert_eq!(&out[..plain_len], b"Hello, world!");
+    }
+    let mut bad_len = 0;
+    assert_eq!(error_code(Error::BadPadding, &mut bad_len), TEA_ERR_BAD_PADDING);
+    assert_eq!(error_code(Error::InvalidKey, &mut bad_len), TEA_ERR_OTHER);
+}
+
+#[test]
+fn streams() {
+    let key = [7u8; 16];
+    let iv = [0u8, 0, 0, 5, 0, 0, 0, 6];
+    let plaintext: Vec<u8> = (0..100).collect();
+    let expected = buffer::encrypt(Key::from_bytes(&key), [5, 6], &plaintext);
+
+    for &chunk_size in [1, 3, 8, 13, 100].iter() {
+        let mut ciphertext = Vec::new();
+        let mut out = [0u8; 128];
+        unsafe {
+            let ctx = tea_encryptor_new(key.as_ptr(), iv.as_ptr());
+            for chunk in plaintext.chunks(chunk_size) {
+                let mut out_len = out.len();
+                assert_eq!(tea_encryptor_update(ctx, chunk.as_ptr(), chunk.len(), out.as_mut_ptr(), &mut out_len), TEA_OK);
+                ciphertext.push_all(&out[..out_len]);
+            }
+            let mut out_len = out.len();
+            assert_eq!(tea_encryptor_final(ctx, out.as_mut_ptr(), &mut out_len), TEA_OK);
+            ciphertext.push_all(&out[..out_len]);
+            tea_encryptor_free(ctx);
+        }
+        assert_eq!(ciphertext, expected);
+
+        let mut decrypted = Vec::new();
+        unsafe {
+            let ctx = tea_decryptor_new(key.as_ptr(), iv.as_ptr());
+            for chunk in ciphertext.chunks(chunk_size) {
+                let mut out_len = out.len();
+                assert_eq!(tea_decryptor_update(ctx, chunk.as_ptr(), chunk.len(), out.as_mut_ptr(), &mut out_len), TEA_OK);
+                decrypted.push_all(&out[..out_len]);
+            }
+            let mut out_len = out.len();
+            assert_eq!(tea_decryptor_final(ctx, out.as_mut_ptr(), &mut out_len), TEA_OK);
+            decrypted.push_all(&out[..out_len]);
+            tea_decryptor_free(ctx);
+        }
+        assert_eq!(decrypted, plaintext);
+    }
+}
+
+#[test]
+fn finished() {
+    let key = [7u8; 16];
+    let iv = [0u8, 0, 0, 5, 0, 0, 0, 6];
+    let mut out = [0u8; 16];
+    let mut out_len = out.len();
+    unsafe {
+        let ctx = tea_encryptor_new(key.as_ptr(), iv.as_ptr());
+        assert_eq!(tea_encryptor_final(ctx, out.as_mut_ptr(), &mut out_len), TEA_OK);
+        assert_eq!(out_len, 8);
+        out_len = out.len();
+        assert_eq!(tea_encryptor_final(ctx, out.as_mut_ptr(), &mut out_len), TEA_ERR_FINISHED);
+        assert_eq!(tea_encryptor_update(ctx, b"more".as_ptr(), 4, out.as_mut_ptr(), &mut out_len), TEA_ERR_FINISHED);
+        tea_encryptor_free(ctx);
+
+        let ctx = tea_decryptor_new(key.as_ptr(), iv.as_ptr());
+        let ciphertext = out;
+        assert_eq!(tea_decryptor_update(ctx, ciphertext.as_ptr(), 8, out.as_mut_ptr(), &mut out_len), TEA_OK);
+        out_len = out.len();
+        assert_eq!(tea_decryptor_final(ctx, out.as_mut_ptr(), &mut out_len), TEA_OK);
+        assert_eq!(out_len, 0);
+        assert_eq!(tea_decryptor_final(ctx, out.as_mut_ptr(), &mut out_len), TEA_ERR_FINISHED);
+        tea_decryptor_free(ctx);
+    }
+}
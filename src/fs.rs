@@ -0,0 +1,729 @@
+//! Encrypts and decrypts whole files.  Each encrypted file is a
+//! `Writer` stream: a `Header` holding a fresh random iv, followed by
+//! the CBC ciphertext of the contents with PKCS#7 padding, so only the
+//! key has to be kept track of.
+//!
+//! `encrypt_file_pipelined` and `decrypt_file_pipelined` do the same
+//! with reading, the cipher and writing on separate threads, and with
+//! the `mmap` feature on Unix, `encrypt_file_mmap` maps both files
+//! into memory and encrypts from one to the other without any reads
+//! or writes at all.
+//!
+//! Keys themselves can be loaded from files with `Key::from_file`.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Key, Block};
+use mem::{self, SecretVec};
+use io::{encrypt_copy, decrypt_copy, encrypt_copy_pipelined, decrypt_copy_pipelined, Reader};
+#[cfg(all(feature = "mmap", unix))]
+use buffer;
+#[cfg(all(feature = "mmap", unix))]
+use cipher::Cipher;
+#[cfg(all(feature = "mmap", unix))]
+use io::Header;
+#[cfg(all(feature = "mmap", unix))]
+use mmap::Map;
+
+/// Something went wrong with one of the files involved.  This says
+/// which file and what we were doing with it, as well as the
+/// underlying `std::io::Error`.
+#[derive(Debug)]
+pub struct Error {
+    path: PathBuf,
+    action: &'static str,
+    cause: io::Error,
+}
+
+impl Error {
+
+    fn new(path: &Path, action: &'static str, cause: io::Error) -> Error {
+        Error{
+            path: path.to_path_buf(),
+            action: action,
+            cause: cause,
+        }
+    }
+
+    /// The file that caused the error.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The underlying I/O or decryption error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.cause
+    }
+
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "couldn't {} {}: {}", self.action, self.path.display(), self.cause)
+    }
+
+}
+
+impl error::Error for Error {
+
+    fn description(&self) -> &str {
+        self.action
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        Some(&self.cause)
+    }
+
+}
+
+// `encrypt_copy` or `decrypt_copy`, or their pipelined versions,
+// between two files.
+type CopyFn = fn(&mut fs::File, &mut fs::File, Key, Block) -> io::Result<u64>;
+
+// Makes a fresh iv from the operating system's random number
+// generator: `Block::generate_iv` when the `rand` feature is on, and
+// the platform's own source otherwise.
+#[cfg(feature = "rand")]
+fn random_iv() -> io::Result<Block> {
+    Ok(Block::generate_iv())
+}
+
+#[cfg(all(not(feature = "rand"), unix))]
+fn random_iv() -> io::Result<Block> {
+    let mut f = try!(fs::File::open("/dev/urandom"));
+    let mut bytes = [0u8; 8];
+    try!(read_full(&mut f, &mut bytes));
+    Ok(Block::from_be_bytes(&bytes))
+}
+
+#[cfg(all(not(feature = "rand"), windows))]
+fn random_iv() -> io::Result<Block> {
+    #[link(name = "advapi32")]
+    extern "system" {
+        #[link_name = "SystemFunction036"]
+        fn RtlGenRandom(buffer: *mut u8, length: u32) -> u8;
+    }
+
+    let mut bytes = [0u8; 8];
+    if unsafe { RtlGenRandom(bytes.as_mut_ptr(), bytes.len() as u32) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Block::from_be_bytes(&bytes))
+}
+
+#[cfg(all(not(feature = "rand"), not(unix), not(windows)))]
+fn random_iv() -> io::Result<Block> {
+    Err(io::Error::new(io::ErrorKind::Other, "no random number generator",
+                       Some("build with the `rand` feature on this platform".to_string())))
+}
+
+// Fills `buf` from `reader`, failing if it runs out first.
+#[cfg(all(not(feature = "rand"), unix))]
+fn read_full<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::InvalidData, "file too short",
+                                               Some(format!("expected at least {} bytes", buf.len())))),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Encrypts the file at `src` with `key` and a new random iv, writing
+/// the result to `dst`, which is created or truncated.  Returns the
+/// number of plaintext bytes encrypted.
+///
+/// # Example:
+/// ```.ignore
+/// let key = [1, 2, 3, 4];
+/// tea::fs::encrypt_file("notes.txt", "notes.txt.tea", key).ok().unwrap();
+/// tea::fs::decrypt_file("notes.txt.tea", "notes-copy.txt", key).ok().unwrap();
+/// ```
+pub fn encrypt_file<P: AsRef<Path>, Q: AsRef<Path>, K: Into<Key>>(src: P, dst: Q, key: K) -> Result<u64, Error> {
+    encrypt_file_with(src.as_ref(), dst.as_ref(), key.into(), encrypt_copy)
+}
+
+/// Like `encrypt_file`, but reads, encrypts and writes on separate
+/// threads, with `io::encrypt_copy_pipelined`, for big files on fast
+/// disks.
+pub fn encrypt_file_pipelined<P: AsRef<Path>, Q: AsRef<Path>, K: Into<Key>>(src: P, dst: Q, key: K) -> Result<u64, Error> {
+    encrypt_file_with(src.as_ref(), dst.as_ref(), key.into(), encrypt_copy_pipelined)
+}
+
+fn encrypt_file_with(src: &Path, dst: &Path, key: Key, copy: CopyFn) -> Result<u64, Error> {
+    let mut src_f = try!(fs::File::open(src).map_err(|e| Error::new(src, "open", e)));
+    let iv = try!(random_iv().map_err(|e| Error::new(dst, "make an iv for", e)));
+    let mut dst_f = try!(fs::File::create(dst).map_err(|e| Error::new(dst, "create", e)));
+    copy(&mut src_f, &mut dst_f, key, iv).map_err(|e| Error::new(dst, "encrypt into", e))
+}
+
+/// Decrypts the file at `src`, made by `encrypt_file` with the same
+/// `key`, writing the plaintext to `dst`, which is created or
+/// truncated.  If decryption fails partway, `dst` is removed again.
+/// Returns the number of plaintext bytes decrypted.
+pub fn decrypt_file<P: AsRef<Path>, Q: AsRef<Path>, K: Into<Key>>(src: P, dst: Q, key: K) -> Result<u64, Error> {
+    decrypt_file_with(src.as_ref(), dst.as_ref(), key.into(), decrypt_copy)
+}
+
+/// Like `decrypt_file`, but pipelined across threads like
+/// `encrypt_file_pipelined`.
+pub fn decrypt_file_pipelined<P: AsRef<Path>, Q: AsRef<Path>, K: Into<Key>>(src: P, dst: Q, key: K) -> Result<u64, Error> {
+    decrypt_file_with(src.as_ref(), dst.as_ref(), key.into(), decrypt_copy_pipelined)
+}
+
+fn decrypt_file_with(src: &Path, dst: &Path, key: Key, copy: CopyFn) -> Result<u64, Error> {
+    let mut src_f = try!(fs::File::open(src).map_err(|e| Error::new(src, "open", e)));
+    let mut dst_f = try!(fs::File::create(dst).map_err(|e| Error::new(dst, "create", e)));
+    // The iv passed on goes unused, since the `Reader` takes the real
+    // one from the header.
+    match copy(&mut src_f, &mut dst_f, key, Block::new([0, 0])) {
+        Ok(n) => Ok(n),
+        Err(e) => {
+            drop(dst_f);
+            let _ = fs::remove_file(dst);
+            Err(Error::new(src, "decrypt", e))
+        },
+    }
+}
+
+// How much of the file `encrypt_file_mmap` encrypts at a time, in a
+// buffer of its own, so the plaintext is still in the cache.
+#[cfg(all(feature = "mmap", unix))]
+const MMAP_CHUNK: usize = 64 * 1024;
+
+/// Like `encrypt_file`, and making the same output, but maps `src` and
+/// `dst` into memory and runs the cipher straight over the mappings,
+/// so a big local file is encrypted without a single `read` or
+/// `write` call.  The plaintext is encrypted in a private buffer and
+/// only ciphertext is copied into `dst`'s mapping, so writeback can
+/// never put plaintext in `dst`.  `dst` is sized up front, so it's created even if
+/// `src` is empty.  Only on Unix, with the `mmap` feature.
+///
+/// Nothing else may truncate `src` while this runs: touching pages of
+/// a mapping that no longer exist in the file kills the process with
+/// `SIGBUS`.  Files that other processes might be changing, and
+/// things that can't be mapped at all, like pipes, should go through
+/// `encrypt_file` instead.
+#[cfg(all(feature = "mmap", unix))]
+pub fn encrypt_file_mmap<P: AsRef<Path>, Q: AsRef<Path>, K: Into<Key>>(src: P, dst: Q, key: K) -> Result<u64, Error> {
+    let (src, dst, key) = (src.as_ref(), dst.as_ref(), key.into());
+    let src_f = try!(fs::File::open(src).map_err(|e| Error::new(src, "open", e)));
+    let len = try!(src_f.metadata().map_err(|e| Error::new(src, "stat", e))).len() as usize;
+    let input = try!(Map::read(&src_f, len).map_err(|e| Error::new(src, "map", e)));
+    let iv = try!(random_iv().map_err(|e| Error::new(dst, "make an iv for", e)));
+    let header = Header::new(iv).to_bytes();
+
+    let start = header.len();
+    let whole = len / 8 * 8;
+    let dst_f = try!(fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(dst)
+                     .map_err(|e| Error::new(dst, "create", e)));
+    try!(dst_f.set_len((start + whole + 8) as u64).map_err(|e| Error::new(dst, "resize", e)));
+    let mut output = try!(Map::write(&dst_f, start + whole + 8).map_err(|e| Error::new(dst, "map", e)));
+
+    output[..start].clone_from_slice(&header);
+    let cipher = Cipher::new(key);
+    let mut prev = iv;
+    let mut buf = SecretVec::with_capacity(MMAP_CHUNK);
+    buf.resize(MMAP_CHUNK, 0);
+    {
+        let (body, last) = output[start..].split_at_mut(whole);
+        for (from, to) in input[..whole].chunks(MMAP_CHUNK).zip(body.chunks_mut(MMAP_CHUNK)) {
+            let buf = &mut buf[..from.len()];
+            buf.clone_from_slice(from);
+            buffer::cbc_encrypt(&cipher, &mut prev, buf);
+            to.clone_from_slice(buf);
+        }
+        let left = len - whole;
+        let buf = &mut buf[..8];
+        buf[..left].clone_from_slice(&input[whole..]);
+        for b in buf[left..].iter_mut() {
+            *b = (8 - left) as u8;
+        }
+        buffer::cbc_encrypt(&cipher, &mut prev, buf);
+        last.clone_from_slice(buf);
+    }
+    Ok(len as u64)
+}
+
+/// Re-encrypts the file at `path`, made by `encrypt_file` with
+/// `old_key`, so that it's encrypted with `new_key` (and a new iv)
+/// instead.  The new version is written to a temporary file next to
+/// `path`, with the original's permissions, synced to disk, and then
+/// renamed over the original, so a crash partway leaves either the old
+/// file or the new one, never a mix.  Returns the number of plaintext
+/// bytes re-encrypted.
+pub fn reencrypt_file<P: AsRef<Path>, K: Into<Key>, L: Into<Key>>(path: P, old_key: K, new_key: L) -> Result<u64, Error> {
+    let path = path.as_ref();
+    let src_f = try!(fs::File::open(path).map_err(|e| Error::new(path, "open", e)));
+    let permissions = try!(src_f.metadata().map_err(|e| Error::new(path, "stat", e))).permissions();
+    let new_iv = try!(random_iv().map_err(|e| Error::new(path, "make an iv for", e)));
+    let (tmp, mut tmp_f) = try!(create_temp(path));
+
+    let result = tmp_f.set_permissions(permissions)
+        .and_then(|_| encrypt_copy(&mut Reader::new(src_f, old_key, Block::new([0, 0])), &mut tmp_f, new_key, new_iv))
+        .and_then(|n| tmp_f.sync_all().map(|_| n));
+    drop(tmp_f);
+    let n = match result {
+        Ok(n) => n,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(Error::new(path, "re-encrypt", e));
+        },
+    };
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(Error::new(path, "replace", e));
+    }
+    // The rename itself is only durable once the directory is synced.
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    try!(fs::File::open(dir).and_then(|d| d.sync_all()).map_err(|e| Error::new(dir, "sync", e)));
+    Ok(n)
+}
+
+// Creates a temporary file next to `path` under a name nothing else
+// is using, so concurrent rotations of the same file, or an unrelated
+// file that happens to have the name, don't get clobbered.
+fn create_temp(path: &Path) -> Result<(PathBuf, fs::File), Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let mut name = path.file_name().unwrap_or(path.as_os_str()).to_os_string();
+        name.push(format!(".tea-tmp-{}-{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let tmp = path.with_file_name(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(f) => return Ok((tmp, f)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::new(&tmp, "create", e)),
+        }
+    }
+}
+
+/// Chooses where `encrypt_tree` puts the files it encrypts.
+#[derive(Clone, Debug)]
+pub struct TreeOptions {
+    extension: String,
+    destination: Option<PathBuf>,
+}
+
+impl TreeOptions {
+
+    /// By default, each file is encrypted into a file next to it with
+    /// `.tea` added to its name.
+    pub fn new() -> TreeOptions {
+        TreeOptions{
+            extension: "tea".to_string(),
+            destination: None,
+        }
+    }
+
+    /// Sets the extension added to the name of each encrypted file.
+    pub fn extension(mut self, extension: &str) -> TreeOptions {
+        self.extension = extension.to_string();
+        self
+    }
+
+    /// Puts the encrypted files in a copy of the directory structure
+    /// under `dir`, instead of next to the originals.
+    pub fn destination<P: AsRef<Path>>(mut self, dir: P) -> TreeOptions {
+        self.destination = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+}
+
+/// Encrypts every regular file under `root` with `key`, each with its
+/// own random iv as in `encrypt_file`.  Symbolic links are skipped, and
+/// so are files that already have the extension when the output goes
+/// next to the originals.  One failure doesn't stop the rest: the
+/// result has an entry for every file tried (and every directory that
+/// couldn't be read), with the number of bytes encrypted or what went
+/// wrong.
+///
+/// # Example:
+/// ```.ignore
+/// use tea::fs::{encrypt_tree, TreeOptions};
+///
+/// let options = TreeOptions::new().destination("/backup/photos");
+/// for (path, result) in encrypt_tree("photos", [1, 2, 3, 4], &options) {
+///     if let Err(e) = result {
+///         println!("skipped {}: {}", path.display(), e);
+///     }
+/// }
+/// ```
+pub fn encrypt_tree<P: AsRef<Path>, K: Into<Key>>(root: P, key: K, options: &TreeOptions) -> Vec<(PathBuf, Result<u64, Error>)> {
+    let mut results = Vec::new();
+    walk_tree(root.as_ref(), Path::new(""), key.into(), options, &mut results);
+    results
+}
+
+// Encrypts everything in `dir`, which is at `rel` relative to the
+// root, and recurses into subdirectories.
+fn walk_tree(dir: &Path, rel: &Path, key: Key, options: &TreeOptions, results: &mut Vec<(PathBuf, Result<u64, Error>)>) {
+    let entries = fs::read_dir(dir).and_then(|entries| entries.map(|entry| entry.map(|e| e.path())).collect());
+    let mut paths: Vec<PathBuf> = match entries {
+        Ok(paths) => paths,
+        Err(e) => {
+            results.push((dir.to_path_buf(), Err(Error::new(dir, "read directory", e))));
+            return;
+        },
+    };
+    paths.sort();
+
+    if let Some(ref destination) = options.destination {
+        let out_dir = destination.join(rel);
+        if let Err(e) = fs::create_dir_all(&out_dir) {
+            results.push((dir.to_path_buf(), Err(Error::new(&out_dir, "create directory", e))));
+            return;
+        }
+    }
+
+    for path in paths {
+        let file_type = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata.file_type(),
+            Err(e) => {
+                let err = Error::new(&path, "stat", e);
+                results.push((path, Err(err)));
+                continue;
+            },
+        };
+        let name = path.file_name().unwrap().to_os_string();
+        if file_type.is_dir() {
+            walk_tree(&path, &rel.join(&name), key, options, results);
+        } else if file_type.is_file() {
+            if options.destination.is_none() && path.extension().map_or(false, |ext| ext == &options.extension[..]) {
+                continue;
+            }
+            let mut out_name = name;
+            out_name.push(".");
+            out_name.push(&options.extension);
+            let out = match options.destination {
+                Some(ref destination) => destination.join(rel).join(out_name),
+                None => path.with_file_name(out_name),
+            };
+            let result = encrypt_file(&path, out, key);
+            results.push((path, result));
+        }
+    }
+}
+
+/// Why `Key::from_file` couldn't load a key.
+#[derive(Debug)]
+pub enum KeyFileError {
+    /// The file couldn't be read.
+    Io(io::Error),
+    /// Anyone on the system can read the file (Unix only).  The mode
+    /// it has is included.
+    WorldReadable(u32),
+    /// The file holds neither 16 raw bytes nor 32 hex digits; this is
+    /// how many bytes it does hold (ignoring surrounding whitespace if
+    /// it looked like text).
+    WrongLength(usize),
+    /// The file has 32 characters but they aren't all hex digits.
+    NotHex,
+}
+
+impl fmt::Display for KeyFileError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyFileError::Io(ref e) => write!(f, "couldn't read key file: {}", e),
+            KeyFileError::WorldReadable(mode) => write!(f, "key file is world-readable (mode {:o})", mode),
+            KeyFileError::WrongLength(len) => write!(f, "key file holds {} bytes, not 16 raw bytes or 32 hex digits", len),
+            KeyFileError::NotHex => f.write_str("key file isn't 32 hex digits"),
+        }
+    }
+
+}
+
+impl error::Error for KeyFileError {
+
+    fn description(&self) -> &str {
+        match *self {
+            KeyFileError::Io(_) => "couldn't read key file",
+            KeyFileError::WorldReadable(_) => "key file is world-readable",
+            KeyFileError::WrongLength(_) => "key file is the wrong length",
+            KeyFileError::NotHex => "key file isn't hex",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            KeyFileError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+}
+
+#[cfg(unix)]
+fn check_permissions(f: &fs::File) -> Result<(), KeyFileError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = try!(f.metadata().map_err(KeyFileError::Io)).permissions().mode();
+    if mode & 0o004 != 0 {
+        return Err(KeyFileError::WorldReadable(mode & 0o7777));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_: &fs::File) -> Result<(), KeyFileError> {
+    Ok(())
+}
+
+impl Key {
+
+    /// Loads a key from the file at `path`, which must hold either
+    /// exactly 16 raw bytes or 32 hex digits (surrounding whitespace,
+    /// like a trailing newline, is fine).  Both are read big-endian,
+    /// as in `Key::from_bytes`.  On Unix, a file that anyone can read
+    /// is refused.
+    ///
+    /// # Example:
+    /// ```.ignore
+    /// // $ head -c 16 /dev/urandom > app.key && chmod 600 app.key
+    /// let key = tea::Key::from_file("app.key").ok().unwrap();
+    /// ```
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Key, KeyFileError> {
+        let mut f = try!(fs::File::open(path).map_err(KeyFileError::Io));
+        try!(check_permissions(&f));
+        let mut contents = SecretVec::new();
+        try!(io::Read::read_to_end(&mut f, &mut contents).map_err(KeyFileError::Io));
+        if contents.len() == 16 {
+            let mut bytes = [0u8; 16];
+            bytes.clone_from_slice(&contents);
+            let key = Key::from_bytes(&bytes);
+            mem::wipe(&mut bytes);
+            return Ok(key);
+        }
+        let is_space = |b: &u8| *b == b' ' || *b == b'\t' || *b == b'\r' || *b == b'\n';
+        let start = contents.iter().position(|b| !is_space(b)).unwrap_or(contents.len());
+        let end = contents.iter().rposition(|b| !is_space(b)).map_or(start, |i| i + 1);
+        let hex = &contents[start..end];
+        if hex.len() != 32 {
+            return Err(KeyFileError::WrongLength(hex.len()));
+        }
+        match str::from_utf8(hex).ok().and_then(|hex| Key::from_hex(hex).ok()) {
+            Some(key) => Ok(key),
+            None => Err(KeyFileError::NotHex),
+        }
+    }
+
+}
+
+// A path in the temp directory for a test to use, with the pid in it
+// so that concurrent runs of the tests don't clobber each other's
+// files.
+#[cfg(test)]
+fn temp_path(name: &str) -> PathBuf {
+    ::std::env::temp_dir().join(format!("{}-{}", name, process::id()))
+}
+
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    let (plain, crypt, copy) = (temp_path("tea-fs-test-plain"), temp_path("tea-fs-test-crypt"), temp_path("tea-fs-test-copy"));
+    let input: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    fs::File::create(&plain).ok().unwrap().write_all(&input).ok().unwrap();
+
+    assert_eq!(encrypt_file(&plain, &crypt, [1, 2, 3, 4]).ok().unwrap(), 1000);
+    assert_eq!(fs::metadata(&crypt).ok().unwrap().len(), 16 + 1008);
+    assert_eq!(decrypt_file(&crypt, &copy, [1, 2, 3, 4]).ok().unwrap(), 1000);
+    let mut output = Vec::new();
+    fs::File::open(&copy).ok().unwrap().read_to_end(&mut output).ok().unwrap();
+    assert_eq!(output, input);
+
+    let err = decrypt_file(&crypt, &copy, [1, 2, 3, 5]).err().unwrap();
+    assert_eq!(err.path(), crypt.as_path());
+    assert!(fs::metadata(&copy).is_err());
+    let err = encrypt_file(temp_path("tea-fs-test-missing"), &crypt, [1, 2, 3, 4]).err().unwrap();
+    assert_eq!(err.io_error().kind(), io::ErrorKind::NotFound);
+
+    for path in [plain, crypt].iter() {
+        fs::remove_file(path).ok().unwrap();
+    }
+}
+
+#[test]
+fn pipelined() {
+    use std::io::{Read, Write};
+
+    let (plain, crypt, copy) = (temp_path("tea-fs-test-pipelined-plain"), temp_path("tea-fs-test-pipelined-crypt"),
+                                temp_path("tea-fs-test-pipelined-copy"));
+    let input: Vec<u8> = (0..300000).map(|i| i as u8).collect();
+    fs::File::create(&plain).ok().unwrap().write_all(&input).ok().unwrap();
+
+    assert_eq!(encrypt_file_pipelined(&plain, &crypt, [1, 2, 3, 4]).ok().unwrap(), 300000);
+    assert_eq!(decrypt_file(&crypt, &copy, [1, 2, 3, 4]).ok().unwrap(), 300000);
+    fs::remove_file(&copy).ok().unwrap();
+    assert_eq!(decrypt_file_pipelined(&crypt, &copy, [1, 2, 3, 4]).ok().unwrap(), 300000);
+    let mut output = Vec::new();
+    fs::File::open(&copy).ok().unwrap().read_to_end(&mut output).ok().unwrap();
+    assert!(output == input);
+
+    for path in [plain, crypt, copy].iter() {
+        fs::remove_file(path).ok().unwrap();
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+#[test]
+fn mmap() {
+    use std::io::{Read, Write};
+
+    let (plain, crypt, copy) = (temp_path("tea-fs-test-mmap-plain"), temp_path("tea-fs-test-mmap-crypt"),
+                                temp_path("tea-fs-test-mmap-copy"));
+    for &len in [0, 13, 64, 300001].iter() {
+        let input: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        fs::File::create(&plain).ok().unwrap().write_all(&input).ok().unwrap();
+
+        assert_eq!(encrypt_file_mmap(&plain, &crypt, [1, 2, 3, 4]).ok().unwrap(), len as u64);
+        assert_eq!(fs::metadata(&crypt).ok().unwrap().len(), 16 + len as u64 / 8 * 8 + 8);
+        assert_eq!(decrypt_file(&crypt, &copy, [1, 2, 3, 4]).ok().unwrap(), len as u64);
+        let mut output = Vec::new();
+        fs::File::open(&copy).ok().unwrap().read_to_end(&mut output).ok().unwrap();
+        assert!(output == input);
+    }
+    assert_eq!(encrypt_file_mmap(temp_path("tea-fs-test-mmap-missing"), &crypt, [1, 2, 3, 4]).err().unwrap().path(),
+               temp_path("tea-fs-test-mmap-missing").as_path());
+
+    for path in [plain, crypt, copy].iter() {
+        fs::remove_file(path).ok().unwrap();
+    }
+}
+
+#[test]
+fn reencrypt() {
+    use std::env;
+    use std::io::{Read, Write};
+
+    let (plain, crypt, copy) = (temp_path("tea-fs-test-re-plain"), temp_path("tea-fs-test-re-crypt"), temp_path("tea-fs-test-re-copy"));
+    fs::File::create(&plain).ok().unwrap().write_all(b"Hello, world!").ok().unwrap();
+    encrypt_file(&plain, &crypt, [1, 2, 3, 4]).ok().unwrap();
+
+    assert_eq!(reencrypt_file(&crypt, [1, 2, 3, 4], [5, 6, 7, 8]).ok().unwrap(), 13);
+    assert!(decrypt_file(&crypt, &copy, [1, 2, 3, 4]).is_err());
+    decrypt_file(&crypt, &copy, [5, 6, 7, 8]).ok().unwrap();
+    let mut output = String::new();
+    fs::File::open(&copy).ok().unwrap().read_to_string(&mut output).ok().unwrap();
+    assert_eq!(output, "Hello, world!");
+
+    // With the wrong old key, the original is left alone, and so is
+    // an unrelated file with a temporary-looking name.
+    let mut bystander = crypt.clone().into_os_string();
+    bystander.push(".tea-tmp");
+    let bystander = PathBuf::from(bystander);
+    fs::File::create(&bystander).ok().unwrap().write_all(b"mine").ok().unwrap();
+    assert!(reencrypt_file(&crypt, [1, 2, 3, 4], [9, 9, 9, 9]).is_err());
+    decrypt_file(&crypt, &copy, [5, 6, 7, 8]).ok().unwrap();
+    let prefix = format!("{}-", bystander.file_name().unwrap().to_string_lossy());
+    let mut leftovers = fs::read_dir(env::temp_dir()).ok().unwrap().map(|entry| entry.ok().unwrap().file_name())
+        .filter(|name| name.to_string_lossy().starts_with(&prefix));
+    assert!(leftovers.next().is_none());
+    output.clear();
+    fs::File::open(&bystander).ok().unwrap().read_to_string(&mut output).ok().unwrap();
+    assert_eq!(output, "mine");
+
+    for path in [plain, crypt, copy, bystander].iter() {
+        fs::remove_file(path).ok().unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn reencrypt_keeps_permissions() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    let (plain, crypt) = (temp_path("tea-fs-test-perm-plain"), temp_path("tea-fs-test-perm-crypt"));
+    fs::File::create(&plain).ok().unwrap().write_all(b"Hello, world!").ok().unwrap();
+    encrypt_file(&plain, &crypt, [1, 2, 3, 4]).ok().unwrap();
+    fs::set_permissions(&crypt, fs::Permissions::from_mode(0o600)).ok().unwrap();
+
+    reencrypt_file(&crypt, [1, 2, 3, 4], [5, 6, 7, 8]).ok().unwrap();
+    assert_eq!(fs::metadata(&crypt).ok().unwrap().permissions().mode() & 0o777, 0o600);
+
+    for path in [plain, crypt].iter() {
+        fs::remove_file(path).ok().unwrap();
+    }
+}
+
+#[test]
+fn tree() {
+    use std::io::Write;
+
+    let (root, backup) = (temp_path("tea-fs-test-tree"), temp_path("tea-fs-test-tree-backup"));
+    let _ = fs::remove_dir_all(&root);
+    let _ = fs::remove_dir_all(&backup);
+    fs::create_dir_all(root.join("sub")).ok().unwrap();
+    fs::File::create(root.join("a")).ok().unwrap().write_all(b"first").ok().unwrap();
+    fs::File::create(root.join("sub").join("b")).ok().unwrap().write_all(b"second!").ok().unwrap();
+
+    let results = encrypt_tree(&root, [1, 2, 3, 4], &TreeOptions::new().destination(&backup));
+    let summary: Vec<(PathBuf, u64)> = results.into_iter().map(|(path, result)| (path, result.ok().unwrap())).collect();
+    assert_eq!(summary, [(root.join("a"), 5), (root.join("sub").join("b"), 7)]);
+    decrypt_file(backup.join("sub").join("b.tea"), root.join("b-copy"), [1, 2, 3, 4]).ok().unwrap();
+
+    // Alongside the originals, a second run skips what's already encrypted.
+    assert_eq!(encrypt_tree(&root, [1, 2, 3, 4], &TreeOptions::new()).len(), 3);
+    assert_eq!(encrypt_tree(&root, [1, 2, 3, 4], &TreeOptions::new()).len(), 3);
+    assert!(fs::metadata(root.join("sub").join("b.tea")).is_ok());
+
+    let results = encrypt_tree(temp_path("tea-fs-test-tree-missing"), [1, 2, 3, 4], &TreeOptions::new());
+    assert_eq!(results.len(), 1);
+    assert!(results[0].1.is_err());
+
+    fs::remove_dir_all(&root).ok().unwrap();
+    fs::remove_dir_all(&backup).ok().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn key_file() {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temp_path("tea-fs-test-key");
+    let write = |contents: &[u8], mode: u32| {
+        let _ = fs::remove_file(&path);
+        fs::File::create(&path).ok().unwrap().write_all(contents).ok().unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).ok().unwrap();
+    };
+    let key = Key::new([0x00010203, 0x04050607, 0x08090a0b, 0x0c0d0e0f]);
+
+    write(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], 0o600);
+    assert_eq!(Key::from_file(&path).ok().unwrap(), key);
+    write(b"000102030405060708090a0b0c0d0e0f\n", 0o640);
+    assert_eq!(Key::from_file(&path).ok().unwrap(), key);
+    write(b"000102030405060708090a0b0c0d0e0f\n", 0o644);
+    match Key::from_file(&path) {
+        Err(KeyFileError::WorldReadable(0o644)) => {},
+        other => panic!("expected WorldReadable, got {:?}", other),
+    }
+    write(b"0001020304050607\n", 0o600);
+    match Key::from_file(&path) {
+        Err(KeyFileError::WrongLength(16)) => {},
+        other => panic!("expected WrongLength, got {:?}", other),
+    }
+    write(b"000102030405060708090a0b0c0d0e0g", 0o600);
+    match Key::from_file(&path) {
+        Err(KeyFileError::NotHex) => {},
+        other => panic!("expected NotHex, got {:?}", other),
+    }
+    fs::remove_file(&path).ok().unwrap();
+}
@@ -0,0 +1,246 @@
+//! Hashes built out of the XTEA cipher, for when it's the only
+//! primitive around.  `Hasher` gives 128 bits with the Abreast-DM
+//! construction, which turns a block cipher with a 64-bit block and a
+//! 128-bit key into a double-block compression function.  `Hasher64`
+//! gives 64 bits with plain Davies-Meyer, which is cheaper but only
+//! good for checksums and hash tables: collisions take about 2^32
+//! tries to find.  Neither has had much study, so prefer a standard
+//! hash where one is available.
+//!
+//! Messages are padded Merkle-Damgård style: a 0x80 byte, zeroes, and
+//! the message length in bits as a big-endian u64.
+
+use std::cmp;
+
+use super::{Key, Block};
+use cipher;
+
+// The starting chaining values, taken from the fractional part of pi
+// so there's nothing up our sleeve.
+const G0: [u32; 2] = [0x243f6a88, 0x85a308d3];
+const H0: [u32; 2] = [0x13198a2e, 0x03707344];
+const D0: [u32; 2] = [0xa4093822, 0x299f31d0];
+
+fn read_word(bytes: &[u8]) -> u32 {
+    bytes[..4].iter().fold(0, |word, &b| (word << 8) | b as u32)
+}
+
+/// Computes a 128-bit hash of data fed to it a piece at a time.
+///
+/// # Example:
+/// ```
+/// use tea::hash::{self, Hasher};
+///
+/// let mut hasher = Hasher::new();
+/// hasher.update(b"Hello, ");
+/// hasher.update(b"world!");
+/// assert_eq!(hasher.finish(), hash::digest(b"Hello, world!"));
+/// ```
+#[derive(Clone)]
+pub struct Hasher {
+    g: [u32; 2],
+    h: [u32; 2],
+    buf: Vec<u8>,
+    len: u64,
+}
+
+impl Hasher {
+
+    /// Starts hashing a new message.
+    pub fn new() -> Hasher {
+        Hasher{
+            g: G0,
+            h: H0,
+            buf: Vec::with_capacity(8),
+            len: 0,
+        }
+    }
+
+    // Abreast-DM: the two halves of the state are each encrypted with
+    // a key made from the other half and the message block.
+    fn compress(&mut self, block: &[u8]) {
+        let m = [read_word(&block[..4]), read_word(&block[4..])];
+        let g = cipher::encipher(&Key::new([self.h[0], self.h[1], m[0], m[1]]), &Block::new(self.g));
+        let h = cipher::encipher(&Key::new([m[0], m[1], self.g[0], self.g[1]]), &Block::new([!self.h[0], !self.h[1]]));
+        self.g = [self.g[0] ^ g[0], self.g[1] ^ g[1]];
+        self.h = [self.h[0] ^ h[0], self.h[1] ^ h[1]];
+    }
+
+    /// Feeds more of the message in.
+    pub fn update(&mut self, data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        let mut data = data;
+        if !self.buf.is_empty() {
+            let n = cmp::min(8 - self.buf.len(), data.len());
+            self.buf.push_all(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() < 8 {
+                return;
+            }
+            let block = self.buf.clone();
+            self.compress(&block);
+            self.buf.truncate(0);
+        }
+        for chunk in data.chunks(8) {
+            if chunk.len() == 8 {
+                self.compress(chunk);
+            } else {
+                self.buf.push_all(chunk);
+            }
+        }
+    }
+
+    /// Pads the message and returns the hash.
+    pub fn finish(mut self) -> [u8; 16] {
+        let bits = self.len.wrapping_mul(8);
+        let mut tail = self.buf.clone();
+        tail.push(0x80);
+        while tail.len() % 8 != 0 {
+            tail.push(0);
+        }
+        for i in 0..8 {
+            tail.push((bits >> (56 - 8 * i)) as u8);
+        }
+        for chunk in tail.chunks(8) {
+            self.compress(chunk);
+        }
+        let mut out = [0u8; 16];
+        for (i, word) in [self.g[0], self.g[1], self.h[0], self.h[1]].iter().enumerate() {
+            for j in 0..4 {
+                out[4 * i + j] = (word >> (24 - 8 * j)) as u8;
+            }
+        }
+        out
+    }
+
+}
+
+/// Hashes `data` to 128 bits in one go.
+pub fn digest(data: &[u8]) -> [u8; 16] {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Computes a 64-bit hash of data fed to it a piece at a time.  Each
+/// 16 bytes of message is used as the key to encrypt the chaining
+/// value, which is then XORed in (Davies-Meyer).
+///
+/// # Example:
+/// ```
+/// use tea::hash::{self, Hasher64};
+///
+/// let mut hasher = Hasher64::new();
+/// hasher.update(b"Hello, ");
+/// hasher.update(b"world!");
+/// assert_eq!(hasher.finish(), hash::digest64(b"Hello, world!"));
+/// ```
+#[derive(Clone)]
+pub struct Hasher64 {
+    h: [u32; 2],
+    buf: Vec<u8>,
+    len: u64,
+}
+
+impl Hasher64 {
+
+    /// Starts hashing a new message.
+    pub fn new() -> Hasher64 {
+        Hasher64{
+            h: D0,
+            buf: Vec::with_capacity(16),
+            len: 0,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let m = Key::new([read_word(&block[..4]), read_word(&block[4..]),
+                          read_word(&block[8..]), read_word(&block[12..])]);
+        let h = cipher::encipher(&m, &Block::new(self.h));
+        self.h = [self.h[0] ^ h[0], self.h[1] ^ h[1]];
+    }
+
+    /// Feeds more of the message in.
+    pub fn update(&mut self, data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        let mut data = data;
+        if !self.buf.is_empty() {
+            let n = cmp::min(16 - self.buf.len(), data.len());
+            self.buf.push_all(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() < 16 {
+                return;
+            }
+            let block = self.buf.clone();
+            self.compress(&block);
+            self.buf.truncate(0);
+        }
+        for chunk in data.chunks(16) {
+            if chunk.len() == 16 {
+                self.compress(chunk);
+            } else {
+                self.buf.push_all(chunk);
+            }
+        }
+    }
+
+    /// Pads the message and returns the hash.
+    pub fn finish(mut self) -> [u8; 8] {
+        let bits = self.len.wrapping_mul(8);
+        let mut tail = self.buf.clone();
+        tail.push(0x80);
+        while tail.len() % 16 != 8 {
+            tail.push(0);
+        }
+        for i in 0..8 {
+            tail.push((bits >> (56 - 8 * i)) as u8);
+        }
+        for chunk in tail.chunks(16) {
+            self.compress(chunk);
+        }
+        Block::new(self.h).to_be_bytes()
+    }
+
+}
+
+/// Hashes `data` to 64 bits in one go.
+pub fn digest64(data: &[u8]) -> [u8; 8] {
+    let mut hasher = Hasher64::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[test]
+fn it_works() {
+    let input: Vec<u8> = (0u8..100).collect();
+    let whole = digest(&input);
+    for chunk_size in 1..20 {
+        let mut hasher = Hasher::new();
+        for chunk in input.chunks(chunk_size) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), whole);
+    }
+    assert!(digest(b"") != digest(b"\0"));
+    assert!(digest(&input[..99]) != whole);
+}
+
+#[test]
+fn hash64() {
+    let input: Vec<u8> = (0u8..100).collect();
+    let whole = digest64(&input);
+    for chunk_size in 1..40 {
+        let mut hasher = Hasher64::new();
+        for chunk in input.chunks(chunk_size) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), whole);
+    }
+    let digests: Vec<[u8; 8]> = (0..input.len()).map(|len| digest64(&input[..len])).collect();
+    for i in 0..digests.len() {
+        for j in 0..i {
+            assert!(digests[i] != digests[j]);
+        }
+    }
+    assert!(digest64(b"") != digest64(b"\0"));
+}
@@ -0,0 +1,187 @@
+//! The streaming state machine behind the C and JavaScript bindings:
+//! CBC mode with PKCS#7 padding, fed a piece at a time, handing back
+//! every whole block that's ready.  Output goes into a buffer the
+//! caller provides, and nothing changes if it's too small, so the C
+//! side can grow its buffer and try again.  Once finished, a stream
+//! refuses to take any more.
+
+use super::{Block, Error, Key};
+use buffer::{cbc_decrypt, cbc_encrypt};
+use cipher::Cipher;
+use mem::{self, SecretVec};
+use padding::pkcs7;
+
+pub struct Encryptor {
+    cipher: Cipher,
+    prev: Block,
+    pending: SecretVec,
+    finished: bool,
+}
+
+impl Encryptor {
+
+    pub fn new(key: Key, iv: Block) -> Encryptor {
+        Encryptor{
+            cipher: Cipher::new(key),
+            prev: iv,
+            pending: SecretVec::with_capacity(8),
+            finished: false,
+        }
+    }
+
+    // How much ciphertext `update` gives for `len` more bytes of
+    // plaintext.
+    pub fn update_len(&self, len: usize) -> usize {
+        (self.pending.len() + len) / 8 * 8
+    }
+
+    // Encrypts `plaintext` into `out`, returning how much ciphertext
+    // that made.  Up to 7 bytes wait for the next call.
+    pub fn update(&mut self, plaintext: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+        if self.finished {
+            return Err(Error::Finished);
+        }
+        let ready = self.update_len(plaintext.len());
+        if out.len() < ready {
+            return Err(Error::BufferTooSmall { needed: ready });
+        }
+        self.pending.push_all(plaintext);
+        out[..ready].clone_from_slice(&self.pending[..ready]);
+        cbc_encrypt(&self.cipher, &mut self.prev, &mut out[..ready]);
+        mem::wipe(&mut self.pending[..ready]);
+        self.pending.drain(..ready);
+        Ok(ready)
+    }
+
+    // Pads and encrypts what's left, which is always one block, into
+    // `out`.
+    pub fn finish(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        if self.finished {
+            return Err(Error::Finished);
+        }
+        if out.len() < 8 {
+            return Err(Error::BufferTooSmall { needed: 8 });
+        }
+        pkcs7::pad(&mut self.pending);
+        out[..8].clone_from_slice(&self.pending);
+        cbc_encrypt(&self.cipher, &mut self.prev, &mut out[..8]);
+        mem::wipe(&mut self.pending);
+        self.pending.truncate(0);
+        self.finished = true;
+        Ok(8)
+    }
+
+}
+
+pub struct Decryptor {
+    cipher: Cipher,
+    prev: Block,
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl Decryptor {
+
+    pub fn new(key: Key, iv: Block) -> Decryptor {
+        Decryptor{
+            cipher: Cipher::new(key),
+            prev: iv,
+            pending: Vec::with_capacity(8),
+            finished: false,
+        }
+    }
+
+    // How much plaintext `update` gives for `len` more bytes of
+    // ciphertext.  The last whole block is held back for `finish`,
+    // since it holds the padding.
+    pub fn update_len(&self, len: usize) -> usize {
+        let total = self.pending.len() + len;
+        if total % 8 == 0 { total.saturating_sub(8) } else { total / 8 * 8 }
+    }
+
+    // Decrypts `ciphertext` into `out`, returning how much plaintext
+    // that made.
+    pub fn update(&mut self, ciphertext: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+        if self.finished {
+            return Err(Error::Finished);
+        }
+        let ready = self.update_len(ciphertext.len());
+        if out.len() < ready {
+            return Err(Error::BufferTooSmall { needed: ready });
+        }
+        self.pending.push_all(ciphertext);
+        out[..ready].clone_from_slice(&self.pending[..ready]);
+        cbc_decrypt(&self.cipher, &mut self.prev, &mut out[..ready]);
+        self.pending.drain(..ready);
+        Ok(ready)
+    }
+
+    // Decrypts the last block and checks its padding, writing the
+    // last few bytes of plaintext (7 at most) to `out`.
+    pub fn finish(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        if self.finished {
+            return Err(Error::Finished);
+        }
+        if self.pending.len() != 8 {
+            return Err(Error::Truncated);
+        }
+        let mut block = [0u8; 8];
+        block.clone_from_slice(&self.pending);
+        let mut prev = self.prev;
+        cbc_decrypt(&self.cipher, &mut prev, &mut block);
+        let result = match pkcs7::unpad(&block) {
+            Some(plaintext) if out.len() < plaintext.len() => Err(Error::BufferTooSmall { needed: plaintext.len() }),
+            Some(plaintext) => {
+                out[..plaintext.len()].clone_from_slice(plaintext);
+                Ok(plaintext.len())
+            },
+            None => Err(Error::BadPadding),
+        };
+        mem::wipe(&mut block);
+        if result.is_ok() {
+            self.pending.truncate(0);
+            self.finished = true;
+        }
+        result
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use buffer;
+
+    let key = Key::new([1, 2, 3, 4]);
+    let plaintext: Vec<u8> = (0..100).collect();
+    let ciphertext = buffer::encrypt(key, [5, 6], &plaintext);
+
+    let mut encryptor = Encryptor::new(key, Block::new([5, 6]));
+    let mut out = [0u8; 128];
+    let mut encrypted = Vec::new();
+    assert_eq!(encryptor.update(&plaintext[..20], &mut out[..15]), Err(Error::BufferTooSmall { needed: 16 }));
+    for chunk in plaintext.chunks(13) {
+        let n = encryptor.update(chunk, &mut out).ok().unwrap();
+        encrypted.push_all(&out[..n]);
+    }
+    assert_eq!(encryptor.finish(&mut out[..7]), Err(Error::BufferTooSmall { needed: 8 }));
+    let n = encryptor.finish(&mut out).ok().unwrap();
+    encrypted.push_all(&out[..n]);
+    assert_eq!(encrypted, ciphertext);
+    assert_eq!(encryptor.finish(&mut out), Err(Error::Finished));
+    assert_eq!(encryptor.update(b"more", &mut out), Err(Error::Finished));
+
+    let mut decryptor = Decryptor::new(key, Block::new([5, 6]));
+    let mut decrypted = Vec::new();
+    for chunk in ciphertext.chunks(13) {
+        let n = decryptor.update(chunk, &mut out).ok().unwrap();
+        decrypted.push_all(&out[..n]);
+    }
+    let n = decryptor.finish(&mut out).ok().unwrap();
+    decrypted.push_all(&out[..n]);
+    assert_eq!(decrypted, plaintext);
+    assert_eq!(decryptor.finish(&mut out), Err(Error::Finished));
+
+    let mut decryptor = Decryptor::new(key, Block::new([5, 6]));
+    decryptor.update(&ciphertext[..ciphertext.len() - 1], &mut out).ok().unwrap();
+    assert_eq!(decryptor.finish(&mut out), Err(Error::Truncated));
+}
@@ -0,0 +1,612 @@
+use std::cmp;
+use std::io;
+use std::ops::Deref;
+
+use super::super::Block;
+
+// The standard base64 alphabet (RFC 4648).
+const ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// How much input is read from the source at a time.
+const READ_SIZE: usize = 4096;
+
+// The lines around an armored `Message`.
+const BEGIN: &'static str = "-----BEGIN TEA MESSAGE-----";
+const END: &'static str = "-----END TEA MESSAGE-----";
+
+// How long the base64 lines of a `Message` are, as in PEM.
+const COLUMNS: usize = 64;
+
+fn encode_group(group: &[u8], out: &mut Vec<u8>) {
+    let n = group.iter().fold(0u32, |n, &b| (n << 8) | b as u32) << (8 * (3 - group.len()));
+    for i in 0..4 {
+        if i <= group.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]);
+        } else {
+            out.push(b'=');
+        }
+    }
+}
+
+fn value(c: u8) -> Option<u8> {
+    match c {
+        b'A'...b'Z' => Some(c - b'A'),
+        b'a'...b'z' => Some(c - b'a' + 26),
+        b'0'...b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+fn bad_armor(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid base64", Some(detail))
+}
+
+// Encodes `data` as base64, with padding and no line breaks.
+pub fn encode(data: &[u8]) -> String {
+    let mut out = Vec::with_capacity((data.len() + 2) / 3 * 4);
+    for group in data.chunks(3) {
+        encode_group(group, &mut out);
+    }
+    String::from_utf8(out).unwrap()
+}
+
+// Decodes base64 from `text`, skipping whitespace.
+pub fn decode(text: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoder = Decoder::new();
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    try!(decoder.push(text, &mut out));
+    try!(decoder.finish(&mut out));
+    Ok(out)
+}
+
+// Decodes base64 a piece at a time.  Whitespace is skipped, padding
+// is optional, and anything after the padding other than whitespace
+// is an error.
+struct Decoder {
+    quad: [u8; 4],
+    n: usize,
+    done: bool,
+}
+
+impl Decoder {
+
+    fn new() -> Decoder {
+        Decoder{ quad: [0; 4], n: 0, done: false }
+    }
+
+    // Writes out the bytes held in a partial (or whole) group.
+    fn flush(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.n == 1 {
+            return Err(bad_armor("a lone character at the end of a group".to_string()));
+        }
+        let n = self.quad.iter().fold(0u32, |n, &v| (n << 6) | v as u32);
+        for i in 0..self.n.saturating_sub(1) {
+            out.push((n >> (16 - 8 * i)) as u8);
+        }
+        self.quad = [0; 4];
+        self.n = 0;
+        Ok(())
+    }
+
+    fn push(&mut self, text: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        for &c in text {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' => {},
+                b'=' => if !self.done {
+                    if self.n < 2 {
+                        return Err(bad_armor("padding in the wrong place".to_string()));
+                    }
+                    try!(self.flush(out));
+                    self.done = true;
+                },
+                _ => {
+                    if self.done {
+                        return Err(bad_armor("data after the padding".to_string()));
+                    }
+                    self.quad[self.n] = match value(c) {
+                        Some(v) => v,
+                        None => return Err(bad_armor(format!("unexpected byte 0x{:02x}", c))),
+                    };
+                    self.n += 1;
+                    if self.n == 4 {
+                        try!(self.flush(out));
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.flush(out)
+    }
+
+}
+
+/// Wraps an underlying `std::io::Write` and base64-encodes (RFC 4648,
+/// with padding) everything written through it, so ciphertext can go
+/// into JSON, email or a config file as text.  Put it under a
+/// `Writer` to armor the ciphertext.  You must call `close()` to write
+/// out the last few bytes and the padding.
+///
+/// # Example:
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use tea::io::{ArmorReader, ArmorWriter, Reader, Writer};
+///
+/// let mut crypt = Writer::new(ArmorWriter::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+/// crypt.write_all(b"Hello, world!").ok().unwrap();
+/// let armored = crypt.close().ok().unwrap().close().ok().unwrap();
+/// assert!(armored.iter().all(|&c| c < 0x80));
+///
+/// let mut decrypt = Reader::new(ArmorReader::new(Cursor::new(armored)), [1, 2, 3, 4], [5, 6]);
+/// let mut s = String::new();
+/// decrypt.read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "Hello, world!");
+/// ```
+pub struct ArmorWriter<W: io::Write> {
+    sink: W,
+    pending: Vec<u8>,
+    out: Vec<u8>,
+    columns: Option<usize>,
+    column: usize,
+}
+
+impl<W: io::Write> ArmorWriter<W> {
+
+    /// Wraps `sink` in an `ArmorWriter` that writes one long line of
+    /// base64.
+    pub fn new(sink: W) -> ArmorWriter<W> {
+        ArmorWriter{
+            sink: sink,
+            pending: Vec::with_capacity(3),
+            out: Vec::new(),
+            columns: None,
+            column: 0,
+        }
+    }
+
+    /// Breaks the output into lines of `columns` characters (rounded
+    /// down to a multiple of 4, and at least 4), each ending in `\n`,
+    /// like the 76-column lines of MIME.
+    pub fn wrap(mut self, columns: usize) -> ArmorWriter<W> {
+        self.columns = Some(cmp::max(columns / 4 * 4, 4));
+        self
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.sink
+    }
+
+    /// Writes the last group of base64, with its padding, and returns
+    /// the underlying `std::io::Write` object.
+    pub fn close(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            encode_group(&self.pending, &mut self.out);
+            self.column += 4;
+        }
+        if self.columns.is_some() && self.column > 0 {
+            self.out.push(b'\n');
+        }
+        try!(self.sink.write_all(&self.out));
+        try!(self.sink.flush());
+        Ok(self.sink)
+    }
+
+    // Encodes one whole group into `out`, breaking the line if it's
+    // full.
+    fn push_group(&mut self, group: &[u8]) {
+        encode_group(group, &mut self.out);
+        self.column += 4;
+        if let Some(columns) = self.columns {
+            if self.column == columns {
+                self.out.push(b'\n');
+                self.column = 0;
+            }
+        }
+    }
+
+}
+
+impl<W: io::Write> io::Write for ArmorWriter<W> {
+
+    /// Encodes all of `buf`, writing out every whole group of base64.
+    /// Up to two bytes wait for the next `write` or `close()`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        if !self.pending.is_empty() {
+            let n = cmp::min(3 - self.pending.len(), rest.len());
+            self.pending.push_all(&rest[..n]);
+            rest = &rest[n..];
+            if self.pending.len() < 3 {
+                return Ok(buf.len());
+            }
+            let group = [self.pending[0], self.pending[1], self.pending[2]];
+            self.push_group(&group);
+            self.pending.truncate(0);
+        }
+        for group in rest.chunks(3) {
+            if group.len() < 3 {
+                self.pending.push_all(group);
+            } else {
+                self.push_group(group);
+            }
+        }
+        try!(self.sink.write_all(&self.out));
+        self.out.truncate(0);
+        Ok(buf.len())
+    }
+
+    /// Flushes the underlying writer.  Bytes waiting for a whole group
+    /// stay put.
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+}
+
+/// Wraps an underlying `std::io::Read` of base64 and decodes it, so
+/// armored ciphertext can go straight into a `Reader`.  Whitespace,
+/// including line breaks, is skipped, and the padding at the end may
+/// be left off.  Anything else that isn't base64 makes `read` fail
+/// with `io::ErrorKind::InvalidData`.
+pub struct ArmorReader<R: io::Read> {
+    source: R,
+    decoder: Decoder,
+    input: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: io::Read> ArmorReader<R> {
+
+    /// Wraps `source` in an `ArmorReader`.
+    pub fn new(source: R) -> ArmorReader<R> {
+        ArmorReader{
+            source: source,
+            decoder: Decoder::new(),
+            input: vec![0u8; READ_SIZE],
+            buf: Vec::with_capacity(READ_SIZE / 4 * 3),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Unwraps this `ArmorReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+}
+
+impl<R: io::Read> io::Read for ArmorReader<R> {
+
+    /// Decodes more of `source` into `buf`.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() && !self.eof && !buf.is_empty() {
+            self.buf.truncate(0);
+            self.pos = 0;
+            let n = match self.source.read(&mut self.input) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                try!(self.decoder.finish(&mut self.buf));
+                self.eof = true;
+            } else {
+                try!(self.decoder.push(&self.input[..n], &mut self.buf));
+            }
+        }
+        let n = cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..n].clone_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+}
+
+fn bad_message(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid armored message", Some(detail))
+}
+
+/// A small encrypted payload in a PEM-like text form, for pasting into
+/// email or chat and picking back up in a script:
+///
+/// ```text
+/// -----BEGIN TEA MESSAGE-----
+/// Key-Id: backup-2016
+/// IV: 0000000500000006
+///
+/// Ypxhxzyl1GWeRMQFdP+sdw==
+/// -----END TEA MESSAGE-----
+/// ```
+///
+/// The headers are optional.  `Key-Id` names the key the body was
+/// encrypted under, so the recipient knows which to use, and `IV`
+/// holds the iv as 16 hex digits, for a body written `raw()` that
+/// doesn't carry its own.  The body is base64, in lines of 64
+/// characters.  Nothing in the armor checks the body; use an
+/// `AuthenticatedWriter` for that.
+///
+/// # Example:
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use tea::io::{Message, Reader, Writer};
+///
+/// let mut crypt = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).raw();
+/// crypt.write_all(b"Hello, world!").ok().unwrap();
+/// let mut message = Message::new(crypt.close().ok().unwrap());
+/// message.key_id = Some("backup-2016".to_string());
+/// message.iv = Some([5, 6].into());
+/// let text = message.to_armor().ok().unwrap();
+/// assert!(text.starts_with("-----BEGIN TEA MESSAGE-----\nKey-Id: backup-2016\n"));
+///
+/// let message = Message::from_armor(&text).ok().unwrap();
+/// assert_eq!(message.key_id.unwrap(), "backup-2016");
+/// let mut decrypt = Reader::new(Cursor::new(message.body), [1, 2, 3, 4], message.iv.unwrap()).raw();
+/// let mut s = String::new();
+/// decrypt.read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "Hello, world!");
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Message {
+    /// Names the key the body was encrypted under.
+    pub key_id: Option<String>,
+    /// The iv the body was encrypted with.
+    pub iv: Option<Block>,
+    /// The ciphertext.
+    pub body: Vec<u8>,
+}
+
+impl Message {
+
+    /// Makes a message out of `body`, with no headers.
+    pub fn new(body: Vec<u8>) -> Message {
+        Message{ key_id: None, iv: None, body: body }
+    }
+
+    /// Writes the message out as text.  Fails with
+    /// `io::ErrorKind::InvalidInput` if `key_id` is empty or doesn't
+    /// fit on one line.
+    pub fn to_armor(&self) -> io::Result<String> {
+        let mut text = String::new();
+        text.push_str(BEGIN);
+        text.push('\n');
+        if let Some(ref key_id) = self.key_id {
+            if key_id.trim().is_empty() || key_id.trim() != key_id || key_id.contains(|c: char| c == '\n' || c == '\r') {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "bad key id",
+                                          Some(format!("{:?} can't go in a header", key_id))));
+            }
+            text.push_str(&format!("Key-Id: {}\n", key_id));
+        }
+        if let Some(iv) = self.iv {
+            text.push_str(&format!("IV: {:016x}\n", u64::from(iv)));
+        }
+        if self.key_id.is_some() || self.iv.is_some() {
+            text.push('\n');
+        }
+        let body = encode(&self.body);
+        for line in body.as_bytes().chunks(COLUMNS) {
+            text.push_str(::std::str::from_utf8(line).unwrap());
+            text.push('\n');
+        }
+        text.push_str(END);
+        text.push('\n');
+        Ok(text)
+    }
+
+    /// Parses the first message found in `text`, ignoring anything
+    /// before its `BEGIN` line or after its `END` line, so a message
+    /// can be pasted along with whatever surrounded it.  Fails with
+    /// `io::ErrorKind::InvalidData` if there's no message, a header
+    /// isn't one of the above, or the body isn't base64.
+    pub fn from_armor(text: &str) -> io::Result<Message> {
+        let mut lines = text.lines().map(|line| line.trim_right());
+        if !lines.by_ref().any(|line| line == BEGIN) {
+            return Err(bad_message(format!("no {} line", BEGIN)));
+        }
+        let mut message = Message::new(Vec::new());
+        let mut body = String::new();
+        let mut in_headers = true;
+        loop {
+            let line = match lines.next() {
+                Some(line) => line,
+                None => return Err(bad_message(format!("no {} line", END))),
+            };
+            if line == END {
+                break;
+            }
+            if in_headers {
+                if line.is_empty() {
+                    in_headers = false;
+                    continue;
+                }
+                if let Some(colon) = line.find(':') {
+                    let value = line[colon + 1..].trim();
+                    match &line[..colon] {
+                        "Key-Id" if message.key_id.is_none() && !value.is_empty() => {
+                            message.key_id = Some(value.to_string());
+                        },
+                        "IV" if message.iv.is_none() && value.len() == 16 => {
+                            match u64::from_str_radix(value, 16) {
+                                Ok(iv) => message.iv = Some(Block::from(iv)),
+                                Err(_) => return Err(bad_message(format!("bad iv {:?}", value))),
+                            }
+                        },
+                        name => return Err(bad_message(format!("bad header {:?}", name))),
+                    }
+                    continue;
+                }
+                // No headers, the body starts straight away.
+                in_headers = false;
+            }
+            body.push_str(line);
+        }
+        message.body = try!(decode(body.as_bytes()));
+        Ok(message)
+    }
+
+}
+
+/// Ciphertext that serializes (with the `serde` feature) as a base64
+/// string, so an encrypted field can sit in a JSON, YAML or TOML
+/// config like any other string and be deserialized straight back to
+/// bytes.  It derefs to the ciphertext, ready to hand to a `Reader`.
+///
+/// # Example:
+/// ```
+/// use std::io::{Read, Write};
+/// use tea::io::{EncryptedBlob, Reader, Writer};
+///
+/// let mut crypt = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+/// crypt.write_all(b"hunter2").ok().unwrap();
+/// let blob = EncryptedBlob::new(crypt.close().ok().unwrap());
+///
+/// let mut decrypt = Reader::new(&blob[..], [1, 2, 3, 4], [5, 6]);
+/// let mut s = String::new();
+/// decrypt.read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "hunter2");
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EncryptedBlob(Vec<u8>);
+
+impl EncryptedBlob {
+
+    /// Wraps `ciphertext`.
+    pub fn new(ciphertext: Vec<u8>) -> EncryptedBlob {
+        EncryptedBlob(ciphertext)
+    }
+
+    /// Unwraps the ciphertext.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+
+}
+
+impl From<Vec<u8>> for EncryptedBlob {
+
+    fn from(ciphertext: Vec<u8>) -> EncryptedBlob {
+        EncryptedBlob(ciphertext)
+    }
+
+}
+
+impl Deref for EncryptedBlob {
+
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for EncryptedBlob {
+
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(&self.0))
+    }
+
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for EncryptedBlob {
+
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<EncryptedBlob, D::Error> {
+        let text: String = try!(::serde::Deserialize::deserialize(deserializer));
+        match decode(text.as_bytes()) {
+            Ok(ciphertext) => Ok(EncryptedBlob(ciphertext)),
+            Err(_) => Err(::serde::de::Error::custom("expected base64 ciphertext")),
+        }
+    }
+
+}
+
+#[test]
+fn it_works() {
+    // From RFC 4648.
+    let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"),
+                   ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+    for &(plain, armored) in vectors.iter() {
+        assert_eq!(encode(plain.as_bytes()), armored);
+        assert_eq!(decode(armored.as_bytes()).ok().unwrap(), plain.as_bytes());
+        assert_eq!(decode(armored.trim_right_matches('=').as_bytes()).ok().unwrap(), plain.as_bytes());
+    }
+    assert_eq!(decode(b" Zm9v\r\nYmFy\n").ok().unwrap(), b"foobar");
+    for bad in [&b"Zm9v!"[..], b"Z", b"Zg==Zg==", b"Z===", b"Zm9\x80"].iter() {
+        assert_eq!(decode(bad).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn streams() {
+    use std::io::{Read, Write};
+
+    let input: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    for &chunk_size in [1, 2, 3, 5, 64, 1000].iter() {
+        let mut writer = ArmorWriter::new(Vec::new()).wrap(76);
+        for chunk in input.chunks(chunk_size) {
+            writer.write_all(chunk).ok().unwrap();
+        }
+        let armored = writer.close().ok().unwrap();
+        let lines: Vec<&[u8]> = armored.split(|&c| c == b'\n').collect();
+        assert!(lines[..lines.len() - 2].iter().all(|line| line.len() == 76));
+        assert_eq!(lines[lines.len() - 1], b"");
+        assert_eq!(decode(&armored).ok().unwrap(), input);
+
+        let mut output = Vec::new();
+        let mut reader = ArmorReader::new(io::Cursor::new(&armored[..]));
+        let mut buf = vec![0u8; chunk_size];
+        loop {
+            let n = reader.read(&mut buf).ok().unwrap();
+            if n == 0 {
+                break;
+            }
+            output.push_all(&buf[..n]);
+        }
+        assert_eq!(output, input);
+    }
+}
+
+#[test]
+fn message() {
+    let mut message = Message::new((0..100).collect());
+    assert_eq!(Message::from_armor(&message.to_armor().ok().unwrap()).ok().unwrap(), message);
+
+    message.key_id = Some("alice".to_string());
+    message.iv = Some(Block::from(0x0123456789abcdef));
+    let text = message.to_armor().ok().unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(&lines[..4], &[BEGIN, "Key-Id: alice", "IV: 0123456789abcdef", ""]);
+    assert_eq!(lines[4].len(), 64);
+    assert_eq!(lines[lines.len() - 1], END);
+    let pasted = format!("Here you go:\r\n\r\n{}\r\nThanks!", text.replace("\n", "\r\n"));
+    assert_eq!(Message::from_armor(&pasted).ok().unwrap(), message);
+
+    message.key_id = Some("two\nlines".to_string());
+    assert_eq!(message.to_armor().err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+    for bad in [&text[..text.len() - 10], "Zm9v", &text.replace("IV:", "Iv:"),
+                &text.replace("0123456789abcdef", "0123456789abcdeg"), &text.replace("Key-Id: alice", "Key-Id:")].iter() {
+        assert_eq!(Message::from_armor(bad).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn blob() {
+    use serde_json;
+
+    let blob = EncryptedBlob::new(b"foobar".to_vec());
+    assert_eq!(serde_json::to_string(&blob).ok().unwrap(), "\"Zm9vYmFy\"");
+    assert_eq!(serde_json::from_str::<EncryptedBlob>("\"Zm9vYmFy\"").ok().unwrap(), blob);
+    assert!(serde_json::from_str::<EncryptedBlob>("\"Zm9v!\"").is_err());
+    assert!(serde_json::from_str::<EncryptedBlob>("[1, 2]").is_err());
+}
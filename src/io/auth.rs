@@ -0,0 +1,467 @@
+use std::cmp;
+use std::io;
+
+use super::super::{Key, Block, decrypt_padded_in_place};
+use super::Writer;
+use ct;
+use mac::{self, CmacContext};
+use mem::SecretVec;
+
+// The iv and key commitment at the front of the stream, and the tag
+// at the end.
+const IV_LEN: usize = 8;
+const COMMIT_LEN: usize = 8;
+const HEADER_LEN: usize = IV_LEN + COMMIT_LEN;
+const TAG_LEN: usize = 8;
+
+// Splits the key into one for encryption and one for the tag, so
+// neither use can interfere with the other.
+fn auth_keys(key: Key) -> (Key, Key) {
+    (key.derive_subkey(b"tea authenticated: enc"), key.derive_subkey(b"tea authenticated: mac"))
+}
+
+// A MAC of a fixed string under its own subkey, stored in the header
+// so a stream only opens under the key it was written with.
+fn commitment(key: &Key) -> [u8; COMMIT_LEN] {
+    mac::cmac(key.derive_subkey(b"tea authenticated: commit"), b"tea key commitment")
+}
+
+// The part of the tag covering associated data, under its own key so
+// it can't be confused with the part covering the stream, in the same
+// way as EAX mode.  No associated data is the same as an empty one,
+// and leaves the tag alone.
+fn aad_tag(mac_key: &Key, aad: &[u8]) -> [u8; 8] {
+    if aad.is_empty() {
+        return [0u8; 8];
+    }
+    mac::cmac(mac_key.derive_subkey(b"tea authenticated: aad"), aad)
+}
+
+fn xor_tags(a: [u8; 8], b: [u8; 8]) -> [u8; 8] {
+    let mut tag = a;
+    for i in 0..8 {
+        tag[i] ^= b[i];
+    }
+    tag
+}
+
+// Passes ciphertext through to `sink`, adding whatever it accepts to
+// the MAC on the way.
+struct MacSink<W: io::Write> {
+    sink: W,
+    mac: CmacContext,
+}
+
+impl<W: io::Write> io::Write for MacSink<W> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.sink.write(buf));
+        self.mac.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+}
+
+/// Wraps an underlying `std::io::Write` like `Writer` does, but also
+/// computes a CMAC of the iv and ciphertext as they go by, and appends
+/// the 8-byte tag on `close()` (encrypt-then-MAC).  An
+/// `AuthenticatedReader` checks the tag before handing out any
+/// plaintext, so a stream that's been tampered with fails to decrypt
+/// instead of quietly decrypting to garbage.  The iv is written at the
+/// start of the stream, so the reader doesn't need to be told it.
+///
+/// Unencrypted data that belongs with the stream, like a filename or
+/// version number stored beside it, can be bound into the tag with
+/// `set_aad()`, so the stream won't decrypt next to anything else.
+///
+/// The header also holds a commitment to the key, a MAC of a fixed
+/// string, so a stream can't be crafted to check out under two
+/// different keys and decrypt to different plaintext under each,
+/// which matters when the keys come from people who don't trust each
+/// other (say, tenants of shared storage).  At 64 bits, finding two
+/// keys with the same commitment takes about 2^32 tries, so this
+/// raises the bar rather than ruling it out.
+///
+/// A stream that isn't closed has no tag and won't decrypt.
+///
+/// # Example:
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use tea::io::{AuthenticatedReader, AuthenticatedWriter};
+///
+/// let mut crypt = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+/// crypt.write_all(b"Hello, world!").ok().unwrap();
+/// let mut ciphertext = crypt.close().ok().unwrap();
+///
+/// let mut s = String::new();
+/// AuthenticatedReader::new(Cursor::new(&ciphertext[..]), [1, 2, 3, 4])
+///     .read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "Hello, world!");
+///
+/// ciphertext[20] ^= 1;
+/// assert!(AuthenticatedReader::new(Cursor::new(&ciphertext[..]), [1, 2, 3, 4])
+///     .read_to_string(&mut s).is_err());
+/// ```
+pub struct AuthenticatedWriter<W: io::Write> {
+    inner: Writer<MacSink<W>>,
+    mac_key: Key,
+    aad_tag: [u8; 8],
+}
+
+impl<W: io::Write> AuthenticatedWriter<W> {
+
+    /// Wraps `sink` in an `AuthenticatedWriter` that will encrypt and
+    /// authenticate with keys derived from `key`, starting from `iv`
+    /// (initialization vector).  The iv and the key commitment are
+    /// written to `sink` right away.
+    pub fn new<K: Into<Key>, I: Into<Block>>(mut sink: W, key: K, iv: I) -> io::Result<AuthenticatedWriter<W>> {
+        let key = key.into();
+        let (enc_key, mac_key) = auth_keys(key);
+        let iv = iv.into();
+        let mut header = [0u8; HEADER_LEN];
+        header[..IV_LEN].clone_from_slice(&iv.to_be_bytes());
+        header[IV_LEN..].clone_from_slice(&commitment(&key));
+        let mut mac = CmacContext::new(mac_key);
+        try!(sink.write_all(&header));
+        mac.update(&header);
+        Ok(AuthenticatedWriter{
+            inner: Writer::new(MacSink{ sink: sink, mac: mac }, enc_key, iv).raw(),
+            mac_key: mac_key,
+            aad_tag: [0u8; 8],
+        })
+    }
+
+    /// Binds `aad` (associated data) into the tag without encrypting
+    /// or writing it.  The `AuthenticatedReader` has to be given the
+    /// same `aad` to decrypt the stream.  This can be called any time
+    /// before `close()`, and replaces any `aad` set before.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{AuthenticatedReader, AuthenticatedWriter};
+    ///
+    /// let mut crypt = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+    /// crypt.set_aad(b"report.txt");
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap();
+    ///
+    /// let mut decrypt = AuthenticatedReader::new(Cursor::new(&ciphertext[..]), [1, 2, 3, 4]);
+    /// decrypt.set_aad(b"other.txt");
+    /// assert!(decrypt.read_to_end(&mut Vec::new()).is_err());
+    /// ```
+    pub fn set_aad(&mut self, aad: &[u8]) {
+        self.aad_tag = aad_tag(&self.mac_key, aad);
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner.get_ref().sink
+    }
+
+    /// Writes the final padding and the tag, destroys the encrypting
+    /// wrapper, and returns the underlying `std::io::Write` object.
+    pub fn close(self) -> io::Result<W> {
+        let aad_tag = self.aad_tag;
+        let MacSink{ mut sink, mac } = try!(self.inner.close());
+        try!(sink.write_all(&xor_tags(mac.finalize(), aad_tag)));
+        try!(sink.flush());
+        Ok(sink)
+    }
+
+}
+
+impl<W: io::Write> io::Write for AuthenticatedWriter<W> {
+
+    /// Encrypts the bytes in `buf` and passes them through, like
+    /// `Writer::write`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    /// Passes the flush call through to the inner `Writer`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+}
+
+/// Wraps an underlying `std::io::Read` to check and decrypt the output
+/// of an `AuthenticatedWriter`.  The tag comes at the end, so the
+/// whole stream is read into memory and checked on the first `read`,
+/// and nothing is decrypted unless the tag is right.  As with
+/// `Reader`, failures are opaque unless `verbose_errors()` is turned
+/// on.
+pub struct AuthenticatedReader<R: io::Read> {
+    source: R,
+    enc_key: Key,
+    mac_key: Key,
+    commitment: [u8; COMMIT_LEN],
+    aad_tag: [u8; 8],
+    buf: SecretVec,
+    pos: usize,
+    end: usize,
+    checked: bool,
+    verbose: bool,
+}
+
+impl<R: io::Read> AuthenticatedReader<R> {
+
+    /// Wraps `source` in an `AuthenticatedReader` that will check and
+    /// decrypt with keys derived from `key`.
+    pub fn new<K: Into<Key>>(source: R, key: K) -> AuthenticatedReader<R> {
+        let key = key.into();
+        let (enc_key, mac_key) = auth_keys(key);
+        AuthenticatedReader{
+            source: source,
+            enc_key: enc_key,
+            mac_key: mac_key,
+            commitment: commitment(&key),
+            aad_tag: [0u8; 8],
+            buf: SecretVec::new(),
+            pos: 0,
+            end: 0,
+            checked: false,
+            verbose: false,
+        }
+    }
+
+    /// Checks the tag against `aad` (associated data), which must be
+    /// what was given to `AuthenticatedWriter::set_aad`.  Call this
+    /// before the first `read`.
+    pub fn set_aad(&mut self, aad: &[u8]) {
+        self.aad_tag = aad_tag(&self.mac_key, aad);
+    }
+
+    /// Makes failures say what went wrong, like
+    /// `Reader::verbose_errors`.
+    pub fn verbose_errors(mut self) -> AuthenticatedReader<R> {
+        self.verbose = true;
+        self
+    }
+
+    /// Unwraps this `AuthenticatedReader`, returning the underlying
+    /// reader.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    /// Checks the key commitment and the tag without decrypting
+    /// anything, and returns the underlying reader.  The stream goes
+    /// through a small fixed buffer rather than being read into
+    /// memory, so this suits integrity checks over big archives, and
+    /// no plaintext is ever produced.  Set any `aad` first.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Write};
+    /// use tea::io::{AuthenticatedReader, AuthenticatedWriter};
+    ///
+    /// let mut crypt = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let mut ciphertext = crypt.close().ok().unwrap();
+    /// assert!(AuthenticatedReader::new(Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).verify_only().is_ok());
+    ///
+    /// ciphertext[20] ^= 1;
+    /// assert!(AuthenticatedReader::new(Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).verify_only().is_err());
+    /// ```
+    pub fn verify_only(mut self) -> io::Result<R> {
+        let mut header = [0u8; HEADER_LEN];
+        try!(super::read_header(&mut self.source, &mut header));
+        if !ct::eq(&self.commitment, &header[IV_LEN..]) {
+            return Err(super::decrypt_error(self.verbose, "wrong key", "the stream was written under a different key"));
+        }
+        let mut mac = CmacContext::new(self.mac_key);
+        mac.update(&header);
+
+        // The last `TAG_LEN` bytes read are held at the front of `buf`
+        // until we know whether they're the tag.
+        let mut buf = [0u8; 8192 + TAG_LEN];
+        let mut held = 0;
+        let mut body = 0u64;
+        loop {
+            let n = match self.source.read(&mut buf[held..]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let filled = held + n;
+            if filled <= TAG_LEN {
+                held = filled;
+                continue;
+            }
+            let release = filled - TAG_LEN;
+            mac.update(&buf[..release]);
+            body += release as u64;
+            for i in 0..TAG_LEN {
+                buf[i] = buf[release + i];
+            }
+            held = TAG_LEN;
+        }
+        if held < TAG_LEN || body < 8 || body % 8 != 0 {
+            return Err(super::decrypt_error(self.verbose, "truncated stream",
+                                            &format!("{} bytes can't be an authenticated stream", HEADER_LEN as u64 + body + held as u64)));
+        }
+        let expected = xor_tags(mac.finalize(), self.aad_tag);
+        if !ct::eq(&expected, &buf[..TAG_LEN]) {
+            return Err(super::decrypt_error(self.verbose, "bad tag", "the stream was modified or the key is wrong"));
+        }
+        Ok(self.source)
+    }
+
+    // Reads the whole stream, checks the tag, and decrypts it in
+    // place.
+    fn check(&mut self) -> io::Result<()> {
+        try!(self.source.read_to_end(&mut self.buf));
+        let len = self.buf.len();
+        if len < HEADER_LEN + 8 + TAG_LEN || (len - HEADER_LEN - TAG_LEN) % 8 != 0 {
+            return Err(super::decrypt_error(self.verbose, "truncated stream",
+                                            &format!("{} bytes can't be an authenticated stream", len)));
+        }
+        if !ct::eq(&self.commitment, &self.buf[IV_LEN..HEADER_LEN]) {
+            return Err(super::decrypt_error(self.verbose, "wrong key", "the stream was written under a different key"));
+        }
+        let mut mac = CmacContext::new(self.mac_key);
+        mac.update(&self.buf[..len - TAG_LEN]);
+        let expected = xor_tags(mac.finalize(), self.aad_tag);
+        if !ct::eq(&expected, &self.buf[len - TAG_LEN..]) {
+            return Err(super::decrypt_error(self.verbose, "bad tag", "the stream was modified or the key is wrong"));
+        }
+        let mut iv = [0u8; 8];
+        iv.clone_from_slice(&self.buf[..IV_LEN]);
+        let plaintext_len = match decrypt_padded_in_place(self.enc_key, Block::from_be_bytes(&iv),
+                                                          &mut self.buf[HEADER_LEN..len - TAG_LEN]) {
+            Ok(n) => n,
+            Err(_) => return Err(super::decrypt_error(self.verbose, "bad padding",
+                                                      "stream doesn't end in valid PKCS#7 padding")),
+        };
+        self.pos = HEADER_LEN;
+        self.end = HEADER_LEN + plaintext_len;
+        Ok(())
+    }
+
+}
+
+impl<R: io::Read> io::Read for AuthenticatedReader<R> {
+
+    /// Copies out decrypted plaintext, reading and checking the whole
+    /// stream first if this is the first call.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.checked {
+            try!(self.check());
+            self.checked = true;
+        }
+        let n = cmp::min(buf.len(), self.end - self.pos);
+        buf[..n].clone_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    let input: Vec<u8> = (0u8..100).collect();
+    for len in 0..input.len() {
+        let mut writer = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+        writer.write_all(&input[..len]).ok().unwrap();
+        let ciphertext = writer.close().ok().unwrap();
+        assert_eq!(ciphertext.len(), HEADER_LEN + (len / 8 + 1) * 8 + TAG_LEN);
+
+        let mut output = Vec::new();
+        AuthenticatedReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4])
+            .read_to_end(&mut output).ok().unwrap();
+        assert_eq!(&output[..], &input[..len]);
+    }
+}
+
+#[test]
+fn tampered() {
+    use std::error::Error;
+    use std::io::{Read, Write};
+
+    let mut writer = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    for i in 0..ciphertext.len() {
+        let mut tampered = ciphertext.clone();
+        tampered[i] ^= 1;
+        let err = AuthenticatedReader::new(io::Cursor::new(tampered), [1, 2, 3, 4]).verbose_errors()
+            .read_to_end(&mut Vec::new()).err().unwrap();
+        let commitment = i >= IV_LEN && i < HEADER_LEN;
+        assert_eq!(err.description(), if commitment { "wrong key" } else { "bad tag" });
+    }
+    for len in 0..ciphertext.len() {
+        let mut reader = AuthenticatedReader::new(io::Cursor::new(&ciphertext[..len]), [1, 2, 3, 4]);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+    let err = AuthenticatedReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 5]).verbose_errors()
+        .read_to_end(&mut Vec::new()).err().unwrap();
+    assert_eq!(err.description(), "wrong key");
+}
+
+#[test]
+fn aad() {
+    use std::io::{Read, Write};
+
+    let encrypt = |aad: &[u8]| {
+        let mut writer = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+        writer.write_all(b"Hello, ").ok().unwrap();
+        writer.set_aad(aad);
+        writer.write_all(b"world!").ok().unwrap();
+        writer.close().ok().unwrap()
+    };
+    let decrypt = |ciphertext: &[u8], aad: &[u8]| {
+        let mut reader = AuthenticatedReader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4]);
+        reader.set_aad(aad);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).map(|_| output).ok()
+    };
+
+    let ciphertext = encrypt(b"v1");
+    assert_eq!(decrypt(&ciphertext, b"v1").unwrap(), b"Hello, world!");
+    assert!(decrypt(&ciphertext, b"v2").is_none());
+    assert!(decrypt(&ciphertext, b"").is_none());
+    let plain = encrypt(b"");
+    assert_eq!(decrypt(&plain, b"").unwrap(), b"Hello, world!");
+    assert!(decrypt(&plain, b"v1").is_none());
+    // Only the tag differs.
+    assert_eq!(&ciphertext[..ciphertext.len() - TAG_LEN], &plain[..plain.len() - TAG_LEN]);
+}
+
+#[test]
+fn verify_only() {
+    use std::error::Error;
+    use std::io::Write;
+
+    let mut writer = AuthenticatedWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).ok().unwrap();
+    writer.set_aad(b"archive.tar");
+    writer.write_all(&[7u8; 20000]).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+
+    let verify = |ciphertext: &[u8], key: [u32; 4], aad: &[u8]| {
+        let mut reader = AuthenticatedReader::new(io::Cursor::new(ciphertext), key).verbose_errors();
+        reader.set_aad(aad);
+        reader.verify_only().map(|source| source.position())
+    };
+    assert_eq!(verify(&ciphertext, [1, 2, 3, 4], b"archive.tar").ok().unwrap(), ciphertext.len() as u64);
+    let err = verify(&ciphertext, [1, 2, 3, 4], b"").err().unwrap();
+    assert_eq!(err.description(), "bad tag");
+    let err = verify(&ciphertext, [1, 2, 3, 5], b"archive.tar").err().unwrap();
+    assert_eq!(err.description(), "wrong key");
+    for &i in [0, 20, 8000, 8200, ciphertext.len() - 1].iter() {
+        let mut tampered = ciphertext.clone();
+        tampered[i] ^= 1;
+        assert!(verify(&tampered, [1, 2, 3, 4], b"archive.tar").is_err());
+    }
+    for &len in [0, HEADER_LEN, HEADER_LEN + TAG_LEN, ciphertext.len() - 8, ciphertext.len() - 1].iter() {
+        assert!(verify(&ciphertext[..len], [1, 2, 3, 4], b"archive.tar").is_err());
+    }
+}
@@ -0,0 +1,502 @@
+use std::cmp;
+use std::io;
+
+use super::super::{Key, Block, encrypt_to, decrypt_padded_in_place};
+use ct;
+use mac::{self, CmacContext, MmoContext};
+use mem::SecretVec;
+
+// How much plaintext goes in each chunk, unless told otherwise.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+// Readers refuse streams with bigger chunks than this, so a hostile
+// header can't make them allocate unbounded amounts.
+const MAX_CHUNK_SIZE: usize = 16 << 20;
+
+// The iv, the chunk size (big-endian) and the checksum, at the start
+// of the stream.
+const HEADER_LEN: usize = 8 + 4 + 1;
+
+const TAG_LEN: usize = 8;
+
+/// Which checksum protects each chunk of a `ChunkedWriter` stream.
+/// It's recorded in the stream, so the `ChunkedReader` doesn't need
+/// to be told.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Checksum {
+    /// CMAC, which stops deliberate tampering as well as accidents.
+    /// This is the default.
+    Cmac,
+    /// A Matyas-Meyer-Oseas checksum (see `tea::mac::MmoContext`),
+    /// which only catches accidental corruption, like bit rot.
+    Mmo,
+}
+
+// The keys, derived from the one given, for encrypting chunks, for
+// their ivs and for their tags, and the header they're all tied to.
+struct ChunkKeys {
+    enc_key: Key,
+    iv_key: Key,
+    mac_key: Key,
+    checksum: Checksum,
+    header: [u8; HEADER_LEN],
+}
+
+impl ChunkKeys {
+
+    fn new(key: Key, checksum: Checksum, header: [u8; HEADER_LEN]) -> ChunkKeys {
+        ChunkKeys{
+            enc_key: key.derive_subkey(b"tea chunked: enc"),
+            iv_key: key.derive_subkey(b"tea chunked: iv"),
+            mac_key: key.derive_subkey(b"tea chunked: mac"),
+            checksum: checksum,
+            header: header,
+        }
+    }
+
+    // The header, the chunk's index and whether it's the last one,
+    // which start every iv and tag.  This is the nonce of the STREAM
+    // construction (Hoang, Reyhanitabar, Rogaway and Vizár), so a
+    // chunk only checks out in the place it was written, and only
+    // the real last chunk can end the stream.
+    fn prefix(&self, index: u64, last: bool) -> [u8; HEADER_LEN + 9] {
+        let mut prefix = [0u8; HEADER_LEN + 9];
+        prefix[..HEADER_LEN].clone_from_slice(&self.header);
+        prefix[HEADER_LEN..HEADER_LEN + 8].clone_from_slice(&Block::from(index).to_be_bytes());
+        prefix[HEADER_LEN + 8] = last as u8;
+        prefix
+    }
+
+    fn iv(&self, index: u64, last: bool) -> Block {
+        Block::from_be_bytes(&mac::cmac(self.iv_key, &self.prefix(index, last)))
+    }
+
+    fn tag(&self, index: u64, last: bool, ciphertext: &[u8]) -> [u8; TAG_LEN] {
+        match self.checksum {
+            Checksum::Cmac => {
+                let mut mac = CmacContext::new(self.mac_key);
+                mac.update(&self.prefix(index, last));
+                mac.update(ciphertext);
+                mac.finalize()
+            },
+            Checksum::Mmo => {
+                let mut checksum = MmoContext::new(self.mac_key);
+                checksum.update(&self.prefix(index, last));
+                checksum.update(ciphertext);
+                checksum.finalize()
+            },
+        }
+    }
+
+}
+
+/// Wraps an underlying `std::io::Write` and encrypts the stream in
+/// separately authenticated chunks, each holding a fixed amount of
+/// plaintext (64 KiB by default) and followed by its own 8-byte tag,
+/// a CMAC or an MMO checksum depending on the `Checksum`.  A
+/// `ChunkedReader` checks each chunk as it reaches it, so it can hand
+/// out plaintext as it goes, without holding a whole multi-gigabyte
+/// stream in memory the way `AuthenticatedReader` does, and damage to
+/// one chunk doesn't stop the chunks before it from being read.
+///
+/// Each chunk is padded and encrypted on its own, with an iv derived
+/// from the stream's iv, the chunk's position and a flag marking the
+/// last chunk, which the tag covers as well as the ciphertext (the
+/// STREAM construction).  So chunks that have been reordered or
+/// dropped are caught, and so is a stream cut off between chunks,
+/// since whatever chunk ends up last wasn't sealed as the last one.
+/// The last chunk is always shorter than the rest (empty, if need
+/// be).  You must call `close()` to write it.
+///
+/// # Example:
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use tea::io::{ChunkedReader, ChunkedWriter};
+///
+/// let mut crypt = ChunkedWriter::with_chunk_size(Vec::new(), [1, 2, 3, 4], [5, 6], 4).ok().unwrap();
+/// crypt.write_all(b"Hello, world!").ok().unwrap();
+/// let ciphertext = crypt.close().ok().unwrap();
+///
+/// let mut decrypt = ChunkedReader::new(Cursor::new(ciphertext), [1, 2, 3, 4]).ok().unwrap();
+/// let mut s = String::new();
+/// decrypt.read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "Hello, world!");
+/// ```
+pub struct ChunkedWriter<W: io::Write> {
+    sink: W,
+    keys: ChunkKeys,
+    chunk_size: usize,
+    index: u64,
+    buf: SecretVec,
+    enc_buf: Vec<u8>,
+}
+
+impl<W: io::Write> ChunkedWriter<W> {
+
+    /// Wraps `sink` in a `ChunkedWriter` that will encrypt with keys
+    /// derived from `key` and the given `iv`, in 64 KiB chunks.  The
+    /// iv and chunk size are written to `sink` right away.
+    pub fn new<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I) -> io::Result<ChunkedWriter<W>> {
+        ChunkedWriter::with_chunk_size(sink, key, iv, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Like `new()`, but puts `chunk_size` bytes of plaintext in each
+    /// chunk, which has to be between 1 byte and 16 MiB.  Smaller
+    /// chunks keep less in memory and localize damage better, but each
+    /// one costs up to 16 bytes for its padding and tag.
+    pub fn with_chunk_size<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I, chunk_size: usize) -> io::Result<ChunkedWriter<W>> {
+        ChunkedWriter::with_checksum(sink, key, iv, chunk_size, Checksum::Cmac)
+    }
+
+    /// Like `with_chunk_size()`, but protects each chunk with the
+    /// given `checksum` instead of CMAC.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{Checksum, ChunkedReader, ChunkedWriter};
+    ///
+    /// let mut crypt = ChunkedWriter::with_checksum(Vec::new(), [1, 2, 3, 4], [5, 6], 4096, Checksum::Mmo).ok().unwrap();
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap();
+    ///
+    /// let mut decrypt = ChunkedReader::new(Cursor::new(ciphertext), [1, 2, 3, 4]).ok().unwrap();
+    /// let mut s = String::new();
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert_eq!(s, "Hello, world!");
+    /// ```
+    pub fn with_checksum<K: Into<Key>, I: Into<Block>>(mut sink: W, key: K, iv: I, chunk_size: usize,
+                                                       checksum: Checksum) -> io::Result<ChunkedWriter<W>> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid chunk size",
+                                      Some(format!("chunks must hold 1 to {} bytes, not {}", MAX_CHUNK_SIZE, chunk_size))));
+        }
+        let mut header = [0u8; HEADER_LEN];
+        header[..8].clone_from_slice(&iv.into().to_be_bytes());
+        for i in 0..4 {
+            header[8 + i] = (chunk_size >> (24 - 8 * i)) as u8;
+        }
+        header[12] = match checksum {
+            Checksum::Cmac => 0,
+            Checksum::Mmo => 1,
+        };
+        try!(sink.write_all(&header));
+        Ok(ChunkedWriter{
+            sink: sink,
+            keys: ChunkKeys::new(key.into(), checksum, header),
+            chunk_size: chunk_size,
+            index: 0,
+            buf: SecretVec::with_capacity(chunk_size),
+            enc_buf: Vec::with_capacity(chunk_size + 8 + TAG_LEN),
+        })
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.sink
+    }
+
+    /// Encrypts and writes the last chunk, with whatever plaintext is
+    /// left over, and returns the underlying `std::io::Write` object.
+    pub fn close(mut self) -> io::Result<W> {
+        if self.buf.len() == self.chunk_size {
+            try!(self.write_chunk(false));
+        }
+        try!(self.write_chunk(true));
+        try!(self.sink.flush());
+        Ok(self.sink)
+    }
+
+    // Encrypts whatever is in `buf` as the next chunk and writes it out
+    // with its tag.
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        self.enc_buf.resize(self.buf.len() / 8 * 8 + 8, 0);
+        encrypt_to(self.keys.enc_key, self.keys.iv(self.index, last), &self.buf, &mut self.enc_buf).ok().unwrap();
+        let tag = self.keys.tag(self.index, last, &self.enc_buf);
+        self.enc_buf.push_all(&tag);
+        try!(self.sink.write_all(&self.enc_buf));
+        self.buf.truncate(0);
+        self.index += 1;
+        Ok(())
+    }
+
+}
+
+impl<W: io::Write> io::Write for ChunkedWriter<W> {
+
+    /// Takes in as much of `buf` as fits in the current chunk, writing
+    /// the chunk out once it's full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full chunk only goes out once there's more to write, since
+        // the last chunk has to be short.
+        if self.buf.len() == self.chunk_size {
+            try!(self.write_chunk(false));
+        }
+        let n = cmp::min(self.chunk_size - self.buf.len(), buf.len());
+        self.buf.push_all(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Flushes the underlying writer.  Plaintext in the current chunk
+    /// stays put until the chunk is full or the stream is closed.
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+}
+
+/// Wraps an underlying `std::io::Read` to check and decrypt the output
+/// of a `ChunkedWriter`, a chunk at a time.  No plaintext from a chunk
+/// is handed out until its tag has been checked.  As with `Reader`,
+/// failures are opaque unless `verbose_errors()` is turned on.
+pub struct ChunkedReader<R: io::Read> {
+    source: R,
+    keys: ChunkKeys,
+    chunk_size: usize,
+    index: u64,
+    buf: SecretVec,
+    pos: usize,
+    end: usize,
+    eof: bool,
+    peeked: Option<u8>,
+    verbose: bool,
+}
+
+impl<R: io::Read> ChunkedReader<R> {
+
+    /// Reads the iv and chunk size from the start of `source` and
+    /// returns a `ChunkedReader` that will check and decrypt the rest
+    /// with keys derived from `key`.
+    pub fn new<K: Into<Key>>(mut source: R, key: K) -> io::Result<ChunkedReader<R>> {
+        let mut header = [0u8; HEADER_LEN];
+        try!(super::read_header(&mut source, &mut header));
+        let chunk_size = header[8..12].iter().fold(0, |n, &b| (n << 8) | b as usize);
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(super::decrypt_error(false, "bad chunk size", ""));
+        }
+        let checksum = match header[12] {
+            0 => Checksum::Cmac,
+            1 => Checksum::Mmo,
+            _ => return Err(super::decrypt_error(false, "unknown checksum", "")),
+        };
+        Ok(ChunkedReader{
+            source: source,
+            keys: ChunkKeys::new(key.into(), checksum, header),
+            chunk_size: chunk_size,
+            index: 0,
+            buf: SecretVec::with_capacity(chunk_size + 8 + TAG_LEN),
+            pos: 0,
+            end: 0,
+            eof: false,
+            peeked: None,
+            verbose: false,
+        })
+    }
+
+    /// Makes failures say what went wrong, like
+    /// `Reader::verbose_errors`.
+    pub fn verbose_errors(mut self) -> ChunkedReader<R> {
+        self.verbose = true;
+        self
+    }
+
+    /// Unwraps this `ChunkedReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    // Reads one byte past the chunk in `buf`, to see whether it's the
+    // last one, and keeps it for the next chunk.
+    fn peek(&mut self) -> io::Result<bool> {
+        let mut byte = [0u8; 1];
+        loop {
+            match self.source.read(&mut byte) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {
+                    self.peeked = Some(byte[0]);
+                    return Ok(true);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Reads, checks and decrypts the next chunk into `buf`.
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let full = self.chunk_size / 8 * 8 + 8 + TAG_LEN;
+        self.buf.resize(full, 0);
+        let mut filled = 0;
+        if let Some(byte) = self.peeked.take() {
+            self.buf[0] = byte;
+            filled = 1;
+        }
+        while filled < full {
+            match self.source.read(&mut self.buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        if filled < 8 + TAG_LEN || (filled - TAG_LEN) % 8 != 0 {
+            return Err(super::decrypt_error(self.verbose, "truncated stream",
+                                            &format!("chunk {} ends after {} bytes", self.index, filled)));
+        }
+        // Whether this is the last chunk is only known from the end of
+        // `source`; if that's wrong, the tag won't match.
+        let last = filled < full || !try!(self.peek());
+        let ct_len = filled - TAG_LEN;
+        let expected = self.keys.tag(self.index, last, &self.buf[..ct_len]);
+        if !ct::eq(&expected, &self.buf[ct_len..filled]) {
+            return Err(super::decrypt_error(self.verbose, "bad tag",
+                                            &format!("chunk {} was modified, moved or cut short", self.index)));
+        }
+        let len = match decrypt_padded_in_place(self.keys.enc_key, self.keys.iv(self.index, last), &mut self.buf[..ct_len]) {
+            Ok(len) => len,
+            Err(_) => return Err(super::decrypt_error(self.verbose, "bad padding",
+                                                      &format!("chunk {} doesn't end in valid PKCS#7 padding", self.index))),
+        };
+        // Only the last chunk is short.
+        if last != (len < self.chunk_size) {
+            return Err(super::decrypt_error(self.verbose, "bad chunk length",
+                                            &format!("chunk {} holds {} bytes", self.index, len)));
+        }
+        self.pos = 0;
+        self.end = len;
+        self.eof = last;
+        self.index += 1;
+        Ok(())
+    }
+
+}
+
+impl<R: io::Read> io::Read for ChunkedReader<R> {
+
+    /// Copies out checked plaintext, reading the next chunk when the
+    /// current one runs out.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.end {
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+            try!(self.read_chunk());
+        }
+        let n = cmp::min(buf.len(), self.end - self.pos);
+        buf[..n].clone_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    let input: Vec<u8> = (0u8..100).collect();
+    for &chunk_size in [1, 7, 8, 9, 16, 50, 100, 1000].iter() {
+        for len in 0..input.len() + 1 {
+            let mut writer = ChunkedWriter::with_chunk_size(Vec::new(), [1, 2, 3, 4], [5, 6], chunk_size).ok().unwrap();
+            writer.write_all(&input[..len]).ok().unwrap();
+            let ciphertext = writer.close().ok().unwrap();
+            let (chunks, tail) = (len / chunk_size, len % chunk_size);
+            let chunk_len = |n: usize| (n / 8 + 1) * 8 + TAG_LEN;
+            assert_eq!(ciphertext.len(), HEADER_LEN + chunks * chunk_len(chunk_size) + chunk_len(tail));
+
+            let mut output = Vec::new();
+            ChunkedReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).ok().unwrap()
+                .read_to_end(&mut output).ok().unwrap();
+            assert_eq!(&output[..], &input[..len]);
+        }
+    }
+    assert!(ChunkedWriter::with_chunk_size(Vec::new(), [1, 2, 3, 4], [5, 6], 0).is_err());
+}
+
+#[test]
+fn mmo() {
+    use std::io::{Read, Write};
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = ChunkedWriter::with_checksum(Vec::new(), [1, 2, 3, 4], [5, 6], 16, Checksum::Mmo).ok().unwrap();
+    writer.write_all(&input).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    let mut output = Vec::new();
+    ChunkedReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).ok().unwrap()
+        .read_to_end(&mut output).ok().unwrap();
+    assert_eq!(output, input);
+
+    for i in 0..ciphertext.len() {
+        let mut flipped = ciphertext.clone();
+        flipped[i] ^= 1;
+        let result = ChunkedReader::new(io::Cursor::new(flipped), [1, 2, 3, 4])
+            .and_then(|mut reader| reader.read_to_end(&mut Vec::new()));
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn tampered() {
+    use std::io::{Read, Write};
+
+    let mut writer = ChunkedWriter::with_chunk_size(Vec::new(), [1, 2, 3, 4], [5, 6], 16).ok().unwrap();
+    writer.write_all(&[7u8; 40]).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    let chunk_len = 16 + 8 + TAG_LEN;
+    assert_eq!(ciphertext.len(), HEADER_LEN + 2 * chunk_len + 16 + TAG_LEN);
+
+    // Damage to the second chunk still lets the first be read.
+    let mut damaged = ciphertext.clone();
+    damaged[HEADER_LEN + chunk_len + 3] ^= 1;
+    let mut reader = ChunkedReader::new(io::Cursor::new(damaged), [1, 2, 3, 4]).ok().unwrap();
+    let mut first = [0u8; 16];
+    reader.read_exact(&mut first).ok().unwrap();
+    assert_eq!(first, [7u8; 16]);
+    assert!(reader.read(&mut first).is_err());
+
+    for i in 0..ciphertext.len() {
+        let mut flipped = ciphertext.clone();
+        flipped[i] ^= 1;
+        let result = ChunkedReader::new(io::Cursor::new(flipped), [1, 2, 3, 4])
+            .and_then(|mut reader| reader.read_to_end(&mut Vec::new()));
+        assert!(result.is_err());
+    }
+
+    let mut swapped = ciphertext[..HEADER_LEN].to_vec();
+    swapped.push_all(&ciphertext[HEADER_LEN + chunk_len..HEADER_LEN + 2 * chunk_len]);
+    swapped.push_all(&ciphertext[HEADER_LEN..HEADER_LEN + chunk_len]);
+    swapped.push_all(&ciphertext[HEADER_LEN + 2 * chunk_len..]);
+    for stream in [&swapped[..], &ciphertext[..HEADER_LEN + 2 * chunk_len]].iter() {
+        let mut reader = ChunkedReader::new(io::Cursor::new(*stream), [1, 2, 3, 4]).ok().unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+}
+
+#[test]
+fn truncated() {
+    use std::error::Error;
+    use std::io::{Read, Write};
+
+    // Chunks of 15 bytes encrypt to 16 bytes, the same as a short
+    // last chunk, so only the last-chunk flag tells them apart.
+    let mut writer = ChunkedWriter::with_chunk_size(Vec::new(), [1, 2, 3, 4], [5, 6], 15).ok().unwrap();
+    writer.write_all(&[7u8; 30]).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    let chunk_len = 16 + TAG_LEN;
+    assert_eq!(ciphertext.len(), HEADER_LEN + 2 * chunk_len + 8 + TAG_LEN);
+
+    for &end in [HEADER_LEN + chunk_len, HEADER_LEN + 2 * chunk_len].iter() {
+        let mut reader = ChunkedReader::new(io::Cursor::new(&ciphertext[..end]), [1, 2, 3, 4]).ok().unwrap()
+            .verbose_errors();
+        let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+        assert_eq!(err.description(), "bad tag");
+    }
+
+    let mut extended = ciphertext.clone();
+    extended.push_all(&ciphertext[HEADER_LEN..HEADER_LEN + chunk_len]);
+    let mut reader = ChunkedReader::new(io::Cursor::new(extended), [1, 2, 3, 4]).ok().unwrap();
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
@@ -0,0 +1,261 @@
+use std::cmp;
+use std::io;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use super::super::{Key, Block};
+use super::{Reader, Writer};
+use mem::SecretVec;
+
+// Big enough that each pass through the loop handles a good run of
+// blocks, without being a burden on the stack or heap.
+const COPY_BUF_SIZE: usize = 64 * 1024;
+
+// How many buffers can wait between two stages of a pipelined copy,
+// so a fast stage can get this far ahead of a slow one and no
+// further.
+const PIPELINE_DEPTH: usize = 4;
+
+// Moves everything from `reader` to `writer` through one large buffer,
+// returning how many bytes went across.
+fn pump<R: ?Sized + io::Read, W: ?Sized + io::Write>(reader: &mut R, writer: &mut W) -> io::Result<u64> {
+    let mut buf = vec![0u8; COPY_BUF_SIZE];
+    let mut total = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        try!(writer.write_all(&buf[..n]));
+        total += n as u64;
+    }
+}
+
+/// Reads everything from `reader`, encrypts it with the given `key`
+/// and `iv` (initialization vector), and writes the ciphertext,
+/// padding and all, to `writer`.  This is like `std::io::copy` with a
+/// `Writer` wrapped around `writer`, but with larger buffers.  Returns
+/// the number of plaintext bytes read.
+///
+/// # Example:
+/// ```
+/// use std::io::Cursor;
+/// use tea::io;
+///
+/// let mut ciphertext = Vec::new();
+/// let n = io::encrypt_copy(&mut Cursor::new(b"Hello, world!"), &mut ciphertext, [1, 2, 3, 4], [5, 6]).ok().unwrap();
+/// assert_eq!(n, 13);
+/// let mut plaintext = Vec::new();
+/// io::decrypt_copy(&mut Cursor::new(ciphertext), &mut plaintext, [1, 2, 3, 4], [5, 6]).ok().unwrap();
+/// assert_eq!(plaintext, b"Hello, world!");
+/// ```
+pub fn encrypt_copy<R: ?Sized, W: ?Sized, K, I>(reader: &mut R, writer: &mut W, key: K, iv: I) -> io::Result<u64>
+    where R: io::Read, W: io::Write, K: Into<Key>, I: Into<Block>
+{
+    let mut crypt = Writer::with_capacity(writer, key, iv, COPY_BUF_SIZE);
+    let total = try!(pump(reader, &mut crypt));
+    try!(crypt.close());
+    Ok(total)
+}
+
+/// Reads everything from `reader`, decrypts it with the given `key`
+/// and `iv`, and writes the plaintext to `writer`.  This is like
+/// `std::io::copy` with a `Reader` wrapped around `reader`, but with
+/// larger buffers.  Returns the number of plaintext bytes written.
+pub fn decrypt_copy<R: ?Sized, W: ?Sized, K, I>(reader: &mut R, writer: &mut W, key: K, iv: I) -> io::Result<u64>
+    where R: io::Read, W: io::Write, K: Into<Key>, I: Into<Block>
+{
+    let mut crypt = Reader::with_capacity(reader, key, iv, COPY_BUF_SIZE);
+    pump(&mut crypt, writer)
+}
+
+// The cipher stage's end of the channel from the reading thread.
+struct ChannelSource {
+    rx: Receiver<io::Result<SecretVec>>,
+    buf: SecretVec,
+    pos: usize,
+}
+
+impl io::Read for ChannelSource {
+
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(Ok(buf)) => {
+                    self.buf = buf;
+                    self.pos = 0;
+                },
+                Ok(Err(e)) => return Err(e),
+                // The reading thread hung up at the end of its input.
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = cmp::min(out.len(), self.buf.len() - self.pos);
+        out[..n].clone_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+}
+
+// The cipher stage's end of the channel to the writing thread.
+struct ChannelSink(SyncSender<SecretVec>);
+
+impl io::Write for ChannelSink {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut copy = SecretVec::with_capacity(buf.len());
+        copy.push_all(buf);
+        match self.0.send(copy) {
+            Ok(()) => Ok(buf.len()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipeline writer stopped", None)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+}
+
+// Reads `reader` into buffers and sends them down `tx` until the end
+// of the input, an error, or the cipher stage hanging up.
+fn read_stage<R: ?Sized + io::Read>(reader: &mut R, tx: SyncSender<io::Result<SecretVec>>) {
+    loop {
+        let mut buf = SecretVec::with_capacity(COPY_BUF_SIZE);
+        buf.resize(COPY_BUF_SIZE, 0);
+        let result = match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => {
+                buf.truncate(n);
+                Ok(buf)
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+        let failed = result.is_err();
+        if tx.send(result).is_err() || failed {
+            return;
+        }
+    }
+}
+
+// Writes everything that comes down `rx` to `writer`.
+fn write_stage<W: ?Sized + io::Write>(writer: &mut W, rx: Receiver<SecretVec>) -> io::Result<()> {
+    for buf in rx.iter() {
+        try!(writer.write_all(&buf));
+    }
+    writer.flush()
+}
+
+// Runs a copy as three stages: one thread reading from `reader`,
+// `work` on this one turning what it reads into what it writes, and
+// one thread writing to `writer`, with bounded channels in between.
+// An error in the writing thread is reported over the broken pipe it
+// leaves `work` with.
+fn pipeline<R: ?Sized, W: ?Sized, F>(reader: &mut R, writer: &mut W, work: F) -> io::Result<u64>
+    where R: io::Read + Send, W: io::Write + Send, F: FnOnce(&mut ChannelSource, &mut ChannelSink) -> io::Result<u64>
+{
+    let (read_tx, read_rx) = sync_channel(PIPELINE_DEPTH);
+    let (write_tx, write_rx) = sync_channel(PIPELINE_DEPTH);
+    thread::scope(|scope| {
+        let reading = scope.spawn(move || read_stage(reader, read_tx));
+        let writing = scope.spawn(move || write_stage(writer, write_rx));
+        let mut source = ChannelSource{ rx: read_rx, buf: SecretVec::new(), pos: 0 };
+        let mut sink = ChannelSink(write_tx);
+        let result = work(&mut source, &mut sink);
+        // Hanging up stops the reading thread early if `work` failed,
+        // and lets the writing thread finish.
+        drop(source);
+        drop(sink);
+        let panicked = || io::Error::new(io::ErrorKind::Other, "pipeline thread panicked", None);
+        let written = writing.join().unwrap_or_else(|_| Err(panicked()));
+        let read = reading.join().map_err(|_| panicked());
+        try!(written);
+        try!(read);
+        result
+    })
+}
+
+/// Like `encrypt_copy`, but reads, encrypts and writes on three
+/// threads at once, with a few buffers of 64 KiB queued between each,
+/// so a fast disk or network link isn't left waiting on the cipher or
+/// the other way around.  It's only worth it for large copies where
+/// both ends are fast; the output is the same.
+///
+/// # Example:
+/// ```
+/// use std::io::Cursor;
+/// use tea::io;
+///
+/// let input = vec![7u8; 1 << 20];
+/// let mut ciphertext = Vec::new();
+/// io::encrypt_copy_pipelined(&mut Cursor::new(&input[..]), &mut ciphertext, [1, 2, 3, 4], [5, 6]).ok().unwrap();
+/// let mut plaintext = Vec::new();
+/// io::decrypt_copy_pipelined(&mut Cursor::new(ciphertext), &mut plaintext, [1, 2, 3, 4], [5, 6]).ok().unwrap();
+/// assert_eq!(plaintext, input);
+/// ```
+pub fn encrypt_copy_pipelined<R: ?Sized, W: ?Sized, K, I>(reader: &mut R, writer: &mut W, key: K, iv: I) -> io::Result<u64>
+    where R: io::Read + Send, W: io::Write + Send, K: Into<Key>, I: Into<Block>
+{
+    let (key, iv) = (key.into(), iv.into());
+    pipeline(reader, writer, |source, sink| {
+        let mut crypt = Writer::with_capacity(sink, key, iv, COPY_BUF_SIZE);
+        let total = try!(pump(source, &mut crypt));
+        try!(crypt.close());
+        Ok(total)
+    })
+}
+
+/// Like `decrypt_copy`, but pipelined across three threads as in
+/// `encrypt_copy_pipelined`.
+pub fn decrypt_copy_pipelined<R: ?Sized, W: ?Sized, K, I>(reader: &mut R, writer: &mut W, key: K, iv: I) -> io::Result<u64>
+    where R: io::Read + Send, W: io::Write + Send, K: Into<Key>, I: Into<Block>
+{
+    let (key, iv) = (key.into(), iv.into());
+    pipeline(reader, writer, |source, sink| {
+        let mut crypt = Reader::with_capacity(source, key, iv, COPY_BUF_SIZE);
+        pump(&mut crypt, sink)
+    })
+}
+
+#[test]
+fn it_works() {
+    let input: Vec<u8> = (0..200000).map(|i| i as u8).collect();
+    let mut ciphertext = Vec::new();
+    assert_eq!(encrypt_copy(&mut io::Cursor::new(&input[..]), &mut ciphertext, [1, 2, 3, 4], [5, 6]).ok().unwrap(),
+               input.len() as u64);
+    assert_eq!(ciphertext.len(), 16 + input.len() + 8);
+
+    let mut output = Vec::new();
+    assert_eq!(decrypt_copy(&mut io::Cursor::new(&ciphertext[..]), &mut output, [1, 2, 3, 4], [5, 6]).ok().unwrap(),
+               input.len() as u64);
+    assert_eq!(output, input);
+}
+
+#[test]
+fn pipelined() {
+    let input: Vec<u8> = (0..1000000).map(|i| i as u8).collect();
+    let mut expected = Vec::new();
+    encrypt_copy(&mut io::Cursor::new(&input[..]), &mut expected, [1, 2, 3, 4], [5, 6]).ok().unwrap();
+    let mut ciphertext = Vec::new();
+    assert_eq!(encrypt_copy_pipelined(&mut io::Cursor::new(&input[..]), &mut ciphertext, [1, 2, 3, 4], [5, 6]).ok().unwrap(),
+               input.len() as u64);
+    assert!(ciphertext == expected);
+
+    let mut output = Vec::new();
+    assert_eq!(decrypt_copy_pipelined(&mut io::Cursor::new(&ciphertext[..]), &mut output, [1, 2, 3, 4], [5, 6]).ok().unwrap(),
+               input.len() as u64);
+    assert!(output == input);
+    let mut output = Vec::new();
+    assert!(decrypt_copy_pipelined(&mut io::Cursor::new(&ciphertext[..ciphertext.len() - 1]), &mut output,
+                                   [1, 2, 3, 4], [5, 6]).is_err());
+
+    // A sink that fails partway reports its own error.
+    let mut full = [0u8; 100000];
+    let err = encrypt_copy_pipelined(&mut io::Cursor::new(&input[..]), &mut io::Cursor::new(&mut full[..]),
+                                     [1, 2, 3, 4], [5, 6]).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
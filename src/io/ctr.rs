@@ -0,0 +1,478 @@
+use std::cmp;
+use std::io;
+
+use super::super::{Key, Block, ByteOrder, Error};
+use cipher::Cipher;
+use mem::{self, SecretVec};
+
+// With the `parallel` feature, a buffer of at least two pieces this
+// size has its keystream split across the rayon pool a piece at a
+// time.  For anything smaller, handing out the work costs more than
+// it saves.
+#[cfg(feature = "parallel")]
+const PARALLEL_CHUNK: usize = 64 * 1024;
+
+// How much keystream is made at a time, in one call to
+// `Cipher::encrypt_blocks`.
+const KEYSTREAM_BATCH: usize = 512;
+
+// How much `ctr_encrypt_copy` reads at a time: enough for a good
+// number of pieces to share out with the `parallel` feature.
+const COPY_BUF_SIZE: usize = 1024 * 1024;
+
+// The CTR keystream: block `i` is the encryption of the iv, read as a
+// 64-bit number, plus `i`, laid out in `order`.  Any byte of it can
+// be computed directly from its offset, which is what makes seeking
+// cheap.
+struct Keystream {
+    cipher: Cipher,
+    nonce: u64,
+    order: ByteOrder,
+}
+
+impl Keystream {
+
+    fn new(key: Key, iv: Block) -> Keystream {
+        Keystream{
+            cipher: Cipher::new(key),
+            nonce: u64::from(iv),
+            order: ByteOrder::BigEndian,
+        }
+    }
+
+    // XORs `buf` with the keystream, starting `offset` bytes in.
+    #[cfg(feature = "parallel")]
+    fn apply(&self, offset: u64, buf: &mut [u8]) {
+        use rayon::prelude::*;
+
+        if buf.len() < 2 * PARALLEL_CHUNK {
+            return self.apply_serial(offset, buf);
+        }
+        buf.par_chunks_mut(PARALLEL_CHUNK).enumerate().for_each(|(i, chunk)| {
+            self.apply_serial(offset + (i * PARALLEL_CHUNK) as u64, chunk)
+        });
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn apply(&self, offset: u64, buf: &mut [u8]) {
+        self.apply_serial(offset, buf)
+    }
+
+    // Lays out a batch of counter blocks and encrypts them all at
+    // once, skipping however far into the first block `offset` is.
+    fn apply_serial(&self, offset: u64, buf: &mut [u8]) {
+        let mut keystream = [0u8; KEYSTREAM_BATCH];
+        let mut counter = self.nonce.wrapping_add(offset / 8);
+        let mut skip = (offset % 8) as usize;
+        let mut done = 0;
+        while done < buf.len() {
+            let len = cmp::min(skip + buf.len() - done, KEYSTREAM_BATCH);
+            let blocks = (len + 7) / 8;
+            for chunk in keystream[..blocks * 8].chunks_mut(8) {
+                chunk.clone_from_slice(&mem::write_block_as(&Block::from(counter), self.order));
+                counter = counter.wrapping_add(1);
+            }
+            self.cipher.encrypt_blocks_as(&mut keystream[..blocks * 8], self.order).ok().unwrap();
+            let n = len - skip;
+            for (b, k) in buf[done..done + n].iter_mut().zip(keystream[skip..len].iter()) {
+                *b ^= *k;
+            }
+            done += n;
+            skip = 0;
+        }
+        mem::wipe(&mut keystream);
+    }
+
+}
+
+/// Encrypts `plaintext` in CTR mode with the given `key` and `iv` into
+/// the front of `out`, and returns its length, which is the same as
+/// the plaintext's.  This makes the same bytes as a `CtrWriter::new`
+/// (so no `Header`), and since CTR decryption is the same operation,
+/// it decrypts them as well.  With the `parallel` feature, a big
+/// buffer is split across a rayon thread pool.  If `out` is too
+/// small, this returns `Error::BufferTooSmall` and leaves `out` alone.
+///
+/// # Example:
+/// ```
+/// use tea::io;
+///
+/// let mut ciphertext = [0u8; 13];
+/// io::ctr_encrypt_to([1, 2, 3, 4], [5, 6], b"Hello, world!", &mut ciphertext).ok().unwrap();
+/// let mut plaintext = [0u8; 13];
+/// io::ctr_encrypt_to([1, 2, 3, 4], [5, 6], &ciphertext, &mut plaintext).ok().unwrap();
+/// assert_eq!(&plaintext, b"Hello, world!");
+/// ```
+pub fn ctr_encrypt_to<K: Into<Key>, I: Into<Block>>(key: K, iv: I, plaintext: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    if out.len() < plaintext.len() {
+        return Err(Error::BufferTooSmall { needed: plaintext.len() });
+    }
+    let out = &mut out[..plaintext.len()];
+    out.clone_from_slice(plaintext);
+    Keystream::new(key.into(), iv.into()).apply(0, out);
+    Ok(out.len())
+}
+
+/// Reads everything from `reader`, encrypts (or decrypts) it in CTR
+/// mode with the given `key` and `iv`, and writes the result to
+/// `writer`, like `std::io::copy` with a `CtrWriter::new` wrapped
+/// around `writer`, but with a buffer big enough to go across the
+/// rayon thread pool with the `parallel` feature.  Returns the number
+/// of bytes copied.
+pub fn ctr_encrypt_copy<R: ?Sized, W: ?Sized, K, I>(reader: &mut R, writer: &mut W, key: K, iv: I) -> io::Result<u64>
+    where R: io::Read, W: io::Write, K: Into<Key>, I: Into<Block>
+{
+    let keystream = Keystream::new(key.into(), iv.into());
+    let mut buf = SecretVec::with_capacity(COPY_BUF_SIZE);
+    buf.resize(COPY_BUF_SIZE, 0);
+    let mut total = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        keystream.apply(total, &mut buf[..n]);
+        try!(writer.write_all(&buf[..n]));
+        total += n as u64;
+    }
+}
+
+// Works out where a seek lands relative to `base`, the position in the
+// underlying stream where the encrypted data starts, and moves the
+// stream there.  On an invalid seek, the stream is put back where it
+// was.
+fn seek_inner<S: io::Seek>(inner: &mut S, offset: u64, pos: io::SeekFrom) -> io::Result<u64> {
+    let current = try!(inner.seek(io::SeekFrom::Current(0)));
+    let base = current - offset;
+    let landed = try!(inner.seek(match pos {
+        io::SeekFrom::Start(n) => io::SeekFrom::Start(base + n),
+        other => other,
+    }));
+    if landed < base {
+        try!(inner.seek(io::SeekFrom::Start(current)));
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position", None));
+    }
+    Ok(landed - base)
+}
+
+/// Wraps an underlying `std::io::Read` so that bytes read get
+/// decrypted in CTR mode on the way through.  There's no padding or
+/// chaining, so the plaintext is exactly as long as the ciphertext
+/// and, if the source is `std::io::Seek`, the `CtrReader` can jump
+/// straight to any offset.
+///
+/// The keystream's blocks are laid out big-endian, as the C reference
+/// and most other XTEA implementations do, so a stream reads back the
+/// same on any host; see `byte_order()`.  With the `parallel`
+/// feature, the keystream for a big read is worked out across a rayon
+/// thread pool.
+///
+/// Never encrypt two streams with the same key and iv in CTR mode.
+///
+/// # Example:
+/// ```.ignore
+/// use std::fs::File;
+/// use std::io::{Read, Seek, SeekFrom};
+/// use tea::io::CtrReader;
+///
+/// let f = File::open("movie.enc").ok().unwrap();
+/// let mut decrypt_f = CtrReader::new(f, [1, 2, 3, 4], [5, 6]);
+/// decrypt_f.seek(SeekFrom::Start(1 << 20)).ok().unwrap();
+/// let mut buf = [0u8; 4096];
+/// decrypt_f.read(&mut buf).ok().unwrap();
+/// ```
+pub struct CtrReader<R: io::Read> {
+    source: R,
+    keystream: Keystream,
+    offset: u64,
+}
+
+impl<R: io::Read> CtrReader<R> {
+
+    /// Wraps `source` in a `CtrReader` that will decrypt with the
+    /// given `key` and `iv` (initialization vector).
+    pub fn new<K: Into<Key>, I: Into<Block>>(source: R, key: K, iv: I) -> CtrReader<R> {
+        CtrReader{
+            source: source,
+            keystream: Keystream::new(key.into(), iv.into()),
+            offset: 0,
+        }
+    }
+
+    /// Lays out the words of each keystream block in `order` instead
+    /// of big-endian, as the `CtrWriter` that produced the stream did
+    /// with `byte_order()`.
+    pub fn byte_order(mut self, order: ByteOrder) -> CtrReader<R> {
+        self.keystream.order = order;
+        self
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.source
+    }
+
+    /// Gets a mutable reference to the underlying reader.  Reading
+    /// from it directly will throw the decryption out of step.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    /// Unwraps this `CtrReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+}
+
+impl<R: io::Read> io::Read for CtrReader<R> {
+
+    /// Reads from `source` and decrypts the data in place in `buf`.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.source.read(buf));
+        self.keystream.apply(self.offset, &mut buf[..n]);
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+}
+
+impl<R: io::Read + io::Seek> io::Seek for CtrReader<R> {
+
+    /// Moves to a plaintext offset, which is the same as the
+    /// ciphertext offset.  Offsets are relative to wherever `source`
+    /// was when the `CtrReader` was created.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.offset = try!(seek_inner(&mut self.source, self.offset, pos));
+        Ok(self.offset)
+    }
+
+}
+
+/// Wraps an underlying `std::io::Write` so that bytes written get
+/// encrypted in CTR mode and passed through.  Nothing is buffered, so
+/// there's nothing to close, and if the sink is `std::io::Seek` the
+/// `CtrWriter` can jump to any offset and overwrite part of the
+/// stream.
+///
+/// Never encrypt two streams with the same key and iv in CTR mode,
+/// and don't overwrite part of a stream with different data, since
+/// both give away the XOR of the two plaintexts.  The keystream is
+/// laid out big-endian unless `byte_order()` says otherwise.  With
+/// the `parallel` feature, big writes are spread across a rayon thread
+/// pool, like reads.
+pub struct CtrWriter<W: io::Write> {
+    sink: W,
+    keystream: Keystream,
+    offset: u64,
+    scratch: Vec<u8>,
+}
+
+impl<W: io::Write> CtrWriter<W> {
+
+    /// Wraps `sink` in a `CtrWriter` that will encrypt with the given
+    /// `key` and `iv` (initialization vector).
+    pub fn new<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I) -> CtrWriter<W> {
+        CtrWriter{
+            sink: sink,
+            keystream: Keystream::new(key.into(), iv.into()),
+            offset: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Lays out the words of each keystream block in `order` instead
+    /// of big-endian.  The `CtrReader` must use the same byte order.
+    pub fn byte_order(mut self, order: ByteOrder) -> CtrWriter<W> {
+        self.keystream.order = order;
+        self
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.sink
+    }
+
+    /// Gets a mutable reference to the underlying writer.  Writing to
+    /// it directly will corrupt the encrypted stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    /// Unwraps this `CtrWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+
+}
+
+impl<W: io::Write> io::Write for CtrWriter<W> {
+
+    /// Encrypts the bytes in `buf` and passes them through to the
+    /// underlying `std::io::Write` in a single `write`.  Only as many
+    /// bytes as the sink accepts are counted as written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.scratch.truncate(0);
+        self.scratch.push_all(buf);
+        self.keystream.apply(self.offset, &mut self.scratch);
+        let n = try!(self.sink.write(&self.scratch));
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    /// Passes the flush call through to the underlying
+    /// `std::io::Write` object.
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+}
+
+impl<W: io::Write + io::Seek> io::Seek for CtrWriter<W> {
+
+    /// Moves to a plaintext offset, which is the same as the
+    /// ciphertext offset.  Offsets are relative to wherever `sink` was
+    /// when the `CtrWriter` was created.
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.offset = try!(seek_inner(&mut self.sink, self.offset, pos));
+        Ok(self.offset)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    let input: Vec<u8> = (0u8..100).collect();
+    for chunk_size in 1..20 {
+        let mut writer = CtrWriter::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+        for chunk in input.chunks(chunk_size) {
+            writer.write_all(chunk).ok().unwrap();
+        }
+        let ciphertext = writer.into_inner().into_inner();
+        assert_eq!(ciphertext.len(), input.len());
+        assert!(ciphertext != input);
+
+        let mut reader = CtrReader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]);
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; chunk_size];
+        loop {
+            let n = reader.read(&mut buf).ok().unwrap();
+            if n == 0 {
+                break;
+            }
+            output.push_all(&buf[..n]);
+        }
+        assert_eq!(output, input);
+    }
+}
+
+#[test]
+fn seek() {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = CtrWriter::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&[0u8; 100]).ok().unwrap();
+    // Fill in the real plaintext back to front.
+    for start in (0..10).rev() {
+        assert_eq!(writer.seek(SeekFrom::Start(start * 10)).ok().unwrap(), start * 10);
+        writer.write_all(&input[start as usize * 10..start as usize * 10 + 10]).ok().unwrap();
+    }
+    let ciphertext = writer.into_inner().into_inner();
+
+    let mut reader = CtrReader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]);
+    for &(pos, expected) in [(SeekFrom::Start(13), 13),
+                             (SeekFrom::Current(-20), 0),
+                             (SeekFrom::End(-7), 93),
+                             (SeekFrom::Start(61), 61)].iter() {
+        assert_eq!(reader.seek(pos).ok().unwrap(), expected as u64);
+        let mut got = Vec::new();
+        reader.by_ref().take(7).read_to_end(&mut got).ok().unwrap();
+        assert_eq!(&got[..], &input[expected..expected + 7]);
+    }
+    assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+    assert_eq!(reader.seek(SeekFrom::Current(0)).ok().unwrap(), 68);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel() {
+    let keystream = Keystream::new(Key::new([1, 2, 3, 4]), Block::new([5, 6]));
+    let input: Vec<u8> = (0..5 * PARALLEL_CHUNK + 13).map(|i| i as u8).collect();
+    for &offset in [0, 3, 8 * 1000 + 5].iter() {
+        let mut parallel = input.clone();
+        keystream.apply(offset, &mut parallel);
+        let mut serial = input.clone();
+        keystream.apply_serial(offset, &mut serial);
+        assert!(parallel == serial);
+    }
+}
+
+#[test]
+fn buffers() {
+    use std::io::Write;
+
+    let input: Vec<u8> = (0..COPY_BUF_SIZE + 1001).map(|i| (i * 7) as u8).collect();
+    let mut writer = CtrWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let ciphertext = writer.into_inner();
+
+    let mut out = vec![0u8; input.len()];
+    assert_eq!(ctr_encrypt_to([1, 2, 3, 4], [5, 6], &input, &mut out[..10]),
+               Err(Error::BufferTooSmall { needed: input.len() }));
+    assert_eq!(ctr_encrypt_to([1, 2, 3, 4], [5, 6], &input, &mut out).ok().unwrap(), input.len());
+    assert!(out == ciphertext);
+
+    let mut copied = Vec::new();
+    assert_eq!(ctr_encrypt_copy(&mut io::Cursor::new(&input[..]), &mut copied, [1, 2, 3, 4], [5, 6]).ok().unwrap(),
+               input.len() as u64);
+    assert!(copied == ciphertext);
+    let mut decrypted = Vec::new();
+    ctr_encrypt_copy(&mut io::Cursor::new(&copied[..]), &mut decrypted, [1, 2, 3, 4], [5, 6]).ok().unwrap();
+    assert!(decrypted == input);
+}
+
+#[test]
+fn keystream() {
+    use cipher;
+
+    let key = Key::new([1, 2, 3, 4]);
+    let keystream = Keystream::new(key, Block::new([5, 6]));
+    for &(offset, len) in [(0, 0), (0, 8), (3, 1), (5, 2 * KEYSTREAM_BATCH + 9), (4096 + 7, 600)].iter() {
+        let mut buf = vec![0u8; len];
+        keystream.apply(offset, &mut buf);
+        for (i, &b) in buf.iter().enumerate() {
+            let pos = offset + i as u64;
+            let block = cipher::encipher(&key, &Block::from(u64::from(Block::new([5, 6])) + pos / 8));
+            assert_eq!(b, block.to_be_bytes()[(pos % 8) as usize]);
+        }
+    }
+}
+
+#[test]
+fn byte_order() {
+    use std::io::{Read, Write};
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut ciphertexts = Vec::new();
+    for &order in [ByteOrder::BigEndian, ByteOrder::LittleEndian].iter() {
+        let mut writer = CtrWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]).byte_order(order);
+        writer.write_all(&input).ok().unwrap();
+        let ciphertext = writer.into_inner();
+        let mut reader = CtrReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4], [5, 6]).byte_order(order);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).ok().unwrap();
+        assert_eq!(output, input);
+        ciphertexts.push(ciphertext);
+    }
+    assert!(ciphertexts[0] != ciphertexts[1]);
+
+    // Big-endian is the default.
+    let mut writer = CtrWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    assert_eq!(writer.into_inner(), ciphertexts[0]);
+}
@@ -0,0 +1,170 @@
+use std::io;
+
+use super::super::{Key, Block, wrap_key, unwrap_key};
+use super::{Reader, Writer};
+
+// Identifies a stream encrypted under its own wrapped data key.
+const MAGIC: &'static [u8; 4] = b"TEAe";
+
+// Each recipient's copy of the data key, wrapped under their key.
+const WRAPPED_LEN: usize = 24;
+
+#[cfg(feature = "rand")]
+impl<W: io::Write> Writer<W> {
+
+    /// Encrypts the stream with a new random data key and iv, and
+    /// writes a header to `sink` holding the data key wrapped under
+    /// `master_key` (see `tea::wrap_key`).  The master key itself
+    /// never touches any plaintext, so each stream exposes it only
+    /// once, and rotating it means rewrapping headers rather than
+    /// re-encrypting everything.  `Reader::with_envelope` unwraps the
+    /// key again.  Only built with the `rand` feature.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{Reader, Writer};
+    ///
+    /// let mut crypt = Writer::with_envelope(Vec::new(), [1, 2, 3, 4]).ok().unwrap();
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap();
+    ///
+    /// let mut decrypt = Reader::with_envelope(Cursor::new(ciphertext), [1, 2, 3, 4]).ok().unwrap();
+    /// let mut s = String::new();
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert_eq!(s, "Hello, world!");
+    /// ```
+    pub fn with_envelope<K: Into<Key>>(sink: W, master_key: K) -> io::Result<Writer<W>> {
+        Writer::with_recipients(sink, &[master_key.into()])
+    }
+
+    /// Like `with_envelope`, but wraps the data key separately under
+    /// each of the `recipients`' keys, so any one of them can decrypt
+    /// the stream (a shared backup, say, or one with a break-glass
+    /// recovery key).  There must be between 1 and 255 recipients.
+    /// Only built with the `rand` feature.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::Key;
+    /// use tea::io::{Reader, Writer};
+    ///
+    /// let (alice, bob) = (Key::new([1, 2, 3, 4]), Key::new([5, 6, 7, 8]));
+    /// let mut crypt = Writer::with_recipients(Vec::new(), &[alice, bob]).ok().unwrap();
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap();
+    ///
+    /// let mut decrypt = Reader::with_envelope(Cursor::new(ciphertext), bob).ok().unwrap();
+    /// let mut s = String::new();
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert_eq!(s, "Hello, world!");
+    /// ```
+    pub fn with_recipients(mut sink: W, recipients: &[Key]) -> io::Result<Writer<W>> {
+        if recipients.is_empty() || recipients.len() > 255 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "wrong number of recipients",
+                                      Some(format!("need 1 to 255 recipients, not {}", recipients.len()))));
+        }
+        let data_key = Key::generate();
+        let iv = Block::generate_iv();
+
+        // The magic, the number of recipients and each recipient's
+        // wrapped data key.  The `Writer`'s own `Header`, with the iv,
+        // follows.
+        let mut header = Vec::with_capacity(4 + 1 + recipients.len() * WRAPPED_LEN);
+        header.push_all(MAGIC);
+        header.push(recipients.len() as u8);
+        for recipient in recipients.iter() {
+            header.push_all(&wrap_key(*recipient, &data_key));
+        }
+        try!(sink.write_all(&header));
+        let mut writer = Writer::new(sink, data_key, iv);
+        super::writer::set_iv_from_source(&mut writer);
+        Ok(writer)
+    }
+
+}
+
+impl<R: io::Read> Reader<io::BufReader<R>> {
+
+    /// Reads the header written by `Writer::with_envelope` or
+    /// `Writer::with_recipients` from `source`, unwraps the data key
+    /// with `key` (which may be any of the recipients' keys), and
+    /// returns a `Reader` for the rest of the stream.  Fails straight
+    /// away if `key` isn't one of them or the header has been tampered
+    /// with.
+    pub fn with_envelope<K: Into<Key>>(mut source: R, key: K) -> io::Result<Reader<io::BufReader<R>>> {
+        let key = key.into();
+        let mut start = [0u8; 5];
+        try!(super::read_header(&mut source, &mut start));
+        if &start[..4] != &MAGIC[..] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an envelope-encrypted stream", None));
+        }
+        let count = start[4] as usize;
+        let mut rest = vec![0u8; count * WRAPPED_LEN];
+        try!(super::read_header(&mut source, &mut rest));
+
+        let mut data_key = None;
+        for chunk in rest.chunks(WRAPPED_LEN) {
+            let mut wrapped = [0u8; WRAPPED_LEN];
+            wrapped.clone_from_slice(chunk);
+            if let Ok(unwrapped) = unwrap_key(key, &wrapped) {
+                data_key = Some(unwrapped);
+                break;
+            }
+        }
+        let data_key = match data_key {
+            Some(data_key) => data_key,
+            None => return Err(super::decrypt_error(false, "bad data key", "")),
+        };
+        Reader::with_header(source, data_key)
+    }
+
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    let mut writer = Writer::with_envelope(Vec::new(), [1, 2, 3, 4]).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    assert_eq!(ciphertext.len(), 5 + WRAPPED_LEN + 16 + 16);
+
+    let mut s = String::new();
+    Reader::with_envelope(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).ok().unwrap()
+        .read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "Hello, world!");
+
+    // Every stream gets its own data key.
+    let mut writer = Writer::with_envelope(Vec::new(), [1, 2, 3, 4]).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert!(writer.close().ok().unwrap()[4..] != ciphertext[4..]);
+
+    assert!(Reader::with_envelope(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 5]).is_err());
+    let mut tampered = ciphertext.clone();
+    tampered[10] ^= 1;
+    assert!(Reader::with_envelope(io::Cursor::new(&tampered[..]), [1, 2, 3, 4]).is_err());
+    assert!(Reader::with_envelope(io::Cursor::new(&ciphertext[..20]), [1, 2, 3, 4]).is_err());
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn recipients() {
+    use std::io::{Read, Write};
+
+    let keys: Vec<Key> = (0..3).map(|i| Key::new([1, 2, 3, i])).collect();
+    let mut writer = Writer::with_recipients(Vec::new(), &keys).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    assert_eq!(ciphertext.len(), 5 + 3 * WRAPPED_LEN + 16 + 16);
+    for key in keys.iter() {
+        let mut s = String::new();
+        Reader::with_envelope(io::Cursor::new(&ciphertext[..]), *key).ok().unwrap()
+            .read_to_string(&mut s).ok().unwrap();
+        assert_eq!(s, "Hello, world!");
+    }
+    assert!(Reader::with_envelope(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 3]).is_err());
+    assert!(Writer::with_recipients(Vec::new(), &[]).is_err());
+}
@@ -0,0 +1,261 @@
+use std::cmp;
+use std::io;
+
+use digest::Mac;
+
+use super::super::{Key, Block, decrypt_padded_in_place};
+use super::Writer;
+use mem::SecretVec;
+
+// The iv at the front of the stream.  The tag at the end is as long
+// as the `Mac` makes it.
+const IV_LEN: usize = 8;
+
+// Passes ciphertext through to `sink`, adding whatever it accepts to
+// the MAC on the way.
+struct DigestSink<W: io::Write, M: Mac> {
+    sink: W,
+    mac: M,
+}
+
+impl<W: io::Write, M: Mac> io::Write for DigestSink<W, M> {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.sink.write(buf));
+        self.mac.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+}
+
+/// Like `AuthenticatedWriter`, but tags the iv and ciphertext with
+/// any `digest::Mac`, for when 64-bit CMAC tags aren't enough.  Only
+/// built with the `digest` feature.  The `Mac` comes already keyed;
+/// its key must be independent of the encryption key (not the same
+/// key, and not derived from it with `tea` itself).
+///
+/// # Example:
+/// ```ignore
+/// use std::io::{Cursor, Read, Write};
+/// use hmac::{Hmac, Mac};
+/// use sha2::Sha256;
+/// use tea::io::{MacReader, MacWriter};
+///
+/// let mac_key = b"a different key, kept separately";
+/// let mac = Hmac::<Sha256>::new_from_slice(mac_key).unwrap();
+/// let mut crypt = MacWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6], mac).ok().unwrap();
+/// crypt.write_all(b"Hello, world!").ok().unwrap();
+/// let ciphertext = crypt.close().ok().unwrap();
+/// assert_eq!(ciphertext.len(), 8 + 16 + 32);
+///
+/// let mac = Hmac::<Sha256>::new_from_slice(mac_key).unwrap();
+/// let mut s = String::new();
+/// MacReader::new(Cursor::new(ciphertext), [1, 2, 3, 4], mac)
+///     .read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "Hello, world!");
+/// ```
+pub struct MacWriter<W: io::Write, M: Mac> {
+    inner: Writer<DigestSink<W, M>>,
+}
+
+impl<W: io::Write, M: Mac> MacWriter<W, M> {
+
+    /// Wraps `sink` in a `MacWriter` that will encrypt with `key`,
+    /// starting from `iv` (initialization vector), and tag the result
+    /// with `mac`.  The iv is written to `sink` right away.
+    pub fn new<K: Into<Key>, I: Into<Block>>(mut sink: W, key: K, iv: I, mut mac: M) -> io::Result<MacWriter<W, M>> {
+        let iv = iv.into();
+        try!(sink.write_all(&iv.to_be_bytes()));
+        mac.update(&iv.to_be_bytes());
+        Ok(MacWriter{
+            inner: Writer::new(DigestSink{ sink: sink, mac: mac }, key, iv).raw(),
+        })
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner.get_ref().sink
+    }
+
+    /// Writes the final padding and the tag, destroys the encrypting
+    /// wrapper, and returns the underlying `std::io::Write` object.
+    pub fn close(self) -> io::Result<W> {
+        let DigestSink{ mut sink, mac } = try!(self.inner.close());
+        try!(sink.write_all(&mac.finalize().into_bytes()));
+        try!(sink.flush());
+        Ok(sink)
+    }
+
+}
+
+impl<W: io::Write, M: Mac> io::Write for MacWriter<W, M> {
+
+    /// Encrypts the bytes in `buf` and passes them through, like
+    /// `Writer::write`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    /// Passes the flush call through to the inner `Writer`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+}
+
+/// Wraps an underlying `std::io::Read` to check and decrypt the output
+/// of a `MacWriter`, given a `Mac` keyed the same way.  Like
+/// `AuthenticatedReader`, the whole stream is read into memory and
+/// checked on the first `read`, and failures are opaque unless
+/// `verbose_errors()` is turned on.  The tag is compared by the `Mac`
+/// itself, in constant time.
+pub struct MacReader<R: io::Read, M: Mac> {
+    source: R,
+    key: Key,
+    mac: Option<M>,
+    buf: SecretVec,
+    pos: usize,
+    end: usize,
+    checked: bool,
+    failure: Option<(&'static str, &'static str)>,
+    verbose: bool,
+}
+
+impl<R: io::Read, M: Mac> MacReader<R, M> {
+
+    /// Wraps `source` in a `MacReader` that will check the tag with
+    /// `mac` and decrypt with `key`.
+    pub fn new<K: Into<Key>>(source: R, key: K, mac: M) -> MacReader<R, M> {
+        MacReader{
+            source: source,
+            key: key.into(),
+            mac: Some(mac),
+            buf: SecretVec::new(),
+            pos: 0,
+            end: 0,
+            checked: false,
+            failure: None,
+            verbose: false,
+        }
+    }
+
+    /// Makes failures say what went wrong, like
+    /// `Reader::verbose_errors`.
+    pub fn verbose_errors(mut self) -> MacReader<R, M> {
+        self.verbose = true;
+        self
+    }
+
+    /// Unwraps this `MacReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    // Reads the whole stream, checks the tag, and decrypts it in
+    // place.  Until the tag is checked, a failure (like
+    // `ErrorKind::WouldBlock` from `source`) can be retried; after
+    // that, the `Mac` is used up and the failure is remembered.
+    fn check(&mut self) -> io::Result<()> {
+        if let Some((description, detail)) = self.failure {
+            return Err(super::decrypt_error(self.verbose, description, detail));
+        }
+        let tag_len = M::output_size();
+        try!(self.source.read_to_end(&mut self.buf));
+        let len = self.buf.len();
+        if len < IV_LEN + 8 + tag_len || (len - IV_LEN - tag_len) % 8 != 0 {
+            return Err(super::decrypt_error(self.verbose, "truncated stream",
+                                            &format!("{} bytes can't be a stream with a {}-byte tag", len, tag_len)));
+        }
+        let mut mac = self.mac.take().unwrap();
+        mac.update(&self.buf[..len - tag_len]);
+        if mac.verify_slice(&self.buf[len - tag_len..]).is_err() {
+            self.failure = Some(("bad tag", "the stream was modified or the key is wrong"));
+            return self.check();
+        }
+        let mut iv = [0u8; 8];
+        iv.clone_from_slice(&self.buf[..IV_LEN]);
+        let plaintext_len = match decrypt_padded_in_place(self.key, Block::from_be_bytes(&iv),
+                                                          &mut self.buf[IV_LEN..len - tag_len]) {
+            Ok(n) => n,
+            Err(_) => {
+                self.failure = Some(("bad padding", "stream doesn't end in valid PKCS#7 padding"));
+                return self.check();
+            },
+        };
+        self.pos = IV_LEN;
+        self.end = IV_LEN + plaintext_len;
+        Ok(())
+    }
+
+}
+
+impl<R: io::Read, M: Mac> io::Read for MacReader<R, M> {
+
+    /// Copies out decrypted plaintext, reading and checking the whole
+    /// stream first if this is the first call.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.checked {
+            try!(self.check());
+            self.checked = true;
+        }
+        let n = cmp::min(buf.len(), self.end - self.pos);
+        buf[..n].clone_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::error::Error;
+    use std::io::{Read, Write};
+    use hmac::Hmac;
+    use sha2::Sha256;
+
+    let mac = || Hmac::<Sha256>::new_from_slice(b"mac key").unwrap();
+    let input: Vec<u8> = (0u8..40).collect();
+    let mut writer = MacWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6], mac()).ok().unwrap();
+    writer.write_all(&input).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    assert_eq!(ciphertext.len(), IV_LEN + 48 + 32);
+
+    let mut output = Vec::new();
+    MacReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4], mac())
+        .read_to_end(&mut output).ok().unwrap();
+    assert_eq!(output, input);
+
+    for i in 0..ciphertext.len() {
+        let mut tampered = ciphertext.clone();
+        tampered[i] ^= 1;
+        let err = MacReader::new(io::Cursor::new(tampered), [1, 2, 3, 4], mac()).verbose_errors()
+            .read_to_end(&mut Vec::new()).err().unwrap();
+        assert_eq!(err.description(), "bad tag");
+    }
+    let other = Hmac::<Sha256>::new_from_slice(b"other key").unwrap();
+    let mut reader = MacReader::new(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4], other);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    // It keeps failing, rather than looking like an empty stream.
+    assert!(reader.read(&mut [0; 8]).is_err());
+
+    // A source that isn't ready yet can be read from again.
+    struct Flaky<'a>(&'a [u8], bool);
+    impl<'a> Read for Flaky<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.1 {
+                self.1 = true;
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "not yet", None));
+            }
+            self.0.read(buf)
+        }
+    }
+    let mut reader = MacReader::new(Flaky(&ciphertext[..], false), [1, 2, 3, 4], mac());
+    assert_eq!(reader.read(&mut [0; 8]).err().unwrap().kind(), io::ErrorKind::WouldBlock);
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).ok().unwrap();
+    assert_eq!(output, input);
+}
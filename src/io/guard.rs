@@ -0,0 +1,122 @@
+use std::io;
+
+use super::super::{Key, Block};
+use super::Writer;
+use hash;
+
+// Bits in each filter per pair remembered, and how many of them each
+// pair sets.  This keeps false alarms to about 1 in 400 when full.
+const BITS_PER_PAIR: usize = 16;
+const HASHES: usize = 4;
+
+/// Remembers which (key, iv) pairs have been used recently and
+/// refuses to hand out a `Writer` for the same pair twice, to catch
+/// iv reuse (which breaks CBC's confidentiality) in tests and staging.
+///
+/// It holds two Bloom filters rather than the pairs themselves, so
+/// memory stays bounded and no keys are kept: once one filter has seen
+/// `capacity` pairs, the older one is dropped.  Any of the last
+/// `capacity` pairs is always caught, older ones may be too, and now
+/// and then a fresh pair is refused by mistake, so this belongs in
+/// testing rather than production.
+///
+/// # Example:
+/// ```
+/// use tea::io::{NonceGuard, Writer};
+///
+/// let mut guard = NonceGuard::new(1000);
+/// assert!(Writer::with_guard(Vec::new(), [1, 2, 3, 4], [5, 6], &mut guard).is_ok());
+/// assert!(Writer::with_guard(Vec::new(), [1, 2, 3, 4], [5, 7], &mut guard).is_ok());
+/// assert!(Writer::with_guard(Vec::new(), [1, 2, 3, 4], [5, 6], &mut guard).is_err());
+/// ```
+pub struct NonceGuard {
+    current: Vec<u64>,
+    previous: Vec<u64>,
+    count: usize,
+    capacity: usize,
+}
+
+impl NonceGuard {
+
+    /// Makes a guard that remembers at least the last `capacity`
+    /// pairs, using about `capacity * 4` bytes.
+    pub fn new(capacity: usize) -> NonceGuard {
+        let capacity = if capacity == 0 { 1 } else { capacity };
+        let words = (capacity * BITS_PER_PAIR + 63) / 64;
+        NonceGuard{
+            current: vec![0; words],
+            previous: vec![0; words],
+            count: 0,
+            capacity: capacity,
+        }
+    }
+
+    // The bits a pair sets in each filter.
+    fn bits(&self, key: &Key, iv: &Block) -> [usize; HASHES] {
+        let mut input = [0u8; 24];
+        input[..16].clone_from_slice(&key.to_bytes());
+        input[16..].clone_from_slice(&iv.to_be_bytes());
+        let digest = hash::digest(&input);
+        let total = self.current.len() * 64;
+        let mut bits = [0; HASHES];
+        for i in 0..HASHES {
+            let n = digest[4 * i..4 * i + 4].iter().fold(0, |n, &b| (n << 8) | b as usize);
+            bits[i] = n % total;
+        }
+        bits
+    }
+
+    /// Records that `key` and `iv` are being used, or returns an error
+    /// of kind `ErrorKind::AlreadyExists` if they've been used before.
+    pub fn check<K: Into<Key>, I: Into<Block>>(&mut self, key: K, iv: I) -> io::Result<()> {
+        let iv = iv.into();
+        let bits = self.bits(&key.into(), &iv);
+        let seen = |filter: &Vec<u64>| bits.iter().all(|&bit| filter[bit / 64] & (1 << (bit % 64)) != 0);
+        if seen(&self.current) || seen(&self.previous) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "iv reused",
+                                      Some(format!("this key has (very likely) been used with iv {:08x}{:08x} before", iv[0], iv[1]))));
+        }
+        if self.count == self.capacity {
+            self.previous = ::std::mem::replace(&mut self.current, vec![0; self.previous.len()]);
+            self.count = 0;
+        }
+        for &bit in bits.iter() {
+            self.current[bit / 64] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+}
+
+impl<W: io::Write> Writer<W> {
+
+    /// Like `new`, but first checks `key` and `iv` with `guard`, and
+    /// fails instead of encrypting with a pair it's seen before.
+    pub fn with_guard<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I, guard: &mut NonceGuard) -> io::Result<Writer<W>> {
+        let (key, iv) = (key.into(), iv.into());
+        try!(guard.check(key, iv));
+        Ok(Writer::new(sink, key, iv))
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let mut guard = NonceGuard::new(100);
+    for i in 0..100 {
+        guard.check([1, 2, 3, 4], [0, i]).ok().unwrap();
+    }
+    for i in 0..100 {
+        assert_eq!(guard.check([1, 2, 3, 4], [0, i]).err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+    }
+    guard.check([1, 2, 3, 5], [0, 0]).ok().unwrap();
+
+    // Old pairs are forgotten after two filters' worth.
+    let mut guard = NonceGuard::new(10);
+    guard.check([1, 2, 3, 4], [5, 6]).ok().unwrap();
+    for i in 0..20 {
+        guard.check([1, 2, 3, 4], [7, i]).ok().unwrap();
+    }
+    guard.check([1, 2, 3, 4], [5, 6]).ok().unwrap();
+}
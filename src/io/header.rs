@@ -0,0 +1,627 @@
+use std::io;
+
+use super::super::{Key, Block, ByteOrder};
+use super::{CtrReader, CtrWriter, Reader, Writer};
+use padding::{Bucket, Padding};
+
+// Identifies a stream that starts with a versioned `Header`.
+const MAGIC: &'static [u8; 4] = b"TEA1";
+
+// Headers asking for more PBKDF2 iterations than this are refused, so
+// a hostile header can't keep the reader busy for hours deriving a
+// key.
+const MAX_ITERATIONS: u32 = 1 << 24;
+
+/// A version of the stream format, recorded in every `Header`.  A
+/// stream written in a version this crate knows will always decrypt
+/// with later versions of the crate: new versions are only ever added,
+/// and a `Reader` picks the version up from the header it finds.
+/// `Writer::with_format` chooses which one to write, for streams that
+/// must be read by an older build.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FormatVersion {
+    /// The first format, laid out as `Header` describes but without
+    /// the flags, so the byte order and any trailers have to be set on
+    /// the `Reader` by hand.
+    V1,
+    /// Adds flags to the mode byte recording the byte order and
+    /// trailers.
+    V2,
+}
+
+impl FormatVersion {
+
+    /// The version new streams are written in unless
+    /// `Writer::with_format` says otherwise.
+    pub fn latest() -> FormatVersion {
+        FormatVersion::V2
+    }
+
+    /// The version byte that goes in the header.
+    pub fn number(&self) -> u8 {
+        match *self {
+            FormatVersion::V1 => 1,
+            FormatVersion::V2 => 2,
+        }
+    }
+
+    /// The version with the given version byte, or `None` if it's from
+    /// a newer crate than this one.
+    pub fn from_number(n: u8) -> Option<FormatVersion> {
+        match n {
+            1 => Some(FormatVersion::V1),
+            2 => Some(FormatVersion::V2),
+            _ => None,
+        }
+    }
+
+}
+
+/// The block cipher mode a stream was encrypted in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Mode {
+    /// CBC, as written by `Writer`.  This is the default.
+    Cbc,
+    /// CTR, as written by `CtrWriter`.
+    Ctr,
+}
+
+/// How the key for a stream is derived, and the parameters needed to
+/// derive it again when reading.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Kdf {
+    /// The key is supplied directly.  This is the default.
+    None,
+    /// The key comes from a password with `Key::from_password`.
+    Pbkdf2 { iterations: u32, salt: [u8; 16] },
+    /// The key comes from a password with scrypt (RFC 7914), which
+    /// needs the `scrypt` feature to derive.
+    Scrypt { log_n: u8, r: u32, p: u32, salt: [u8; 16] },
+}
+
+/// A header at the start of a stream recording everything besides the
+/// key that's needed to decrypt it: the mode, padding, key derivation
+/// parameters, byte order, trailers and iv.  It starts with a magic
+/// number and a format version, so streams written now can still be
+/// told apart and read once the format changes.  Every `Writer` that
+/// isn't `raw()` starts with one.
+///
+/// Laid out as the magic `"TEA1"`, the `FormatVersion` byte, a mode
+/// byte (0 for CBC, 1 for CTR, plus flags in the top four bits from
+/// `V2` on: 0x10 for `ByteOrder::LittleEndian` words rather than
+/// `BigEndian`, 0x20 for a CRC trailer and 0x40 for a length
+/// trailer), a padding byte (0 for PKCS#7, 1 for none, 2 for
+/// power-of-two buckets, 3 for multiple-of-n buckets followed by n as
+/// 8 bytes), a key derivation byte (0 for none, 1 for PBKDF2 followed
+/// by the iteration count as 4 bytes and a 16-byte salt, 2 for scrypt
+/// followed by log2(N) as 1 byte, r and p as 4 bytes each and a
+/// 16-byte salt), and then the 8-byte iv.  Numbers are big-endian.  `V1` headers have no flags,
+/// and read back with the host's byte order and no trailers.
+///
+/// # Example:
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use tea::io::{Header, Reader, Writer};
+///
+/// let header = Header::new([5, 6]);
+/// let mut crypt = Writer::with_header(Vec::new(), [1, 2, 3, 4], &header).ok().unwrap();
+/// crypt.write_all(b"Hello, world!").ok().unwrap();
+/// let ciphertext = crypt.close().ok().unwrap();
+///
+/// let mut decrypt = Reader::with_header(Cursor::new(ciphertext), [1, 2, 3, 4]).ok().unwrap();
+/// let mut s = String::new();
+/// decrypt.read_to_string(&mut s).ok().unwrap();
+/// assert_eq!(s, "Hello, world!");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Header {
+    pub version: FormatVersion,
+    pub mode: Mode,
+    pub padding: Padding,
+    pub kdf: Kdf,
+    pub byte_order: ByteOrder,
+    pub crc_trailer: bool,
+    pub length_trailer: bool,
+    pub iv: Block,
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+    for i in 0..4 {
+        out.push((n >> (24 - 8 * i)) as u8);
+    }
+}
+
+fn get_u32(bytes: &[u8]) -> u32 {
+    bytes[..4].iter().fold(0, |n, &b| (n << 8) | b as u32)
+}
+
+fn get_u64(bytes: &[u8]) -> u64 {
+    bytes[..8].iter().fold(0, |n, &b| (n << 8) | b as u64)
+}
+
+// The byte order `ByteOrder::Native` stands for on this host.
+pub fn host_order() -> ByteOrder {
+    if cfg!(target_endian = "big") { ByteOrder::BigEndian } else { ByteOrder::LittleEndian }
+}
+
+// Flags in the top half of the mode byte, from `V2` on.
+const FLAG_LITTLE_ENDIAN: u8 = 0x10;
+const FLAG_CRC_TRAILER: u8 = 0x20;
+const FLAG_LENGTH_TRAILER: u8 = 0x40;
+
+impl Header {
+
+    /// Makes a header in the latest format for a CBC stream with
+    /// PKCS#7 padding, a key supplied directly, the host's byte order
+    /// and no trailers, starting from `iv`.
+    pub fn new<I: Into<Block>>(iv: I) -> Header {
+        Header{
+            version: FormatVersion::latest(),
+            mode: Mode::Cbc,
+            padding: Padding::Pkcs7,
+            kdf: Kdf::None,
+            byte_order: host_order(),
+            crc_trailer: false,
+            length_trailer: false,
+            iv: iv.into(),
+        }
+    }
+
+    /// Whether the header records the byte order and trailers, which
+    /// `V1` headers leave to be set by hand.
+    pub fn has_flags(&self) -> bool {
+        self.version != FormatVersion::V1
+    }
+
+    /// Serializes the header.  `ByteOrder::Native` is recorded as the
+    /// host's byte order, and a `V1` header leaves out the byte order
+    /// and trailers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64);
+        out.push_all(MAGIC);
+        out.push(self.version.number());
+        let mut flags = 0;
+        if self.has_flags() {
+            let order = match self.byte_order {
+                ByteOrder::Native => host_order(),
+                order => order,
+            };
+            flags = (if order == ByteOrder::LittleEndian { FLAG_LITTLE_ENDIAN } else { 0 }) |
+                    (if self.crc_trailer { FLAG_CRC_TRAILER } else { 0 }) |
+                    (if self.length_trailer { FLAG_LENGTH_TRAILER } else { 0 });
+        }
+        out.push(flags | match self.mode {
+            Mode::Cbc => 0,
+            Mode::Ctr => 1,
+        });
+        match self.padding {
+            Padding::Pkcs7 => out.push(0),
+            Padding::None => out.push(1),
+            Padding::Bucket(Bucket::PowerOfTwo) => out.push(2),
+            Padding::Bucket(Bucket::Multiple(n)) => {
+                out.push(3);
+                put_u32(&mut out, (n >> 32) as u32);
+                put_u32(&mut out, n as u32);
+            },
+        }
+        match self.kdf {
+            Kdf::None => out.push(0),
+            Kdf::Pbkdf2 { iterations, salt } => {
+                out.push(1);
+                put_u32(&mut out, iterations);
+                out.push_all(&salt);
+            },
+            Kdf::Scrypt { log_n, r, p, salt } => {
+                out.push(2);
+                out.push(log_n);
+                put_u32(&mut out, r);
+                put_u32(&mut out, p);
+                out.push_all(&salt);
+            },
+        }
+        out.push_all(&self.iv.to_be_bytes());
+        out
+    }
+
+    /// Writes the header to `sink`.
+    pub fn write_to<W: io::Write>(&self, sink: &mut W) -> io::Result<()> {
+        sink.write_all(&self.to_bytes())
+    }
+
+    /// Reads a header from `source`, leaving it at the start of the
+    /// ciphertext.  Fails with `io::ErrorKind::InvalidData` if `source`
+    /// doesn't start with a header, or with one from a format version
+    /// this crate doesn't know, with the same opaque
+    /// `"decryption failed"` description either way, as a `Reader`
+    /// gives for any other bad ciphertext.
+    pub fn read_from<R: io::Read>(source: &mut R) -> io::Result<Header> {
+        read_as(source, false)
+    }
+
+    /// Derives the key from `password` as the header's `kdf` says.
+    /// Fails for `Kdf::None`, which has no password, for
+    /// `Kdf::Scrypt` without the `scrypt` feature, and for parameters
+    /// too expensive to be trusted from a stream (more than 2^24
+    /// PBKDF2 iterations, or more than 1 GiB for scrypt).
+    pub fn derive_key(&self, password: &[u8]) -> io::Result<Key> {
+        match self.kdf {
+            Kdf::None => Err(io::Error::new(io::ErrorKind::InvalidInput, "stream key isn't derived from a password", None)),
+            Kdf::Pbkdf2 { iterations, .. } if iterations > MAX_ITERATIONS => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "PBKDF2 parameters too expensive",
+                                   Some(format!("{} iterations is more than the {} allowed", iterations, MAX_ITERATIONS))))
+            },
+            Kdf::Pbkdf2 { iterations, ref salt } => Ok(Key::from_password(password, salt, iterations)),
+            #[cfg(feature = "scrypt")]
+            Kdf::Scrypt { log_n, r, p, ref salt } => super::password::scrypt_key(log_n, r, p, password, salt),
+            #[cfg(not(feature = "scrypt"))]
+            Kdf::Scrypt { .. } => Err(io::Error::new(io::ErrorKind::InvalidInput, "scrypt support not built",
+                                                     Some("rebuild with the `scrypt` feature".to_string()))),
+        }
+    }
+
+    fn expect_mode(&self, mode: Mode) -> io::Result<()> {
+        if self.mode != mode {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "wrong mode for this stream",
+                                      Some(format!("header says {:?}, not {:?}", self.mode, mode))));
+        }
+        Ok(())
+    }
+
+}
+
+// Reads a header from `source` like `Header::read_from`, with the
+// real reason for a failure in the error if `verbose` is set.
+fn read_as<R: io::Read>(source: &mut R, verbose: bool) -> io::Result<Header> {
+    let mut start = [0u8; 7];
+    try!(super::read_header(source, &mut start));
+    if &start[..4] != &MAGIC[..] {
+        return Err(super::decrypt_error(verbose, "no stream header", ""));
+    }
+    let version = match FormatVersion::from_number(start[4]) {
+        Some(version) => version,
+        None => return Err(super::decrypt_error(verbose, "unknown header version", &format!("version {}", start[4]))),
+    };
+    let flags = if version == FormatVersion::V1 { 0 } else { start[5] & 0xf0 };
+    if flags & !(FLAG_LITTLE_ENDIAN | FLAG_CRC_TRAILER | FLAG_LENGTH_TRAILER) != 0 {
+        return Err(super::decrypt_error(verbose, "unknown flags in header", &format!("flags {:#x}", flags)));
+    }
+    let mode = match start[5] & !flags {
+        0 => Mode::Cbc,
+        1 => Mode::Ctr,
+        n => return Err(super::decrypt_error(verbose, "unknown mode in header", &format!("mode {}", n))),
+    };
+    let padding = match start[6] {
+        0 => Padding::Pkcs7,
+        1 => Padding::None,
+        2 => Padding::Bucket(Bucket::PowerOfTwo),
+        3 => {
+            let mut n = [0u8; 8];
+            try!(super::read_header(source, &mut n));
+            let bucket = Bucket::Multiple(get_u64(&n));
+            if bucket.checked_padded_len(1).is_none() {
+                return Err(super::decrypt_error(verbose, "bucket size in header out of range", &format!("{:?}", bucket)));
+            }
+            Padding::Bucket(bucket)
+        },
+        n => return Err(super::decrypt_error(verbose, "unknown padding in header", &format!("padding {}", n))),
+    };
+    let mut kdf = [0u8; 1];
+    try!(super::read_header(source, &mut kdf));
+    let kdf = match kdf[0] {
+        0 => Kdf::None,
+        1 => {
+            let mut params = [0u8; 4 + 16];
+            try!(super::read_header(source, &mut params));
+            let mut salt = [0u8; 16];
+            salt.clone_from_slice(&params[4..]);
+            Kdf::Pbkdf2{ iterations: get_u32(&params), salt: salt }
+        },
+        2 => {
+            let mut params = [0u8; 1 + 4 + 4 + 16];
+            try!(super::read_header(source, &mut params));
+            let mut salt = [0u8; 16];
+            salt.clone_from_slice(&params[9..]);
+            Kdf::Scrypt{ log_n: params[0], r: get_u32(&params[1..]), p: get_u32(&params[5..]), salt: salt }
+        },
+        n => return Err(super::decrypt_error(verbose, "unknown key derivation in header", &format!("kdf {}", n))),
+    };
+    let byte_order = if version == FormatVersion::V1 {
+        host_order()
+    } else if flags & FLAG_LITTLE_ENDIAN != 0 {
+        ByteOrder::LittleEndian
+    } else {
+        ByteOrder::BigEndian
+    };
+    let mut iv = [0u8; 8];
+    try!(super::read_header(source, &mut iv));
+    Ok(Header{
+        version: version,
+        mode: mode,
+        padding: padding,
+        kdf: kdf,
+        byte_order: byte_order,
+        crc_trailer: flags & FLAG_CRC_TRAILER != 0,
+        length_trailer: flags & FLAG_LENGTH_TRAILER != 0,
+        iv: Block::from_be_bytes(&iv),
+    })
+}
+
+// Reads the header a `Writer` starts a stream with, unless it's
+// `raw()`, and checks that it's for CBC.
+pub fn read_cbc<R: io::Read>(source: &mut R, verbose: bool) -> io::Result<Header> {
+    let header = try!(read_as(source, verbose));
+    try!(header.expect_mode(Mode::Cbc));
+    Ok(header)
+}
+
+// CTR streams have nowhere to put trailers.
+fn expect_no_trailers(header: &Header) -> io::Result<()> {
+    if header.crc_trailer || header.length_trailer {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "trailers aren't supported in CTR mode", None));
+    }
+    Ok(())
+}
+
+// A `raw()` `Reader` for the stream after `header`, set up as it says.
+fn reader_after<R: io::Read>(source: R, key: Key, header: &Header) -> Reader<io::BufReader<R>> {
+    let mut reader = Reader::new(source, key, header.iv).padding(header.padding).raw();
+    if header.has_flags() {
+        reader = reader.byte_order(header.byte_order);
+        if header.crc_trailer {
+            reader = reader.crc_trailer();
+        }
+        if header.length_trailer {
+            reader = reader.length_trailer();
+        }
+    }
+    reader
+}
+
+impl<W: io::Write> Writer<W> {
+
+    /// Writes `header` to `sink`, and returns a `Writer` that encrypts
+    /// the rest of the stream with `key` and the header's iv, padding,
+    /// byte order and trailers.  The header's mode must be
+    /// `Mode::Cbc`.  For a password-derived key, pass
+    /// `header.derive_key(password)`.
+    pub fn with_header<K: Into<Key>>(mut sink: W, key: K, header: &Header) -> io::Result<Writer<W>> {
+        try!(header.expect_mode(Mode::Cbc));
+        try!(header.write_to(&mut sink));
+        let mut writer = Writer::new(sink, key, header.iv).padding(header.padding).raw();
+        if header.has_flags() {
+            writer = writer.byte_order(header.byte_order);
+            if header.crc_trailer {
+                writer = writer.crc_trailer();
+            }
+            if header.length_trailer {
+                writer = writer.length_trailer();
+            }
+        }
+        Ok(writer)
+    }
+
+}
+
+impl<R: io::Read> Reader<io::BufReader<R>> {
+
+    /// Reads the header written by `Writer::with_header` from `source`
+    /// and returns a `Reader` that decrypts the rest of the stream with
+    /// `key` and the iv, padding, byte order and trailers it gives.
+    pub fn with_header<K: Into<Key>>(mut source: R, key: K) -> io::Result<Reader<io::BufReader<R>>> {
+        let header = try!(Header::read_from(&mut source));
+        try!(header.expect_mode(Mode::Cbc));
+        Ok(reader_after(source, key.into(), &header))
+    }
+
+    /// Like `with_header`, but derives the key from `password` with
+    /// the parameters in the header.
+    pub fn with_header_password(mut source: R, password: &[u8]) -> io::Result<Reader<io::BufReader<R>>> {
+        let header = try!(Header::read_from(&mut source));
+        try!(header.expect_mode(Mode::Cbc));
+        let key = try!(header.derive_key(password));
+        Ok(reader_after(source, key, &header))
+    }
+
+}
+
+impl<W: io::Write> CtrWriter<W> {
+
+    /// Writes `header` to `sink`, and returns a `CtrWriter` that
+    /// encrypts the rest of the stream with `key` and the header's iv
+    /// and byte order.  The header's mode must be `Mode::Ctr`, with no
+    /// trailers.  Offsets for seeking start after the header.
+    pub fn with_header<K: Into<Key>>(mut sink: W, key: K, header: &Header) -> io::Result<CtrWriter<W>> {
+        try!(header.expect_mode(Mode::Ctr));
+        try!(expect_no_trailers(header));
+        try!(header.write_to(&mut sink));
+        let writer = CtrWriter::new(sink, key, header.iv);
+        Ok(if header.has_flags() { writer.byte_order(header.byte_order) } else { writer })
+    }
+
+}
+
+impl<R: io::Read> CtrReader<R> {
+
+    /// Reads the header written by `CtrWriter::with_header` from
+    /// `source` and returns a `CtrReader` that decrypts the rest of the
+    /// stream with `key` and the iv and byte order it gives.
+    pub fn with_header<K: Into<Key>>(mut source: R, key: K) -> io::Result<CtrReader<R>> {
+        let header = try!(Header::read_from(&mut source));
+        try!(header.expect_mode(Mode::Ctr));
+        try!(expect_no_trailers(&header));
+        let reader = CtrReader::new(source, key, header.iv);
+        Ok(if header.has_flags() { reader.byte_order(header.byte_order) } else { reader })
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let mut headers = vec![Header::new([5, 6])];
+    for &padding in [Padding::None, Padding::Bucket(Bucket::PowerOfTwo),
+                     Padding::Bucket(Bucket::Multiple(1 << 40))].iter() {
+        headers.push(Header{ padding: padding, ..Header::new([5, 6]) });
+    }
+    headers.push(Header{ mode: Mode::Ctr, ..Header::new([5, 6]) });
+    headers.push(Header{ kdf: Kdf::Pbkdf2{ iterations: 1000, salt: [7; 16] }, ..Header::new([5, 6]) });
+    headers.push(Header{ kdf: Kdf::Scrypt{ log_n: 10, r: 8, p: 1, salt: [7; 16] }, ..Header::new([5, 6]) });
+    for &order in [ByteOrder::BigEndian, ByteOrder::LittleEndian].iter() {
+        headers.push(Header{ byte_order: order, crc_trailer: true, ..Header::new([5, 6]) });
+        headers.push(Header{ byte_order: order, length_trailer: true, ..Header::new([5, 6]) });
+    }
+    headers.push(Header{ version: FormatVersion::V1, ..Header::new([5, 6]) });
+    for header in headers.iter() {
+        let mut bytes = header.to_bytes();
+        bytes.push_all(b"rest");
+        let mut source = io::Cursor::new(bytes.clone());
+        assert_eq!(Header::read_from(&mut source).ok().unwrap(), *header);
+        assert_eq!(source.position() as usize, bytes.len() - 4);
+        for len in 0..bytes.len() - 4 {
+            assert!(Header::read_from(&mut io::Cursor::new(&bytes[..len])).is_err());
+        }
+    }
+
+    // A V1 header has nowhere to record the byte order or trailers.
+    let old = Header{ version: FormatVersion::V1, byte_order: ByteOrder::BigEndian, crc_trailer: true, ..Header::new([5, 6]) };
+    assert_eq!(old.to_bytes(), Header{ version: FormatVersion::V1, ..Header::new([5, 6]) }.to_bytes());
+    let native = Header{ byte_order: ByteOrder::Native, ..Header::new([5, 6]) };
+    assert_eq!(Header::read_from(&mut io::Cursor::new(native.to_bytes())).ok().unwrap(), Header::new([5, 6]));
+
+    // Whatever's wrong with a header, the error doesn't say what,
+    // unless the `Reader` asks for verbose errors.
+    let mut bad = Vec::new();
+    for &(at, byte) in [(0, b'X'), (4, 3), (5, 0x80), (6, 9)].iter() {
+        let mut bytes = Header::new([5, 6]).to_bytes();
+        bytes[at] = byte;
+        bad.push(bytes);
+    }
+    let mut v1_flags = Header{ version: FormatVersion::V1, ..Header::new([5, 6]) }.to_bytes();
+    v1_flags[5] = FLAG_CRC_TRAILER;
+    bad.push(v1_flags);
+    for bytes in bad.iter() {
+        let err = Header::read_from(&mut io::Cursor::new(&bytes[..])).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), super::decrypt_error(false, "", "").to_string());
+        assert!(read_as(&mut io::Cursor::new(&bytes[..]), true).err().unwrap().to_string() != err.to_string());
+    }
+
+    let huge = Header{ padding: Padding::Bucket(Bucket::Multiple(!0)), ..Header::new([5, 6]) }.to_bytes();
+    assert!(Header::read_from(&mut io::Cursor::new(huge)).is_err());
+}
+
+#[test]
+fn streams() {
+    use std::io::{Read, Write};
+
+    let header = Header{ padding: Padding::Bucket(Bucket::PowerOfTwo),
+                         kdf: Kdf::Pbkdf2{ iterations: 10, salt: [7; 16] },
+                         ..Header::new([5, 6]) };
+    let key = header.derive_key(b"hunter2").ok().unwrap();
+    let mut writer = Writer::with_header(Vec::new(), key, &header).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    assert_eq!(ciphertext.len(), header.to_bytes().len() + 16);
+
+    let mut s = String::new();
+    Reader::with_header_password(io::Cursor::new(&ciphertext[..]), b"hunter2").ok().unwrap()
+        .read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "Hello, world!");
+    assert!(CtrReader::with_header(io::Cursor::new(&ciphertext[..]), key).is_err());
+
+    let greedy = Header{ kdf: Kdf::Pbkdf2{ iterations: !0, salt: [7; 16] }, ..Header::new([5, 6]) };
+    let err = Reader::with_header_password(io::Cursor::new(greedy.to_bytes()), b"hunter2").err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let header = Header{ mode: Mode::Ctr, ..Header::new([5, 6]) };
+    let mut writer = CtrWriter::with_header(Vec::new(), [1, 2, 3, 4], &header).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.into_inner();
+    let mut s = String::new();
+    CtrReader::with_header(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).ok().unwrap()
+        .read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "Hello, world!");
+    assert!(Reader::with_header(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).is_err());
+}
+
+// Streams written by earlier releases, which every later release must
+// still read and write byte for byte.  Never change these; add new ones
+// when the format changes.
+#[test]
+fn golden() {
+    use std::io::{Read, Write};
+
+    let fixtures: [(ByteOrder, Padding, &[u8]); 2] = [
+        // The default: the host's byte order, written on a little-endian
+        // host, and PKCS#7 padding.
+        (ByteOrder::Native, Padding::Pkcs7,
+         &[0x54, 0x45, 0x41, 0x31, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06,
+           0x62, 0x9c, 0x61, 0xc7, 0x3c, 0xa5, 0xd4, 0x65, 0x9e, 0x44, 0xc4, 0x05, 0x74, 0xff, 0xac, 0x77]),
+        (ByteOrder::BigEndian, Padding::Bucket(Bucket::Multiple(32)),
+         &[0x54, 0x45, 0x41, 0x31, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00,
+           0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06, 0x5e, 0xaa, 0x0b, 0xb7, 0x8d, 0xe3, 0xa6, 0x78,
+           0xa5, 0x81, 0xa4, 0x35, 0xe9, 0x09, 0x66, 0x65, 0x2c, 0xc2, 0x9b, 0xd4, 0x6e, 0x82, 0x46, 0xef,
+           0x2c, 0x39, 0x2e, 0x4f, 0x5d, 0x79, 0x7e, 0x8d]),
+    ];
+    for &(order, padding, ciphertext) in fixtures.iter() {
+        if order == ByteOrder::Native && cfg!(target_endian = "big") {
+            continue;
+        }
+        let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).byte_order(order).padding(padding)
+            .with_format(FormatVersion::V1);
+        writer.write_all(b"Hello, world!").ok().unwrap();
+        assert_eq!(writer.close().ok().unwrap(), ciphertext);
+
+        let mut reader = Reader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]).byte_order(order).padding(padding);
+        assert_eq!(reader.format_version(), None);
+        let mut s = String::new();
+        reader.read_to_string(&mut s).ok().unwrap();
+        assert_eq!(s, "Hello, world!");
+        assert_eq!(reader.format_version(), Some(FormatVersion::V1));
+    }
+
+    // From V2 on, the header says which byte order and trailers the
+    // stream uses, so the `Reader` needs to be told nothing.
+    let v2: &[u8] = &[0x54, 0x45, 0x41, 0x31, 0x02, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06,
+                    0x5e, 0xaa, 0x0b, 0xb7, 0x8d, 0xe3, 0xa6, 0x78, 0x0f, 0x2d, 0x80, 0xa8, 0xd8, 0x80, 0xa8, 0xd5,
+                    0xa3, 0xfa, 0x90, 0xf8, 0x72, 0x6a, 0xf3, 0xd9, 0x70, 0x88, 0x1b, 0xfc, 0x84, 0x8e, 0x55, 0x60];
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).byte_order(ByteOrder::BigEndian)
+        .crc_trailer().length_trailer().with_format(FormatVersion::V2);
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert_eq!(writer.close().ok().unwrap(), v2);
+    let mut reader = Reader::new(io::Cursor::new(v2), [1, 2, 3, 4], [5, 6]);
+    let mut s = String::new();
+    reader.read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "Hello, world!");
+    assert_eq!(reader.format_version(), Some(FormatVersion::V2));
+
+    for n in 0..256 {
+        let version = FormatVersion::from_number(n as u8);
+        assert_eq!(version.map(|version| version.number()), if n == 1 || n == 2 { Some(n as u8) } else { None });
+    }
+    assert_eq!(FormatVersion::latest(), FormatVersion::V2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    use serde_json;
+    use padding::Bucket;
+
+    let header = Header{
+        version: FormatVersion::V1,
+        mode: Mode::Ctr,
+        padding: Padding::Bucket(Bucket::Multiple(64)),
+        kdf: Kdf::Pbkdf2 { iterations: 10000, salt: [7; 16] },
+        byte_order: ByteOrder::BigEndian,
+        crc_trailer: false,
+        length_trailer: true,
+        iv: Block::new([5, 6]),
+    };
+    let json = serde_json::to_string(&header).ok().unwrap();
+    assert_eq!(serde_json::from_str::<Header>(&json).ok().unwrap(), header);
+    assert!(serde_json::from_str::<Header>("{\"mode\": \"Cbc\"}").is_err());
+}
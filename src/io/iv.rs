@@ -0,0 +1,287 @@
+use std::io;
+
+#[cfg(feature = "rand")]
+use rand::rngs::OsRng;
+#[cfg(feature = "rand")]
+use rand::RngCore;
+
+use super::super::{Key, Block};
+use super::{Reader, Writer};
+use cipher;
+
+/// Hands out ivs for one key, never the same one twice.  Keep one
+/// around for as long as the key, and let it pick the iv for every
+/// stream with `Writer::with_iv_source`, instead of tracking ivs by
+/// hand.
+pub trait IvSource {
+
+    /// Returns the next iv, or an error if there are none left.
+    fn next_iv(&mut self) -> io::Result<Block>;
+
+}
+
+/// An `IvSource` counting up from a starting point.  Each count is
+/// enciphered (under a key derived from the one given) before it's
+/// used, since CBC needs ivs that can't be predicted as well as ones
+/// that don't repeat (NIST SP 800-38A, appendix C).  Save `position()`
+/// somewhere durable and start from it next time, or ivs will repeat.
+///
+/// # Example:
+/// ```
+/// use tea::io::{CounterIv, IvSource};
+///
+/// let mut ivs = CounterIv::new([1, 2, 3, 4], 0);
+/// let first = ivs.next_iv().ok().unwrap();
+/// assert!(ivs.next_iv().ok().unwrap() != first);
+/// assert_eq!(ivs.position(), 2);
+/// ```
+pub struct CounterIv {
+    key: Key,
+    next: u64,
+}
+
+impl CounterIv {
+
+    /// Makes a counter for ivs to use with `key`, starting at `start`.
+    pub fn new<K: Into<Key>>(key: K, start: u64) -> CounterIv {
+        CounterIv{
+            key: key.into().derive_subkey(b"tea iv counter"),
+            next: start,
+        }
+    }
+
+    /// The count the next iv will come from; this is what to start
+    /// from next time.
+    pub fn position(&self) -> u64 {
+        self.next
+    }
+
+}
+
+impl IvSource for CounterIv {
+
+    /// Fails once the count reaches 2^64 - 1.
+    fn next_iv(&mut self) -> io::Result<Block> {
+        let n = self.next;
+        if n == !0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "iv counter exhausted", None));
+        }
+        self.next = n + 1;
+        Ok(cipher::encipher(&self.key, &Block::from(n)))
+    }
+
+}
+
+/// An `IvSource` that makes every iv at random, from the operating
+/// system.  With 64-bit ivs, expect a repeat after about 2^32 of them.
+/// Only built with the `rand` feature.
+#[cfg(feature = "rand")]
+pub struct RandomIv;
+
+#[cfg(feature = "rand")]
+impl IvSource for RandomIv {
+
+    fn next_iv(&mut self) -> io::Result<Block> {
+        Ok(Block::from(OsRng.next_u64()))
+    }
+
+}
+
+/// Rules a `Writer`'s iv has to follow, enforced with
+/// `Writer::iv_policy`.  Build one policy for the whole application
+/// and hand it to every `Writer`, so the rules live in one place
+/// instead of at every call site.  The default allows any iv.
+///
+/// # Example:
+/// ```
+/// use tea::io::IvPolicy;
+///
+/// let policy = IvPolicy::new().reject_zero().require_source();
+/// assert!(policy.check(&[0, 0].into(), true).is_err());
+/// assert!(policy.check(&[5, 6].into(), false).is_err());
+/// assert!(policy.check(&[5, 6].into(), true).is_ok());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct IvPolicy {
+    reject_zero: bool,
+    require_source: bool,
+}
+
+impl IvPolicy {
+
+    /// Makes a policy that allows any iv.
+    pub fn new() -> IvPolicy {
+        IvPolicy::default()
+    }
+
+    /// Rejects the all-zero iv, which usually means nobody picked one.
+    pub fn reject_zero(mut self) -> IvPolicy {
+        self.reject_zero = true;
+        self
+    }
+
+    /// Rejects ivs that weren't handed out by an `IvSource` through
+    /// `Writer::with_iv_source` or `Writer::new_with_random_iv`.  The
+    /// fresh random ivs `Writer::with_envelope` and
+    /// `Writer::with_password` pick for themselves count as well.
+    pub fn require_source(mut self) -> IvPolicy {
+        self.require_source = true;
+        self
+    }
+
+    /// Checks `iv`, given whether it came from an `IvSource`, failing
+    /// with `ErrorKind::InvalidInput` if it breaks the rules.
+    pub fn check(&self, iv: &Block, from_source: bool) -> io::Result<()> {
+        if self.reject_zero && *iv == Block::default() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "iv rejected by policy",
+                                      Some("the iv is all zeroes".to_string())));
+        }
+        if self.require_source && !from_source {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "iv rejected by policy",
+                                      Some("the iv didn't come from an IvSource".to_string())));
+        }
+        Ok(())
+    }
+
+}
+
+impl<W: io::Write> Writer<W> {
+
+    /// Takes the next iv from `ivs` and returns a `Writer` that
+    /// encrypts with it, recording it in the stream's `Header` (like
+    /// `new_with_random_iv`).  `Reader::new_from_stream` reads it
+    /// back.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::Write;
+    /// use tea::io::{CounterIv, Writer};
+    ///
+    /// let mut ivs = CounterIv::new([1, 2, 3, 4], 0);
+    /// for message in ["one", "two", "three"].iter() {
+    ///     let mut crypt = Writer::with_iv_source(Vec::new(), [1, 2, 3, 4], &mut ivs).ok().unwrap();
+    ///     crypt.write_all(message.as_bytes()).ok().unwrap();
+    ///     crypt.close().ok().unwrap();
+    /// }
+    /// ```
+    pub fn with_iv_source<K: Into<Key>, S: IvSource + ?Sized>(sink: W, key: K, ivs: &mut S) -> io::Result<Writer<W>> {
+        let iv = try!(ivs.next_iv());
+        let mut writer = Writer::new(sink, key, iv);
+        super::writer::set_iv_from_source(&mut writer);
+        Ok(writer)
+    }
+
+}
+
+#[cfg(feature = "rand")]
+impl<W: io::Write> Writer<W> {
+
+    /// Makes a new random iv and returns a `Writer` that encrypts with
+    /// it, recording it in the stream's `Header` (as `tea::fs` does).
+    /// `Reader::new_from_stream` reads it back, so
+    /// there's no iv to keep track of, and no way to reuse one by
+    /// accident.  Only built with the `rand` feature.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{Reader, Writer};
+    ///
+    /// let mut crypt = Writer::new_with_random_iv(Vec::new(), [1, 2, 3, 4]).ok().unwrap();
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap();
+    /// assert_eq!(ciphertext.len(), 16 + 16);
+    ///
+    /// let mut decrypt = Reader::new_from_stream(Cursor::new(ciphertext), [1, 2, 3, 4]).ok().unwrap();
+    /// let mut s = String::new();
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert_eq!(s, "Hello, world!");
+    /// ```
+    pub fn new_with_random_iv<K: Into<Key>>(sink: W, key: K) -> io::Result<Writer<W>> {
+        Writer::with_iv_source(sink, key, &mut RandomIv)
+    }
+
+}
+
+impl<R: io::Read> Reader<io::BufReader<R>> {
+
+    /// Reads the iv from the `Header` at the start of `source`, as
+    /// written by `Writer::new_with_random_iv`, and returns a `Reader`
+    /// that decrypts the rest with `key`.
+    pub fn new_from_stream<K: Into<Key>>(source: R, key: K) -> io::Result<Reader<io::BufReader<R>>> {
+        Reader::with_header(source, key)
+    }
+
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn random_iv() {
+    use std::io::{Read, Write};
+
+    let mut writer = Writer::new_with_random_iv(Vec::new(), [1, 2, 3, 4]).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+
+    let mut writer = Writer::new_with_random_iv(Vec::new(), [1, 2, 3, 4]).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert!(writer.close().ok().unwrap() != ciphertext);
+
+    let mut s = String::new();
+    Reader::new_from_stream(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).ok().unwrap()
+        .read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "Hello, world!");
+    assert!(Reader::new_from_stream(io::Cursor::new(&ciphertext[..7]), [1, 2, 3, 4]).is_err());
+}
+
+#[test]
+fn counter() {
+    use std::io::Read;
+
+    let mut ivs = CounterIv::new([1, 2, 3, 4], 5);
+    let first = ivs.next_iv().ok().unwrap();
+    assert_eq!(CounterIv::new([1, 2, 3, 4], 5).next_iv().ok().unwrap(), first);
+    assert!(CounterIv::new([1, 2, 3, 5], 5).next_iv().ok().unwrap() != first);
+    assert!(ivs.next_iv().ok().unwrap() != first);
+    assert_eq!(ivs.position(), 7);
+
+    let writer = Writer::with_iv_source(Vec::new(), [1, 2, 3, 4], &mut ivs).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    let mut reader = Reader::new_from_stream(io::Cursor::new(&ciphertext[..]), [1, 2, 3, 4]).ok().unwrap();
+    assert_eq!(reader.read_to_end(&mut Vec::new()).ok().unwrap(), 0);
+
+    let mut last = CounterIv::new([1, 2, 3, 4], !0 - 1);
+    assert!(last.next_iv().is_ok());
+    assert!(last.next_iv().is_err());
+}
+
+#[test]
+fn policy() {
+    use std::io::Write;
+
+    let policy = IvPolicy::new().reject_zero().require_source();
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).iv_policy(policy);
+    assert_eq!(writer.write(b"abc").err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    assert!(writer.close().is_err());
+
+    let writer = Writer::new(Vec::new(), [1, 2, 3, 4], [0, 0]).iv_policy(IvPolicy::new().reject_zero());
+    assert!(writer.close().is_err());
+    let writer = Writer::new(Vec::new(), [1, 2, 3, 4], [0, 0]).iv_policy(IvPolicy::new());
+    assert_eq!(writer.close().ok().unwrap().len(), 24);
+
+    let mut ivs = CounterIv::new([1, 2, 3, 4], 0);
+    let mut writer = Writer::with_iv_source(Vec::new(), [1, 2, 3, 4], &mut ivs).ok().unwrap().iv_policy(policy);
+    writer.write_all(b"abc").ok().unwrap();
+    assert_eq!(writer.close().ok().unwrap().len(), 24);
+
+    // A refused stream leaves nothing in the sink, iv included.
+    let mut writer = Writer::with_iv_source(Vec::new(), [1, 2, 3, 4], &mut ivs).ok().unwrap()
+        .iv_policy(IvPolicy::new().reject_zero()).key_usage_limit(0);
+    assert!(writer.write(b"abc").is_err());
+    writer.flush().ok().unwrap();
+    assert!(writer.get_ref().is_empty());
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).iv_policy(policy);
+    assert!(writer.write(b"abc").is_err());
+    writer.flush().ok().unwrap();
+    assert!(writer.get_ref().is_empty());
+}
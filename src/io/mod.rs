@@ -1,11 +1,15 @@
 //! Bundles the `cipher` module into a CBC-mode block cipher, which
 //! wraps and implements the `std::io::Read` and `std::io::Write`
-//! interfaces.
+//! interfaces.  A `Writer` starts its stream with a versioned `Header`
+//! recording the mode, padding, byte order, trailers and iv, which the
+//! `Reader` reads them back from, unless both are `raw()`.  The other types here build on
+//! the same pair: CTR mode, authenticated and record framing, armor,
+//! and helpers for copying whole streams.
 //!
 //! # Example:
 //! ```
 //! use std::fs;
-//! use std::io::{BufReader, Read, Write};
+//! use std::io::{Read, Write};
 //! use tea::io::{Reader, Writer};
 //!
 //! let tmp_dir = fs::TempDir::new("tea-reader-test-0").ok().unwrap();
@@ -19,15 +23,82 @@
 //! }
 //! {
 //!     let f = fs::File::open(&filename).ok().unwrap();
-//!     let mut decrypt_f = Reader::new(BufReader::new(f), [1, 2, 3, 4], [5, 6]);
+//!     let mut decrypt_f = Reader::new(f, [1, 2, 3, 4], [5, 6]);
 //!     let mut s: String = "".to_string();
 //!     decrypt_f.read_to_string(&mut s).ok().unwrap();
 //!     assert_eq!("Hello, world!", s);
 //! }
 //! ```
 
+use std::io;
+
+pub use self::armor::{ArmorReader, ArmorWriter, EncryptedBlob, Message};
+pub use self::auth::{AuthenticatedReader, AuthenticatedWriter};
+pub use self::chunked::{Checksum, ChunkedReader, ChunkedWriter};
+pub use self::compat::Compat;
+pub use self::copy::{encrypt_copy, decrypt_copy, encrypt_copy_pipelined, decrypt_copy_pipelined};
+pub use self::ctr::{CtrReader, CtrWriter, ctr_encrypt_to, ctr_encrypt_copy};
+#[cfg(feature = "digest")]
+pub use self::external::{MacReader, MacWriter};
+pub use self::guard::NonceGuard;
+pub use self::header::{FormatVersion, Header, Kdf, Mode};
+pub use self::iv::{IvSource, CounterIv, IvPolicy};
+#[cfg(feature = "rand")]
+pub use self::iv::RandomIv;
+#[cfg(feature = "scrypt")]
+pub use self::password::ScryptParams;
 pub use self::reader::Reader;
+pub use self::record::{RecordReader, RecordWriter};
+pub use self::reencrypt::ReEncryptor;
+pub use self::sized::{SizedReader, SizedWriter};
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub use self::uring::{encrypt_copy_uring, decrypt_copy_uring};
 pub use self::writer::Writer;
 
+mod armor;
+mod auth;
+mod chunked;
+mod compat;
+mod copy;
+mod ctr;
+mod envelope;
+#[cfg(feature = "digest")]
+mod external;
+mod guard;
+mod header;
+mod iv;
+#[cfg(feature = "scrypt")]
+mod password;
 mod reader;
+mod record;
+mod reencrypt;
+mod sized;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
 mod writer;
+
+// Builds the error returned when ciphertext can't be decrypted.
+// Unless `verbose` is set, every kind of failure looks the same, so
+// that a server passing errors back to its clients doesn't turn into
+// a padding oracle.
+fn decrypt_error(verbose: bool, description: &'static str, detail: &str) -> io::Error {
+    if verbose {
+        io::Error::new(io::ErrorKind::InvalidData, description, Some(detail.to_string()))
+    } else {
+        io::Error::new(io::ErrorKind::InvalidData, "decryption failed", None)
+    }
+}
+
+// Fills `buf` from `source`, failing if the stream ends first.
+fn read_header<R: io::Read>(source: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match source.read(&mut buf[filled..]) {
+            Ok(0) => return Err(decrypt_error(false, "truncated header", "")),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
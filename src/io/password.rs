@@ -0,0 +1,177 @@
+use std::io;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use scrypt;
+
+use super::super::Key;
+use super::{Header, Kdf, Reader, Writer};
+use mem;
+
+// Streams asking for more memory than this to derive their key are
+// refused, so a hostile header can't make the reader allocate
+// unbounded amounts.
+const MAX_MEMORY: u64 = 1 << 30;
+
+/// The cost parameters for deriving a key from a password with scrypt
+/// (RFC 7914).  They're stored in the stream's `Header` as a
+/// `Kdf::Scrypt`, so the reading side doesn't need to know them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScryptParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+impl ScryptParams {
+
+    /// Sets the CPU/memory cost to 2^`log_n`, with block size `r` and
+    /// parallelism `p`.  Deriving a key takes about 128 * `r` * 2^`log_n`
+    /// bytes of memory, and neither a `Writer` nor a `Reader` will go
+    /// above 1 GiB.
+    pub fn new(log_n: u8, r: u32, p: u32) -> ScryptParams {
+        ScryptParams{
+            log_n: log_n,
+            r: r,
+            p: p,
+        }
+    }
+
+    // How many bytes deriving a key takes, or `None` if it's too many
+    // to count in a `u64`.  The parameters can come from a hostile
+    // header, so nothing here may overflow.
+    fn memory(&self) -> Option<u64> {
+        1u64.checked_shl(self.log_n as u32)
+            .and_then(|n| n.checked_add(self.p as u64))
+            .and_then(|n| n.checked_mul(self.r as u64))
+            .and_then(|n| n.checked_mul(128))
+    }
+
+    fn derive(&self, password: &[u8], salt: &[u8]) -> io::Result<Key> {
+        let params = match scrypt::Params::new(self.log_n, self.r, self.p, 16) {
+            Ok(params) => params,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid scrypt parameters",
+                                                Some(format!("{:?}", self)))),
+        };
+        let mut bytes = [0u8; 16];
+        scrypt::scrypt(password, salt, &params, &mut bytes).ok().unwrap();
+        let key = Key::from_bytes(&bytes);
+        mem::wipe(&mut bytes);
+        Ok(key)
+    }
+
+}
+
+impl Default for ScryptParams {
+
+    /// N = 2^15, r = 8 and p = 1, which takes 32 MiB and a fraction
+    /// of a second.
+    fn default() -> ScryptParams {
+        ScryptParams::new(15, 8, 1)
+    }
+
+}
+
+// Derives a key with scrypt from parameters read out of a stream
+// header, refusing any that would take too much memory.
+pub fn scrypt_key(log_n: u8, r: u32, p: u32, password: &[u8], salt: &[u8]) -> io::Result<Key> {
+    let params = ScryptParams::new(log_n, r, p);
+    if params.memory().map_or(true, |memory| memory > MAX_MEMORY) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "scrypt parameters too expensive",
+                                  Some(format!("{:?} would need more than {} bytes", params, MAX_MEMORY))));
+    }
+    params.derive(password, salt)
+}
+
+impl<W: io::Write> Writer<W> {
+
+    /// Derives the key from `password` with scrypt, using a new random
+    /// salt and iv, and starts the stream with a `Header` recording
+    /// them as a `Kdf::Scrypt` along with `params`.
+    /// `Reader::with_password` reads the header back and derives the
+    /// same key.  Only built with the `scrypt` feature.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{Reader, ScryptParams, Writer};
+    ///
+    /// let params = ScryptParams::new(10, 8, 1);
+    /// let mut crypt = Writer::with_password(Vec::new(), b"hunter2", params).ok().unwrap();
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap();
+    ///
+    /// let mut decrypt = Reader::with_password(Cursor::new(ciphertext), b"hunter2").ok().unwrap();
+    /// let mut s = String::new();
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert_eq!(s, "Hello, world!");
+    /// ```
+    pub fn with_password(sink: W, password: &[u8], params: ScryptParams) -> io::Result<Writer<W>> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let kdf = Kdf::Scrypt{ log_n: params.log_n, r: params.r, p: params.p, salt: salt };
+        let header = Header{ kdf: kdf, ..Header::new(OsRng.next_u64()) };
+        let key = try!(header.derive_key(password));
+        let mut writer = try!(Writer::with_header(sink, key, &header));
+        super::writer::set_iv_from_source(&mut writer);
+        Ok(writer)
+    }
+
+}
+
+impl<R: io::Read> Reader<io::BufReader<R>> {
+
+    /// Reads the `Header` written by `Writer::with_password` from
+    /// `source`, derives the key from `password` with the parameters
+    /// it gives, and returns a `Reader` for the rest of the stream.
+    /// This is `with_header_password`, kept under the name that pairs
+    /// with the `Writer`.  Only built with the `scrypt` feature.
+    pub fn with_password(source: R, password: &[u8]) -> io::Result<Reader<io::BufReader<R>>> {
+        Reader::with_header_password(source, password)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    let params = ScryptParams::new(4, 8, 1);
+    let mut writer = Writer::with_password(Vec::new(), b"hunter2", params).ok().unwrap();
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap();
+    let header = Header::read_from(&mut &ciphertext[..]).ok().unwrap();
+    match header.kdf {
+        Kdf::Scrypt { log_n: 4, r: 8, p: 1, .. } => {},
+        kdf => panic!("unexpected kdf {:?}", kdf),
+    }
+    assert_eq!(ciphertext.len(), header.to_bytes().len() + 16);
+
+    let mut s = String::new();
+    Reader::with_password(io::Cursor::new(&ciphertext[..]), b"hunter2").ok().unwrap()
+        .read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "Hello, world!");
+
+    let mut wrong = Reader::with_password(io::Cursor::new(&ciphertext[..]), b"hunter3").ok().unwrap();
+    assert!(wrong.read_to_string(&mut String::new()).is_err());
+
+    let greedy = Header{ kdf: Kdf::Scrypt{ log_n: 40, r: 8, p: 1, salt: [7; 16] }, ..header };
+    let mut greedy = greedy.to_bytes();
+    greedy.push_all(&ciphertext[header.to_bytes().len()..]);
+    assert!(Reader::with_password(io::Cursor::new(&greedy[..]), b"hunter2").is_err());
+    assert!(Reader::with_password(io::Cursor::new(&ciphertext[1..]), b"hunter2").is_err());
+}
+
+#[test]
+fn hostile_header() {
+    // Parameters whose cost overflows a u64 must be refused like any
+    // other that's too expensive, not panic or wrap round to cheap.
+    for &(log_n, r, p) in [(57, 4, 1), (63, 1, !0), (64, 1, 1), (255, 8, 1), (10, !0, !0)].iter() {
+        let header = Header{ kdf: Kdf::Scrypt{ log_n: log_n, r: r, p: p, salt: [7; 16] }, ..Header::new([5, 6]) };
+        let mut stream = header.to_bytes();
+        stream.push_all(&[0; 16]);
+        let err = Reader::with_password(io::Cursor::new(&stream[..]), b"hunter2").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,51 +1,637 @@
+use std::cmp;
+use std::fmt;
+use std::fs;
 use std::io;
 
-use super::super::{Key, Block};
-use cipher;
-use mem;
+use super::super::{Key, Block, ByteOrder};
+use super::FormatVersion;
+use super::header;
+use buffer;
+use cipher::Cipher;
+use crc::Crc32;
+use ct;
+use mem::{self, SecretArray, SecretVec};
+use padding::{pkcs7, Check, Padding};
 
-fn decrypt_chunk(key: &Key, prev: &mut Block, chunk: &[u8]) -> [u8; 8] {
-    let input_block = mem::read_block(chunk);
-    let mut decrypted_block = cipher::decipher(&key, input_block);
+fn decrypt_chunk(cipher: &Cipher, prev: &mut Block, chunk: &[u8], order: ByteOrder) -> [u8; 8] {
+    let input_block = mem::read_block_as(chunk, order);
+    let mut decrypted_block = cipher.decipher(&input_block);
     decrypted_block[0] ^= prev[0];
     decrypted_block[1] ^= prev[1];
-    *prev = *input_block;
-    *mem::write_block(&decrypted_block)
+    *prev = input_block;
+    mem::write_block_as(&decrypted_block, order)
 }
 
-/// Wraps an underlying `std::io::BufRead` so that bytes read get
-/// decrypted on the way through.
+// Checks the PKCS#7 padding on the final decrypted block and returns
+// the real plaintext bytes in front of it, or `None` if the padding is
+// malformed and `check` is strict.
+fn strip_padding(block: &[u8], check: Check) -> Option<&[u8]> {
+    match check {
+        Check::Strict => pkcs7::unpad(block),
+        Check::Lenient => Some(pkcs7::unpad_lenient(block)),
+    }
+}
+
+// How much plaintext the queue holds, allocated once up front; each
+// pass decrypts at most this much of what `source` has buffered.
+const QUEUE_SIZE: usize = 8 * 1024;
+
+// How much `read_to_end` grows its buffer by when it doesn't know how
+// much plaintext is coming.
+const READ_TO_END_BATCH: usize = 64 * 1024;
+
+// Lets `read_to_end` ask how much ciphertext the source has left,
+// for the sources that can say without reading it: slices, cursors
+// over them and regular files, each buffered or not.
+trait Remaining {
+    fn remaining(&mut self) -> Option<u64>;
+}
+
+impl<R: io::BufRead> Remaining for R {
+
+    default fn remaining(&mut self) -> Option<u64> {
+        None
+    }
+
+}
+
+// The same, for what a `BufReader` wraps.
+trait Unbuffered {
+    fn left(&mut self) -> Option<u64>;
+}
+
+impl<'a> Unbuffered for &'a [u8] {
+
+    fn left(&mut self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+
+}
+
+impl<T: AsRef<[u8]>> Unbuffered for io::Cursor<T> {
+
+    fn left(&mut self) -> Option<u64> {
+        Some((self.get_ref().as_ref().len() as u64).saturating_sub(self.position()))
+    }
+
+}
+
+impl Unbuffered for fs::File {
+
+    /// Only regular files have a length to go by.
+    fn left(&mut self) -> Option<u64> {
+        let metadata = match self.metadata() {
+            Ok(ref metadata) if metadata.is_file() => metadata.len(),
+            _ => return None,
+        };
+        io::Seek::seek(self, io::SeekFrom::Current(0)).ok().map(|pos| metadata.saturating_sub(pos))
+    }
+
+}
+
+impl<'a> Remaining for &'a [u8] {
+
+    fn remaining(&mut self) -> Option<u64> {
+        self.left()
+    }
+
+}
+
+impl Remaining for io::Cursor<Vec<u8>> {
+
+    fn remaining(&mut self) -> Option<u64> {
+        self.left()
+    }
+
+}
+
+impl<'a> Remaining for io::Cursor<&'a [u8]> {
+
+    fn remaining(&mut self) -> Option<u64> {
+        self.left()
+    }
+
+}
+
+impl Remaining for io::BufReader<fs::File> {
+
+    fn remaining(&mut self) -> Option<u64> {
+        let buffered = self.buffer().len() as u64;
+        self.get_mut().left().map(|left| left + buffered)
+    }
+
+}
+
+impl Remaining for io::BufReader<io::Cursor<Vec<u8>>> {
+
+    fn remaining(&mut self) -> Option<u64> {
+        let buffered = self.buffer().len() as u64;
+        self.get_mut().left().map(|left| left + buffered)
+    }
+
+}
+
+impl<'a> Remaining for io::BufReader<io::Cursor<&'a [u8]>> {
+
+    fn remaining(&mut self) -> Option<u64> {
+        let buffered = self.buffer().len() as u64;
+        self.get_mut().left().map(|left| left + buffered)
+    }
+
+}
+
+impl<'a> Remaining for io::BufReader<&'a [u8]> {
+
+    fn remaining(&mut self) -> Option<u64> {
+        let buffered = self.buffer().len() as u64;
+        self.get_mut().left().map(|left| left + buffered)
+    }
+
+}
+
+// Tracks a possible `Padding::Bucket` run (a 0x80 byte followed by
+// zeroes) at the end of the plaintext seen so far, so it can be
+// stripped without knowing the bucket size or holding back more than
+// a count of zeroes.
+struct BucketState {
+    marker: bool,
+    zeroes: u64,
+}
+
+impl BucketState {
+
+    // Appends the bytes of `block` to `out`, holding back anything
+    // that might still turn out to be padding.
+    fn push(&mut self, block: &[u8], out: &mut Vec<u8>) {
+        for &b in block {
+            if self.marker {
+                if b == 0 {
+                    self.zeroes += 1;
+                    continue;
+                }
+                out.push(0x80);
+                for _ in 0..self.zeroes {
+                    out.push(0);
+                }
+                self.marker = false;
+                self.zeroes = 0;
+            }
+            if b == 0x80 {
+                self.marker = true;
+            } else {
+                out.push(b);
+            }
+        }
+    }
+
+}
+
+// Hands a decrypted block on to the plaintext queue, holding back
+// anything that might be bucket padding.
+fn release(padding: Padding, bucket: &mut BucketState, block: &[u8], out: &mut Vec<u8>) {
+    match padding {
+        Padding::Bucket(_) => bucket.push(block, out),
+        _ => out.push_all(block),
+    }
+}
+
+/// Wraps an underlying `std::io::Read` so that bytes read get
+/// decrypted on the way through.  `Reader::new` puts a
+/// `std::io::BufReader` in front of the source; if it's already a
+/// `std::io::BufRead`, use `Reader::from_bufread` instead to avoid
+/// buffering twice.  The `Reader` is a `std::io::BufRead` itself, so
+/// `read_line()` and `lines()` work on it directly.
+///
+/// Unless it's `raw()`, the `Reader` expects the stream to start with
+/// the `Header` a `Writer` writes, and decrypts with the iv and
+/// padding it finds there rather than the ones it was given.  It fails
+/// with `io::ErrorKind::InvalidData` if there's no header, or one from
+/// a format version this crate doesn't know.
+///
+/// The end of the stream is only recognized by the end of `source`,
+/// so two encrypted streams concatenated together can't be told
+/// apart.  Use `SizedWriter` and `SizedReader::next_stream` for that.
+///
+/// When the end of `source` is reached, the padding on the final
+/// block is checked; if it's malformed (most likely because the key
+/// or iv is wrong), `read` fails with `io::ErrorKind::InvalidData`.
+/// All such failures carry the same opaque `"decryption failed"`
+/// description, unless `verbose_errors()` is turned on.
 ///
 /// # Example:
 /// ```.ignore
 /// use std::fs::File;
-/// use std::io::{BufReader, Read};
+/// use std::io::Read;
 /// use tea::io::Reader;
 ///
 /// let f = File::open("foo.txt").ok().unwrap();
-/// let mut decrypt_f = Reader::new(BufReader::new(f),
-///                                 [1, 2, 3, 4], [5, 6]);
+/// let mut decrypt_f = Reader::new(f, [1, 2, 3, 4], [5, 6]);
 /// let mut s = "".to_string();
 /// decrypt_f.read_to_string(&mut s).ok().unwrap();
 /// ```
 pub struct Reader<R: io::BufRead> {
     source: R,
-    key: Key,
+    cipher: Cipher,
+    iv: Block,
     prev: Block,
-    buf: Vec<u8>,
+    consumed: u64,
+    offset: u64,
+    partial: SecretArray,
+    tail: Option<[u8; 8]>,
+    buf: SecretVec,
+    pos: usize,
+    eof: bool,
+    padding: Padding,
+    check: Check,
+    bucket: BucketState,
+    verbose: bool,
+    crc: Option<Crc32>,
+    crc_from_start: bool,
+    length_trailer: bool,
+    released: u64,
+    trailer: SecretArray,
+    raw: bool,
+    header_read: bool,
+    format: Option<FormatVersion>,
+    byte_order: ByteOrder,
 }
 
-impl<R: io::BufRead> Reader<R> {
+impl<R: io::Read> Reader<io::BufReader<R>> {
 
     /// Wraps `source` in a `Reader` that will decrypt with the given
     /// `key` and `iv` (initialization vector).
-    pub fn new(source: R, key: Key, iv: Block) -> Reader<R> {
+    pub fn new<K: Into<Key>, I: Into<Block>>(source: R, key: K, iv: I) -> Reader<io::BufReader<R>> {
+        Reader::from_bufread(io::BufReader::new(source), key, iv)
+    }
+
+    /// Like `new()`, but reads `source` through a buffer of `capacity`
+    /// bytes, so a bigger buffer means fewer, larger reads.
+    pub fn with_capacity<K: Into<Key>, I: Into<Block>>(source: R, key: K, iv: I, capacity: usize) -> Reader<io::BufReader<R>> {
+        Reader::from_bufread(io::BufReader::with_capacity(capacity, source), key, iv)
+    }
+
+}
+
+impl<R: io::BufRead> Reader<R> {
+
+    /// Wraps an already buffered `source` in a `Reader` that will
+    /// decrypt with the given `key` and `iv`, reading straight out of
+    /// `source`'s buffer.
+    pub fn from_bufread<K: Into<Key>, I: Into<Block>>(source: R, key: K, iv: I) -> Reader<R> {
+        let iv = iv.into();
         Reader{
             source: source,
-            key: key,
+            cipher: Cipher::new(key),
+            iv: iv,
             prev: iv,
-            buf: Vec::with_capacity(8),
+            consumed: 0,
+            offset: 0,
+            partial: SecretArray::new(),
+            tail: None,
+            buf: SecretVec::with_capacity(QUEUE_SIZE + 16),
+            pos: 0,
+            eof: false,
+            padding: Padding::Pkcs7,
+            check: Check::Strict,
+            bucket: BucketState{ marker: false, zeroes: 0 },
+            verbose: false,
+            crc: None,
+            crc_from_start: true,
+            length_trailer: false,
+            released: 0,
+            trailer: SecretArray::new(),
+            raw: false,
+            header_read: false,
+            format: None,
+            byte_order: ByteOrder::Native,
+        }
+    }
+
+    /// Chooses how the end of the stream is interpreted.  This must
+    /// match the padding used by the `Writer` that produced it.  Only
+    /// `raw()` streams need this; otherwise the header says.
+    pub fn padding(mut self, padding: Padding) -> Reader<R> {
+        self.padding = padding;
+        self
+    }
+
+    /// Chooses whether malformed padding is an error
+    /// (`Check::Strict`, the default) or is stripped as well as
+    /// possible (`Check::Lenient`).
+    pub fn padding_check(mut self, check: Check) -> Reader<R> {
+        self.check = check;
+        self
+    }
+
+    /// Makes decryption failures say what went wrong (e.g. `"bad
+    /// padding"`), rather than all looking alike.  This is handy for
+    /// debugging, but don't turn it on anywhere an attacker can see
+    /// the errors.
+    pub fn verbose_errors(mut self) -> Reader<R> {
+        self.verbose = true;
+        self
+    }
+
+    /// Expects the stream to end in a CRC-32 of the plaintext, as
+    /// written by a `Writer` with `crc_trailer()`.  The trailer is
+    /// never returned as plaintext; once the end of the stream is
+    /// reached, it's checked, and if it doesn't match, `read` fails
+    /// with `io::ErrorKind::InvalidData`.  After a seek to anywhere
+    /// but the start, the plaintext in front of the new position
+    /// hasn't been seen, so the trailer is stripped but not checked.
+    /// Only `raw()` streams and `V1` headers need this; later headers
+    /// say whether the trailer is there.
+    pub fn crc_trailer(mut self) -> Reader<R> {
+        self.crc = Some(Crc32::new());
+        self
+    }
+
+    /// Expects the stream to end in the length of the plaintext, as
+    /// written by a `Writer` with `length_trailer()`.  Like the CRC
+    /// trailer, it's never returned as plaintext, and is checked once
+    /// the end of the stream is reached: if the stream was cut short
+    /// (or the key is wrong), `read` fails with
+    /// `io::ErrorKind::InvalidData`.  This works after a seek, too.
+    /// As with `crc_trailer()`, only `raw()` streams and `V1` headers
+    /// need this.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{Reader, Writer};
+    /// use tea::padding::Padding;
+    ///
+    /// let mut crypt = Writer::new(Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+    ///     .padding(Padding::None)
+    ///     .length_trailer()
+    ///     .raw();
+    /// crypt.write_all(b"sixteen bytes!!!").ok().unwrap();
+    /// let ciphertext = crypt.close().ok().unwrap().into_inner();
+    /// assert_eq!(ciphertext.len(), 24);
+    ///
+    /// let mut decrypt = Reader::new(Cursor::new(&ciphertext[..16]), [1, 2, 3, 4], [5, 6])
+    ///     .padding(Padding::None)
+    ///     .length_trailer()
+    ///     .raw();
+    /// assert!(decrypt.read_to_end(&mut Vec::new()).is_err());
+    /// ```
+    pub fn length_trailer(mut self) -> Reader<R> {
+        self.length_trailer = true;
+        self
+    }
+
+    /// Expects bare CBC ciphertext, with no `Header` in front, as
+    /// written by a `raw()` `Writer`.
+    pub fn raw(mut self) -> Reader<R> {
+        self.raw = true;
+        self
+    }
+
+    /// Lays out the words of each block in `order`, as the `Writer`
+    /// that produced the stream did with `byte_order()`.  Only `raw()`
+    /// streams and `V1` headers need this; later headers record the
+    /// byte order.
+    pub fn byte_order(mut self, order: ByteOrder) -> Reader<R> {
+        self.byte_order = order;
+        self
+    }
+
+    /// The format version of the stream, from its `Header`.  It's
+    /// `None` until the header has been read, on the first read, and
+    /// for `raw()` streams, which have no header.
+    pub fn format_version(&self) -> Option<FormatVersion> {
+        self.format
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.source
+    }
+
+    /// Gets a mutable reference to the underlying reader.  Reading
+    /// from it directly will throw the decryption out of step.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    /// Unwraps this `Reader`, returning the underlying reader.  Any
+    /// plaintext that was decrypted but not yet read is lost.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    // How many bytes at the end of the plaintext are trailers rather
+    // than data.
+    fn trailer_len(&self) -> usize {
+        (if self.length_trailer { 8 } else { 0 }) + (if self.crc.is_some() { 4 } else { 0 })
+    }
+
+    // Reads the header at the start of the stream, the first time
+    // through, and takes the iv and padding from it, and the byte
+    // order and trailers too unless it's a `V1` header.
+    fn read_stream_header(&mut self) -> io::Result<()> {
+        if !self.raw && !self.header_read {
+            let header = try!(header::read_cbc(&mut self.source, self.verbose));
+            self.iv = header.iv;
+            self.prev = header.iv;
+            self.padding = header.padding;
+            if header.has_flags() {
+                self.byte_order = header.byte_order;
+                self.crc = if header.crc_trailer { Some(Crc32::new()) } else { None };
+                self.length_trailer = header.length_trailer;
+            }
+            self.format = Some(header.version);
+            self.header_read = true;
+        }
+        Ok(())
+    }
+
+    // Refills the plaintext queue, keeping the trailers (if any) out
+    // of it.
+    fn fill(&mut self) -> io::Result<()> {
+        try!(self.read_stream_header());
+        try!(self.decrypt_more());
+        if self.trailer_len() > 0 {
+            try!(self.hold_trailer());
         }
+        Ok(())
+    }
+
+    // Holds back the last few bytes of plaintext seen so far, since
+    // they might be the trailers, and checks them against the rest
+    // once the end of the stream is reached.
+    fn hold_trailer(&mut self) -> io::Result<()> {
+        let trailer_len = self.trailer_len();
+        // The trailers are the last `trailer_len` bytes of what was
+        // held and what's just been decrypted, taken together;
+        // everything in front of them is released, held bytes first.
+        let (held, fresh) = (self.trailer.len(), self.buf.len());
+        let release = (held + fresh).saturating_sub(trailer_len);
+        let mut trailer = SecretArray::new();
+        for i in release..held + fresh {
+            trailer.push(if i < held { self.trailer[i] } else { self.buf[i - held] });
+        }
+        self.buf.truncate(release.saturating_sub(held));
+        self.buf.splice(0..0, self.trailer[..cmp::min(held, release)].iter().cloned());
+        self.trailer = trailer;
+        self.released += release as u64;
+        if let Some(ref mut crc) = self.crc {
+            crc.update(&self.buf);
+        }
+
+        if !self.eof {
+            return Ok(());
+        }
+        if self.trailer.len() < trailer_len {
+            return Err(super::decrypt_error(self.verbose, "truncated ciphertext",
+                                            &format!("the stream should end in a {}-byte trailer, but there are only {} bytes", trailer_len, self.trailer.len())));
+        }
+        let mut crc_at = 0;
+        if self.length_trailer {
+            crc_at = 8;
+            let len = self.trailer[..8].iter().fold(0, |n, &b| (n << 8) | b as u64);
+            if len != self.released {
+                return Err(super::decrypt_error(self.verbose, "bad length",
+                                                &format!("the stream should hold {} bytes, but it holds {}, cut short or wrong key?", len, self.released)));
+            }
+        }
+        if let Some(ref crc) = self.crc {
+            if self.crc_from_start && !ct::eq(&crc.to_be_bytes(), &self.trailer[crc_at..]) {
+                return Err(super::decrypt_error(self.verbose, "bad checksum",
+                                                "CRC trailer doesn't match the plaintext, wrong key or corrupted data?"));
+            }
+        }
+        Ok(())
+    }
+
+    // Decrypts whole blocks from `source` straight into `out`, rather
+    // than through the plaintext queue, when the queue is empty and
+    // nothing but the tail block has to be held back (so no trailers
+    // and no bucket padding).  Returns `None` if the slow path has to
+    // be taken instead: near the end of the stream, when `source` has
+    // less than a block ready, or when `out` is too small to take a
+    // block.
+    fn read_direct(&mut self, out: &mut [u8]) -> io::Result<Option<usize>> {
+        // The header can change the padding and trailers.
+        try!(self.read_stream_header());
+        let bucket = match self.padding {
+            Padding::Bucket(_) => true,
+            _ => false,
+        };
+        if self.pos < self.buf.len() || self.eof || bucket || self.trailer_len() > 0 || !self.partial.is_empty() {
+            return Ok(None);
+        }
+        let (n, used) = {
+            let encrypted_bytes = try!(self.source.fill_buf());
+            // Each block decrypted releases the one before it, so with
+            // no tail yet the first block gives nothing.
+            let blocks = cmp::min(encrypted_bytes.len() / 8, out.len() / 8);
+            if blocks == 0 || (self.tail.is_none() && blocks < 2) {
+                return Ok(None);
+            }
+            // All but the last block decrypt as a batch, straight into
+            // `out` after the old tail, and the last becomes the new
+            // tail.
+            let start = if self.tail.is_some() { 8 } else { 0 };
+            let n = start + (blocks - 1) * 8;
+            out[start..n].clone_from_slice(&encrypted_bytes[..(blocks - 1) * 8]);
+            buffer::cbc_decrypt_as(&self.cipher, &mut self.prev, &mut out[start..n], self.byte_order);
+            let last = decrypt_chunk(&self.cipher, &mut self.prev, &encrypted_bytes[(blocks - 1) * 8..blocks * 8], self.byte_order);
+            if let Some(tail) = self.tail.take() {
+                out[..8].clone_from_slice(&tail);
+            }
+            self.tail = Some(last);
+            (n, blocks * 8)
+        };
+        self.source.consume(used);
+        self.consumed += used as u64;
+        self.offset += n as u64;
+        Ok(Some(n))
+    }
+
+    // Decrypts every complete block that `source` has ready into the
+    // plaintext queue.  The last block seen is always held back in
+    // `tail` until we know whether it's the final one, which has to
+    // have its padding checked and stripped.
+    fn decrypt_more(&mut self) -> io::Result<()> {
+        self.buf.truncate(0);
+        self.pos = 0;
+
+        let consumed = {
+            let encrypted_bytes = try!(self.source.fill_buf());
+            // Taking no more than the queue was made to hold keeps it
+            // from ever growing.
+            let encrypted_bytes = &encrypted_bytes[..cmp::min(encrypted_bytes.len(), QUEUE_SIZE)];
+            if encrypted_bytes.is_empty() {
+                0
+            } else {
+                let mut used = 0;
+                if !self.partial.is_empty() {
+                    // The source handed us less than a block last
+                    // time, so finish that one off first.
+                    used = cmp::min(8 - self.partial.len(), encrypted_bytes.len());
+                    self.partial.push_slice(&encrypted_bytes[..used]);
+                    if self.partial.len() == 8 {
+                        let block = decrypt_chunk(&self.cipher, &mut self.prev, &self.partial, self.byte_order);
+                        self.partial.truncate(0);
+                        if let Some(tail) = self.tail.take() {
+                            release(self.padding, &mut self.bucket, &tail, &mut self.buf);
+                        }
+                        self.tail = Some(block);
+                    }
+                }
+                for chunk in encrypted_bytes[used..].chunks(8) {
+                    if chunk.len() < 8 {
+                        self.partial.push_slice(chunk);
+                    } else {
+                        let block = decrypt_chunk(&self.cipher, &mut self.prev, chunk, self.byte_order);
+                        if let Some(tail) = self.tail.take() {
+                            release(self.padding, &mut self.bucket, &tail, &mut self.buf);
+                        }
+                        self.tail = Some(block);
+                    }
+                }
+                encrypted_bytes.len()
+            }
+        };
+        if consumed > 0 {
+            self.source.consume(consumed);
+            self.consumed += consumed as u64;
+            return Ok(());
+        }
+
+        if !self.partial.is_empty() {
+            return Err(super::decrypt_error(self.verbose, "truncated ciphertext",
+                                            &format!("encrypted data should be a multiple of 8 bytes, but {} were left over", self.partial.len())));
+        }
+        let tail = self.tail.take();
+        match self.padding {
+            Padding::Pkcs7 => {
+                let block: &[u8] = match tail {
+                    Some(ref block) => block,
+                    None => &[],
+                };
+                match strip_padding(block, self.check) {
+                    Some(plaintext) => self.buf.push_all(plaintext),
+                    None => return Err(super::decrypt_error(self.verbose, "bad padding",
+                                                            "final block doesn't end in valid PKCS#7 padding, wrong key or iv?")),
+                }
+            },
+            Padding::Bucket(_) => {
+                if let Some(block) = tail {
+                    self.bucket.push(&block, &mut self.buf);
+                }
+                if !self.bucket.marker && self.check == Check::Strict {
+                    return Err(super::decrypt_error(self.verbose, "bad padding",
+                                                    "stream doesn't end in bucket padding, wrong key or iv?"));
+                }
+            },
+            Padding::None => if let Some(block) = tail {
+                self.buf.push_all(&block);
+            },
+        }
+        self.eof = true;
+        Ok(())
     }
 
 }
@@ -53,56 +639,278 @@ impl<R: io::BufRead> Reader<R> {
 impl<R: io::BufRead> io::Read for Reader<R> {
 
     /// Reads from `source`, decrypts the data, and writes the result
-    /// to `buf`.
+    /// to `buf`.  Every complete block `source` has buffered gets
+    /// decrypted in one pass.  When `buf` has room for whole blocks,
+    /// they're decrypted straight into it, and only the last block of
+    /// the stream and any leftovers go through the `Reader`'s own
+    /// buffer.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut pos = 0;
-        while pos < buf.len() {
-            {
-                let encrypted_bytes = try!(self.source.fill_buf());
-                if encrypted_bytes.is_empty() {
-                    if !self.buf.is_empty() {
-                        // Handle padding bytes.
-                        let real_bytes = self.buf.len() - self.buf[self.buf.len()-1] as usize;
-
-                        // Weird scoping follows.  We want to discard
-                        // the lifetime where real_slice is borrowing
-                        // from self.buf so we can truncate it, but we
-                        // still want to know what n is, so we have to
-                        // let that value escape.
-                        let n = {
-                            let real_slice = &self.buf[..real_bytes];
-                            let n2 = buf[pos..].clone_from_slice(real_slice);
-                            pos += n2;
-                            n2
-                        };
-                        if n == real_bytes {
-                            self.buf.truncate(0);
-                        } else {
-                            self.buf = self.buf.split_off(n);
-                        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(n) = try!(self.read_direct(buf)) {
+            return Ok(n);
+        }
+        let n = buf.clone_from_slice(try!(io::BufRead::fill_buf(self)));
+        io::BufRead::consume(self, n);
+        Ok(n)
+    }
+
+    /// Reads the rest of the stream onto the end of `buf`.  When the
+    /// source can say how much ciphertext is left (a slice, a `Cursor`
+    /// over one, or a regular file, with or without a `BufReader`
+    /// around it), room for all of the plaintext is reserved up front,
+    /// at most a block or two more than it needs; otherwise `buf`
+    /// grows 64 KiB at a time.  Either way the plaintext is decrypted
+    /// straight into `buf` a whole buffer of ciphertext at a time.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        if let Some(remaining) = Remaining::remaining(&mut self.source) {
+            let queued = self.buf.len() - self.pos + if self.tail.is_some() { 8 } else { 0 };
+            buf.reserve(cmp::min(remaining, usize::max_value() as u64) as usize + queued);
+        }
+        // `buf[len..]` has been zeroed already and is waiting to be
+        // read into, so it's only zeroed once however many reads it
+        // takes to fill.
+        let mut len = buf.len();
+        loop {
+            if len == buf.len() {
+                if buf.len() == buf.capacity() {
+                    // Full up, maybe exactly; check there's more to come
+                    // before growing.
+                    let mut probe = [0u8; 32];
+                    match self.read(&mut probe) {
+                        Ok(0) => return Ok(len - start),
+                        Ok(n) => {
+                            buf.push_all(&probe[..n]);
+                            mem::wipe(&mut probe);
+                            len += n;
+                        },
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
                     }
-                    return Ok(pos);
+                    buf.reserve(READ_TO_END_BATCH);
+                }
+                let capacity = buf.capacity();
+                buf.resize(capacity, 0);
+            }
+            match self.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => {
+                    buf.truncate(len);
+                    return Err(e);
+                },
+            }
+        }
+        buf.truncate(len);
+        Ok(len - start)
+    }
+
+    /// Like `read()`, but spreads the decrypted bytes that are ready
+    /// across all of `bufs` in one call.
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            return Ok(0);
+        }
+        let mut n = 0;
+        {
+            let mut ready = try!(io::BufRead::fill_buf(self));
+            for buf in bufs.iter_mut() {
+                if ready.is_empty() {
+                    break;
+                }
+                let copied = buf.clone_from_slice(ready);
+                ready = &ready[copied..];
+                n += copied;
+            }
+        }
+        io::BufRead::consume(self, n);
+        Ok(n)
+    }
+
+}
+
+impl<R: io::BufRead> io::BufRead for Reader<R> {
+
+    /// Returns the decrypted bytes that are ready, decrypting more
+    /// from `source` if there aren't any.  An empty slice means the
+    /// end of the stream.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.buf.len() && !self.eof {
+            try!(self.fill());
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = cmp::min(amt, self.buf.len() - self.pos);
+        self.pos += amt;
+        self.offset += amt as u64;
+    }
+
+}
+
+impl<R: io::BufRead + fmt::Debug> fmt::Debug for Reader<R> {
+
+    /// Shows the source and how far along the stream is, but never
+    /// the key, the iv or any plaintext.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("source", &self.source)
+            .field("padding", &self.padding)
+            .field("consumed", &self.consumed)
+            .field("eof", &self.eof)
+            .finish()
+    }
+
+}
+
+// Applies a signed seek offset, or returns `None` if that would land
+// before the start.
+fn offset_by(from: u64, by: i64) -> Option<u64> {
+    if by >= 0 {
+        from.checked_add(by as u64)
+    } else {
+        from.checked_sub(0u64.wrapping_sub(by as u64))
+    }
+}
+
+impl<R: io::BufRead + io::Seek> Reader<R> {
+
+    // Reads the ciphertext block at offset `at` in `source`, or
+    // returns `None` if the source ends before it does.
+    fn block_at(&mut self, at: u64) -> io::Result<Option<[u8; 8]>> {
+        try!(self.source.seek(io::SeekFrom::Start(at)));
+        let mut block = [0u8; 8];
+        let mut filled = 0;
+        while filled < 8 {
+            match self.source.read(&mut block[filled..]) {
+                Ok(0) => return Ok(None),
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Some(block))
+    }
+
+    // Works out how long the plaintext is, not counting any
+    // trailers.
+    fn plaintext_len(&mut self, base: u64) -> io::Result<u64> {
+        let len = try!(self.decrypted_len(base));
+        let trailer_len = self.trailer_len() as u64;
+        match len.checked_sub(trailer_len) {
+            Some(len) => Ok(len),
+            None => Err(super::decrypt_error(self.verbose, "truncated ciphertext",
+                                             &format!("the stream should end in a {}-byte trailer, but there are only {} bytes", trailer_len, len))),
+        }
+    }
+
+    // Works out how long the decrypted stream is by decrypting just
+    // the last block and looking at its padding.
+    fn decrypted_len(&mut self, base: u64) -> io::Result<u64> {
+        let end = try!(self.source.seek(io::SeekFrom::End(0)));
+        let len = end - base;
+        match self.padding {
+            Padding::None => Ok(len),
+            Padding::Pkcs7 => {
+                let verbose = self.verbose;
+                let truncated = move || super::decrypt_error(verbose, "truncated ciphertext",
+                                                             &format!("encrypted data should be a non-zero multiple of 8 bytes, but there are {}", len));
+                if len < 8 || len % 8 != 0 {
+                    return Err(truncated());
+                }
+                let mut prev = if len == 8 {
+                    self.iv
                 } else {
-                    assert!(encrypted_bytes.len() >= 8,
-                            "not enough bytes to decrypt, encrypted data should be a multiple of 8 bytes but we got {}", encrypted_bytes.len());
-
-                    if !self.buf.is_empty() {
-                        let n = buf[pos..].clone_from_slice(&self.buf);
-                        pos += n;
-                        if n == self.buf.len() {
-                            self.buf.truncate(0);
-                        } else {
-                            self.buf = self.buf.split_off(n);
-                            return Ok(pos);
-                        }
+                    match try!(self.block_at(end - 16)) {
+                        Some(block) => mem::read_block_as(&block, self.byte_order),
+                        None => return Err(truncated()),
                     }
-
-                    self.buf.push_all(&decrypt_chunk(&self.key, &mut self.prev, &encrypted_bytes[0..8]));
+                };
+                let last = match try!(self.block_at(end - 8)) {
+                    Some(block) => block,
+                    None => return Err(truncated()),
+                };
+                let block = decrypt_chunk(&self.cipher, &mut prev, &last, self.byte_order);
+                match strip_padding(&block, self.check) {
+                    Some(plaintext) => Ok(len - 8 + plaintext.len() as u64),
+                    None => Err(super::decrypt_error(self.verbose, "bad padding",
+                                                     "final block doesn't end in valid PKCS#7 padding, wrong key or iv?")),
                 }
+            },
+            Padding::Bucket(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "can't seek from the end with bucket padding",
+                                                     Some("the padding length isn't known without decrypting the whole tail".to_string()))),
+        }
+    }
+
+}
+
+impl<R: io::BufRead + io::Seek> io::Seek for Reader<R> {
+
+    /// Moves to a plaintext offset.  Since CBC decryption only needs
+    /// the ciphertext block in front of the one being decrypted, this
+    /// reads at most two blocks, plus the last two for
+    /// `SeekFrom::End` to find the padding.  Offsets are relative to
+    /// wherever `source` was when the `Reader` was created, after the
+    /// header (if any).
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        try!(self.read_stream_header());
+        let base = try!(self.source.seek(io::SeekFrom::Current(0))) - self.consumed;
+        let target = match pos {
+            io::SeekFrom::Start(n) => Some(n),
+            io::SeekFrom::Current(n) => offset_by(self.offset, n),
+            io::SeekFrom::End(n) => offset_by(try!(self.plaintext_len(base)), n),
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position", None)),
+        };
+
+        let block = target / 8;
+        let prev = if block == 0 {
+            Some(self.iv)
+        } else {
+            let order = self.byte_order;
+            try!(self.block_at(base + (block - 1) * 8)).map(|b| mem::read_block_as(&b, order))
+        };
+        try!(self.source.seek(io::SeekFrom::Start(base + block * 8)));
+        self.consumed = block * 8;
+        self.offset = target;
+        self.partial.truncate(0);
+        self.tail = None;
+        self.buf.truncate(0);
+        self.pos = 0;
+        self.bucket = BucketState{ marker: false, zeroes: 0 };
+        mem::wipe(&mut self.trailer);
+        self.trailer.truncate(0);
+        self.released = block * 8;
+        if self.crc.is_some() {
+            self.crc = Some(Crc32::new());
+            self.crc_from_start = target == 0;
+        }
+        match prev {
+            Some(prev) => {
+                self.prev = prev;
+                self.eof = false;
+            },
+            // Past the end, so there's nothing left to read.
+            None => self.eof = true,
+        }
+
+        let mut skip = (target % 8) as usize;
+        while skip > 0 && !(self.eof && self.pos == self.buf.len()) {
+            if self.pos == self.buf.len() {
+                try!(self.fill());
+            } else {
+                let n = cmp::min(skip, self.buf.len() - self.pos);
+                self.pos += n;
+                skip -= n;
             }
-            self.source.consume(8);
         }
-        Ok(pos)
+        Ok(target)
     }
 
 }
@@ -121,7 +929,7 @@ fn it_works() {
         }
 
         let crypted = writer.close().ok().unwrap().into_inner();
-        assert!(crypted.len() == input.len() + 8);
+        assert!(crypted.len() == 16 + input.len() + 8);
         assert!(crypted != input);
 
         let mut reader = Reader::new(io::Cursor::new(crypted),
@@ -131,3 +939,447 @@ fn it_works() {
         assert_eq!(decrypted, input);
     }
 }
+
+#[test]
+fn bad_padding() {
+    use std::error::Error;
+    use std::io::{Read, Write};
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let mut crypted = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::new(io::Cursor::new(crypted.clone()), [4, 3, 2, 1], [5, 6]);
+    let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.description(), "decryption failed");
+
+    let mut reader = Reader::new(io::Cursor::new(crypted.clone()), [4, 3, 2, 1], [5, 6])
+        .verbose_errors();
+    let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.description(), "bad padding");
+
+    let last = crypted.len() - 1;
+    crypted[last] ^= 1;
+    let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6]);
+    let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn bucket_padding() {
+    use std::io::{Read, Write};
+    use super::Writer;
+    use padding::Bucket;
+    use super::Header;
+
+    let mut input: Vec<u8> = (0u8..200).collect();
+    input.push_all(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0]);
+    for &bucket in [Bucket::PowerOfTwo, Bucket::Multiple(100)].iter() {
+        for len in (0..input.len()).filter(|n| n % 13 == 0 || *n >= 200) {
+            let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+                .padding(Padding::Bucket(bucket));
+            writer.write_all(&input[..len]).ok().unwrap();
+            let crypted = writer.close().ok().unwrap().into_inner();
+            let header_len = Header{ padding: Padding::Bucket(bucket), ..Header::new([5, 6]) }.to_bytes().len();
+            assert_eq!((crypted.len() - header_len) as u64, bucket.padded_len(len as u64 + 1));
+
+            let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6])
+                .padding(Padding::Bucket(bucket));
+            let mut decrypted = Vec::new();
+            assert!(reader.read_to_end(&mut decrypted).is_ok());
+            assert_eq!(&decrypted[..], &input[..len]);
+        }
+    }
+}
+
+#[test]
+fn lenient_padding() {
+    use std::io::{Read, Write};
+    use super::Writer;
+
+    // Simulate a legacy encoder that zero-pads.
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+        .padding(Padding::None)
+        .raw();
+    writer.write_all(b"legacy data\0\0\0\0\0").ok().unwrap();
+    let crypted = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::new(io::Cursor::new(crypted.clone()), [1, 2, 3, 4], [5, 6]).raw();
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+    let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6])
+        .raw()
+        .padding_check(Check::Lenient);
+    let mut decrypted = Vec::new();
+    assert!(reader.read_to_end(&mut decrypted).is_ok());
+    assert_eq!(decrypted, b"legacy data");
+}
+
+#[test]
+fn any_read() {
+    use std::io::{Read, Write};
+    use super::Writer;
+
+    // Only implements `Read`, not `BufRead`.
+    struct Source(io::Cursor<Vec<u8>>);
+    impl Read for Source {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let crypted = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::new(Source(io::Cursor::new(crypted.clone())), [1, 2, 3, 4], [5, 6]);
+    let mut decrypted = Vec::new();
+    assert!(reader.read_to_end(&mut decrypted).is_ok());
+    assert_eq!(decrypted, input);
+
+    let mut reader = Reader::from_bufread(&crypted[..], [1, 2, 3, 4], [5, 6]);
+    let mut decrypted = Vec::new();
+    assert!(reader.read_to_end(&mut decrypted).is_ok());
+    assert_eq!(decrypted, input);
+}
+
+#[test]
+fn truncated() {
+    use std::io::{Read, Write};
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let crypted = writer.close().ok().unwrap().into_inner();
+
+    for len in (1..crypted.len()).filter(|n| n % 8 != 0) {
+        let mut reader = Reader::new(io::Cursor::new(crypted[..len].to_vec()), [1, 2, 3, 4], [5, 6]);
+        let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn chunky_source() {
+    use std::io::{Read, Write};
+    use super::Writer;
+
+    // Hands out between 1 and 7 bytes per read, like a busy socket.
+    struct Chunky(io::Cursor<Vec<u8>>, usize);
+    impl Read for Chunky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 = self.1 % 7 + 1;
+            let n = cmp::min(self.1, buf.len());
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let crypted = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::new(Chunky(io::Cursor::new(crypted), 0), [1, 2, 3, 4], [5, 6]);
+    let mut decrypted = Vec::new();
+    assert!(reader.read_to_end(&mut decrypted).is_ok());
+    assert_eq!(decrypted, input);
+}
+
+#[test]
+fn direct_reads() {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use padding::Bucket;
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..200).collect();
+    for &padding in [Padding::Pkcs7, Padding::None, Padding::Bucket(Bucket::PowerOfTwo)].iter() {
+        for &order in [ByteOrder::Native, ByteOrder::BigEndian].iter() {
+            let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).padding(padding).byte_order(order);
+            writer.write_all(&input).ok().unwrap();
+            let crypted = writer.close().ok().unwrap();
+
+            for &size in [1, 7, 8, 9, 16, 24, 1000].iter() {
+                let mut reader = Reader::with_capacity(io::Cursor::new(&crypted[..]), [1, 2, 3, 4], [5, 6], 64)
+                    .padding(padding).byte_order(order);
+                let mut decrypted = Vec::new();
+                let mut buf = vec![0u8; size];
+                loop {
+                    let n = reader.read(&mut buf).ok().unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    decrypted.push_all(&buf[..n]);
+                    assert_eq!(reader.seek(SeekFrom::Current(0)).ok().unwrap(), decrypted.len() as u64);
+                }
+                assert_eq!(decrypted, input);
+
+                let mut reader = Reader::new(io::Cursor::new(&crypted[..crypted.len() - 8]), [1, 2, 3, 4], [5, 6])
+                    .padding(padding).byte_order(order);
+                if padding == Padding::Pkcs7 {
+                    let mut buf = vec![0u8; size];
+                    let err = loop {
+                        match reader.read(&mut buf) {
+                            Ok(n) => assert!(n > 0),
+                            Err(err) => break err,
+                        }
+                    };
+                    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn accessors() {
+    use std::io::Read;
+
+    let ciphertext = {
+        use std::io::Write;
+        use super::Writer;
+        let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+        writer.write_all(b"some plaintext").ok().unwrap();
+        writer.close().ok().unwrap().into_inner()
+    };
+    let mut reader = Reader::from_bufread(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]);
+    assert_eq!(reader.get_ref().position(), 0);
+    let mut s = String::new();
+    reader.read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "some plaintext");
+    reader.get_mut().set_position(0);
+    assert_eq!(reader.into_inner().into_inner().len(), 32);
+}
+
+#[test]
+fn seek() {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..100).collect();
+    for &padding in [Padding::Pkcs7, Padding::None].iter() {
+        let input = if padding == Padding::None { &input[..96] } else { &input[..] };
+        let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]).padding(padding);
+        writer.write_all(input).ok().unwrap();
+        let ciphertext = writer.close().ok().unwrap().into_inner();
+
+        let mut reader = Reader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]).padding(padding);
+        for &(pos, expected) in [(SeekFrom::Start(13), 13),
+                                 (SeekFrom::Current(-18), 0),
+                                 (SeekFrom::Start(64), 64),
+                                 (SeekFrom::Current(3), 72),
+                                 (SeekFrom::End(-5), input.len() - 5)].iter() {
+            assert_eq!(reader.seek(pos).ok().unwrap(), expected as u64);
+            let mut got = Vec::new();
+            reader.by_ref().take(5).read_to_end(&mut got).ok().unwrap();
+            assert_eq!(&got[..], &input[expected..expected + 5]);
+        }
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).ok().unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(reader.seek(SeekFrom::Start(1000)).ok().unwrap(), 1000);
+        assert_eq!(reader.read(&mut [0u8; 5]).ok().unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-2000)).is_err());
+    }
+}
+
+#[test]
+fn bufread() {
+    use std::io::{BufRead, Write};
+    use super::Writer;
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(b"first line\nsecond line\nno newline").ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap().into_inner();
+
+    let reader = Reader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]);
+    let lines: Vec<String> = reader.lines().map(|l| l.ok().unwrap()).collect();
+    assert_eq!(lines, ["first line", "second line", "no newline"]);
+}
+
+#[test]
+fn read_vectored() {
+    use std::io::{IoSliceMut, Read, Write};
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..40).collect();
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let ciphertext = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::new(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6]);
+    let (mut a, mut b, mut c) = ([0u8; 3], [0u8; 0], [0u8; 10]);
+    let n = reader.read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b), IoSliceMut::new(&mut c)]).ok().unwrap();
+    assert_eq!(n, 13);
+    assert_eq!(&a[..], &input[..3]);
+    assert_eq!(&c[..], &input[3..13]);
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).ok().unwrap();
+    assert_eq!(&rest[..], &input[13..]);
+}
+
+#[test]
+fn debug() {
+    let reader = Reader::new(io::Cursor::new(vec![0x12u8; 8]), [0x1234, 2, 3, 4], [0x5678, 6]);
+    let debug = format!("{:?}", reader);
+    assert!(debug.starts_with("Reader { source: BufReader"));
+    assert!(!debug.contains("4660") && !debug.contains("22136"));
+}
+
+#[test]
+fn crc_trailer() {
+    use std::error::Error;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..100).collect();
+    for len in [0, 3, 4, 12, 100].iter().cloned() {
+        let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]).crc_trailer();
+        writer.write_all(&input[..len]).ok().unwrap();
+        let crypted = writer.close().ok().unwrap().into_inner();
+        assert_eq!(crypted.len(), 16 + (len + 4) / 8 * 8 + 8);
+
+        let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6]).crc_trailer();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).ok().unwrap();
+        assert_eq!(&decrypted[..], &input[..len]);
+    }
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]).crc_trailer();
+    writer.write_all(&input).ok().unwrap();
+    let mut crypted = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::new(io::Cursor::new(crypted.clone()), [1, 2, 3, 4], [5, 6]).crc_trailer();
+    assert_eq!(reader.seek(SeekFrom::End(-10)).ok().unwrap(), 90);
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).ok().unwrap();
+    assert_eq!(&tail[..], &input[90..]);
+
+    // Flipping a bit in the first block garbles it and one bit of the
+    // next, but the padding still looks fine.
+    crypted[16] ^= 1;
+    let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6])
+        .crc_trailer()
+        .verbose_errors();
+    let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.description(), "bad checksum");
+}
+
+#[test]
+fn length_trailer() {
+    use std::error::Error;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use super::Writer;
+
+    let input: Vec<u8> = (0u8..100).collect();
+    for &padding in [Padding::Pkcs7, Padding::None].iter() {
+        let len = if padding == Padding::None { 92 } else { 100 };
+        let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+            .padding(padding)
+            .length_trailer()
+            .crc_trailer();
+        writer.write_all(&input[..len]).ok().unwrap();
+        let crypted = writer.close().ok().unwrap().into_inner();
+
+        let mut reader = Reader::new(io::Cursor::new(crypted.clone()), [1, 2, 3, 4], [5, 6])
+            .padding(padding)
+            .length_trailer()
+            .crc_trailer();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).ok().unwrap();
+        assert_eq!(&decrypted[..], &input[..len]);
+
+        assert_eq!(reader.seek(SeekFrom::Start(50)).ok().unwrap(), 50);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).ok().unwrap();
+        assert_eq!(&tail[..], &input[50..len]);
+        assert_eq!(reader.seek(SeekFrom::End(0)).ok().unwrap(), len as u64);
+    }
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+        .padding(Padding::None)
+        .length_trailer();
+    writer.write_all(&input[..96]).ok().unwrap();
+    let crypted = writer.close().ok().unwrap().into_inner();
+    for end in (3..crypted.len() / 8).map(|n| n * 8) {
+        let mut reader = Reader::new(io::Cursor::new(&crypted[..end]), [1, 2, 3, 4], [5, 6])
+            .padding(Padding::None)
+            .length_trailer()
+            .verbose_errors();
+        let err = reader.read_to_end(&mut Vec::new()).err().unwrap();
+        assert_eq!(err.description(), "bad length");
+    }
+}
+
+#[test]
+fn read_to_end() {
+    use std::env;
+    use std::io::{Read, Write};
+    use std::process;
+    use padding::Bucket;
+    use super::Writer;
+
+    // Hides the length of whatever it wraps.
+    struct Opaque<'a>(&'a [u8]);
+    impl<'a> io::Read for Opaque<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    for &len in [0, 7, 8, 200, 100000].iter() {
+        let input: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        for &padding in [Padding::Pkcs7, Padding::None, Padding::Bucket(Bucket::PowerOfTwo)].iter() {
+            if padding == Padding::None && len % 8 != 0 {
+                continue;
+            }
+            let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).padding(padding);
+            writer.write_all(&input).ok().unwrap();
+            let crypted = writer.close().ok().unwrap();
+
+            let mut decrypted = b"prefix".to_vec();
+            let mut reader = Reader::new(io::Cursor::new(crypted.clone()), [1, 2, 3, 4], [5, 6]).padding(padding);
+            assert_eq!(reader.read_to_end(&mut decrypted).ok().unwrap(), len);
+            assert_eq!(&decrypted[..6], b"prefix");
+            assert!(&decrypted[6..] == &input[..]);
+            if padding == Padding::Pkcs7 {
+                assert!(decrypted.capacity() <= 6 + len + 32, "{} bytes reserved for {}", decrypted.capacity(), len);
+            }
+
+            let mut decrypted = Vec::new();
+            Reader::from_bufread(&crypted[..], [1, 2, 3, 4], [5, 6]).padding(padding).read_to_end(&mut decrypted).ok().unwrap();
+            assert!(decrypted == input);
+
+            let mut decrypted = Vec::new();
+            Reader::new(Opaque(&crypted), [1, 2, 3, 4], [5, 6]).padding(padding).read_to_end(&mut decrypted).ok().unwrap();
+            assert!(decrypted == input);
+        }
+    }
+
+    let path = env::temp_dir().join(format!("tea-reader-test-read-to-end-{}", process::id()));
+    let input: Vec<u8> = (0..100000).map(|i| i as u8).collect();
+    let mut writer = Writer::new(fs::File::create(&path).ok().unwrap(), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    writer.close().ok().unwrap();
+    let mut reader = Reader::new(fs::File::open(&path).ok().unwrap(), [1, 2, 3, 4], [5, 6]);
+    let mut decrypted = vec![0u8; 10];
+    reader.read_exact(&mut decrypted).ok().unwrap();
+    assert_eq!(reader.read_to_end(&mut decrypted).ok().unwrap(), 100000 - 10);
+    assert!(decrypted == input);
+    assert!(decrypted.capacity() <= 100000 + 32);
+    fs::remove_file(&path).ok().unwrap();
+
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(&input).ok().unwrap();
+    let crypted = writer.close().ok().unwrap();
+    let mut decrypted = Vec::new();
+    let err = Reader::new(io::Cursor::new(&crypted[..crypted.len() - 8]), [1, 2, 3, 4], [5, 6])
+        .read_to_end(&mut decrypted).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,360 @@
+use std::io;
+
+use super::super::{Key, Block, encrypt, decrypt};
+use ct;
+use mac;
+
+// Each record starts with this many bytes: the ciphertext length as a
+// big-endian u32, then the record's tag.
+const HEADER_LEN: usize = 8;
+
+// Readers refuse records with more ciphertext than this, so a hostile
+// length can't make them allocate unbounded amounts.
+const MAX_RECORD_SIZE: usize = 16 << 20;
+
+// Fills as much of `buf` from `reader` as it can, and returns how much
+// that was, which is less than `buf.len()` only at the end of the
+// stream.
+fn read_full<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// The keys, derived from the one given, for record ivs and tags, and
+// the stream iv they're both tied to.
+struct RecordKeys {
+    key: Key,
+    iv_key: Key,
+    mac_key: Key,
+    stream_iv: [u8; 8],
+}
+
+impl RecordKeys {
+
+    fn new(key: Key, iv: Block) -> RecordKeys {
+        RecordKeys{
+            key: key,
+            iv_key: key.derive_subkey(b"tea record iv"),
+            mac_key: key.derive_subkey(b"tea record mac"),
+            stream_iv: iv.to_be_bytes(),
+        }
+    }
+
+    // The iv for record number `seq`, a CMAC of the stream iv and
+    // `seq`, so every record gets its own unpredictable iv.
+    fn iv(&self, seq: u64) -> Block {
+        let mut input = [0u8; 16];
+        input[..8].clone_from_slice(&self.stream_iv);
+        input[8..].clone_from_slice(&Block::from(seq).to_be_bytes());
+        Block::from_be_bytes(&mac::cmac(self.iv_key, &input))
+    }
+
+    // The 4-byte tag for record number `seq` with `ciphertext`.
+    fn tag(&self, seq: u64, ciphertext: &[u8]) -> [u8; 4] {
+        let mut input = Vec::with_capacity(16 + ciphertext.len());
+        input.push_all(&self.stream_iv);
+        input.push_all(&Block::from(seq).to_be_bytes());
+        input.push_all(ciphertext);
+        let mut tag = [0u8; 4];
+        tag.clone_from_slice(&mac::cmac(self.mac_key, &input)[..4]);
+        tag
+    }
+
+}
+
+/// Wraps an underlying `std::io::Write` and encrypts one record at a
+/// time.  Each record is padded separately and written with its length
+/// and a 4-byte tag in front (8 bytes in all).  Records are numbered
+/// from 0, and each one's iv is worked out from the stream's iv and
+/// its number rather than stored, so a `RecordReader` can decrypt any
+/// record on its own given where it falls in the stream.  The tag
+/// covers the record's number as well as its ciphertext, so records
+/// that have been changed, reordered, dropped from the middle, or
+/// copied in from another stream are caught.  Records dropped from
+/// the end can't be told from a log that hasn't been written yet;
+/// compare `RecordReader::position()` with the count you expect.
+///
+/// # Example:
+/// ```
+/// use std::io::Cursor;
+/// use tea::io::{RecordReader, RecordWriter};
+///
+/// let mut log = RecordWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+/// log.write_record(b"first entry").ok().unwrap();
+/// log.write_record(b"second entry").ok().unwrap();
+///
+/// let mut reader = RecordReader::new(Cursor::new(log.into_inner()), [1, 2, 3, 4], [5, 6]);
+/// assert_eq!(reader.read_record().ok().unwrap().unwrap(), b"first entry");
+/// assert_eq!(reader.read_record().ok().unwrap().unwrap(), b"second entry");
+/// assert!(reader.read_record().ok().unwrap().is_none());
+/// ```
+pub struct RecordWriter<W: io::Write> {
+    sink: W,
+    keys: RecordKeys,
+    seq: u64,
+}
+
+impl<W: io::Write> RecordWriter<W> {
+
+    /// Wraps `sink` in a `RecordWriter` that will encrypt with the
+    /// given `key`, deriving record ivs from the stream's `iv`
+    /// (initialization vector).  Use a new iv for each stream.
+    pub fn new<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I) -> RecordWriter<W> {
+        RecordWriter{
+            sink: sink,
+            keys: RecordKeys::new(key.into(), iv.into()),
+            seq: 0,
+        }
+    }
+
+    /// Numbers the next record `seq` instead of 0, for appending to a
+    /// stream that already has `seq` records.
+    pub fn start_at(mut self, seq: u64) -> RecordWriter<W> {
+        self.seq = seq;
+        self
+    }
+
+    /// The number the next record will get, which is how many records
+    /// the stream holds so far.
+    pub fn position(&self) -> u64 {
+        self.seq
+    }
+
+    /// Encrypts `record` and writes it to the sink, header and all.
+    /// Records can hold just under 16 MiB.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if record.len() / 8 * 8 + 8 > MAX_RECORD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "record too long",
+                                      Some(format!("records can be at most {} bytes, this one is {} bytes", MAX_RECORD_SIZE - 1, record.len()))));
+        }
+        let ciphertext = encrypt(self.keys.key, self.keys.iv(self.seq), record);
+        let len = ciphertext.len() as u32;
+        let mut header = [0u8; HEADER_LEN];
+        for i in 0..4 {
+            header[i] = (len >> (24 - 8 * i)) as u8;
+        }
+        header[4..].clone_from_slice(&self.keys.tag(self.seq, &ciphertext));
+        try!(self.sink.write_all(&header));
+        try!(self.sink.write_all(&ciphertext));
+        self.seq += 1;
+        Ok(())
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.sink
+    }
+
+    /// Gets a mutable reference to the underlying writer.  Writing to
+    /// it directly will corrupt the stream of records.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    /// Unwraps this `RecordWriter`, returning the underlying writer.
+    /// Every record is complete as soon as it's written, so there's
+    /// nothing to finish first.
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+
+}
+
+/// Wraps an underlying `std::io::Read` to decrypt the output of a
+/// `RecordWriter`, one record at a time.  As with `Reader`, decryption
+/// failures are opaque unless `verbose_errors()` is turned on.
+pub struct RecordReader<R: io::Read> {
+    source: R,
+    keys: RecordKeys,
+    seq: u64,
+    verbose: bool,
+}
+
+impl<R: io::Read> RecordReader<R> {
+
+    /// Wraps `source` in a `RecordReader` that will decrypt with the
+    /// given `key` and the stream's `iv`, starting from record 0.
+    pub fn new<K: Into<Key>, I: Into<Block>>(source: R, key: K, iv: I) -> RecordReader<R> {
+        RecordReader{
+            source: source,
+            keys: RecordKeys::new(key.into(), iv.into()),
+            seq: 0,
+            verbose: false,
+        }
+    }
+
+    /// Expects the first record read to be number `seq`, for reading
+    /// from the middle of a stream.  `source` must be at the start of
+    /// that record.
+    pub fn start_at(mut self, seq: u64) -> RecordReader<R> {
+        self.seq = seq;
+        self
+    }
+
+    /// Makes decryption failures say what went wrong, like
+    /// `Reader::verbose_errors`.
+    pub fn verbose_errors(mut self) -> RecordReader<R> {
+        self.verbose = true;
+        self
+    }
+
+    /// The number of the next record to be read, which at the end of
+    /// the stream is how many records it holds.
+    pub fn position(&self) -> u64 {
+        self.seq
+    }
+
+    /// Reads and decrypts the next record, or returns `None` at the
+    /// end of the stream.
+    pub fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; HEADER_LEN];
+        match try!(read_full(&mut self.source, &mut header)) {
+            0 => return Ok(None),
+            HEADER_LEN => {},
+            n => return Err(super::decrypt_error(self.verbose, "truncated record",
+                                                 &format!("stream ended {} bytes into a record header", n))),
+        }
+        let len = header[..4].iter().fold(0, |len, &b| (len << 8) | b as usize);
+        if len == 0 || len % 8 != 0 || len > MAX_RECORD_SIZE {
+            return Err(super::decrypt_error(self.verbose, "bad record length",
+                                            &format!("records are a non-zero multiple of 8 bytes up to {}, not {}", MAX_RECORD_SIZE, len)));
+        }
+        let mut ciphertext = vec![0u8; len];
+        let n = try!(read_full(&mut self.source, &mut ciphertext));
+        if n < len {
+            return Err(super::decrypt_error(self.verbose, "truncated record",
+                                            &format!("stream ended {} bytes into a {}-byte record", n, len)));
+        }
+        if !ct::eq(&self.keys.tag(self.seq, &ciphertext), &header[4..]) {
+            return Err(super::decrypt_error(self.verbose, "bad record tag",
+                                            &format!("record {} was changed, moved, or is from another stream", self.seq)));
+        }
+        match decrypt(self.keys.key, self.keys.iv(self.seq), &ciphertext) {
+            Ok(record) => {
+                self.seq += 1;
+                Ok(Some(record))
+            },
+            Err(_) => Err(super::decrypt_error(self.verbose, "bad padding",
+                                               "record doesn't end in valid PKCS#7 padding, wrong key?")),
+        }
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.source
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    /// Unwraps this `RecordReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let records: Vec<Vec<u8>> = (0..20).map(|len| (0..len).collect()).collect();
+    let mut writer = RecordWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    for record in records.iter() {
+        writer.write_record(record).ok().unwrap();
+    }
+    assert_eq!(writer.position(), 20);
+    let stream = writer.into_inner();
+
+    let mut reader = RecordReader::new(io::Cursor::new(&stream[..]), [1, 2, 3, 4], [5, 6]);
+    for record in records.iter() {
+        assert_eq!(&reader.read_record().ok().unwrap().unwrap(), record);
+    }
+    assert!(reader.read_record().ok().unwrap().is_none());
+    assert_eq!(reader.position(), 20);
+
+    // The third record can be read on its own.
+    let third = 2 * (HEADER_LEN + 8);
+    let mut reader = RecordReader::new(io::Cursor::new(&stream[third..]), [1, 2, 3, 4], [5, 6]).start_at(2);
+    assert_eq!(reader.read_record().ok().unwrap().unwrap(), records[2]);
+
+    // Appending carries on the same numbering.
+    let mut writer = RecordWriter::new(stream, [1, 2, 3, 4], [5, 6]).start_at(20);
+    writer.write_record(b"more").ok().unwrap();
+    let stream = writer.into_inner();
+    let mut reader = RecordReader::new(io::Cursor::new(&stream[..]), [1, 2, 3, 4], [5, 6]);
+    for _ in 0..20 {
+        reader.read_record().ok().unwrap().unwrap();
+    }
+    assert_eq!(reader.read_record().ok().unwrap().unwrap(), b"more");
+}
+
+#[test]
+fn tampered() {
+    use std::error::Error;
+
+    let mut writer = RecordWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    for record in [&b"first"[..], b"second", b"third"].iter() {
+        writer.write_record(record).ok().unwrap();
+    }
+    let stream = writer.into_inner();
+    let record_len = HEADER_LEN + 8;
+    let read_all = |stream: &[u8], iv: [u32; 2]| -> Result<Vec<Vec<u8>>, String> {
+        let mut reader = RecordReader::new(io::Cursor::new(stream), [1, 2, 3, 4], iv).verbose_errors();
+        let mut records = Vec::new();
+        loop {
+            match reader.read_record() {
+                Ok(Some(record)) => records.push(record),
+                Ok(None) => return Ok(records),
+                Err(e) => return Err(e.description().to_string()),
+            }
+        }
+    };
+    assert_eq!(read_all(&stream, [5, 6]).ok().unwrap().len(), 3);
+    assert_eq!(read_all(&stream, [5, 7]).err().unwrap(), "bad record tag");
+
+    let mut swapped = stream[record_len..2 * record_len].to_vec();
+    swapped.push_all(&stream[..record_len]);
+    swapped.push_all(&stream[2 * record_len..]);
+    assert_eq!(read_all(&swapped, [5, 6]).err().unwrap(), "bad record tag");
+
+    let mut dropped = stream[..record_len].to_vec();
+    dropped.push_all(&stream[2 * record_len..]);
+    assert_eq!(read_all(&dropped, [5, 6]).err().unwrap(), "bad record tag");
+
+    let mut huge = stream.clone();
+    huge[..4].clone_from_slice(&[0xff, 0xff, 0xff, 0xf8]);
+    assert_eq!(read_all(&huge, [5, 6]).err().unwrap(), "bad record length");
+
+    for i in 0..stream.len() {
+        if i % record_len < 4 {
+            continue;
+        }
+        let mut flipped = stream.clone();
+        flipped[i] ^= 1;
+        assert_eq!(read_all(&flipped, [5, 6]).err().unwrap(), "bad record tag");
+    }
+}
+
+#[test]
+fn truncated() {
+    use std::error::Error;
+
+    let mut writer = RecordWriter::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    writer.write_record(b"Hello, world!").ok().unwrap();
+    let stream = writer.into_inner();
+    for len in 1..stream.len() {
+        let mut reader = RecordReader::new(io::Cursor::new(&stream[..len]), [1, 2, 3, 4], [5, 6]).verbose_errors();
+        let err = reader.read_record().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.description(), "truncated record");
+    }
+}
@@ -0,0 +1,121 @@
+use std::io;
+
+use super::super::{Key, Block};
+use super::{Reader, Writer};
+use mem::SecretVec;
+
+// How much plaintext is decrypted and re-encrypted at a time.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Wraps an underlying `std::io::Read` of ciphertext and re-encrypts
+/// it on the fly: reading from a `ReEncryptor` yields the same
+/// plaintext encrypted under a new key and iv.  The plaintext never
+/// exists anywhere except a small internal buffer, so a whole store
+/// can be rotated to a new key by piping each stream through one of
+/// these.  Both streams are bare CBC ciphertext, with no `Header`, as
+/// made by `tea::encrypt` or a `raw()` `Writer`.
+///
+/// # Example:
+/// ```
+/// use std::io::{Cursor, Read};
+/// use tea::io::ReEncryptor;
+///
+/// let old = tea::encrypt([1, 2, 3, 4], [5, 6], b"Hello, world!");
+/// let mut rotate = ReEncryptor::new(Cursor::new(old), [1, 2, 3, 4], [5, 6], [7, 8, 9, 10], [11, 12]);
+/// let mut new = Vec::new();
+/// rotate.read_to_end(&mut new).ok().unwrap();
+/// assert_eq!(tea::decrypt([7, 8, 9, 10], [11, 12], &new).ok().unwrap(), b"Hello, world!");
+/// ```
+pub struct ReEncryptor<R: io::Read> {
+    source: Reader<io::BufReader<R>>,
+    sink: Writer<Vec<u8>>,
+    pos: usize,
+    plaintext: SecretVec,
+    done: bool,
+}
+
+impl<R: io::Read> ReEncryptor<R> {
+
+    /// Wraps `source`, which is decrypted with `old_key` and `old_iv`
+    /// and then encrypted again with `new_key` and `new_iv`.
+    pub fn new<K, I, L, J>(source: R, old_key: K, old_iv: I, new_key: L, new_iv: J) -> ReEncryptor<R>
+        where K: Into<Key>, I: Into<Block>, L: Into<Key>, J: Into<Block>
+    {
+        let mut plaintext = SecretVec::with_capacity(CHUNK_SIZE);
+        plaintext.resize(CHUNK_SIZE, 0);
+        ReEncryptor{
+            source: Reader::with_capacity(source, old_key, old_iv, CHUNK_SIZE).raw(),
+            sink: Writer::with_capacity(Vec::with_capacity(CHUNK_SIZE + 8), new_key, new_iv, CHUNK_SIZE).raw(),
+            pos: 0,
+            plaintext: plaintext,
+            done: false,
+        }
+    }
+
+    /// Unwraps this `ReEncryptor`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source.into_inner().into_inner()
+    }
+
+}
+
+impl<R: io::Read> io::Read for ReEncryptor<R> {
+
+    /// Reads ciphertext under the new key into `buf`, decrypting and
+    /// re-encrypting more from `source` as needed.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.pos < self.sink.get_ref().len() {
+                let n = buf.clone_from_slice(&self.sink.get_ref()[self.pos..]);
+                self.pos += n;
+                if self.pos == self.sink.get_ref().len() {
+                    self.sink.get_mut().truncate(0);
+                    self.pos = 0;
+                }
+                return Ok(n);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            let n = try!(self.source.read(&mut self.plaintext));
+            if n == 0 {
+                try!(self.sink.finish());
+                self.done = true;
+            } else {
+                try!(io::Write::write_all(&mut self.sink, &self.plaintext[..n]));
+                try!(io::Write::flush(&mut self.sink));
+            }
+        }
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::io::Read;
+    use super::super::{encrypt, decrypt};
+
+    for &len in [0, 1, 8, 100, 20000].iter() {
+        let input: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let old = encrypt([1, 2, 3, 4], [5, 6], &input);
+        let mut rotate = ReEncryptor::new(io::Cursor::new(old), [1, 2, 3, 4], [5, 6], [7, 8, 9, 10], [11, 12]);
+        let mut new = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            let n = rotate.read(&mut buf).ok().unwrap();
+            if n == 0 {
+                break;
+            }
+            new.push_all(&buf[..n]);
+        }
+        assert_eq!(new, encrypt([7, 8, 9, 10], [11, 12], &input));
+        assert_eq!(decrypt([7, 8, 9, 10], [11, 12], &new).ok().unwrap(), input);
+    }
+
+    let old = encrypt([1, 2, 3, 4], [5, 6], b"Hello, world!");
+    let mut rotate = ReEncryptor::new(io::Cursor::new(old), [1, 2, 3, 5], [5, 6], [7, 8, 9, 10], [11, 12]);
+    assert!(rotate.read_to_end(&mut Vec::new()).is_err());
+}
@@ -0,0 +1,348 @@
+use std::io;
+use std::cmp;
+
+use super::super::{Key, Block, SecretKey};
+use super::{header, writer};
+use super::{Header, Mode, Writer};
+use cipher;
+use mem;
+use padding::Padding;
+
+fn encode_len(len: u64) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    for i in 0..8 {
+        bytes[i] = (len >> (56 - 8 * i)) as u8;
+    }
+    bytes
+}
+
+fn decode_len(bytes: &[u8; 8]) -> u64 {
+    bytes.iter().fold(0, |len, &b| (len << 8) | b as u64)
+}
+
+/// Wraps an underlying `std::io::Write` like `Writer` does, but
+/// instead of padding the end of the stream, writes the plaintext
+/// length as an encrypted 8-byte block straight after the `Header`.
+/// The length has to be known up front, and exactly that many bytes
+/// must be written before calling `close()`.
+///
+/// # Example:
+/// ```.ignore
+/// use std::fs::File;
+/// use std::io::Write;
+/// use tea::io::SizedWriter;
+///
+/// let f = File::create("foo.txt").ok().unwrap();
+/// let mut crypt_f = SizedWriter::new(f, [1, 2, 3, 4], [5, 6], 13).ok().unwrap();
+/// crypt_f.write_all(b"Hello, world!").ok().unwrap();
+/// crypt_f.close().ok().unwrap();
+/// ```
+pub struct SizedWriter<W: io::Write> {
+    inner: Writer<W>,
+    remaining: u64,
+}
+
+impl<W: io::Write> SizedWriter<W> {
+
+    /// Wraps `sink` in a `SizedWriter` that will encrypt exactly `len`
+    /// bytes with the given `key` and `iv` (initialization vector).
+    /// The length header is written to `sink` immediately.
+    pub fn new<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I, len: u64) -> io::Result<SizedWriter<W>> {
+        let mut inner = Writer::new(sink, key, iv).padding(Padding::None);
+        try!(io::Write::write_all(&mut inner, &encode_len(len)));
+        Ok(SizedWriter{
+            inner: inner,
+            remaining: len,
+        })
+    }
+
+    /// Fills out the final block with zeroes, destroys the encrypting
+    /// wrapper, and returns the underlying `std::io::Write` object.
+    /// It's an error to close before all the promised bytes have been
+    /// written.
+    pub fn close(mut self) -> io::Result<W> {
+        if self.remaining > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "closed before writing all bytes",
+                                      Some(format!("{} bytes were promised but never written", self.remaining))));
+        }
+        let zeroes = [0u8; 8];
+        let tail = writer::buffered_len(&self.inner);
+        if tail > 0 {
+            try!(io::Write::write_all(&mut self.inner, &zeroes[tail..]));
+        }
+        self.inner.close()
+    }
+
+}
+
+impl<W: io::Write> io::Write for SizedWriter<W> {
+
+    /// Encrypts the bytes in `buf` and passes them through, like
+    /// `Writer::write`.  Fails if that would go past the length
+    /// declared in `new`.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "write exceeds declared length",
+                                      Some(format!("tried to write {} bytes but only {} remain", buf.len(), self.remaining))));
+        }
+        let n = try!(self.inner.write(buf));
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+
+    /// Passes the flush call through to the inner `Writer`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+}
+
+/// Wraps an underlying `std::io::Read` to decrypt the output of a
+/// `SizedWriter`.  The iv comes from the stream's `Header`, and the
+/// plaintext length is known as soon as the `SizedReader` is
+/// constructed.  As with `Reader`, decryption failures are opaque
+/// unless `verbose_errors()` is turned on (a truncated header in
+/// `new` is always reported opaquely).
+///
+/// # Example:
+/// ```.ignore
+/// use std::fs::File;
+/// use std::io::Read;
+/// use tea::io::SizedReader;
+///
+/// let f = File::open("foo.txt").ok().unwrap();
+/// let mut decrypt_f = SizedReader::new(f, [1, 2, 3, 4]).ok().unwrap();
+/// let mut s = String::with_capacity(decrypt_f.len() as usize);
+/// decrypt_f.read_to_string(&mut s).ok().unwrap();
+/// ```
+pub struct SizedReader<R: io::Read> {
+    source: R,
+    key: SecretKey,
+    prev: Block,
+    len: u64,
+    remaining: u64,
+    block: [u8; 8],
+    block_pos: usize,
+    verbose: bool,
+}
+
+impl<R: io::Read> SizedReader<R> {
+
+    /// Wraps `source` in a `SizedReader` that will decrypt with the
+    /// given `key`, reading the header and the length right away.
+    pub fn new<K: Into<Key>>(source: R, key: K) -> io::Result<SizedReader<R>> {
+        let mut reader = SizedReader{
+            source: source,
+            key: SecretKey::new(key.into()),
+            prev: Block::new([0, 0]),
+            len: 0,
+            remaining: 0,
+            block: [0; 8],
+            block_pos: 8,
+            verbose: false,
+        };
+        if !try!(reader.start_stream()) {
+            return Err(super::decrypt_error(false, "truncated header", ""));
+        }
+        Ok(reader)
+    }
+
+    /// Makes decryption failures say what went wrong, like
+    /// `Reader::verbose_errors`.
+    pub fn verbose_errors(mut self) -> SizedReader<R> {
+        self.verbose = true;
+        self
+    }
+
+    /// Returns the total number of plaintext bytes in the stream.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Moves on to the next stream, for when the output of several
+    /// `SizedWriter`s has been concatenated.  Whatever is left of the
+    /// current stream is skipped, then the next stream's header and
+    /// length are read.  Returns `false` if `source` ended cleanly
+    /// instead, so there are no more streams.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Read, Write};
+    /// use tea::io::{SizedReader, SizedWriter};
+    ///
+    /// let mut both = Vec::new();
+    /// for &(iv, text) in [([5, 6], "Hello"), ([7, 8], "world")].iter() {
+    ///     let mut crypt = SizedWriter::new(both, [1, 2, 3, 4], iv, 5).ok().unwrap();
+    ///     crypt.write_all(text.as_bytes()).ok().unwrap();
+    ///     both = crypt.close().ok().unwrap();
+    /// }
+    ///
+    /// let mut decrypt = SizedReader::new(Cursor::new(both), [1, 2, 3, 4]).ok().unwrap();
+    /// let mut s = String::new();
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert!(decrypt.next_stream().ok().unwrap());
+    /// decrypt.read_to_string(&mut s).ok().unwrap();
+    /// assert_eq!(s, "Helloworld");
+    /// assert!(!decrypt.next_stream().ok().unwrap());
+    /// ```
+    pub fn next_stream(&mut self) -> io::Result<bool> {
+        try!(io::copy(self, &mut io::sink()));
+        self.start_stream()
+    }
+
+    // Reads a stream's header and decrypts its length, or returns
+    // `false` if `source` ends cleanly first.
+    fn start_stream(&mut self) -> io::Result<bool> {
+        self.len = 0;
+        self.remaining = 0;
+        self.block_pos = 8;
+        let mut first = [0u8; 1];
+        loop {
+            match self.source.read(&mut first) {
+                Ok(0) => return Ok(false),
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        let header = try!(Header::read_from(&mut io::Read::chain(&first[..], &mut self.source)));
+        if header.mode != Mode::Cbc || header.padding != Padding::None {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a sized stream",
+                                      Some(format!("header is for {:?} with {:?} padding", header.mode, header.padding))));
+        }
+        if header.byte_order != header::host_order() || header.crc_trailer || header.length_trailer {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a sized stream",
+                                      Some("header gives a foreign byte order or trailers".to_string())));
+        }
+        self.prev = header.iv;
+        let len = try!(self.next_block());
+        self.len = decode_len(&len);
+        self.remaining = self.len;
+        Ok(true)
+    }
+
+    // Fills as much of `chunk` from `source` as it can, and returns
+    // how much that was, which is less than 8 only at the end of the
+    // stream.
+    fn read_chunk(&mut self, chunk: &mut [u8; 8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < 8 {
+            match self.source.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    fn decrypt_block(&mut self, chunk: &[u8; 8]) -> [u8; 8] {
+        let input_block = mem::read_block(chunk);
+        let mut decrypted_block = cipher::decipher(&self.key, &input_block);
+        decrypted_block[0] ^= self.prev[0];
+        decrypted_block[1] ^= self.prev[1];
+        self.prev = input_block;
+        *mem::write_block(&decrypted_block)
+    }
+
+    // Reads and decrypts the next 8 bytes from `source`.
+    fn next_block(&mut self) -> io::Result<[u8; 8]> {
+        let mut chunk = [0u8; 8];
+        let filled = try!(self.read_chunk(&mut chunk));
+        if filled < 8 {
+            return Err(super::decrypt_error(self.verbose, "truncated ciphertext",
+                                            &format!("stream ended {} bytes into a block", filled)));
+        }
+        Ok(self.decrypt_block(&chunk))
+    }
+
+}
+
+impl<R: io::Read> Drop for SizedReader<R> {
+
+    fn drop(&mut self) {
+        mem::wipe(&mut self.block);
+    }
+
+}
+
+impl<R: io::Read> io::Read for SizedReader<R> {
+
+    /// Reads from `source`, decrypts the data, and writes the result
+    /// to `buf`, stopping at the length given in the header.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.block_pos == 8 {
+            self.block = try!(self.next_block());
+            self.block_pos = 0;
+        }
+        let available = cmp::min(8 - self.block_pos as u64, self.remaining) as usize;
+        let n = buf.clone_from_slice(&self.block[self.block_pos..self.block_pos + available]);
+        self.block_pos += n;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    use std::io::{Read, Write};
+
+    for len in 0..40 {
+        let input: Vec<u8> = (0u8..len).collect();
+        let mut writer = SizedWriter::new(io::Cursor::new(Vec::new()),
+                                          [1, 2, 3, 4], [5, 6], len as u64).ok().unwrap();
+        writer.write_all(&input).ok().unwrap();
+        let crypted = writer.close().ok().unwrap().into_inner();
+        assert_eq!(crypted.len(), 16 + 8 + (input.len() + 7) / 8 * 8);
+
+        let mut reader = SizedReader::new(io::Cursor::new(crypted), [1, 2, 3, 4]).ok().unwrap();
+        assert_eq!(reader.len(), len as u64);
+        let mut decrypted = Vec::new();
+        assert!(reader.read_to_end(&mut decrypted).is_ok());
+        assert_eq!(decrypted, input);
+    }
+}
+
+#[test]
+fn wrong_length() {
+    use std::io::Write;
+
+    let mut writer = SizedWriter::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6], 4).ok().unwrap();
+    assert!(writer.write(b"hello").is_err());
+    assert_eq!(writer.write(b"hel").ok().unwrap(), 3);
+    assert!(writer.close().is_err());
+}
+
+#[test]
+fn concatenated() {
+    use std::io::{Read, Write};
+
+    let mut stream = Vec::new();
+    for len in 0..20 {
+        let input: Vec<u8> = (0u8..len).collect();
+        let mut writer = SizedWriter::new(stream, [1, 2, 3, 4], [5, len as u32], len as u64).ok().unwrap();
+        writer.write_all(&input).ok().unwrap();
+        stream = writer.close().ok().unwrap();
+    }
+
+    let mut reader = SizedReader::new(io::Cursor::new(stream), [1, 2, 3, 4]).ok().unwrap();
+    for len in 0..20 {
+        if len > 0 {
+            assert!(reader.next_stream().ok().unwrap());
+        }
+        // Leave odd streams half read, which next_stream should skip.
+        let mut decrypted = Vec::new();
+        if len % 2 == 0 {
+            reader.read_to_end(&mut decrypted).ok().unwrap();
+            assert_eq!(decrypted, (0u8..len).collect::<Vec<u8>>());
+        } else {
+            reader.by_ref().take(len as u64 / 2).read_to_end(&mut decrypted).ok().unwrap();
+        }
+    }
+    assert!(!reader.next_stream().ok().unwrap());
+}
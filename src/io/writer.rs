@@ -1,23 +1,102 @@
+use std::cmp;
+use std::fmt;
 use std::io;
 
-use super::super::{Key, Block};
-use cipher;
-use mem;
+use super::super::{Key, Block, ByteOrder};
+use super::{FormatVersion, Header, IvPolicy};
+use buffer;
+use cipher::Cipher;
+use crc::Crc32;
+use mem::{self, SecretArray, SecretVec};
+use padding::Padding;
 
-fn encrypt_chunk<'a>(key: &Key, prev: &'a mut Block, chunk: &[u8]) -> &'a [u8; 8] {
+// How many bytes of ciphertext we collect before handing them to the
+// sink, unless told otherwise.
+const DEFAULT_BATCH_SIZE: usize = 8 * 1024;
+
+fn encrypt_chunk(cipher: &Cipher, prev: &mut Block, chunk: &[u8], order: ByteOrder) -> [u8; 8] {
     let input_block = {
-        let mut mut_input_block = *mem::read_block(chunk);
+        let mut mut_input_block = mem::read_block_as(chunk, order);
         mut_input_block[0] ^= prev[0];
         mut_input_block[1] ^= prev[1];
         mut_input_block
     };
-    *prev = cipher::encipher(&key, &input_block);
-    mem::write_block(prev)
+    *prev = cipher.encipher(&input_block);
+    mem::write_block_as(prev, order)
+}
+
+fn limit_reached(used: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "key usage limit reached",
+                   Some(format!("{} bytes have been encrypted under this key, start a new stream with a new key", used)))
+}
+
+// Lets `Writer` encrypt straight into an in-memory sink's own buffer,
+// instead of collecting the ciphertext in `enc_buf` and copying it
+// over with `write`, which for a `Vec` doubles the memory traffic.
+trait Sink {
+    // Calls `f` with the sink's buffer to append to and returns true,
+    // if the sink is one that writes by appending to a `Vec`.
+    fn append<F: FnOnce(&mut Vec<u8>)>(&mut self, f: F) -> bool;
+}
+
+impl<W: io::Write> Sink for W {
+
+    default fn append<F: FnOnce(&mut Vec<u8>)>(&mut self, _: F) -> bool {
+        false
+    }
+
+}
+
+impl Sink for Vec<u8> {
+
+    fn append<F: FnOnce(&mut Vec<u8>)>(&mut self, f: F) -> bool {
+        f(self);
+        true
+    }
+
+}
+
+impl Sink for io::Cursor<Vec<u8>> {
+
+    /// Only appends when the cursor is at the end; anywhere else a
+    /// write overwrites what's there.
+    fn append<F: FnOnce(&mut Vec<u8>)>(&mut self, f: F) -> bool {
+        if self.position() != self.get_ref().len() as u64 {
+            return false;
+        }
+        f(self.get_mut());
+        let end = self.get_ref().len() as u64;
+        self.set_position(end);
+        true
+    }
+
 }
 
 /// Wraps an underlying `std::io::Write` so that bytes written get
-/// encrypted and passed through.  You must call `close()` when
-/// finished writing to append the padding bytes.
+/// encrypted and passed through.  You must call `close()` (or
+/// `finish()`) when finished writing to append the padding bytes.  A
+/// `Writer` that's dropped without being finished makes a best-effort
+/// attempt to finish itself, but any error is lost, so don't rely on
+/// it.
+///
+/// Every byte `write` reports as written has been taken in for good:
+/// if the sink accepts only part of the ciphertext, or fails, the rest
+/// stays queued and goes out on the next `write`, `flush` or
+/// `close`.  Writes interrupted by a signal (`ErrorKind::Interrupted`)
+/// are retried, and any other error, like `ErrorKind::WouldBlock`
+/// from a non-blocking sink, leaves the CBC state untouched so the
+/// same call can simply be made again later.  A `Vec<u8>` sink, or a
+/// `Cursor<Vec<u8>>` at its end, skips the batching altogether: the
+/// ciphertext is encrypted straight into the vector, with room for
+/// each write reserved up front.
+///
+/// The stream starts with a `Header` (the magic `"TEA1"`, a format
+/// version, the mode, the padding, any key derivation parameters, the
+/// byte order and trailers, and the iv), so it says how it was
+/// written, and a `Reader` can tell it's been handed the wrong
+/// thing, or something from a newer version of the format, instead
+/// of decrypting garbage.  `raw()` leaves the header out, for bare CBC
+/// ciphertext.
 ///
 /// # Example:
 /// ```.ignore
@@ -31,77 +110,459 @@ fn encrypt_chunk<'a>(key: &Key, prev: &'a mut Block, chunk: &[u8]) -> &'a [u8; 8
 /// crypt_f.close().ok().unwrap();
 /// ```
 pub struct Writer<W: io::Write> {
-    sink: W,
-    key: Key,
+    sink: Option<W>,
+    cipher: Cipher,
     prev: Block,
-    buf: Vec<u8>,
+    buf: SecretArray,
     enc_buf: Vec<u8>,
+    enc_pos: usize,
+    batch_size: usize,
+    staged: SecretVec,
+    staged_pos: usize,
+    coalesce: usize,
+    padding: Padding,
+    total: u64,
+    sunk: u64,
+    pad_start: Option<u64>,
+    finished: bool,
+    limit: Option<u64>,
+    used: u64,
+    iv: Block,
+    iv_policy: IvPolicy,
+    iv_from_source: bool,
+    crc: Option<Crc32>,
+    length_trailer: bool,
+    raw: bool,
+    header_written: bool,
+    format: FormatVersion,
+    byte_order: ByteOrder,
 }
 
 impl<W: io::Write> Writer<W> {
 
     /// Wraps `sink` in a `Writer` that will encrypt with the given
     /// `key` and `iv` (initialization vector).
-    pub fn new(sink: W, key: Key, iv: Block) -> Writer<W> {
+    pub fn new<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I) -> Writer<W> {
+        Writer::with_capacity(sink, key, iv, DEFAULT_BATCH_SIZE)
+    }
+
+    /// Like `new()`, but collects up to `capacity` bytes of ciphertext
+    /// (rounded down to whole blocks) before passing them to the sink,
+    /// just like `batch_size()`.
+    pub fn with_capacity<K: Into<Key>, I: Into<Block>>(sink: W, key: K, iv: I, capacity: usize) -> Writer<W> {
+        let batch_size = cmp::max(capacity / 8 * 8, 8);
+        let iv = iv.into();
         Writer{
-            sink: sink,
-            key: key,
+            sink: Some(sink),
+            cipher: Cipher::new(key),
             prev: iv,
-            buf: Vec::with_capacity(8),
-            enc_buf: Vec::with_capacity(8),
+            buf: SecretArray::new(),
+            enc_buf: Vec::with_capacity(batch_size),
+            enc_pos: 0,
+            batch_size: batch_size,
+            staged: SecretVec::new(),
+            staged_pos: 0,
+            coalesce: 0,
+            padding: Padding::Pkcs7,
+            total: 0,
+            sunk: 0,
+            pad_start: None,
+            finished: false,
+            limit: None,
+            used: 0,
+            iv: iv,
+            iv_policy: IvPolicy::new(),
+            iv_from_source: false,
+            crc: None,
+            length_trailer: false,
+            raw: false,
+            header_written: false,
+            format: FormatVersion::latest(),
+            byte_order: ByteOrder::Native,
         }
     }
 
-    /// Writes the final padding bytes according to PKCS#7, destroys
-    /// the encrypting wrapper, and returns the underlying
-    /// `std::io::Write` object.
-    pub fn close(mut self) -> io::Result<W> {
-        if !try!(self.flush_enc_buf()) {
-            return Err(io::Error::new(io::ErrorKind::Other, "couldn't flush encrypted bytes to sink",
-                                      Some(format!("sink couldn't write the last {} bytes that were already encoded", self.enc_buf.len()))));
-        }
-        self.enc_buf.truncate(0);
+    /// Sets how many bytes of ciphertext (rounded down to whole
+    /// blocks) are collected before they're passed to the sink in a
+    /// single `write`.  The default is 8 KiB; a batch size of 8 hands
+    /// blocks to the sink one at a time.  Whatever is left in a
+    /// partial batch goes out on `flush()` or `close()`.
+    pub fn batch_size(mut self, bytes: usize) -> Writer<W> {
+        self.batch_size = cmp::max(bytes / 8 * 8, 8);
+        let additional = self.batch_size.saturating_sub(self.enc_buf.len());
+        self.enc_buf.reserve(additional);
+        self
+    }
+
+    /// Collects writes shorter than `bytes` in a staging buffer and
+    /// only encrypts them once `bytes` of plaintext have piled up, or
+    /// on `flush()` or `close()`, so lots of tiny writes (from
+    /// `write!`, say) cost one pass through the cipher instead of one
+    /// each.  Staged bytes count as written as soon as `write` takes
+    /// them.  A longer write encrypts whatever is staged, then itself,
+    /// as usual.  Off by default; `coalesce_writes(0)` turns it off
+    /// again.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::Write;
+    /// use tea::io::Writer;
+    ///
+    /// let mut crypt = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).coalesce_writes(256);
+    /// for i in 0..10 {
+    ///     write!(crypt, "{},", i).ok().unwrap();
+    /// }
+    /// assert_eq!(crypt.close().ok().unwrap().len(), 40);
+    /// ```
+    pub fn coalesce_writes(mut self, bytes: usize) -> Writer<W> {
+        self.coalesce = bytes;
+        let additional = bytes.saturating_sub(self.staged.len());
+        self.staged.reserve(additional);
+        self
+    }
+
+    /// Chooses how `close()` finishes the stream.  With
+    /// `Padding::None`, the total plaintext written must be a multiple
+    /// of 8 bytes.  The padding is recorded in the header; for a
+    /// `raw()` stream, the `Reader` must be set up with the same
+    /// padding.
+    pub fn padding(mut self, padding: Padding) -> Writer<W> {
+        self.padding = padding;
+        self
+    }
+
+    /// Caps how many bytes of plaintext this `Writer` will encrypt
+    /// under its key.  XTEA's blocks are only 64 bits, so after a few
+    /// GiB under one key, repeated ciphertext blocks become likely
+    /// enough to leak plaintext (the birthday bound); 2^32 bytes or
+    /// less is a reasonable limit.  A `write` that would go over takes
+    /// only what fits, and after that writes fail with
+    /// `ErrorKind::Other`, at which point the stream should be closed
+    /// and a new one started under a new key.  Bucket padding counts
+    /// too: if it won't fit, `close` fails the same way, before any of
+    /// it is written.  There's no limit by default.
+    pub fn key_usage_limit(mut self, bytes: u64) -> Writer<W> {
+        self.limit = Some(bytes);
+        self
+    }
+
+    /// Holds this `Writer`'s iv to the rules in `policy`.  If the iv
+    /// breaks them, nothing gets encrypted: every `write`, and
+    /// `close()`, fails with `ErrorKind::InvalidInput`.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::Write;
+    /// use tea::io::{IvPolicy, Writer};
+    ///
+    /// let policy = IvPolicy::new().reject_zero();
+    /// let mut crypt = Writer::new(Vec::new(), [1, 2, 3, 4], [0, 0]).iv_policy(policy);
+    /// assert!(crypt.write_all(b"Hello, world!").is_err());
+    /// ```
+    pub fn iv_policy(mut self, policy: IvPolicy) -> Writer<W> {
+        self.iv_policy = policy;
+        self
+    }
+
+    /// Appends a CRC-32 of the plaintext (4 bytes, big-endian) to the
+    /// end of the stream, in front of the padding, so it's encrypted
+    /// along with everything else.  The `Reader` strips it off and
+    /// checks it (given `crc_trailer()` too for a `raw()` stream),
+    /// which catches a wrong key or corrupted ciphertext more reliably
+    /// than the padding alone.  It's not a MAC, though: use
+    /// `AuthenticatedWriter` to stop deliberate tampering.  With
+    /// `Padding::None`, the plaintext plus the 4-byte trailer must be
+    /// a multiple of 8 bytes.
+    pub fn crc_trailer(mut self) -> Writer<W> {
+        self.crc = Some(Crc32::new());
+        self
+    }
+
+    /// Appends the length of the plaintext (8 bytes, big-endian) to the
+    /// end of the stream, in front of the padding and any CRC trailer,
+    /// so it's encrypted along with everything else.  The `Reader`
+    /// strips it off and checks it, as with `crc_trailer()`, so a stream
+    /// that's been cut short is caught even when it's cut on a block
+    /// boundary, which otherwise often decrypts without complaint (and
+    /// always does with `Padding::None`).  Like `crc_trailer()`, this
+    /// won't stop someone who means to tamper with the stream.
+    pub fn length_trailer(mut self) -> Writer<W> {
+        self.length_trailer = true;
+        self
+    }
+
+    /// Leaves out the `Header` that otherwise starts the stream, so
+    /// the output is nothing but CBC ciphertext (for a format that
+    /// keeps the iv and padding somewhere else, or to continue a chain
+    /// from `close_into_parts()`).  The `Reader` must be `raw()` as
+    /// well.
+    pub fn raw(mut self) -> Writer<W> {
+        self.raw = true;
+        self
+    }
+
+    /// Writes the `Header` in the given format version rather than
+    /// the latest, so a build of this crate that only knows `version`
+    /// can read the stream.  The `Reader` picks the version up from
+    /// the header.
+    pub fn with_format(mut self, version: FormatVersion) -> Writer<W> {
+        self.format = version;
+        self
+    }
+
+    /// Lays out the words of each block in `order` when reading
+    /// plaintext and writing ciphertext, rather than the host's byte
+    /// order.  Use `ByteOrder::BigEndian` to match the C reference
+    /// and most other XTEA implementations.  The byte order is recorded
+    /// in the header; for a `raw()` stream, the `Reader` must use the
+    /// same one.
+    pub fn byte_order(mut self, order: ByteOrder) -> Writer<W> {
+        self.byte_order = order;
+        self
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.sink.as_ref().unwrap()
+    }
+
+    /// Gets a mutable reference to the underlying writer.  Writing to
+    /// it directly will corrupt the encrypted stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.sink.as_mut().unwrap()
+    }
+
+    /// Unwraps this `Writer` without finishing it, returning the
+    /// underlying writer.  Complete blocks already encrypted are
+    /// passed through first, but any bytes waiting for a full block
+    /// are dropped and no padding is written, so the stream won't
+    /// decrypt unless it's picked up again (e.g. by a `Writer` that
+    /// starts from the last ciphertext block).
+    pub fn into_inner(mut self) -> io::Result<W> {
+        try!(self.encrypt_staged());
+        try!(self.flush_enc_buf());
+        Ok(self.sink.take().unwrap())
+    }
 
-        let pad_byte = 8 - self.buf.len() as u8;
-        self.buf.resize(8, pad_byte);
-        let written = try!(self.sink.write(encrypt_chunk(&self.key, &mut self.prev, &self.buf)));
-        if written != 8 {
-            return Err(io::Error::new(io::ErrorKind::Other, "couldn't write final 8 bytes to sink",
-                                      Some(format!("sink only accepted {} bytes, can't close this writer", written))));
+    /// Writes the final padding bytes (according to PKCS#7, unless
+    /// `Padding::None` was chosen), destroys the encrypting wrapper,
+    /// and returns the underlying `std::io::Write` object.
+    pub fn close(self) -> io::Result<W> {
+        let (sink, _, _) = try!(self.close_into_parts());
+        Ok(sink)
+    }
+
+    /// Like `close()`, but also returns the total number of bytes
+    /// written to the sink (header included) and the final chaining
+    /// value (the last ciphertext block).  Passing that chaining value
+    /// as the iv of another, `raw()`, `Writer` continues the same CBC
+    /// chain, so segments can be stitched together without tracking
+    /// offsets separately.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::{Cursor, Write};
+    /// use tea::io::Writer;
+    ///
+    /// let mut crypt = Writer::new(Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    /// crypt.write_all(b"Hello, world!").ok().unwrap();
+    /// let (sink, written, _chain) = crypt.close_into_parts().ok().unwrap();
+    /// assert_eq!(written, 32);
+    /// assert_eq!(sink.into_inner().len(), 32);
+    /// ```
+    pub fn close_into_parts(mut self) -> io::Result<(W, u64, Block)> {
+        try!(self.finish());
+        let sink = self.sink.take().unwrap();
+        Ok((sink, self.sunk, self.prev))
+    }
+
+    /// Writes the final padding bytes and flushes everything to the
+    /// sink, like `close()`, but keeps the `Writer` around.  If the
+    /// sink fails partway (say with `ErrorKind::WouldBlock`), calling
+    /// `finish()` again picks up where it left off.  Once finishing
+    /// has started, no more data can be written.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
         }
+        try!(self.encrypt_staged());
+        try!(self.pad_final());
+        try!(self.flush_enc_buf());
+        try!(self.sink.as_mut().unwrap().flush());
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Starts a new stream with the same key and settings, but a new
+    /// `iv`, so a loop encrypting lots of small messages can keep one
+    /// `Writer` (and its buffers) instead of making one per message.
+    /// Call `finish()` first: anything of the old stream that hasn't
+    /// gone to the sink yet is thrown away.  The new ciphertext goes to
+    /// the same sink, straight after the old one, and the key usage
+    /// limit counts the bytes of every stream together.
+    ///
+    /// # Example:
+    /// ```
+    /// use std::io::Write;
+    /// use tea::io::Writer;
+    ///
+    /// let mut crypt = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]);
+    /// for (i, message) in ["one", "two", "three"].iter().enumerate() {
+    ///     crypt.reset([5, 6 + i as u32]);
+    ///     crypt.get_mut().clear();
+    ///     crypt.write_all(message.as_bytes()).ok().unwrap();
+    ///     crypt.finish().ok().unwrap();
+    ///     assert_eq!(crypt.get_ref().len(), 24);
+    /// }
+    /// ```
+    pub fn reset<I: Into<Block>>(&mut self, iv: I) {
+        let iv = iv.into();
+        self.used += self.total;
+        self.prev = iv;
+        self.iv = iv;
+        self.iv_from_source = false;
+        mem::wipe(&mut self.buf);
         self.buf.truncate(0);
-        try!(self.sink.flush());
-        Ok(self.sink)
-    }
-
-    // Flushes the buffer of encrypted data.  Returns Ok(true) if
-    // everything's ok, Ok(false) if we couldn't write everything and
-    // should stop.
-    fn flush_enc_buf(&mut self) -> io::Result<bool> {
-        let n = try!(self.sink.write(&self.enc_buf));
-        if n < self.enc_buf.len() {
-            let rest = self.enc_buf.split_off(n);
-            self.enc_buf = rest;
-            Ok(false)
+        mem::wipe(&mut self.staged);
+        self.staged.truncate(0);
+        self.staged_pos = 0;
+        self.enc_buf.truncate(0);
+        self.enc_pos = 0;
+        self.total = 0;
+        self.sunk = 0;
+        self.pad_start = None;
+        self.finished = false;
+        self.header_written = false;
+        if self.crc.is_some() {
+            self.crc = Some(Crc32::new());
+        }
+    }
+
+    /// Returns whether `finish()` has completed, so the stream has all
+    /// its padding and can be decrypted.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Like `flush()`, but it is an error to call this if we have some
+    /// cached bytes that are waiting for a full block before they can
+    /// be encrypted.
+    pub fn flush_aligned(&mut self) -> io::Result<()> {
+        try!(io::Write::flush(self));
+        if self.buf.is_empty() {
+            Ok(())
         } else {
-            self.enc_buf.truncate(0);
-            Ok(true)
+            Err(io::Error::new(io::ErrorKind::Other, "can't flush when not on a 64-bit block boundary",
+                               Some(format!("we have {} plaintext bytes that we can't encrypt until a full block is done", self.buf.len()))))
         }
     }
-}
 
-impl<W: io::Write> io::Write for Writer<W> {
+    // Queues the header in front of the first ciphertext, unless this
+    // is a raw stream.
+    fn start(&mut self) {
+        if !self.raw && !self.header_written {
+            let header = Header{
+                version: self.format,
+                padding: self.padding,
+                byte_order: self.byte_order,
+                crc_trailer: self.crc.is_some(),
+                length_trailer: self.length_trailer,
+                ..Header::new(self.iv)
+            };
+            self.enc_buf.push_all(&header.to_bytes());
+            self.header_written = true;
+        }
+    }
 
-    /// Encrypts the bytes in `buf` and passes them through to the
-    /// underlying `std::io::Write`.  If there are not an exact
-    /// multiple of 8 bytes available, the remaining ones will be
-    /// cached until more data is written or the `Writer` is closed.
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if !self.enc_buf.is_empty() {
-            if !try!(self.flush_enc_buf()) {
-                return Ok(0);
+    // How many of `len` more bytes can be encrypted under the key
+    // usage limit, failing if there's no room left at all.
+    fn room(&self, len: u64) -> io::Result<u64> {
+        match self.limit {
+            Some(limit) if len > 0 => {
+                let used = self.used + self.total;
+                match limit.saturating_sub(used) {
+                    0 => Err(limit_reached(used)),
+                    left => Ok(cmp::min(left, len)),
+                }
+            },
+            _ => Ok(len),
+        }
+    }
+
+    // Encrypts whatever `padding` calls for at the end of the stream.
+    // This can be called again if it fails partway, and won't pad
+    // twice.
+    fn pad_final(&mut self) -> io::Result<()> {
+        let start = match self.pad_start {
+            Some(start) => start,
+            None => {
+                try!(self.iv_policy.check(&self.iv, self.iv_from_source));
+                self.start();
+                let mut trailer = SecretArray::new();
+                if self.length_trailer {
+                    trailer.push_slice(&Block::from(self.total).to_be_bytes());
+                }
+                if let Some(ref crc) = self.crc {
+                    trailer.push_slice(&crc.to_be_bytes());
+                }
+                let left_over = self.buf.len() + trailer.len();
+                if self.padding == Padding::None && left_over % 8 != 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't close when not on a 64-bit block boundary",
+                                              Some(format!("{} plaintext bytes are left over and padding is disabled", left_over % 8))));
+                }
+                // The trailer goes straight into the buffers rather than
+                // through `write_blocks`, so a slow sink can't leave it
+                // half written.
+                for &b in trailer.iter() {
+                    self.buf.push(b);
+                    if self.buf.len() == 8 {
+                        self.enc_buf.push_all(&encrypt_chunk(&self.cipher, &mut self.prev, &self.buf, self.byte_order));
+                        self.buf.truncate(0);
+                    }
+                }
+                self.total += trailer.len() as u64;
+                if self.padding == Padding::Pkcs7 {
+                    let pad_byte = (8 - self.buf.len()) as u8;
+                    while self.buf.len() < 8 {
+                        self.buf.push(pad_byte);
+                    }
+                    self.enc_buf.push_all(&encrypt_chunk(&self.cipher, &mut self.prev, &self.buf, self.byte_order));
+                    self.buf.truncate(0);
+                }
+                self.pad_start = Some(self.total);
+                self.total
+            },
+        };
+        if let Padding::Bucket(bucket) = self.padding {
+            let end = match bucket.checked_padded_len(start + 1) {
+                Some(end) => end,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "bucket size too large",
+                                                  Some(format!("{:?} can't pad {} bytes", bucket, start)))),
+            };
+            // The padding is encrypted under the key like anything
+            // else, so it has to fit in the key usage limit, all of it.
+            let pad_len = end - self.total;
+            if try!(self.room(pad_len)) < pad_len {
+                return Err(limit_reached(self.used + self.total));
+            }
+            let mut pad = [0u8; 64];
+            while self.total < end {
+                pad[0] = if self.total == start { 0x80 } else { 0 };
+                let n = cmp::min(end - self.total, 64) as usize;
+                let written = try!(self.write_blocks(&pad[..n]));
+                self.total += written as u64;
             }
         }
+        Ok(())
+    }
+
+    // Does the real work for `write`.
+    fn write_blocks(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Ciphertext left over from a sink that couldn't keep up goes
+        // out before we take on any more plaintext.
+        if self.enc_buf.len() >= self.batch_size {
+            try!(self.flush_enc_buf());
+        }
 
         if buf.is_empty() {
             return Ok(0);
@@ -112,53 +573,223 @@ impl<W: io::Write> io::Write for Writer<W> {
         if !self.buf.is_empty() {
             let remaining = 8 - self.buf.len();
             if buf.len() < remaining {
-                self.buf.push_all(buf);
+                self.buf.push_slice(buf);
                 return Ok(buf.len());
             }
 
-            self.buf.push_all(&buf[..remaining]);
+            self.buf.push_slice(&buf[..remaining]);
             written += remaining;
 
-            self.enc_buf.push_all(encrypt_chunk(&self.key, &mut self.prev, &self.buf));
+            self.enc_buf.push_all(&encrypt_chunk(&self.cipher, &mut self.prev, &self.buf, self.byte_order));
             self.buf.truncate(0);
-
-            if !try!(self.flush_enc_buf()) {
-                return Ok(written);
-            }
         }
 
+        written += self.write_direct(&buf[written..]);
+
         for chunk in buf[written..].chunks(8) {
+            if self.enc_buf.len() >= self.batch_size {
+                if let Err(e) = self.flush_enc_buf() {
+                    // Whatever plaintext we've taken so far is safely
+                    // encrypted in `enc_buf`, so it counts as written;
+                    // the error will come up again on the next call.
+                    return if written > 0 { Ok(written) } else { Err(e) };
+                }
+            }
+
             if chunk.len() < 8 {
-                self.buf.push_all(chunk);
+                self.buf.push_slice(chunk);
                 written += chunk.len();
                 break;
             }
 
-            self.enc_buf.push_all(encrypt_chunk(&self.key, &mut self.prev, chunk));
+            self.enc_buf.push_all(&encrypt_chunk(&self.cipher, &mut self.prev, chunk, self.byte_order));
             written += 8;
+        }
 
-            if !try!(self.flush_enc_buf()) {
-                return Ok(written);
+        Ok(written)
+    }
+
+    // Encrypts the whole blocks at the front of `buf` straight into
+    // the sink's buffer, if it's a `Vec<u8>` or a `Cursor<Vec<u8>>` at
+    // its end, returning how many bytes it took.  Whatever is still
+    // queued in `enc_buf` goes in first, so the ciphertext stays in
+    // order, and the room for all of it is reserved up front.
+    fn write_direct(&mut self, buf: &[u8]) -> usize {
+        let len = buf.len() / 8 * 8;
+        if len == 0 {
+            return 0;
+        }
+        let pending = self.enc_buf.len() - self.enc_pos;
+        let (cipher, prev, order) = (&self.cipher, &mut self.prev, self.byte_order);
+        let queued = &self.enc_buf[self.enc_pos..];
+        let appended = self.sink.as_mut().unwrap().append(|out| {
+            out.reserve(pending + len);
+            out.push_all(queued);
+            let start = out.len();
+            out.push_all(&buf[..len]);
+            buffer::cbc_encrypt_as(cipher, prev, &mut out[start..], order);
+        });
+        if !appended {
+            return 0;
+        }
+        self.sunk += (pending + len) as u64;
+        self.enc_buf.truncate(0);
+        self.enc_pos = 0;
+        len
+    }
+
+    // Encrypts everything in `staged`.  If the sink fails partway,
+    // `staged` still holds exactly the plaintext that hasn't been
+    // encrypted (from `staged_pos` on), so calling this again picks up
+    // where it left off.
+    fn encrypt_staged(&mut self) -> io::Result<()> {
+        while self.staged_pos < self.staged.len() {
+            let staged = ::std::mem::replace(&mut self.staged, SecretVec::new());
+            let result = self.write_blocks(&staged[self.staged_pos..]);
+            self.staged = staged;
+            self.staged_pos += try!(result);
+        }
+        mem::wipe(&mut self.staged);
+        self.staged.truncate(0);
+        self.staged_pos = 0;
+        Ok(())
+    }
+
+    // Hands everything in `enc_buf` to the sink, calling `write` until
+    // it's all been accepted.  If the sink fails partway, `enc_buf`
+    // still holds exactly the ciphertext the sink hasn't taken (from
+    // `enc_pos` on), so calling this again picks up where it left off.
+    fn flush_enc_buf(&mut self) -> io::Result<()> {
+        while self.enc_pos < self.enc_buf.len() {
+            match self.sink.as_mut().unwrap().write(&self.enc_buf[self.enc_pos..]) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "sink accepted no bytes",
+                                                   Some(format!("{} bytes of ciphertext are still waiting to be written", self.enc_buf.len() - self.enc_pos)))),
+                Ok(n) => {
+                    self.enc_pos += n;
+                    self.sunk += n as u64;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
             }
         }
+        self.enc_buf.truncate(0);
+        self.enc_pos = 0;
+        Ok(())
+    }
+}
 
+impl<W: io::Write> Drop for Writer<W> {
+
+    /// Finishes the stream if nobody else did, ignoring errors.
+    fn drop(&mut self) {
+        if self.sink.is_some() && !self.finished {
+            let _ = self.finish();
+        }
+    }
+
+}
+
+impl<W: io::Write + fmt::Debug> fmt::Debug for Writer<W> {
+
+    /// Shows the sink and how far along the stream is, but never the
+    /// key, the chaining value or any plaintext.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Writer")
+            .field("sink", &self.sink)
+            .field("padding", &self.padding)
+            .field("batch_size", &self.batch_size)
+            .field("total", &self.total)
+            .field("finished", &self.finished)
+            .finish()
+    }
+
+}
+
+// The number of plaintext bytes waiting for a full block.
+pub fn buffered_len<W: io::Write>(writer: &Writer<W>) -> usize {
+    (writer.buf.len() + writer.staged.len() - writer.staged_pos) % 8
+}
+
+// Records that the writer's iv came from an `IvSource`, for
+// `IvPolicy::require_source`.
+pub fn set_iv_from_source<W: io::Write>(writer: &mut Writer<W>) {
+    writer.iv_from_source = true;
+}
+
+impl<W: io::Write> io::Write for Writer<W> {
+
+    /// Encrypts the bytes in `buf` and passes them through to the
+    /// underlying `std::io::Write`.  If there are not an exact
+    /// multiple of 8 bytes available, the remaining ones will be
+    /// cached until more data is written or the `Writer` is closed.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pad_start.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't write to a finished writer", None));
+        }
+        // Everything is checked before the header is queued, so a
+        // stream that's refused leaves nothing behind in the sink.
+        try!(self.iv_policy.check(&self.iv, self.iv_from_source));
+        let buf = &buf[..try!(self.room(buf.len() as u64)) as usize];
+        self.start();
+        if buf.len() < self.coalesce {
+            // A full staging buffer left over from a sink that
+            // couldn't keep up is encrypted before we take on more.
+            if self.staged.len() >= self.coalesce {
+                try!(self.encrypt_staged());
+            }
+            self.staged.push_all(buf);
+            self.total += buf.len() as u64;
+            if let Some(ref mut crc) = self.crc {
+                crc.update(buf);
+            }
+            if self.staged.len() >= self.coalesce {
+                // These bytes are taken either way; an error will
+                // come up again on the next call.
+                let _ = self.encrypt_staged();
+            }
+            return Ok(buf.len());
+        }
+        try!(self.encrypt_staged());
+        let written = try!(self.write_blocks(buf));
+        self.total += written as u64;
+        if let Some(ref mut crc) = self.crc {
+            crc.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    /// Like `write()`, but encrypts from each of `bufs` in turn, so
+    /// the ciphertext for all of them goes to the sink in as few
+    /// writes as the batch size allows.
+    fn write_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        let mut written = 0;
+        for buf in bufs {
+            match self.write(buf) {
+                Ok(n) => {
+                    written += n;
+                    if n < buf.len() {
+                        break;
+                    }
+                },
+                Err(e) => if written == 0 {
+                    return Err(e);
+                } else {
+                    break;
+                },
+            }
+        }
         Ok(written)
     }
 
-    /// Passes the flush call through to the underlying
-    /// `std::io::Write` object, but only if the internal buffer is
-    /// clear.  If we have some cached bytes that are waiting for a
-    /// full block before they can be encrypted, it is an error to try
-    /// to call `flush()`.
+    /// Passes every complete block encrypted so far through to the
+    /// underlying `std::io::Write` object and flushes it.  Cached
+    /// bytes that are waiting for a full block stay cached, so this
+    /// is always safe to call; use `flush_aligned()` if being off a
+    /// block boundary should be an error.
     fn flush(&mut self) -> io::Result<()> {
+        try!(self.encrypt_staged());
         try!(self.flush_enc_buf());
-
-        if self.buf.is_empty() {
-            self.sink.flush()
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "can't flush when not on a 64-bit block boundary",
-                               Some(format!("we have {} plaintext bytes that we can't encrypt until a full block is done", self.buf.len()))))
-        }
+        self.sink.as_mut().unwrap().flush()
     }
 
 }
@@ -175,8 +806,454 @@ fn it_works() {
             assert_eq!(writer.write(chunk).ok().unwrap(), chunk.len());
         }
 
+        // The header, the input, and a block of padding.
         let result = writer.close().ok().unwrap().into_inner();
-        assert!(result.len() == input.len() + 8);
+        assert!(result.len() == 16 + input.len() + 8);
         assert!(result != input)
     }
 }
+
+#[test]
+fn no_padding() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    let input: Vec<u8> = (0u8..64).collect();
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+        .padding(Padding::None);
+    writer.write_all(&input).ok().unwrap();
+    let crypted = writer.close().ok().unwrap().into_inner();
+    assert_eq!(crypted.len(), 16 + input.len());
+
+    let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6])
+        .padding(Padding::None);
+    let mut decrypted = Vec::new();
+    assert!(reader.read_to_end(&mut decrypted).is_ok());
+    assert_eq!(decrypted, input);
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6])
+        .padding(Padding::None);
+    writer.write_all(&input[..63]).ok().unwrap();
+    assert!(writer.close().is_err());
+}
+
+#[test]
+fn close_into_parts() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(b"first segment").ok().unwrap();
+    let (sink, written, chain) = writer.close_into_parts().ok().unwrap();
+    assert_eq!(written, 32);
+    assert_eq!(chain, mem::read_block(&sink.get_ref()[24..32]));
+
+    let mut writer = Writer::new(sink, [1, 2, 3, 4], chain).raw();
+    writer.write_all(b"second segment").ok().unwrap();
+    let (sink, written, _) = writer.close_into_parts().ok().unwrap();
+    assert_eq!(written, 16);
+    let crypted = sink.into_inner();
+
+    let mut reader = Reader::new(io::Cursor::new(crypted[32..].to_vec()), [1, 2, 3, 4], chain).raw();
+    let mut s = String::new();
+    reader.read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "second segment");
+}
+
+#[test]
+fn batching() {
+    use std::io::Write;
+
+    struct CountingSink(Vec<u8>, usize);
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1 += 1;
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let input: Vec<u8> = (0..64 * 1024).map(|i| i as u8).collect();
+    let mut writer = Writer::new(CountingSink(Vec::new(), 0), [1, 2, 3, 4], [5, 6])
+        .batch_size(4096);
+    for chunk in input.chunks(8) {
+        writer.write_all(chunk).ok().unwrap();
+    }
+    let sink = writer.close().ok().unwrap();
+    assert_eq!(sink.0.len(), 16 + input.len() + 8);
+    assert!(sink.1 <= input.len() / 4096 + 2, "{} sink writes", sink.1);
+}
+
+#[test]
+fn slow_sink() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    // Only ever accepts a single byte per write.
+    struct OneByte(Vec<u8>);
+    impl Write for OneByte {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(&buf[..cmp::min(buf.len(), 1)])
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let input: Vec<u8> = (0u8..200).collect();
+    for chunk_size in 1..20 {
+        let mut writer = Writer::new(OneByte(Vec::new()), [1, 2, 3, 4], [5, 6])
+            .batch_size(16);
+        for chunk in input.chunks(chunk_size) {
+            writer.write_all(chunk).ok().unwrap();
+        }
+        let crypted = writer.close().ok().unwrap().0;
+        assert_eq!(crypted.len(), 16 + input.len() + 8);
+
+        let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6]);
+        let mut decrypted = Vec::new();
+        assert!(reader.read_to_end(&mut decrypted).is_ok());
+        assert_eq!(decrypted, input);
+    }
+}
+
+#[test]
+fn nonblocking_sink() {
+    use std::cell::Cell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+    use super::Reader;
+
+    // Cycles through refusing, getting interrupted, and taking 3
+    // bytes, for as long as `flaky` is set.
+    struct Flaky(Vec<u8>, usize, Rc<Cell<bool>>);
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.2.get() {
+                return self.0.write(buf);
+            }
+            self.1 += 1;
+            match self.1 % 3 {
+                0 => Err(io::Error::new(io::ErrorKind::WouldBlock, "try again", None)),
+                1 => Err(io::Error::new(io::ErrorKind::Interrupted, "signal", None)),
+                _ => self.0.write(&buf[..cmp::min(buf.len(), 3)]),
+            }
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let input: Vec<u8> = (0u8..200).collect();
+    let flaky = Rc::new(Cell::new(true));
+    let mut writer = Writer::new(Flaky(Vec::new(), 0, flaky.clone()), [1, 2, 3, 4], [5, 6])
+        .batch_size(16);
+    let mut pos = 0;
+    while pos < input.len() {
+        match writer.write(&input[pos..cmp::min(pos + 5, input.len())]) {
+            Ok(n) => pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+    loop {
+        match writer.flush() {
+            Ok(()) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+    flaky.set(false);
+    let crypted = writer.close().ok().unwrap().0;
+
+    let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6]);
+    let mut decrypted = Vec::new();
+    assert!(reader.read_to_end(&mut decrypted).is_ok());
+    assert_eq!(decrypted, input);
+}
+
+#[test]
+fn finish() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    let mut crypted = Vec::new();
+    {
+        let mut writer = Writer::new(&mut crypted, [1, 2, 3, 4], [5, 6]);
+        writer.write_all(b"dropped, not closed").ok().unwrap();
+        assert!(!writer.is_finished());
+    }
+    let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6]);
+    let mut s = String::new();
+    reader.read_to_string(&mut s).ok().unwrap();
+    assert_eq!(s, "dropped, not closed");
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(b"finished").ok().unwrap();
+    writer.finish().ok().unwrap();
+    assert!(writer.is_finished());
+    assert!(writer.write(b"more").is_err());
+    writer.finish().ok().unwrap();
+    assert_eq!(writer.close().ok().unwrap().into_inner().len(), 32);
+}
+
+#[test]
+fn flush_partial_block() {
+    use std::io::Write;
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    write!(writer, "exactly sixteen!").ok().unwrap();
+    writer.flush_aligned().ok().unwrap();
+    writeln!(writer, "short").ok().unwrap();
+    writer.flush().ok().unwrap();
+    assert!(writer.flush_aligned().is_err());
+    assert_eq!(writer.close().ok().unwrap().into_inner().len(), 40);
+}
+
+#[test]
+fn accessors() {
+    use std::io::Write;
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(b"eight by").ok().unwrap();
+    writer.flush().ok().unwrap();
+    assert_eq!(writer.get_ref().get_ref().len(), 24);
+    writer.get_mut().set_position(24);
+    writer.write_all(b"te and a bit").ok().unwrap();
+    assert_eq!(writer.into_inner().ok().unwrap().into_inner().len(), 32);
+}
+
+#[test]
+fn with_capacity() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    let input: Vec<u8> = (0u8..100).collect();
+    let mut writer = Writer::with_capacity(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6], 20);
+    writer.write_all(&input).ok().unwrap();
+    assert_eq!(writer.get_ref().get_ref().len(), 112);
+    let ciphertext = writer.close().ok().unwrap().into_inner();
+
+    let mut reader = Reader::with_capacity(io::Cursor::new(ciphertext), [1, 2, 3, 4], [5, 6], 3);
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).ok().unwrap();
+    assert_eq!(output, input);
+}
+
+#[test]
+fn write_vectored() {
+    use std::io::{IoSlice, Write};
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    let n = writer.write_vectored(&[IoSlice::new(b"Hello"), IoSlice::new(b""), IoSlice::new(b", world!")]).ok().unwrap();
+    assert_eq!(n, 13);
+    let vectored = writer.close().ok().unwrap().into_inner();
+
+    let mut writer = Writer::new(io::Cursor::new(Vec::new()), [1, 2, 3, 4], [5, 6]);
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert_eq!(vectored, writer.close().ok().unwrap().into_inner());
+}
+
+#[test]
+fn key_usage_limit() {
+    use std::io::Write;
+    use padding::Bucket;
+
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).key_usage_limit(20);
+    writer.write_all(&[0; 16]).ok().unwrap();
+    assert_eq!(writer.write(&[0; 16]).ok().unwrap(), 4);
+    assert_eq!(writer.write(&[0; 16]).err().unwrap().kind(), io::ErrorKind::Other);
+    assert_eq!(writer.write(&[]).ok().unwrap(), 0);
+    assert_eq!(writer.close().ok().unwrap().len(), 40);
+
+    // Bucket padding is counted like the plaintext: 13 bytes pad out
+    // to 16, which doesn't fit under a limit of 15.
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).key_usage_limit(15)
+        .padding(Padding::Bucket(Bucket::PowerOfTwo));
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert_eq!(writer.finish().err().unwrap().kind(), io::ErrorKind::Other);
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).key_usage_limit(16)
+        .padding(Padding::Bucket(Bucket::PowerOfTwo));
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert_eq!(writer.close().ok().unwrap().len(), 16 + 16);
+}
+
+#[test]
+fn debug() {
+    use std::io::Write;
+
+    let mut writer = Writer::new(Vec::new(), [0x1234, 2, 3, 4], [0x5678, 6]);
+    writer.write_all(b"secret").ok().unwrap();
+    let debug = format!("{:?}", writer);
+    assert!(debug.starts_with("Writer { sink: Some([]), padding: Pkcs7, batch_size: 8192, total: 6"));
+    assert!(!debug.contains("4660") && !debug.contains("22136") && !debug.contains("115"));
+}
+
+#[test]
+fn reset() {
+    use std::io::Write;
+
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).key_usage_limit(20);
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    writer.finish().ok().unwrap();
+    let first = writer.get_mut().split_off(0);
+
+    // Unfinished streams are abandoned.
+    writer.reset([5, 7]);
+    writer.write_all(b"abc").ok().unwrap();
+    writer.reset([5, 6]);
+    assert_eq!(writer.write(b"Hello, world!").ok().unwrap(), 4);
+    writer.reset([5, 6]);
+    assert!(writer.write(b"Hello, world!").is_err());
+
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [9, 9]);
+    writer.write_all(b"abc").ok().unwrap();
+    writer.reset([5, 6]);
+    writer.write_all(b"Hello, world!").ok().unwrap();
+    assert_eq!(writer.close().ok().unwrap(), first);
+}
+
+#[test]
+fn header() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).padding(Padding::None);
+    writer.write_all(b"sixteen bytes!!!").ok().unwrap();
+    let crypted = writer.close().ok().unwrap();
+    assert_eq!(&crypted[..16], &Header{ padding: Padding::None, ..Header::new([5, 6]) }.to_bytes()[..]);
+
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).padding(Padding::None).raw();
+    writer.write_all(b"sixteen bytes!!!").ok().unwrap();
+    let raw = writer.close().ok().unwrap();
+    assert_eq!(&raw[..], &crypted[16..]);
+
+    let read = |crypted: &[u8], padding: Padding, iv: [u32; 2]| {
+        let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], iv).padding(padding);
+        let mut s = String::new();
+        reader.read_to_string(&mut s).map(|_| s)
+    };
+    assert_eq!(read(&crypted, Padding::None, [5, 6]).ok().unwrap(), "sixteen bytes!!!");
+    // The iv and padding come from the header.
+    assert_eq!(read(&crypted, Padding::Pkcs7, [0, 0]).ok().unwrap(), "sixteen bytes!!!");
+    assert!(read(&raw, Padding::None, [5, 6]).is_err());
+    let mut future = crypted.clone();
+    future[4] = 3;
+    let err = read(&future, Padding::None, [5, 6]).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn byte_order() {
+    use std::io::{Read, Write};
+    use super::Reader;
+
+    // A published XTEA test vector; with a zero iv, the first block of
+    // CBC is just the cipher.
+    let key = Key::from_hex("000102030405060708090a0b0c0d0e0f").ok().unwrap();
+    let mut writer = Writer::new(Vec::new(), key, [0, 0]).padding(Padding::None).raw()
+        .byte_order(ByteOrder::BigEndian);
+    writer.write_all(b"ABCDEFGH").ok().unwrap();
+    let crypted = writer.close().ok().unwrap();
+    assert_eq!(crypted, [0x49, 0x7d, 0xf3, 0xd0, 0x72, 0x61, 0x2c, 0xb5]);
+
+    let encrypt = |order: ByteOrder| {
+        let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).byte_order(order);
+        writer.write_all(b"Hello, world!").ok().unwrap();
+        writer.close().ok().unwrap()
+    };
+    let decrypt = |crypted: &[u8], order: ByteOrder| {
+        let mut reader = Reader::new(io::Cursor::new(crypted), [1, 2, 3, 4], [5, 6]).byte_order(order);
+        let mut s = String::new();
+        reader.read_to_string(&mut s).map(|_| s)
+    };
+    let big = encrypt(ByteOrder::BigEndian);
+    let little = encrypt(ByteOrder::LittleEndian);
+    assert!(big != little);
+    assert_eq!(decrypt(&big, ByteOrder::BigEndian).ok().unwrap(), "Hello, world!");
+    assert_eq!(decrypt(&little, ByteOrder::LittleEndian).ok().unwrap(), "Hello, world!");
+    // The header records the byte order, so the `Reader`'s is only
+    // used for raw streams.
+    assert_eq!(decrypt(&big, ByteOrder::LittleEndian).ok().unwrap(), "Hello, world!");
+    let header_len = Header::new([5, 6]).to_bytes().len();
+    let mut reader = Reader::new(io::Cursor::new(&big[header_len..]), [1, 2, 3, 4], [5, 6]).raw()
+        .byte_order(ByteOrder::LittleEndian);
+    assert!(reader.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn in_memory_sinks() {
+    use std::io::Write;
+
+    // Goes through `write` like any other sink.
+    struct Opaque(Vec<u8>);
+    impl Write for Opaque {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn encrypt<W: Write>(mut writer: Writer<W>, input: &[u8], chunk_size: usize) -> W {
+        for chunk in input.chunks(chunk_size) {
+            writer.write_all(chunk).ok().unwrap();
+        }
+        writer.close().ok().unwrap()
+    }
+
+    let input: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    for &chunk_size in [1, 7, 8, 13, 64, 1000].iter() {
+        let expected = encrypt(Writer::new(Opaque(b"abc".to_vec()), [1, 2, 3, 4], [5, 6]).batch_size(16), &input, chunk_size).0;
+        assert_eq!(encrypt(Writer::new(b"abc".to_vec(), [1, 2, 3, 4], [5, 6]).batch_size(16), &input, chunk_size), expected);
+
+        let mut cursor = io::Cursor::new(b"abc".to_vec());
+        cursor.set_position(3);
+        let cursor = encrypt(Writer::new(cursor, [1, 2, 3, 4], [5, 6]).batch_size(16), &input, chunk_size);
+        assert_eq!(cursor.position(), expected.len() as u64);
+        assert_eq!(cursor.into_inner(), expected);
+
+        // A cursor that isn't at its end gets overwritten, not appended to.
+        let cursor = encrypt(Writer::new(io::Cursor::new(vec![0xff; 2000]), [1, 2, 3, 4], [5, 6]), &input, chunk_size);
+        assert_eq!(cursor.position(), expected.len() as u64 - 3);
+        assert_eq!(&cursor.get_ref()[..expected.len() - 3], &expected[3..]);
+        assert!(cursor.get_ref()[expected.len() - 3..].iter().all(|&b| b == 0xff));
+    }
+}
+
+#[test]
+fn coalesce_writes() {
+    use std::io::Write;
+
+    let input: Vec<u8> = (0u8..200).collect();
+    let encrypt = |coalesce: usize| {
+        let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).crc_trailer().length_trailer()
+            .coalesce_writes(coalesce);
+        for chunk in input.chunks(3) {
+            assert_eq!(writer.write(chunk).ok().unwrap(), chunk.len());
+        }
+        writer.write_all(&input).ok().unwrap();
+        writer.close().ok().unwrap()
+    };
+    let expected = encrypt(0);
+    for &coalesce in [1, 4, 8, 64, 1000].iter() {
+        assert_eq!(encrypt(coalesce), expected);
+    }
+
+    // Nothing is encrypted until the threshold, or a flush.
+    let mut writer = Writer::new(Vec::new(), [1, 2, 3, 4], [5, 6]).raw().coalesce_writes(32);
+    writer.write_all(b"Hello, ").ok().unwrap();
+    writer.write_all(b"world!").ok().unwrap();
+    assert_eq!(writer.get_ref().len(), 0);
+    assert_eq!(buffered_len(&writer), 5);
+    writer.flush().ok().unwrap();
+    assert_eq!(writer.get_ref().len(), 8);
+    writer.write_all(&[0; 24]).ok().unwrap();
+    assert_eq!(writer.get_ref().len(), 8);
+    writer.write_all(&[0; 8]).ok().unwrap();
+    assert_eq!(writer.get_ref().len(), 40);
+    assert_eq!(buffered_len(&writer), 5);
+}
@@ -0,0 +1,120 @@
+//! Derives keys from passwords, PBKDF2-style, using HMAC over the
+//! crate's own XTEA-based hash, and subkeys from a master key using
+//! CMAC.
+
+use super::Key;
+use mac;
+use hash::{self, Hasher};
+
+// The HMAC block size.  The hash's compression function takes 8 bytes
+// at a time, but HMAC wants keys padded to at least the output size.
+const BLOCK_SIZE: usize = 16;
+
+// HMAC (RFC 2104) of `data` under `key`.
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut padded = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        padded.clone_from_slice(&hash::digest(key));
+    } else {
+        padded[..key.len()].clone_from_slice(key);
+    }
+    let mut inner = Hasher::new();
+    let mut outer = Hasher::new();
+    let (mut ipad, mut opad) = ([0u8; BLOCK_SIZE], [0u8; BLOCK_SIZE]);
+    for i in 0..BLOCK_SIZE {
+        ipad[i] = padded[i] ^ 0x36;
+        opad[i] = padded[i] ^ 0x5c;
+    }
+    inner.update(&ipad);
+    inner.update(data);
+    outer.update(&opad);
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+impl Key {
+
+    /// Derives a key from a password, PBKDF2-style (RFC 8018), with
+    /// HMAC over an XTEA-based hash as the pseudorandom function.  The
+    /// `salt` should be random and stored alongside whatever is
+    /// encrypted; the more `iterations`, the slower each guess at the
+    /// password is for an attacker (and for you).  Use at least
+    /// 100,000 or so; zero is treated as one.
+    ///
+    /// # Example:
+    /// ```
+    /// use tea::Key;
+    ///
+    /// let key = Key::from_password(b"correct horse battery staple", b"some salt", 1000);
+    /// assert_eq!(key, Key::from_password(b"correct horse battery staple", b"some salt", 1000));
+    /// ```
+    pub fn from_password(password: &[u8], salt: &[u8], iterations: u32) -> Key {
+        // We only need one output block, so the block index is always
+        // 1.
+        let mut first = salt.to_vec();
+        first.push_all(&[0, 0, 0, 1]);
+        let mut u = hmac(password, &first);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac(password, &u);
+            for i in 0..16 {
+                t[i] ^= u[i];
+            }
+        }
+        Key::from_bytes(&t)
+    }
+
+    /// Derives a subkey for the given `context` (a file name, a
+    /// purpose like `b"backups"`, and so on) from this master key, so
+    /// that one secret can cover many streams without any two of them
+    /// sharing a key.  The same master key and context always give the
+    /// same subkey.  This is a counter-mode KDF (NIST SP 800-108) with
+    /// XTEA-CMAC as the pseudorandom function.
+    ///
+    /// # Example:
+    /// ```
+    /// use tea::Key;
+    ///
+    /// let master = Key::new([1, 2, 3, 4]);
+    /// let photos = master.derive_subkey(b"photos");
+    /// assert_eq!(photos, master.derive_subkey(b"photos"));
+    /// assert!(photos != master.derive_subkey(b"documents"));
+    /// ```
+    pub fn derive_subkey(&self, context: &[u8]) -> Key {
+        // Each CMAC gives half the key; they differ only in the
+        // leading counter byte.
+        let mut input = vec![0u8];
+        input.push_all(context);
+        input[0] = 1;
+        let mut bytes = [0u8; 16];
+        bytes[..8].clone_from_slice(&mac::cmac(*self, &input));
+        input[0] = 2;
+        bytes[8..].clone_from_slice(&mac::cmac(*self, &input));
+        Key::from_bytes(&bytes)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let key = Key::from_password(b"password", b"salt", 10);
+    assert_eq!(key, Key::from_password(b"password", b"salt", 10));
+    assert!(key != Key::from_password(b"passwore", b"salt", 10));
+    assert!(key != Key::from_password(b"password", b"salu", 10));
+    assert!(key != Key::from_password(b"password", b"salt", 11));
+    assert_eq!(Key::from_password(b"password", b"salt", 0), Key::from_password(b"password", b"salt", 1));
+    // Long passwords are hashed down first.
+    assert!(Key::from_password(&[1; 40], b"salt", 1) != Key::from_password(&[1; 41], b"salt", 1));
+}
+
+#[test]
+fn subkeys() {
+    let master = Key::new([1, 2, 3, 4]);
+    let a = master.derive_subkey(b"a");
+    assert_eq!(a, master.derive_subkey(b"a"));
+    assert!(a != master);
+    assert!(a != master.derive_subkey(b"b"));
+    assert!(a != master.derive_subkey(b""));
+    assert!(a != Key::new([1, 2, 3, 5]).derive_subkey(b"a"));
+    assert!(a[0] != a[2] || a[1] != a[3]);
+}
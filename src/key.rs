@@ -0,0 +1,317 @@
+//! The 128-bit XTEA key, and the ways of building one.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Deref, Index};
+use std::ptr;
+
+use super::Error;
+#[cfg(feature = "serde")]
+use mem;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer};
+#[cfg(feature = "mlock")]
+use lock::LockedKey as KeyBox;
+
+// Where a `SecretKey` keeps its key: on the heap, so moving the
+// `SecretKey` around doesn't leave copies behind, and with the
+// `mlock` feature, on a page that can't be swapped out.
+#[cfg(not(feature = "mlock"))]
+type KeyBox = Box<Key>;
+
+/// A key is 128 bits, held as the four 32-bit words the cipher works
+/// on.  Anywhere a key is taken, a plain `[u32; 4]` works too.  To get
+/// a key from bytes, use `from_bytes`, `from_hex` or `from_u128`
+/// rather than packing the words by hand; these all read the key
+/// big-endian, as in the XTEA reference test vectors.  Its `Debug`
+/// output is just `Key(..)`, so keys don't end up in logs.
+///
+/// # Example:
+/// ```
+/// use tea::Key;
+///
+/// let key = Key::from_hex("000102030405060708090a0b0c0d0e0f").ok().unwrap();
+/// assert_eq!(key, Key::new([0x00010203, 0x04050607, 0x08090a0b, 0x0c0d0e0f]));
+/// assert_eq!(key, Key::from_u128(0x000102030405060708090a0b0c0d0e0f));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key([u32; 4]);
+
+impl Key {
+
+    /// Makes a key out of its four 32-bit words.
+    pub fn new(words: [u32; 4]) -> Key {
+        Key(words)
+    }
+
+    /// Makes a key out of 16 bytes, read as four big-endian words.
+    pub fn from_bytes(bytes: &[u8; 16]) -> Key {
+        let mut words = [0u32; 4];
+        for i in 0..16 {
+            words[i / 4] = (words[i / 4] << 8) | bytes[i] as u32;
+        }
+        Key(words)
+    }
+
+    /// Parses a key from 32 hex digits (in either case), that is, the
+    /// 16 bytes `from_bytes` takes.  Fails with `Error::InvalidKey` on
+    /// anything else.
+    pub fn from_hex(hex: &str) -> Result<Key, Error> {
+        let digits = hex.as_bytes();
+        if digits.len() != 32 {
+            return Err(Error::InvalidKey);
+        }
+        let mut bytes = [0u8; 16];
+        for i in 0..32 {
+            let nibble = match digits[i] {
+                b @ b'0'...b'9' => b - b'0',
+                b @ b'a'...b'f' => b - b'a' + 10,
+                b @ b'A'...b'F' => b - b'A' + 10,
+                _ => return Err(Error::InvalidKey),
+            };
+            bytes[i / 2] = (bytes[i / 2] << 4) | nibble;
+        }
+        Ok(Key::from_bytes(&bytes))
+    }
+
+    /// The 16 bytes `from_bytes` takes to make this key.
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        for i in 0..16 {
+            bytes[i] = (self.0[i / 4] >> (24 - 8 * (i % 4))) as u8;
+        }
+        bytes
+    }
+
+    /// Makes a key out of a 128-bit number, most significant word
+    /// first.
+    pub fn from_u128(n: u128) -> Key {
+        Key([(n >> 96) as u32, (n >> 64) as u32, (n >> 32) as u32, n as u32])
+    }
+
+}
+
+impl From<[u32; 4]> for Key {
+
+    fn from(words: [u32; 4]) -> Key {
+        Key(words)
+    }
+
+}
+
+impl<'a> TryFrom<&'a [u8]> for Key {
+
+    type Error = Error;
+
+    /// Makes a key out of a slice that must be exactly 16 bytes long,
+    /// like `from_bytes`.
+    fn try_from(bytes: &'a [u8]) -> Result<Key, Error> {
+        if bytes.len() != 16 {
+            return Err(Error::InvalidKey);
+        }
+        let mut array = [0u8; 16];
+        array.clone_from_slice(bytes);
+        Ok(Key::from_bytes(&array))
+    }
+
+}
+
+impl fmt::Debug for Key {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+
+}
+
+/// With the `serde` feature, a key can be read from the 32 hex digits
+/// `from_hex` takes, so it can come from a config file.  There's
+/// deliberately no `Serialize`, so a key can't be written back out
+/// alongside the rest of the settings by accident.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Key {
+
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let mut hex = try!(String::deserialize(deserializer));
+        let key = Key::from_hex(&hex);
+        mem::wipe(unsafe { hex.as_mut_vec() });
+        key.map_err(|_| de::Error::custom("expected a key as 32 hex digits"))
+    }
+
+}
+
+impl Index<usize> for Key {
+
+    type Output = u32;
+
+    fn index(&self, i: usize) -> &u32 {
+        &self.0[i]
+    }
+
+}
+
+/// Overwrites `key` with zeroes, in a way the compiler won't optimize
+/// out.
+pub fn wipe(key: &mut Key) {
+    for word in key.0.iter_mut() {
+        unsafe { ptr::write_volatile(word, 0) };
+    }
+}
+
+/// Holds a `Key` that's wiped from memory when it's dropped.  It can't
+/// be copied by accident, and it prints as `SecretKey(..)` so it stays
+/// out of logs.  Pass `&secret_key` anywhere a key is taken; the
+/// `io` wrappers keep their own copy too, in a `SecretKey` or as a
+/// `cipher::Cipher` key schedule, which is wiped the same way, and
+/// wipe their plaintext buffers when dropped.
+///
+/// The key lives on the heap.  With the `mlock` feature it gets a page
+/// of its own that's locked into RAM, so it's never swapped to disk;
+/// see `is_locked`.
+///
+/// # Example:
+/// ```
+/// use tea::SecretKey;
+///
+/// let key = SecretKey::from_bytes(b"sixteen byte key");
+/// let ciphertext = tea::encrypt(&key, [5, 6], b"Hello, world!");
+/// assert_eq!(format!("{:?}", key), "SecretKey(..)");
+/// ```
+pub struct SecretKey(KeyBox);
+
+impl SecretKey {
+
+    /// Takes charge of `key`.  The `Key` passed in is a copy, so wipe
+    /// the original too if it's still around.
+    pub fn new(key: Key) -> SecretKey {
+        SecretKey(KeyBox::new(key))
+    }
+
+    /// Makes a key out of 16 bytes, like `Key::from_bytes`.
+    pub fn from_bytes(bytes: &[u8; 16]) -> SecretKey {
+        SecretKey::new(Key::from_bytes(bytes))
+    }
+
+    /// Whether the key is locked into RAM.  Locking is best-effort:
+    /// the operating system can refuse, most often because the
+    /// process has hit its limit on locked memory (`ulimit -l`).  Only
+    /// built with the `mlock` feature.
+    #[cfg(feature = "mlock")]
+    pub fn is_locked(&self) -> bool {
+        self.0.is_locked()
+    }
+
+}
+
+impl From<Key> for SecretKey {
+
+    fn from(key: Key) -> SecretKey {
+        SecretKey::new(key)
+    }
+
+}
+
+/// Reads a key like `Key` does.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for SecretKey {
+
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SecretKey, D::Error> {
+        let mut key = try!(Key::deserialize(deserializer));
+        let secret_key = SecretKey::new(key);
+        wipe(&mut key);
+        Ok(secret_key)
+    }
+
+}
+
+impl<'a> From<&'a SecretKey> for Key {
+
+    fn from(key: &'a SecretKey) -> Key {
+        *key.0
+    }
+
+}
+
+impl Deref for SecretKey {
+
+    type Target = Key;
+
+    fn deref(&self) -> &Key {
+        &self.0
+    }
+
+}
+
+impl fmt::Debug for SecretKey {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+
+}
+
+impl Drop for SecretKey {
+
+    fn drop(&mut self) {
+        wipe(&mut *self.0);
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let key = Key::new([0x00010203, 0x04050607, 0x08090a0b, 0x0c0d0e0f]);
+    let bytes = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    assert_eq!(Key::from_bytes(&bytes), key);
+    assert_eq!(key.to_bytes(), bytes);
+    assert_eq!(Key::try_from(&bytes[..]), Ok(key));
+    assert_eq!(Key::try_from(&bytes[1..]), Err(Error::InvalidKey));
+    assert_eq!(Key::from_hex("000102030405060708090A0B0C0D0E0F"), Ok(key));
+    assert_eq!(Key::from_hex("000102030405060708090a0b0c0d0e0"), Err(Error::InvalidKey));
+    assert_eq!(Key::from_hex("000102030405060708090a0b0c0d0e0g"), Err(Error::InvalidKey));
+    assert_eq!(Key::from_u128(0x000102030405060708090a0b0c0d0e0f), key);
+    assert_eq!(Key::from([0x00010203, 0x04050607, 0x08090a0b, 0x0c0d0e0f]), key);
+    assert_eq!(key[3], 0x0c0d0e0f);
+    assert_eq!(format!("{:?}", key), "Key(..)");
+}
+
+#[test]
+fn reference_vector() {
+    use cipher;
+    use Block;
+
+    let key = Key::from_hex("000102030405060708090a0b0c0d0e0f").ok().unwrap();
+    assert_eq!(cipher::encipher(&key, &Block::new([0x41424344, 0x45464748])), Block::new([0x497df3d0, 0x72612cb5]));
+}
+
+#[test]
+fn secret() {
+    let mut key = Key::new([1, 2, 3, 4]);
+    let secret = SecretKey::new(key);
+    assert_eq!(*secret, key);
+    assert_eq!(Key::from(&secret), key);
+    assert_eq!(format!("{:?}", secret), "SecretKey(..)");
+    wipe(&mut key);
+    assert_eq!(key, Key::new([0, 0, 0, 0]));
+}
+
+#[cfg(feature = "mlock")]
+#[test]
+fn locked() {
+    let secret = SecretKey::new(Key::new([1, 2, 3, 4]));
+    assert_eq!(*secret, Key::new([1, 2, 3, 4]));
+    // Whether the lock took depends on the limits we're run under, but
+    // asking mustn't fail either way.
+    secret.is_locked();
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn deserialize() {
+    let key: Key = ::serde_json::from_str("\"000102030405060708090a0b0c0d0e0f\"").ok().unwrap();
+    assert_eq!(key, Key::from_u128(0x000102030405060708090a0b0c0d0e0f));
+    let secret_key: SecretKey = ::serde_json::from_str("\"000102030405060708090A0B0C0D0E0F\"").ok().unwrap();
+    assert_eq!(Key::from(&secret_key), key);
+    assert!(::serde_json::from_str::<Key>("\"0001\"").is_err());
+    assert!(::serde_json::from_str::<Key>("[0, 1, 2, 3]").is_err());
+}
@@ -1,4 +1,5 @@
-#![feature(collections,core,io)]
+#![feature(collections,core,io,min_specialization)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
 //! Implements the XTEA block cipher, whose reference source is public
 //! domain.  This code is also public domain.
@@ -6,14 +7,91 @@
 //! Also implements a CBC-mode block cipher with padding.  I'm not
 //! good at crypto so don't use this.
 
-/// A key is 128 bits.  We don't seem to need SIMD anywhere so it's
-/// just an array.
-pub type Key = [u32; 4];
+#[cfg(feature = "digest")]
+extern crate digest;
+#[cfg(all(test, feature = "digest"))]
+extern crate hmac;
+#[cfg(feature = "keyring")]
+extern crate keyring;
+#[cfg(all(any(feature = "mlock", feature = "mmap", feature = "uring"), unix))]
+extern crate libc;
+// pyo3's macros name `::core`, which this edition only finds at the
+// crate root.
+#[cfg(feature = "pyo3")]
+extern crate core;
+#[cfg(feature = "pyo3")]
+extern crate pyo3;
+#[cfg(feature = "rand")]
+extern crate rand;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "rustcrypto")]
+extern crate rustcrypto_cipher;
+#[cfg(feature = "scrypt")]
+extern crate scrypt;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(all(test, feature = "digest"))]
+extern crate sha2;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(all(feature = "mlock", windows))]
+extern crate windows_sys;
 
-/// XTEA uses 64-bit blocks; for simplicity and to match the reference
-/// source, we use an array here too.
-pub type Block = [u32; 2];
+pub use block::{Block, ByteOrder};
+pub use buffer::{encrypt, decrypt, encrypt_to, encrypt_in_place, decrypt_in_place};
+pub use buffer::{encrypt_padded_in_place, decrypt_padded_in_place};
+pub use buffer::{encrypt_convergent, decrypt_convergent};
+pub use error::Error;
+pub use key::{Key, SecretKey};
+#[cfg(feature = "keyring")]
+pub use credential::KeyringError;
+pub use sector::SectorCipher;
+pub use selftest::{self_test, Check, SelfTestReport};
+pub use wrap::{wrap_key, unwrap_key};
+#[cfg(feature = "rustcrypto")]
+pub use xtea::Xtea;
 
 pub mod cipher;
+pub mod ct;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fs;
+pub mod hash;
 pub mod io;
+pub mod mac;
+pub mod padding;
+#[cfg(feature = "pyo3")]
+pub mod python;
+#[cfg(feature = "secretshare")]
+pub mod secretshare;
+pub mod vectors;
+mod block;
+mod buffer;
+mod crc;
+#[cfg(feature = "keyring")]
+mod credential;
+mod error;
+#[cfg(any(feature = "ffi", feature = "wasm"))]
+mod incremental;
+mod kdf;
+mod key;
+#[cfg(feature = "mlock")]
+mod lock;
 mod mem;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+#[cfg(feature = "rand")]
+mod random;
+mod sector;
+mod selftest;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod wrap;
+#[cfg(feature = "rustcrypto")]
+mod xtea;
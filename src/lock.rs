@@ -0,0 +1,125 @@
+//! Keeps keys in memory that's locked into RAM (with `mlock` on Unix
+//! and `VirtualLock` on Windows), so they're never written out to
+//! swap.  Only built with the `mlock` feature.
+
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+#[cfg(unix)]
+use libc;
+#[cfg(windows)]
+use windows_sys;
+
+use super::Key;
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(unix)]
+unsafe fn lock(ptr: *mut u8, len: usize) -> bool {
+    libc::mlock(ptr as *const libc::c_void, len) == 0
+}
+
+#[cfg(unix)]
+unsafe fn unlock(ptr: *mut u8, len: usize) {
+    libc::munlock(ptr as *const libc::c_void, len);
+}
+
+#[cfg(windows)]
+fn page_size() -> usize {
+    use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+    unsafe {
+        let mut info: SYSTEM_INFO = ::std::mem::zeroed();
+        GetSystemInfo(&mut info);
+        info.dwPageSize as usize
+    }
+}
+
+#[cfg(windows)]
+unsafe fn lock(ptr: *mut u8, len: usize) -> bool {
+    windows_sys::Win32::System::Memory::VirtualLock(ptr as *const _, len) != 0
+}
+
+#[cfg(windows)]
+unsafe fn unlock(ptr: *mut u8, len: usize) {
+    windows_sys::Win32::System::Memory::VirtualUnlock(ptr as *const _, len);
+}
+
+/// A `Key` on a page of its own, locked into RAM if the operating
+/// system allows it.  Locks apply to whole pages, so sharing one with
+/// anything else would mean unlocking it out from under them.
+pub struct LockedKey {
+    ptr: *mut Key,
+    layout: Layout,
+    locked: bool,
+}
+
+impl LockedKey {
+
+    /// Moves `key` onto a new page and tries to lock it.  Failing to
+    /// lock (say the process is over `RLIMIT_MEMLOCK`) isn't an error;
+    /// check `is_locked` if it matters.
+    pub fn new(key: Key) -> LockedKey {
+        let size = page_size();
+        let layout = Layout::from_size_align(size, size).unwrap();
+        unsafe {
+            let page = alloc::alloc_zeroed(layout);
+            if page.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            let locked = lock(page, size);
+            let ptr = page as *mut Key;
+            ptr::write(ptr, key);
+            LockedKey{
+                ptr: ptr,
+                layout: layout,
+                locked: locked,
+            }
+        }
+    }
+
+    /// Whether the page is actually locked.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+}
+
+impl Deref for LockedKey {
+
+    type Target = Key;
+
+    fn deref(&self) -> &Key {
+        unsafe { &*self.ptr }
+    }
+
+}
+
+impl DerefMut for LockedKey {
+
+    fn deref_mut(&mut self) -> &mut Key {
+        unsafe { &mut *self.ptr }
+    }
+
+}
+
+impl Drop for LockedKey {
+
+    /// Unlocks and frees the page.  The owner wipes the key first.
+    fn drop(&mut self) {
+        unsafe {
+            if self.locked {
+                unlock(self.ptr as *mut u8, self.layout.size());
+            }
+            alloc::dealloc(self.ptr as *mut u8, self.layout);
+        }
+    }
+
+}
+
+unsafe impl Send for LockedKey {}
+unsafe impl Sync for LockedKey {}
@@ -0,0 +1,278 @@
+//! Message authentication codes and checksums built on XTEA, for a
+//! 64-bit integrity tag without bringing in another primitive.  Message blocks are read
+//! big-endian, so tags don't depend on the host.
+
+use std::cmp;
+
+use super::{Key, Block};
+use cipher;
+
+// The constant for doubling in GF(2^64), from SP 800-38B.
+const RB: u64 = 0x1b;
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    bytes[..8].iter().fold(0, |n, &b| (n << 8) | b as u64)
+}
+
+// The fixed half of every key in `MmoContext`, from the fractional
+// part of pi.
+const MMO_KEY: [u32; 2] = [0x082efa98, 0xec4e6c89];
+
+// Multiplies `n` by x in GF(2^64).
+fn double(n: u64) -> u64 {
+    if n >> 63 == 1 { (n << 1) ^ RB } else { n << 1 }
+}
+
+/// Computes the CBC-MAC of `data` under `key`.  Plain CBC-MAC is only
+/// safe when every message has the same length, so the length of
+/// `data` (as a big-endian u64) goes in as the first block, and the
+/// last partial block is filled out with zeroes.  That way no message
+/// is a prefix of another, and messages of any length can share a
+/// key.  Don't use the same key for encryption as well.
+///
+/// # Example:
+/// ```
+/// use tea::Key;
+///
+/// let key = Key::new([1, 2, 3, 4]);
+/// let tag = tea::mac::cbc_mac(key, b"Hello, world!");
+/// assert_eq!(tag, tea::mac::cbc_mac(key, b"Hello, world!"));
+/// assert!(tag != tea::mac::cbc_mac(key, b"Hello, world?"));
+/// ```
+pub fn cbc_mac<K: Into<Key>>(key: K, data: &[u8]) -> [u8; 8] {
+    let key = key.into();
+    let mut state = cipher::encipher(&key, &Block::from(data.len() as u64));
+    for chunk in data.chunks(8) {
+        let mut bytes = [0u8; 8];
+        bytes[..chunk.len()].clone_from_slice(chunk);
+        let block = Block::from_be_bytes(&bytes);
+        state = cipher::encipher(&key, &Block::new([state[0] ^ block[0], state[1] ^ block[1]]));
+    }
+    state.to_be_bytes()
+}
+
+/// Computes CMAC (OMAC1, NIST SP 800-38B) over XTEA a piece at a
+/// time, for messages that don't fit in memory or arrive in parts.
+/// Unlike `cbc_mac`, it doesn't need to know the length up front, and
+/// it's safe for messages of any length.
+///
+/// # Example:
+/// ```
+/// use tea::mac::CmacContext;
+///
+/// let mut mac = CmacContext::new([1, 2, 3, 4]);
+/// mac.update(b"Hello, ");
+/// mac.update(b"world!");
+/// assert_eq!(mac.finalize(), tea::mac::cmac([1, 2, 3, 4], b"Hello, world!"));
+/// ```
+pub struct CmacContext {
+    key: Key,
+    k1: u64,
+    k2: u64,
+    state: u64,
+    buf: [u8; 8],
+    buf_len: usize,
+}
+
+impl CmacContext {
+
+    /// Starts a CMAC under `key`, working out its two subkeys.
+    pub fn new<K: Into<Key>>(key: K) -> CmacContext {
+        let key = key.into();
+        let k1 = double(u64::from(cipher::encipher(&key, &Block::from(0))));
+        CmacContext{
+            key: key,
+            k1: k1,
+            k2: double(k1),
+            state: 0,
+            buf: [0u8; 8],
+            buf_len: 0,
+        }
+    }
+
+    /// Adds `data` to the message.
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // A full block is held back until there's more data,
+            // since the last block is treated differently.
+            if self.buf_len == 8 {
+                self.state = u64::from(self.encipher(self.state ^ read_u64(&self.buf)));
+                self.buf_len = 0;
+            }
+            let n = cmp::min(8 - self.buf_len, data.len());
+            self.buf[self.buf_len..self.buf_len + n].clone_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+        }
+    }
+
+    /// Finishes the message and returns its tag.  The last block is
+    /// masked with the first subkey if it's whole, or padded and
+    /// masked with the second.
+    pub fn finalize(mut self) -> [u8; 8] {
+        let last = if self.buf_len == 8 {
+            read_u64(&self.buf) ^ self.k1
+        } else {
+            for i in self.buf_len..8 {
+                self.buf[i] = if i == self.buf_len { 0x80 } else { 0 };
+            }
+            read_u64(&self.buf) ^ self.k2
+        };
+        self.encipher(self.state ^ last).to_be_bytes()
+    }
+
+    fn encipher(&self, n: u64) -> Block {
+        cipher::encipher(&self.key, &Block::from(n))
+    }
+
+}
+
+/// Computes the CMAC of `data` under `key` in one go.
+pub fn cmac<K: Into<Key>>(key: K, data: &[u8]) -> [u8; 8] {
+    let mut mac = CmacContext::new(key);
+    mac.update(data);
+    mac.finalize()
+}
+
+/// Computes a keyed 64-bit checksum with the Matyas-Meyer-Oseas
+/// construction: each message block is encrypted under a key made
+/// from the chaining value, then XORed back in, starting from a value
+/// derived from `key`.  This catches accidental corruption like bit
+/// rot just as well as CMAC, but it isn't a MAC: anyone who can see
+/// one checksum can forge others, so only use it where nobody is
+/// trying to tamper with the data.
+///
+/// # Example:
+/// ```
+/// use tea::mac::MmoContext;
+///
+/// let mut checksum = MmoContext::new([1, 2, 3, 4]);
+/// checksum.update(b"Hello, ");
+/// checksum.update(b"world!");
+/// assert_eq!(checksum.finalize(), tea::mac::mmo([1, 2, 3, 4], b"Hello, world!"));
+/// ```
+pub struct MmoContext {
+    state: u64,
+    buf: [u8; 8],
+    buf_len: usize,
+    len: u64,
+}
+
+impl MmoContext {
+
+    /// Starts a checksum under `key`.
+    pub fn new<K: Into<Key>>(key: K) -> MmoContext {
+        MmoContext{
+            state: u64::from(cipher::encipher(&key.into(), &Block::from(0))),
+            buf: [0u8; 8],
+            buf_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Adds `data` to the message.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let n = cmp::min(8 - self.buf_len, data.len());
+            self.buf[self.buf_len..self.buf_len + n].clone_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+            if self.buf_len == 8 {
+                let block = read_u64(&self.buf);
+                self.compress(block);
+                self.buf_len = 0;
+            }
+        }
+    }
+
+    /// Pads the message Merkle-Damgård style, with a 0x80 byte, zeroes
+    /// and the length in bits, and returns the checksum.
+    pub fn finalize(mut self) -> [u8; 8] {
+        for i in self.buf_len..8 {
+            self.buf[i] = if i == self.buf_len { 0x80 } else { 0 };
+        }
+        let block = read_u64(&self.buf);
+        self.compress(block);
+        let bits = self.len.wrapping_mul(8);
+        self.compress(bits);
+        Block::from(self.state).to_be_bytes()
+    }
+
+    fn compress(&mut self, block: u64) {
+        let key = Key::new([(self.state >> 32) as u32, self.state as u32, MMO_KEY[0], MMO_KEY[1]]);
+        self.state = u64::from(cipher::encipher(&key, &Block::from(block))) ^ block;
+    }
+
+}
+
+/// Computes the Matyas-Meyer-Oseas checksum of `data` under `key` in
+/// one go.
+pub fn mmo<K: Into<Key>>(key: K, data: &[u8]) -> [u8; 8] {
+    let mut checksum = MmoContext::new(key);
+    checksum.update(data);
+    checksum.finalize()
+}
+
+#[test]
+fn it_works() {
+    let key = Key::new([1, 2, 3, 4]);
+    let input = [0u8; 24];
+    // Zero padding would make these collide without the length block.
+    let tags: Vec<[u8; 8]> = (0..input.len() + 1).map(|len| cbc_mac(key, &input[..len])).collect();
+    for i in 0..tags.len() {
+        for j in 0..i {
+            assert!(tags[i] != tags[j]);
+        }
+    }
+    assert!(cbc_mac(key, b"abc") != cbc_mac([1, 2, 3, 5], b"abc"));
+}
+
+#[test]
+fn cmac_works() {
+    let key = Key::new([1, 2, 3, 4]);
+    let input: Vec<u8> = (0u8..40).collect();
+    let tags: Vec<[u8; 8]> = (0..input.len()).map(|len| cmac(key, &input[..len])).collect();
+    for i in 0..tags.len() {
+        for j in 0..i {
+            assert!(tags[i] != tags[j]);
+        }
+    }
+    // Padding can't be forged by appending its own bytes.
+    assert!(cmac(key, b"abc") != cmac(key, b"abc\x80"));
+    assert!(cmac(key, b"abc") != cmac([1, 2, 3, 5], b"abc"));
+
+    let whole = cmac(key, &input);
+    for split in 0..input.len() + 1 {
+        for step in 1..10 {
+            let mut mac = CmacContext::new(key);
+            mac.update(&input[..split]);
+            for chunk in input[split..].chunks(step) {
+                mac.update(chunk);
+            }
+            assert_eq!(mac.finalize(), whole);
+        }
+    }
+}
+
+#[test]
+fn mmo_works() {
+    let key = Key::new([1, 2, 3, 4]);
+    let input: Vec<u8> = (0u8..40).collect();
+    let sums: Vec<[u8; 8]> = (0..input.len()).map(|len| mmo(key, &input[..len])).collect();
+    for i in 0..sums.len() {
+        for j in 0..i {
+            assert!(sums[i] != sums[j]);
+        }
+    }
+    assert!(mmo(key, b"abc") != mmo([1, 2, 3, 5], b"abc"));
+
+    let whole = mmo(key, &input);
+    for step in 1..20 {
+        let mut checksum = MmoContext::new(key);
+        for chunk in input.chunks(step) {
+            checksum.update(chunk);
+        }
+        assert_eq!(checksum.finalize(), whole);
+    }
+}
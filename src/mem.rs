@@ -1,16 +1,188 @@
-//! Memory twiddling utilities, so far just for reinterpreting between
-//! [u8] and Block.
+//! Memory twiddling utilities, for reinterpreting between [u8] and
+//! Block, and for wiping secrets.
 
-use super::Block;
+use super::{Block, ByteOrder};
 use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
 
-/// Interprets an 8-byte `[u8]` array as a `Block`.
-pub fn read_block<'a>(chunk: &'a [u8]) -> &'a Block {
+/// Reads an 8-byte `[u8]` array as a `Block`, in the host's byte
+/// order.  The words are copied out rather than borrowed, since
+/// `chunk` needn't be aligned for a `Block`.
+pub fn read_block(chunk: &[u8]) -> Block {
     debug_assert_eq!(chunk.len(), 8);
-    unsafe { mem::transmute(chunk.as_ptr() as *const [u8; 8]) }
+    let word = |i: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.clone_from_slice(&chunk[4 * i..4 * i + 4]);
+        u32::from_ne_bytes(bytes)
+    };
+    Block::new([word(0), word(1)])
 }
 
 /// Interprets a `Block` as an 8-byte `[u8]` array.
 pub fn write_block<'a>(block: &'a Block) -> &'a [u8; 8] {
     unsafe { mem::transmute(block) }
 }
+
+/// Reads an 8-byte `[u8]` array as a `Block`, with its words laid
+/// out in `order`.
+pub fn read_block_as(chunk: &[u8], order: ByteOrder) -> Block {
+    debug_assert_eq!(chunk.len(), 8);
+    let word = |i: usize| {
+        let bytes = &chunk[4 * i..4 * i + 4];
+        match order {
+            ByteOrder::BigEndian => bytes.iter().fold(0u32, |n, &b| (n << 8) | b as u32),
+            ByteOrder::LittleEndian => bytes.iter().rev().fold(0u32, |n, &b| (n << 8) | b as u32),
+            ByteOrder::Native => read_block(chunk)[i],
+        }
+    };
+    Block::new([word(0), word(1)])
+}
+
+/// Writes out a `Block` as an 8-byte `[u8]` array, with its words laid
+/// out in `order`.
+pub fn write_block_as(block: &Block, order: ByteOrder) -> [u8; 8] {
+    let mut bytes = *write_block(block);
+    match order {
+        ByteOrder::Native => {},
+        ByteOrder::BigEndian => bytes = block.to_be_bytes(),
+        ByteOrder::LittleEndian => {
+            for i in 0..8 {
+                bytes[i] = (block[i / 4] >> (8 * (i % 4))) as u8;
+            }
+        },
+    }
+    bytes
+}
+
+/// Overwrites `buf` with zeroes, in a way the compiler won't optimize
+/// out even if `buf` is never read again.
+pub fn wipe(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) };
+    }
+}
+
+/// A `Vec<u8>` for holding plaintext, which is wiped (all of its
+/// capacity, not just what's in use) when dropped.
+pub struct SecretVec(Vec<u8>);
+
+impl SecretVec {
+
+    pub fn new() -> SecretVec {
+        SecretVec(Vec::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> SecretVec {
+        SecretVec(Vec::with_capacity(capacity))
+    }
+
+}
+
+impl Deref for SecretVec {
+
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+
+}
+
+impl DerefMut for SecretVec {
+
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+
+}
+
+impl Drop for SecretVec {
+
+    fn drop(&mut self) {
+        let capacity = self.0.capacity();
+        self.0.resize(capacity, 0);
+        wipe(&mut self.0);
+    }
+
+}
+
+/// Up to 16 bytes held in a fixed array, for the partial blocks and
+/// trailers that come and go too often to be worth a heap allocation
+/// each.  Wiped when dropped, like `SecretVec`.
+pub struct SecretArray {
+    bytes: [u8; 16],
+    len: usize,
+}
+
+impl SecretArray {
+
+    pub fn new() -> SecretArray {
+        SecretArray{ bytes: [0; 16], len: 0 }
+    }
+
+    /// Appends `b`; panics if the array is full.
+    pub fn push(&mut self, b: u8) {
+        self.bytes[self.len] = b;
+        self.len += 1;
+    }
+
+    /// Appends all of `bytes`; panics if they don't fit.
+    pub fn push_slice(&mut self, bytes: &[u8]) {
+        self.bytes[self.len..self.len + bytes.len()].clone_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.len = len;
+        }
+    }
+
+}
+
+impl Deref for SecretArray {
+
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+}
+
+impl DerefMut for SecretArray {
+
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+
+}
+
+impl Drop for SecretArray {
+
+    fn drop(&mut self) {
+        wipe(&mut self.bytes);
+    }
+
+}
+
+#[test]
+fn byte_order() {
+    let block = Block::new([0x01020304, 0x05060708]);
+    let be = [1u8, 2, 3, 4, 5, 6, 7, 8];
+    let le = [4u8, 3, 2, 1, 8, 7, 6, 5];
+    assert_eq!(write_block_as(&block, ByteOrder::BigEndian), be);
+    assert_eq!(write_block_as(&block, ByteOrder::LittleEndian), le);
+    assert_eq!(&write_block_as(&block, ByteOrder::Native), write_block(&block));
+    assert_eq!(read_block_as(&be, ByteOrder::BigEndian), block);
+    assert_eq!(read_block_as(&le, ByteOrder::LittleEndian), block);
+    assert_eq!(read_block_as(&be, ByteOrder::Native), read_block(&be));
+
+    // Any offset will do, aligned or not.
+    let mut bytes = [0u8; 11];
+    for offset in 0..4 {
+        bytes[offset..offset + 8].clone_from_slice(write_block(&block));
+        assert_eq!(read_block(&bytes[offset..offset + 8]), block);
+    }
+}
@@ -0,0 +1,84 @@
+//! Maps files into memory with `mmap`, so `fs::encrypt_file_mmap` can
+//! run the cipher straight over them.  Only built with the `mmap`
+//! feature, on Unix.
+
+use std::fs;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+use libc;
+
+/// The first `len` bytes of a file, mapped into memory.  An empty
+/// mapping doesn't call `mmap` at all, since it can't map nothing.
+pub struct Map {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Map {
+
+    fn new(f: &fs::File, len: usize, prot: libc::c_int, flags: libc::c_int) -> io::Result<Map> {
+        if len == 0 {
+            return Ok(Map{ ptr: ptr::null_mut(), len: 0 });
+        }
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, prot, flags, f.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Map{ ptr: ptr as *mut u8, len: len })
+    }
+
+    /// Maps `f` read-only.  If someone else truncates the file while
+    /// it's mapped, touching the missing pages kills the process with
+    /// `SIGBUS`.
+    pub fn read(f: &fs::File, len: usize) -> io::Result<Map> {
+        Map::new(f, len, libc::PROT_READ, libc::MAP_PRIVATE)
+    }
+
+    /// Maps `f` so that writes to the mapping go to the file.  `f` must
+    /// be open for reading and writing, and already `len` bytes long.
+    pub fn write(f: &fs::File, len: usize) -> io::Result<Map> {
+        Map::new(f, len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED)
+    }
+
+}
+
+impl Deref for Map {
+
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+}
+
+impl DerefMut for Map {
+
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+}
+
+impl Drop for Map {
+
+    /// Unmaps the file.  Anything written to a shared mapping is in
+    /// the page cache by now, and reaches the disk like any other
+    /// write.
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len); }
+        }
+    }
+
+}
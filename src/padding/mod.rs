@@ -0,0 +1,145 @@
+//! Padding schemes for the end of a stream.  The PKCS#7 check on
+//! decrypt is written to run in constant time, so that a server
+//! decrypting attacker-supplied data doesn't hand out a padding oracle
+//! through its timing.
+
+pub mod pkcs7;
+
+/// How the final partial block of a stream is handled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Padding {
+    /// Always append 1 to 8 bytes, each holding the number of bytes
+    /// appended.  This is the default.
+    Pkcs7,
+    /// Don't pad at all.  The plaintext must be a multiple of 8 bytes
+    /// long, which saves a whole block for fixed-size records.
+    None,
+    /// Append a 0x80 byte and then zeroes until the ciphertext length
+    /// reaches the next `Bucket` boundary (as in ISO/IEC 7816-4), so
+    /// the exact plaintext length is hidden.
+    Bucket(Bucket),
+}
+
+/// How strictly a reader treats the padding it finds at the end of a
+/// stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Check {
+    /// Malformed padding is an error.  This is the default.
+    Strict,
+    /// Strip whatever looks like padding and carry on, for reading
+    /// data from legacy encoders that wrote zero padding or a
+    /// miscounted pad byte.  See `pkcs7::unpad_lenient`.
+    Lenient,
+}
+
+/// Size classes that `Padding::Bucket` rounds the stream up to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Bucket {
+    /// The next power of two, at least 8 bytes.
+    PowerOfTwo,
+    /// The next multiple of the given number of bytes, which is
+    /// itself rounded up to a multiple of 8.
+    Multiple(u64),
+}
+
+impl Bucket {
+
+    /// Returns the smallest bucket size that can hold `len` bytes.
+    /// Panics if that doesn't fit in a `u64`; see
+    /// `checked_padded_len`.
+    ///
+    /// # Example:
+    /// ```
+    /// use tea::padding::Bucket;
+    ///
+    /// assert_eq!(Bucket::PowerOfTwo.padded_len(100), 128);
+    /// assert_eq!(Bucket::Multiple(256).padded_len(100), 256);
+    /// assert_eq!(Bucket::Multiple(256).padded_len(300), 512);
+    /// ```
+    pub fn padded_len(&self, len: u64) -> u64 {
+        match self.checked_padded_len(len) {
+            Some(padded) => padded,
+            None => panic!("{:?} bucket for {} bytes overflows", self, len),
+        }
+    }
+
+    /// Like `padded_len`, but returns `None` if the bucket size
+    /// doesn't fit in a `u64`, as with a `Bucket::Multiple` read from
+    /// an untrusted header that's close to `u64::MAX`.
+    pub fn checked_padded_len(&self, len: u64) -> Option<u64> {
+        match *self {
+            Bucket::PowerOfTwo => if len <= 8 { Some(8) } else { len.checked_next_power_of_two() },
+            Bucket::Multiple(n) => {
+                let n = if n < 8 { Some(8) } else { n.checked_add(7).map(|n| n / 8 * 8) };
+                n.and_then(|n| len.checked_add(n - 1).map(|end| end / n * n))
+            },
+        }
+    }
+
+}
+
+// Returns 0xff if `a < b`, 0 otherwise, without branching.
+fn ct_lt(a: u8, b: u8) -> u8 {
+    ((a as u16).wrapping_sub(b as u16) >> 8) as u8
+}
+
+/// Checks that the 8-byte `block` ends in valid PKCS#7 padding and
+/// returns the number of padding bytes, or `None` if the padding is
+/// malformed.  Every byte of the block is examined no matter where
+/// the first mismatch is, and there are no data-dependent branches
+/// until the final result.
+///
+/// # Example:
+/// ```
+/// use tea::padding;
+///
+/// assert_eq!(padding::verify_ct(b"abcde\x03\x03\x03"), Some(3));
+/// assert_eq!(padding::verify_ct(b"abcde\x03\x02\x03"), None);
+/// ```
+pub fn verify_ct(block: &[u8]) -> Option<usize> {
+    debug_assert_eq!(block.len(), 8);
+    let pad = block[7];
+    // Nonzero if pad isn't in 1...8.
+    let mut bad = pad.wrapping_sub(1) & 0xf8;
+    for i in 0..8 {
+        let in_pad = ct_lt(7 - i as u8, pad);
+        bad |= in_pad & (block[i] ^ pad);
+    }
+    if bad == 0 {
+        Some(pad as usize)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn it_works() {
+    for pad in 1u8..9 {
+        let mut block = [0xaa; 8];
+        for b in block[8 - pad as usize..].iter_mut() {
+            *b = pad;
+        }
+        assert_eq!(verify_ct(&block), Some(pad as usize));
+        if pad > 1 {
+            block[8 - pad as usize] ^= 1;
+            assert_eq!(verify_ct(&block), None);
+        }
+    }
+    assert_eq!(verify_ct(&[0, 0, 0, 0, 0, 0, 0, 0]), None);
+    assert_eq!(verify_ct(&[9, 9, 9, 9, 9, 9, 9, 9]), None);
+    assert_eq!(verify_ct(&[1, 2, 3, 4, 5, 6, 7, 0xff]), None);
+}
+
+#[test]
+fn checked_padded_len() {
+    use std::u64;
+
+    assert_eq!(Bucket::Multiple(24).checked_padded_len(1), Some(24));
+    assert_eq!(Bucket::Multiple(u64::MAX).checked_padded_len(1), None);
+    assert_eq!(Bucket::Multiple(u64::MAX - 7).checked_padded_len(1), Some(u64::MAX - 7));
+    assert_eq!(Bucket::Multiple(1 << 40).checked_padded_len(u64::MAX - 8), None);
+    assert_eq!(Bucket::PowerOfTwo.checked_padded_len((1 << 63) + 1), None);
+}
@@ -0,0 +1,92 @@
+//! PKCS#7 padding on plain byte buffers.  This is exactly what
+//! `io::Writer` and `io::Reader` do at the end of a stream, exposed
+//! for callers encrypting whole buffers at once.
+
+use super::verify_ct;
+
+/// Appends 1 to 8 padding bytes to `buf`, each holding the number of
+/// bytes appended, so that its length becomes a multiple of 8.
+///
+/// # Example:
+/// ```
+/// use tea::padding::pkcs7;
+///
+/// let mut buf = b"Hello".to_vec();
+/// pkcs7::pad(&mut buf);
+/// assert_eq!(buf, b"Hello\x03\x03\x03");
+/// ```
+pub fn pad(buf: &mut Vec<u8>) {
+    let pad_byte = 8 - (buf.len() % 8) as u8;
+    let new_len = buf.len() + pad_byte as usize;
+    buf.resize(new_len, pad_byte);
+}
+
+/// Checks the padding at the end of `buf` and returns the part of it
+/// that came before the padding, or `None` if `buf` isn't a multiple
+/// of 8 bytes long or its padding is malformed.  The padding check
+/// itself is constant-time (see `verify_ct`).
+///
+/// # Example:
+/// ```
+/// use tea::padding::pkcs7;
+///
+/// assert_eq!(pkcs7::unpad(b"Hello\x03\x03\x03"), Some(&b"Hello"[..]));
+/// assert_eq!(pkcs7::unpad(b"Hello\x03\x03\x02"), None);
+/// ```
+pub fn unpad(buf: &[u8]) -> Option<&[u8]> {
+    if buf.is_empty() || buf.len() % 8 != 0 {
+        return None;
+    }
+    let len = buf.len();
+    verify_ct(&buf[len-8..]).map(|pad| &buf[..len-pad])
+}
+
+/// Strips whatever looks like padding from the end of `buf`, without
+/// ever failing: a final byte from 1 to 8 is taken as the pad length
+/// even if the other pad bytes don't match it, otherwise up to 8
+/// trailing zero bytes are stripped, and otherwise `buf` is returned
+/// whole.  This is meant for migrating data from sloppy encoders, and
+/// unlike `unpad` it is not constant-time.
+///
+/// # Example:
+/// ```
+/// use tea::padding::pkcs7;
+///
+/// assert_eq!(pkcs7::unpad_lenient(b"Hello\x03\x01\x03"), &b"Hello"[..]);
+/// assert_eq!(pkcs7::unpad_lenient(b"Hello\0\0\0"), &b"Hello"[..]);
+/// assert_eq!(pkcs7::unpad_lenient(b"Hello!!!"), &b"Hello!!!"[..]);
+/// ```
+pub fn unpad_lenient(buf: &[u8]) -> &[u8] {
+    let len = buf.len();
+    match buf.last() {
+        Some(&0) => {
+            let zeroes = buf.iter().rev().take(8).take_while(|&&b| b == 0).count();
+            &buf[..len-zeroes]
+        },
+        Some(&pad) if pad as usize <= 8 && pad as usize <= len => &buf[..len-pad as usize],
+        _ => buf,
+    }
+}
+
+#[test]
+fn it_works() {
+    for len in 0..20 {
+        let input: Vec<u8> = (0u8..len).collect();
+        let mut buf = input.clone();
+        pad(&mut buf);
+        assert_eq!(buf.len() % 8, 0);
+        assert!(buf.len() > input.len() && buf.len() <= input.len() + 8);
+        assert_eq!(unpad(&buf), Some(&input[..]));
+    }
+    assert_eq!(unpad(b""), None);
+    assert_eq!(unpad(b"\x01\x01\x01"), None);
+}
+
+#[test]
+fn lenient() {
+    assert_eq!(unpad_lenient(b""), &b""[..]);
+    assert_eq!(unpad_lenient(b"\x08\x08\x08\x08\x08\x08\x08\x08"), &b""[..]);
+    assert_eq!(unpad_lenient(b"abcdefgh\x02\x02\x02\x02\x02\x02\x02\x09"), &b"abcdefgh\x02\x02\x02\x02\x02\x02\x02\x09"[..]);
+    assert_eq!(unpad_lenient(b"a\0\0\0\0\0\0\0\0\0"), &b"a\0"[..]);
+    assert_eq!(unpad_lenient(b"\x05\x05"), &b"\x05\x05"[..]);
+}
@@ -0,0 +1,121 @@
+//! Python bindings through `pyo3`, so Python code can read and write
+//! what this crate encrypts without a second XTEA implementation.
+//! Only built with the `pyo3` feature; build the extension module with
+//! `maturin build --features pyo3,pyo3/extension-module`.
+//!
+//! The module is called `tea`.  Keys are 16 `bytes` and ivs 8, read
+//! big-endian as by `Key::from_bytes` and `Block::from_be_bytes`.
+//! `encrypt` and `decrypt` match `tea::encrypt` and `tea::decrypt`,
+//! raising `ValueError` on a bad key, iv or padding;
+//! `encrypt_file` and `decrypt_file` match the ones in `tea::fs`,
+//! raising `OSError` when something goes wrong with a file.
+//!
+//! ```python
+//! import tea
+//!
+//! ciphertext = tea.encrypt(key, iv, b"Hello, world!")
+//! assert tea.decrypt(key, iv, ciphertext) == b"Hello, world!"
+//! tea.decrypt_file("export.csv.tea", "export.csv", key)
+//! ```
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use super::{Block, Key};
+use buffer;
+use fs;
+
+fn read_key(key: &[u8]) -> PyResult<Key> {
+    Key::try_from(key).map_err(|_| PyValueError::new_err("key must be 16 bytes"))
+}
+
+fn read_iv(iv: &[u8]) -> PyResult<Block> {
+    if iv.len() != 8 {
+        return Err(PyValueError::new_err("iv must be 8 bytes"));
+    }
+    let mut bytes = [0u8; 8];
+    bytes.clone_from_slice(iv);
+    Ok(Block::from_be_bytes(&bytes))
+}
+
+/// Encrypts `plaintext` with PKCS#7 padding.
+#[pyfunction]
+fn encrypt<'py>(py: Python<'py>, key: &[u8], iv: &[u8], plaintext: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let ciphertext = buffer::encrypt(try!(read_key(key)), try!(read_iv(iv)), plaintext);
+    Ok(PyBytes::new_bound(py, &ciphertext))
+}
+
+/// Decrypts `ciphertext` and strips the padding.
+#[pyfunction]
+fn decrypt<'py>(py: Python<'py>, key: &[u8], iv: &[u8], ciphertext: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    match buffer::decrypt(try!(read_key(key)), try!(read_iv(iv)), ciphertext) {
+        Ok(plaintext) => Ok(PyBytes::new_bound(py, &plaintext)),
+        Err(err) => Err(PyValueError::new_err(err.to_string())),
+    }
+}
+
+/// Encrypts the file at `src` into `dst` under a new random iv,
+/// returning the number of plaintext bytes.
+#[pyfunction]
+fn encrypt_file(py: Python, src: PathBuf, dst: PathBuf, key: &[u8]) -> PyResult<u64> {
+    let key = try!(read_key(key));
+    py.allow_threads(|| fs::encrypt_file(&src, &dst, key)).map_err(|err| PyOSError::new_err(err.to_string()))
+}
+
+/// Decrypts the file at `src`, made by `encrypt_file`, into `dst`,
+/// returning the number of plaintext bytes.
+#[pyfunction]
+fn decrypt_file(py: Python, src: PathBuf, dst: PathBuf, key: &[u8]) -> PyResult<u64> {
+    let key = try!(read_key(key));
+    py.allow_threads(|| fs::decrypt_file(&src, &dst, key)).map_err(|err| PyOSError::new_err(err.to_string()))
+}
+
+#[pymodule]
+#[pyo3(name = "tea")]
+fn init(m: &Bound<PyModule>) -> PyResult<()> {
+    try!(m.add_function(try!(wrap_pyfunction!(self::encrypt, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(self::decrypt, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(self::encrypt_file, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(self::decrypt_file, m))));
+    Ok(())
+}
+
+#[test]
+fn it_works() {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let tea = PyModule::new_bound(py, "tea").ok().unwrap();
+        init(&tea).ok().unwrap();
+        let globals = pyo3::types::PyDict::new_bound(py);
+        globals.set_item("tea", tea).ok().unwrap();
+        py.run_bound(r#"
+key = bytes(range(16))
+iv = bytes([0, 0, 0, 5, 0, 0, 0, 6])
+ciphertext = tea.encrypt(key, iv, b"Hello, world!")
+assert len(ciphertext) == 16
+assert tea.decrypt(key, iv, ciphertext) == b"Hello, world!"
+for bad in [lambda: tea.decrypt(key, iv, ciphertext[:15]),
+            lambda: tea.decrypt(bytes(16), iv, ciphertext),
+            lambda: tea.encrypt(key[:15], iv, b""),
+            lambda: tea.encrypt(key, iv[:7], b"")]:
+    try:
+        bad()
+        assert False
+    except ValueError:
+        pass
+try:
+    tea.decrypt_file("/nonexistent/tea-test", "/nonexistent/tea-test.out", key)
+    assert False
+except OSError:
+    pass
+"#, Some(&globals), None).ok().unwrap();
+        let ciphertext = py.eval_bound("ciphertext", Some(&globals), None).ok().unwrap();
+        let ciphertext: Vec<u8> = ciphertext.extract().ok().unwrap();
+        assert_eq!(buffer::decrypt(Key::from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]), [5, 6], &ciphertext).ok().unwrap(),
+                   b"Hello, world!");
+    });
+}
@@ -0,0 +1,47 @@
+//! Generates keys and ivs from the operating system's random number
+//! generator.  Only built with the `rand` feature.
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use super::{Key, Block};
+
+impl Key {
+
+    /// Makes a new random key.
+    ///
+    /// # Example:
+    /// ```
+    /// let key = tea::Key::generate();
+    /// assert!(key != tea::Key::generate());
+    /// ```
+    pub fn generate() -> Key {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        Key::from_bytes(&bytes)
+    }
+
+}
+
+impl Block {
+
+    /// Makes a new random iv.  Use a fresh one for every stream or
+    /// buffer encrypted with the same key.
+    ///
+    /// # Example:
+    /// ```
+    /// let iv = tea::Block::generate_iv();
+    /// let ciphertext = tea::encrypt([1, 2, 3, 4], iv, b"Hello, world!");
+    /// assert_eq!(tea::decrypt([1, 2, 3, 4], iv, &ciphertext).ok().unwrap(), b"Hello, world!");
+    /// ```
+    pub fn generate_iv() -> Block {
+        Block::from(OsRng.next_u64())
+    }
+
+}
+
+#[test]
+fn it_works() {
+    assert!(Block::generate_iv() != Block::generate_iv());
+    assert!(Key::generate() != Key::generate());
+}
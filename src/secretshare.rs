@@ -0,0 +1,230 @@
+//! Splits a key into shares with Shamir's secret sharing, so that any
+//! `threshold` of them can put the key back together but fewer reveal
+//! nothing about it.  Handy for offline backups of a master key that
+//! no single custodian should hold.  Only built with the `secretshare`
+//! feature.
+//!
+//! Each byte of the key is shared separately, as the constant term of
+//! a random polynomial over GF(2^8).
+//!
+//! # Example:
+//! ```
+//! use tea::Key;
+//! use tea::secretshare;
+//!
+//! let key = Key::new([1, 2, 3, 4]);
+//! let shares = secretshare::split(&key, 2, 3).ok().unwrap();
+//! assert_eq!(secretshare::combine(&shares[1..]).ok().unwrap(), key);
+//! ```
+
+use std::error;
+use std::fmt;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use super::Key;
+use mem;
+
+/// Why a key couldn't be split or put back together.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The threshold is zero, or more than the number of shares.
+    InvalidThreshold,
+    /// No shares were given to `combine`.
+    NoShares,
+    /// Two of the shares given to `combine` have the same index.
+    DuplicateShare,
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(error::Error::description(self))
+    }
+
+}
+
+impl error::Error for Error {
+
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidThreshold => "threshold must be between 1 and the number of shares",
+            Error::NoShares => "no shares given",
+            Error::DuplicateShare => "duplicate share",
+        }
+    }
+
+}
+
+/// One share of a key: its index (never zero) and 16 bytes.  It's
+/// wiped from memory when dropped, and its bytes stay out of `Debug`
+/// output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Share {
+    index: u8,
+    bytes: [u8; 16],
+}
+
+impl Share {
+
+    /// The share's index, from 1 up to the number of shares made.
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// The share as 17 bytes, index first, for writing down or
+    /// printing out.
+    pub fn to_bytes(&self) -> [u8; 17] {
+        let mut out = [0u8; 17];
+        out[0] = self.index;
+        out[1..].clone_from_slice(&self.bytes);
+        out
+    }
+
+    /// Reads back a share written by `to_bytes`, or returns `None` if
+    /// its index is zero.
+    pub fn from_bytes(bytes: &[u8; 17]) -> Option<Share> {
+        if bytes[0] == 0 {
+            return None;
+        }
+        let mut share = Share{
+            index: bytes[0],
+            bytes: [0u8; 16],
+        };
+        share.bytes.clone_from_slice(&bytes[1..]);
+        Some(share)
+    }
+
+}
+
+impl fmt::Debug for Share {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Share {{ index: {}, .. }}", self.index)
+    }
+
+}
+
+impl Drop for Share {
+
+    fn drop(&mut self) {
+        mem::wipe(&mut self.bytes);
+    }
+
+}
+
+// Multiplies in GF(2^8) with the AES polynomial, x^8 + x^4 + x^3 + x
+// + 1, without branching on the operands.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+// The multiplicative inverse in GF(2^8), as a^254.
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    for _ in 0..7 {
+        power = mul(power, power);
+        result = mul(result, power);
+    }
+    // That's a^(2 + 4 + ... + 128) = a^254.
+    result
+}
+
+/// Splits `key` into `shares` shares, any `threshold` of which are
+/// enough to get it back with `combine`.
+pub fn split(key: &Key, threshold: u8, shares: u8) -> Result<Vec<Share>, Error> {
+    if threshold == 0 || threshold > shares {
+        return Err(Error::InvalidThreshold);
+    }
+    let secret = key.to_bytes();
+    let mut out: Vec<Share> = (1..shares as u16 + 1).map(|x| Share{ index: x as u8, bytes: [0u8; 16] }).collect();
+    let mut coefficients = vec![0u8; threshold as usize];
+    for i in 0..16 {
+        coefficients[0] = secret[i];
+        OsRng.fill_bytes(&mut coefficients[1..]);
+        for share in out.iter_mut() {
+            // Horner's rule, from the highest coefficient down.
+            let mut y = 0;
+            for &c in coefficients.iter().rev() {
+                y = mul(y, share.index) ^ c;
+            }
+            share.bytes[i] = y;
+        }
+    }
+    mem::wipe(&mut coefficients);
+    Ok(out)
+}
+
+/// Puts a key back together from `shares` made by `split`.  There must
+/// be at least as many as the threshold it was split with; with fewer,
+/// this returns a key, just not the right one.
+pub fn combine(shares: &[Share]) -> Result<Key, Error> {
+    if shares.is_empty() {
+        return Err(Error::NoShares);
+    }
+    for i in 0..shares.len() {
+        for j in 0..i {
+            if shares[i].index == shares[j].index {
+                return Err(Error::DuplicateShare);
+            }
+        }
+    }
+    // Lagrange interpolation at zero.  Subtraction in GF(2^8) is XOR.
+    let mut secret = [0u8; 16];
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = mul(basis, mul(other.index, inverse(other.index ^ share.index)));
+            }
+        }
+        for k in 0..16 {
+            secret[k] ^= mul(basis, share.bytes[k]);
+        }
+    }
+    let key = Key::from_bytes(&secret);
+    mem::wipe(&mut secret);
+    Ok(key)
+}
+
+#[test]
+fn it_works() {
+    let key = Key::new([0x00010203, 0x04050607, 0x08090a0b, 0x0c0d0e0f]);
+    let shares = split(&key, 3, 5).ok().unwrap();
+    assert_eq!(shares.len(), 5);
+    for a in 0..5 {
+        for b in 0..a {
+            for c in 0..b {
+                let subset = vec![shares[a].clone(), shares[b].clone(), shares[c].clone()];
+                assert_eq!(combine(&subset), Ok(key));
+            }
+        }
+    }
+    assert!(combine(&shares[..2]) != Ok(key));
+    assert_eq!(combine(&[shares[0].clone(), shares[0].clone()]), Err(Error::DuplicateShare));
+    assert_eq!(combine(&[]), Err(Error::NoShares));
+    assert_eq!(split(&key, 0, 5).err(), Some(Error::InvalidThreshold));
+    assert_eq!(split(&key, 6, 5).err(), Some(Error::InvalidThreshold));
+
+    let share = Share::from_bytes(&shares[2].to_bytes()).unwrap();
+    assert_eq!(share, shares[2]);
+    assert_eq!(share.index(), 3);
+    assert_eq!(format!("{:?}", share), "Share { index: 3, .. }");
+}
+
+#[test]
+fn field() {
+    for a in 1..256 {
+        assert_eq!(mul(a as u8, inverse(a as u8)), 1);
+    }
+    assert_eq!(mul(0x57, 0x83), 0xc1);
+}
@@ -0,0 +1,82 @@
+//! Encrypts fixed-size sectors of a disk or file in place, each with
+//! an iv worked out from its number, so any sector can be read or
+//! rewritten on its own.
+
+use super::{Key, Block, Error, SecretKey};
+use super::{encrypt_in_place, decrypt_in_place};
+use cipher;
+use hash;
+
+/// Encrypts numbered sectors, deriving each one's iv with ESSIV
+/// (encrypted salt-sector iv, as in Linux's dm-crypt): the sector
+/// number is enciphered under a second key, the hash of the first, so
+/// ivs can't be predicted without the key.  Sectors must be a multiple
+/// of 8 bytes long, and aren't padded.
+///
+/// Rewriting a sector in place reuses its iv, so someone who sees the
+/// disk before and after can tell which leading blocks of the sector
+/// didn't change.  That's the usual trade-off for disk encryption.
+///
+/// # Example:
+/// ```
+/// use tea::SectorCipher;
+///
+/// let sectors = SectorCipher::new([1, 2, 3, 4]);
+/// let mut sector = [7u8; 512];
+/// sectors.encrypt_sector(42, &mut sector).ok().unwrap();
+/// sectors.decrypt_sector(42, &mut sector).ok().unwrap();
+/// assert_eq!(&sector[..], &[7u8; 512][..]);
+/// ```
+pub struct SectorCipher {
+    key: SecretKey,
+    salt: SecretKey,
+}
+
+impl SectorCipher {
+
+    /// Makes a `SectorCipher` for the given `key`.
+    pub fn new<K: Into<Key>>(key: K) -> SectorCipher {
+        let key = key.into();
+        let salt = Key::from_bytes(&hash::digest(&key.to_bytes()));
+        SectorCipher{
+            key: SecretKey::new(key),
+            salt: SecretKey::new(salt),
+        }
+    }
+
+    /// The iv for sector number `sector`.
+    pub fn iv(&self, sector: u64) -> Block {
+        cipher::encipher(&self.salt, &Block::from(sector))
+    }
+
+    /// Encrypts sector number `sector`, held in `buf`, in place.
+    /// Returns `Error::Unaligned` if `buf` isn't a multiple of 8
+    /// bytes long.
+    pub fn encrypt_sector(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        encrypt_in_place(&self.key, self.iv(sector), buf)
+    }
+
+    /// Decrypts sector number `sector`, held in `buf`, in place.
+    pub fn decrypt_sector(&self, sector: u64, buf: &mut [u8]) -> Result<(), Error> {
+        decrypt_in_place(&self.key, self.iv(sector), buf)
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let sectors = SectorCipher::new([1, 2, 3, 4]);
+    assert!(sectors.iv(0) != sectors.iv(1));
+    assert!(sectors.iv(0) != Block::default());
+    assert_eq!(sectors.iv(5), SectorCipher::new([1, 2, 3, 4]).iv(5));
+
+    let plaintext = [7u8; 64];
+    let mut a = plaintext;
+    let mut b = plaintext;
+    sectors.encrypt_sector(0, &mut a).ok().unwrap();
+    sectors.encrypt_sector(1, &mut b).ok().unwrap();
+    assert!(&a[..] != &b[..]);
+    sectors.decrypt_sector(1, &mut b).ok().unwrap();
+    assert_eq!(&b[..], &plaintext[..]);
+    assert_eq!(sectors.encrypt_sector(0, &mut a[..60]), Err(Error::Unaligned));
+}
@@ -0,0 +1,155 @@
+//! Known-answer tests of the cipher and each mode, to run at startup
+//! before trusting the crate with real data, as FIPS 140 asks of a
+//! cryptographic module.  A failure means the build or the hardware
+//! is broken, not that some input was bad.
+
+use std::io::{Read, Write};
+use std::io::Cursor;
+
+use super::{Block, ByteOrder, Error, Key, SectorCipher};
+use super::{encrypt_in_place, decrypt_in_place, wrap_key, unwrap_key};
+use cipher;
+use io::{CtrReader, CtrWriter};
+use mac;
+use mem;
+
+// Every check uses the key 00 01 02 .. 0f.
+const KEY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+const IV: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+// The XTEA reference vector.
+const PLAINTEXT_BLOCK: [u8; 8] = *b"ABCDEFGH";
+const CIPHERTEXT_BLOCK: [u8; 8] = [0x49, 0x7d, 0xf3, 0xd0, 0x72, 0x61, 0x2c, 0xb5];
+
+// "Hello, world!" with PKCS#7 padding, and the same in CBC mode as
+// Bouncy Castle encrypts it.
+const CBC_PLAINTEXT: [u8; 16] = *b"Hello, world!\x03\x03\x03";
+const CBC_CIPHERTEXT: [u8; 16] = [0xa2, 0x41, 0xa4, 0x97, 0xbf, 0x2d, 0x5e, 0xdf,
+                                  0xfc, 0x0e, 0x26, 0x0d, 0xb5, 0x0f, 0xe0, 0x5a];
+// The first two blocks of the CTR keystream.
+const CTR_KEYSTREAM: [u8; 16] = [0xff, 0xc5, 0x2d, 0x10, 0xa0, 0x10, 0x01, 0x0b,
+                                 0xbc, 0x70, 0x3b, 0x20, 0xc0, 0x57, 0x5e, 0x3f];
+// CMAC of "Hello, world!".
+const MESSAGE: &'static [u8] = b"Hello, world!";
+const CMAC_TAG: [u8; 8] = [0xce, 0x32, 0x03, 0x56, 0x93, 0xb0, 0x1a, 0x9b];
+// The key bytes encrypted as sector 1.
+const SECTOR_CIPHERTEXT: [u8; 16] = [0xb9, 0xd5, 0xb4, 0xba, 0x49, 0x19, 0x72, 0x65,
+                                     0xd2, 0x98, 0x65, 0x2c, 0xb5, 0x95, 0x44, 0x52];
+// The key wrapped under itself.
+const WRAPPED_KEY: [u8; 24] = [0xd7, 0x0e, 0x20, 0xe6, 0xbd, 0xbe, 0x42, 0xb4,
+                               0xbc, 0x5c, 0x8e, 0x06, 0xea, 0x22, 0xfb, 0xf3,
+                               0x27, 0x29, 0x65, 0x7b, 0xbe, 0x09, 0xe5, 0x4b];
+
+/// The outcome of one known-answer test.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Check {
+    /// What was tested, like `"cbc encrypt"`.
+    pub name: &'static str,
+    /// Whether it gave the known answer.
+    pub passed: bool,
+}
+
+/// What `self_test` found, one `Check` per test in the order they
+/// ran.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SelfTestReport {
+    /// Every test that was run.
+    pub checks: Vec<Check>,
+}
+
+impl SelfTestReport {
+
+    /// Whether every test passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    /// The names of the tests that failed.
+    pub fn failures(&self) -> Vec<&'static str> {
+        self.checks.iter().filter(|check| !check.passed).map(|check| check.name).collect()
+    }
+
+}
+
+// The buffer functions read blocks in the host's byte order, so the
+// fixtures above are written as big-endian words and turned around
+// here on little-endian hosts.  CTR mode is big-endian already.
+fn native(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for chunk in bytes.chunks(8) {
+        out.push_all(mem::write_block(&mem::read_block_as(chunk, ByteOrder::BigEndian)));
+    }
+    out
+}
+
+fn ctr_apply(buf: &[u8]) -> Vec<u8> {
+    let mut writer = CtrWriter::new(Vec::new(), Key::from_bytes(&KEY), Block::from_be_bytes(&IV));
+    match writer.write_all(buf) {
+        Ok(()) => writer.into_inner(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn ctr_read(buf: &[u8]) -> Vec<u8> {
+    let mut reader = CtrReader::new(Cursor::new(buf), Key::from_bytes(&KEY), Block::from_be_bytes(&IV));
+    let mut out = Vec::new();
+    match reader.read_to_end(&mut out) {
+        Ok(_) => out,
+        Err(_) => Vec::new(),
+    }
+}
+
+// Runs `f` over a copy of `input` and checks it came out as `expected`.
+fn in_place<F: Fn(&mut [u8]) -> Result<(), Error>>(input: &[u8], expected: &[u8], f: F) -> bool {
+    let mut buf = input.to_vec();
+    f(&mut buf).is_ok() && buf == expected
+}
+
+/// Runs known-answer tests of `encipher` and `decipher`, CBC mode
+/// with padding, CTR mode, CMAC, `SectorCipher` and key wrapping, each
+/// against output fixed in the source, and reports which passed.
+/// Call it once at startup and refuse to go on unless
+/// `SelfTestReport::passed` is true.
+///
+/// # Example:
+/// ```
+/// let report = tea::self_test();
+/// assert!(report.passed(), "self-test failed: {:?}", report.failures());
+/// ```
+pub fn self_test() -> SelfTestReport {
+    let key = Key::from_bytes(&KEY);
+    let iv = Block::from_be_bytes(&IV);
+    let (cbc_plaintext, cbc_ciphertext) = (native(&CBC_PLAINTEXT), native(&CBC_CIPHERTEXT));
+    let (sector_plaintext, sector_ciphertext) = (native(&KEY), native(&SECTOR_CIPHERTEXT));
+    let sectors = SectorCipher::new(key);
+    let checks = vec![
+        ("encipher", cipher::encipher(&key, &Block::from_be_bytes(&PLAINTEXT_BLOCK)).to_be_bytes() == CIPHERTEXT_BLOCK),
+        ("decipher", cipher::decipher(&key, &Block::from_be_bytes(&CIPHERTEXT_BLOCK)).to_be_bytes() == PLAINTEXT_BLOCK),
+        ("cbc encrypt", in_place(&cbc_plaintext, &cbc_ciphertext, |buf| encrypt_in_place(key, iv, buf))),
+        ("cbc decrypt", in_place(&cbc_ciphertext, &cbc_plaintext, |buf| decrypt_in_place(key, iv, buf))),
+        ("ctr encrypt", ctr_apply(&[0u8; 16]) == &CTR_KEYSTREAM[..]),
+        ("ctr decrypt", ctr_read(&CTR_KEYSTREAM) == &[0u8; 16][..]),
+        ("cmac", mac::cmac(key, MESSAGE) == CMAC_TAG),
+        ("sector encrypt", in_place(&sector_plaintext, &sector_ciphertext, |buf| sectors.encrypt_sector(1, buf))),
+        ("sector decrypt", in_place(&sector_ciphertext, &sector_plaintext, |buf| sectors.decrypt_sector(1, buf))),
+        ("key wrap", &wrap_key(key, &key)[..] == &WRAPPED_KEY[..]),
+        ("key unwrap", unwrap_key(key, &WRAPPED_KEY) == Ok(key)),
+    ];
+    SelfTestReport{
+        checks: checks.into_iter().map(|(name, passed)| Check{ name: name, passed: passed }).collect(),
+    }
+}
+
+#[test]
+fn it_works() {
+    let report = self_test();
+    assert_eq!(report.failures(), Vec::<&str>::new());
+    assert!(report.passed());
+    assert_eq!(report.checks.len(), 11);
+    assert_eq!(report.checks[0], Check{ name: "encipher", passed: true });
+
+    let mut broken = report.clone();
+    broken.checks[4].passed = false;
+    assert!(!broken.passed());
+    assert_eq!(broken.failures(), vec!["ctr encrypt"]);
+}
@@ -0,0 +1,167 @@
+//! A `core::simd` kernel for `Cipher::encrypt_blocks` and
+//! `decrypt_blocks` that runs four independent blocks through the
+//! rounds side by side, one in each lane.  Only built with the `simd`
+//! feature.  The same code becomes SSE2 on x86 and NEON on aarch64,
+//! so there are no intrinsics to keep in step with the scalar cipher.
+//! Whether the CPU has them is checked at runtime, once; where it
+//! doesn't, or on any other architecture, the kernel takes nothing and
+//! `Cipher` does every block with the scalar code.
+
+use std::simd::u32x4;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::Block;
+use mem;
+
+// How many bytes go through the kernel at a time: one block per lane.
+const STRIDE: usize = 32;
+
+// What `detect` found: 0 until it's been run, then 1 if the kernel
+// can't be used and 2 if it can.
+static SUPPORTED: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn detect() -> bool {
+    is_x86_feature_detected!("sse2")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect() -> bool {
+    ::std::arch::is_aarch64_feature_detected!("neon")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn detect() -> bool {
+    false
+}
+
+// Whether this CPU has the vector unit the kernel is compiled for.
+pub fn available() -> bool {
+    match SUPPORTED.load(Ordering::Relaxed) {
+        0 => {
+            let found = detect();
+            SUPPORTED.store(if found { 2 } else { 1 }, Ordering::Relaxed);
+            found
+        },
+        n => n == 2,
+    }
+}
+
+// The Feistel function, before the key goes in, in every lane.
+fn mix(v: u32x4) -> u32x4 {
+    ((v << 4) ^ (v >> 5)) + v
+}
+
+// Splits four blocks into a vector of their first words and one of
+// their second words.
+fn load(chunk: &[u8]) -> (u32x4, u32x4) {
+    let (mut v0, mut v1) = ([0u32; 4], [0u32; 4]);
+    for (lane, block) in chunk.chunks(8).enumerate() {
+        let block = mem::read_block(block);
+        v0[lane] = block[0];
+        v1[lane] = block[1];
+    }
+    (u32x4::from_array(v0), u32x4::from_array(v1))
+}
+
+fn store(chunk: &mut [u8], v0: u32x4, v1: u32x4) {
+    for (lane, block) in chunk.chunks_mut(8).enumerate() {
+        block.clone_from_slice(mem::write_block(&Block::new([v0[lane], v1[lane]])));
+    }
+}
+
+// Encrypts as many whole groups of four blocks from the front of
+// `buf` as there are, with `schedule` from a `Cipher`, and returns
+// how many bytes that was.  The caller does the rest a block at a
+// time, which is all of it if the kernel isn't `available`.
+pub fn encrypt(schedule: &[u32; 64], buf: &mut [u8]) -> usize {
+    if !available() {
+        return 0;
+    }
+    unsafe { encrypt_vector(schedule, buf) }
+}
+
+// Like `encrypt`, but decrypts.
+pub fn decrypt(schedule: &[u32; 64], buf: &mut [u8]) -> usize {
+    if !available() {
+        return 0;
+    }
+    unsafe { decrypt_vector(schedule, buf) }
+}
+
+// The kernels themselves, compiled for the vector unit whether or not
+// the target enables it by default.  Only safe to call once
+// `available` says the CPU has it.
+#[cfg_attr(any(target_arch = "x86", target_arch = "x86_64"), target_feature(enable = "sse2"))]
+#[cfg_attr(target_arch = "aarch64", target_feature(enable = "neon"))]
+unsafe fn encrypt_vector(schedule: &[u32; 64], buf: &mut [u8]) -> usize {
+    let len = buf.len() / STRIDE * STRIDE;
+    for chunk in buf[..len].chunks_mut(STRIDE) {
+        let (mut v0, mut v1) = load(chunk);
+        for round in schedule.chunks(2) {
+            v0 += mix(v1) ^ u32x4::splat(round[0]);
+            v1 += mix(v0) ^ u32x4::splat(round[1]);
+        }
+        store(chunk, v0, v1);
+    }
+    len
+}
+
+#[cfg_attr(any(target_arch = "x86", target_arch = "x86_64"), target_feature(enable = "sse2"))]
+#[cfg_attr(target_arch = "aarch64", target_feature(enable = "neon"))]
+unsafe fn decrypt_vector(schedule: &[u32; 64], buf: &mut [u8]) -> usize {
+    let len = buf.len() / STRIDE * STRIDE;
+    for chunk in buf[..len].chunks_mut(STRIDE) {
+        let (mut v0, mut v1) = load(chunk);
+        for round in schedule.chunks(2).rev() {
+            v1 -= mix(v0) ^ u32x4::splat(round[1]);
+            v0 -= mix(v1) ^ u32x4::splat(round[0]);
+        }
+        store(chunk, v0, v1);
+    }
+    len
+}
+
+#[test]
+fn it_works() {
+    use cipher::Cipher;
+    use super::Key;
+
+    let xtea = Cipher::new(Key::new([10, 20, 30, 42]));
+    for &len in [0, 8, 24, 32, 40, 96, 1000].iter() {
+        let plain: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+        let mut buf = plain.clone();
+        xtea.encrypt_blocks(&mut buf).ok().unwrap();
+        for (crypted, block) in buf.chunks(8).zip(plain.chunks(8)) {
+            assert_eq!(mem::read_block(crypted), xtea.encipher(&mem::read_block(block)));
+        }
+        xtea.decrypt_blocks(&mut buf).ok().unwrap();
+        assert_eq!(buf, plain);
+    }
+}
+
+#[test]
+fn fallback() {
+    use cipher::Cipher;
+    use super::Key;
+
+    // The kernel against the scalar rounds `Cipher` uses for every
+    // block when the kernel can't be, called directly rather than by
+    // ruling the kernel out in `SUPPORTED`, which other tests share.
+    let xtea = Cipher::new(Key::new([10, 20, 30, 42]));
+    let plain: Vec<u8> = (0..72).collect();
+    let mut scalar = plain.clone();
+    for chunk in scalar.chunks_mut(8) {
+        let block = xtea.encipher(&mem::read_block(chunk));
+        chunk.clone_from_slice(mem::write_block(&block));
+    }
+    if !available() {
+        return;
+    }
+    let mut vector = plain.clone();
+    assert_eq!(unsafe { encrypt_vector(xtea.schedule(), &mut vector) }, 64);
+    assert_eq!(&vector[..64], &scalar[..64]);
+    assert_eq!(&vector[64..], &plain[64..]);
+    assert_eq!(unsafe { decrypt_vector(xtea.schedule(), &mut vector) }, 64);
+    assert_eq!(vector, plain);
+}
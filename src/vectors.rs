@@ -0,0 +1,175 @@
+//! Test vectors for XTEA and for each mode and padding this crate
+//! defines, so other projects can check in their own CI that they
+//! agree with it.  The block vectors are the published XTEA ones;
+//! the rest are this crate's own output.
+//!
+//! Blocks are read and written big-endian throughout, as most other
+//! implementations do.  `Writer` and `Reader` match with
+//! `byte_order(ByteOrder::BigEndian)`; `CtrWriter` and `CtrReader`
+//! are big-endian by default.
+//!
+//! # Example:
+//! ```
+//! use tea::vectors;
+//!
+//! assert_eq!(vectors::verify_all(), Ok(()));
+//! let vector = vectors::get("cbc pkcs7").unwrap();
+//! assert_eq!(vector.plaintext, b"Hello, world!");
+//! ```
+
+use std::io::{Cursor, Read, Write};
+
+use super::{Block, ByteOrder, Key};
+use cipher;
+use io::{CtrWriter, Reader, Writer};
+use padding::{Bucket, Padding};
+
+/// How a `Vector`'s plaintext becomes its ciphertext.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// A single 8-byte block enciphered with no mode; the iv is
+    /// unused.
+    Block,
+    /// CBC mode with the given padding and no header, as written by
+    /// `Writer::raw`.
+    Cbc(Padding),
+    /// CTR mode with no header, as written by `CtrWriter`.
+    Ctr,
+}
+
+/// One test vector.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Vector {
+    /// A short name for the vector, like `"cbc pkcs7"`.
+    pub name: &'static str,
+    /// How the vector was made.
+    pub kind: Kind,
+    /// The key, read big-endian as by `Key::from_bytes`.
+    pub key: [u8; 16],
+    /// The iv, read big-endian as by `Block::from_be_bytes`.
+    pub iv: [u8; 8],
+    /// The plaintext.
+    pub plaintext: &'static [u8],
+    /// The ciphertext.
+    pub ciphertext: &'static [u8],
+}
+
+const ZERO_KEY: [u8; 16] = [0; 16];
+const KEY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+const IV: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+static VECTORS: [Vector; 11] = [
+    Vector{ name: "block 1", kind: Kind::Block, key: KEY, iv: [0; 8],
+            plaintext: b"ABCDEFGH", ciphertext: &[0x49, 0x7d, 0xf3, 0xd0, 0x72, 0x61, 0x2c, 0xb5] },
+    Vector{ name: "block 2", kind: Kind::Block, key: KEY, iv: [0; 8],
+            plaintext: b"AAAAAAAA", ciphertext: &[0xe7, 0x8f, 0x2d, 0x13, 0x74, 0x43, 0x41, 0xd8] },
+    Vector{ name: "block 3", kind: Kind::Block, key: KEY, iv: [0; 8],
+            plaintext: &[0x5a, 0x5b, 0x6e, 0x27, 0x89, 0x48, 0xd7, 0x7f], ciphertext: b"AAAAAAAA" },
+    Vector{ name: "block 4", kind: Kind::Block, key: ZERO_KEY, iv: [0; 8],
+            plaintext: b"ABCDEFGH", ciphertext: &[0xa0, 0x39, 0x05, 0x89, 0xf8, 0xb8, 0xef, 0xa5] },
+    Vector{ name: "block 5", kind: Kind::Block, key: ZERO_KEY, iv: [0; 8],
+            plaintext: b"AAAAAAAA", ciphertext: &[0xed, 0x23, 0x37, 0x5a, 0x82, 0x1a, 0x8c, 0x2d] },
+    Vector{ name: "block 6", kind: Kind::Block, key: ZERO_KEY, iv: [0; 8],
+            plaintext: &[0x70, 0xe1, 0x22, 0x5d, 0x6e, 0x4e, 0x76, 0x55], ciphertext: b"AAAAAAAA" },
+    Vector{ name: "cbc pkcs7", kind: Kind::Cbc(Padding::Pkcs7), key: KEY, iv: IV,
+            plaintext: b"Hello, world!",
+            ciphertext: &[0xa2, 0x41, 0xa4, 0x97, 0xbf, 0x2d, 0x5e, 0xdf,
+                          0xfc, 0x0e, 0x26, 0x0d, 0xb5, 0x0f, 0xe0, 0x5a] },
+    Vector{ name: "cbc none", kind: Kind::Cbc(Padding::None), key: KEY, iv: IV,
+            plaintext: b"Hello, world!!!!",
+            ciphertext: &[0xa2, 0x41, 0xa4, 0x97, 0xbf, 0x2d, 0x5e, 0xdf,
+                          0x3e, 0x56, 0x41, 0x29, 0xb6, 0x21, 0xf2, 0xb5] },
+    Vector{ name: "cbc bucket power of two", kind: Kind::Cbc(Padding::Bucket(Bucket::PowerOfTwo)), key: KEY, iv: IV,
+            plaintext: b"Hello, world!",
+            ciphertext: &[0xa2, 0x41, 0xa4, 0x97, 0xbf, 0x2d, 0x5e, 0xdf,
+                          0x2b, 0xc3, 0x1d, 0x12, 0x73, 0xbf, 0x59, 0x2f] },
+    Vector{ name: "cbc bucket multiple of 24", kind: Kind::Cbc(Padding::Bucket(Bucket::Multiple(24))), key: KEY, iv: IV,
+            plaintext: b"Hello, world!",
+            ciphertext: &[0xa2, 0x41, 0xa4, 0x97, 0xbf, 0x2d, 0x5e, 0xdf,
+                          0x2b, 0xc3, 0x1d, 0x12, 0x73, 0xbf, 0x59, 0x2f,
+                          0x52, 0xd4, 0x0c, 0xf3, 0x52, 0xae, 0x44, 0x78] },
+    Vector{ name: "ctr", kind: Kind::Ctr, key: KEY, iv: IV,
+            plaintext: b"Hello, world!",
+            ciphertext: &[0xb7, 0xa0, 0x41, 0x7c, 0xcf, 0x3c, 0x21, 0x7c,
+                          0xd3, 0x02, 0x57, 0x44, 0xe1] },
+];
+
+/// Every vector.
+pub fn all() -> &'static [Vector] {
+    &VECTORS
+}
+
+/// The vector called `name`, if there is one.
+pub fn get(name: &str) -> Option<&'static Vector> {
+    VECTORS.iter().find(|vector| vector.name == name)
+}
+
+// The CTR keystream for `len` bytes.
+fn ctr_keystream(key: Key, iv: Block, len: usize) -> Vec<u8> {
+    let mut writer = CtrWriter::new(Vec::new(), key, iv);
+    match writer.write_all(&vec![0u8; len]) {
+        Ok(()) => writer.into_inner(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn cbc_encrypt(key: Key, iv: Block, padding: Padding, plaintext: &[u8]) -> Option<Vec<u8>> {
+    let mut writer = Writer::new(Vec::new(), key, iv).raw().byte_order(ByteOrder::BigEndian).padding(padding);
+    writer.write_all(plaintext).and_then(|()| writer.close()).ok()
+}
+
+fn cbc_decrypt(key: Key, iv: Block, padding: Padding, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = Reader::new(Cursor::new(ciphertext), key, iv).raw().byte_order(ByteOrder::BigEndian).padding(padding);
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext).ok().map(|_| plaintext)
+}
+
+impl Vector {
+
+    /// Encrypts the plaintext and decrypts the ciphertext with this
+    /// crate, returning whether both came out as they should.
+    pub fn verify(&self) -> bool {
+        let key = Key::from_bytes(&self.key);
+        let iv = Block::from_be_bytes(&self.iv);
+        match self.kind {
+            Kind::Block => {
+                if self.plaintext.len() != 8 || self.ciphertext.len() != 8 {
+                    return false;
+                }
+                let (mut plaintext, mut ciphertext) = ([0u8; 8], [0u8; 8]);
+                plaintext.clone_from_slice(self.plaintext);
+                ciphertext.clone_from_slice(self.ciphertext);
+                cipher::encipher(&key, &Block::from_be_bytes(&plaintext)).to_be_bytes() == ciphertext &&
+                    cipher::decipher(&key, &Block::from_be_bytes(&ciphertext)).to_be_bytes() == plaintext
+            },
+            Kind::Cbc(padding) => {
+                cbc_encrypt(key, iv, padding, self.plaintext).map_or(false, |out| out == self.ciphertext) &&
+                    cbc_decrypt(key, iv, padding, self.ciphertext).map_or(false, |out| out == self.plaintext)
+            },
+            Kind::Ctr => {
+                let keystream = ctr_keystream(key, iv, self.plaintext.len());
+                self.ciphertext.len() == keystream.len() &&
+                    self.plaintext.iter().zip(keystream.iter()).zip(self.ciphertext.iter()).all(|((p, k), c)| p ^ k == *c)
+            },
+        }
+    }
+
+}
+
+/// Verifies every vector, returning the names of any that fail.
+pub fn verify_all() -> Result<(), Vec<&'static str>> {
+    let failures: Vec<&'static str> = VECTORS.iter().filter(|vector| !vector.verify()).map(|vector| vector.name).collect();
+    if failures.is_empty() { Ok(()) } else { Err(failures) }
+}
+
+#[test]
+fn it_works() {
+    assert_eq!(verify_all(), Ok(()));
+    assert_eq!(all().len(), 11);
+    assert_eq!(get("cbc none").map(|vector| vector.kind), Some(Kind::Cbc(Padding::None)));
+    assert_eq!(get("ecb"), None);
+
+    let mut tampered = *get("cbc pkcs7").unwrap();
+    tampered.ciphertext = &[0; 16];
+    assert!(!tampered.verify());
+}
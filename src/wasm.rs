@@ -0,0 +1,186 @@
+//! JavaScript bindings through `wasm-bindgen`, so a browser can
+//! decrypt what a Rust backend encrypted with this crate, and the
+//! other way around.  Only built with the `wasm` feature; since the
+//! crate doesn't ask for a `cdylib` itself, build the module with
+//! `cargo rustc --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib`
+//! and run `wasm-bindgen` on the result.
+//!
+//! Bytes go in and come out as `Uint8Array`s.  Keys are 16 bytes and
+//! ivs 8 bytes, read big-endian as by `Key::from_bytes` and
+//! `Block::from_be_bytes`.  `encrypt` and `decrypt` match
+//! `tea::encrypt` and `tea::decrypt`; `Encryptor` and `Decryptor` do
+//! the same a piece at a time, for payloads that arrive in chunks
+//! (from a `ReadableStream`, say).  Errors are thrown as JavaScript
+//! `Error`s.
+//!
+//! ```js
+//! import { encrypt, Decryptor } from "tea";
+//!
+//! const ciphertext = encrypt(key, iv, new TextEncoder().encode("Hello, world!"));
+//! const decryptor = new Decryptor(key, iv);
+//! const parts = [decryptor.update(ciphertext.subarray(0, 5)), decryptor.update(ciphertext.subarray(5))];
+//! parts.push(decryptor.finish());
+//! ```
+
+use std::convert::TryFrom;
+
+use wasm_bindgen::prelude::*;
+
+use super::{Block, Error, Key};
+use buffer;
+use incremental;
+
+fn read_key(key: &[u8]) -> Result<Key, Error> {
+    Key::try_from(key)
+}
+
+fn read_iv(iv: &[u8]) -> Result<Block, Error> {
+    if iv.len() != 8 {
+        return Err(Error::InvalidIv);
+    }
+    let mut bytes = [0u8; 8];
+    bytes.clone_from_slice(iv);
+    Ok(Block::from_be_bytes(&bytes))
+}
+
+fn js_error(err: Error) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// Encrypts `plaintext` with PKCS#7 padding.
+#[wasm_bindgen]
+pub fn encrypt(key: &[u8], iv: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, JsError> {
+    read_key(key)
+        .and_then(|key| read_iv(iv).map(|iv| buffer::encrypt(key, iv, plaintext)))
+        .map_err(js_error)
+}
+
+/// Decrypts `ciphertext` and strips the padding, throwing if the
+/// padding is bad (most likely a wrong key or iv).
+#[wasm_bindgen]
+pub fn decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, JsError> {
+    read_key(key)
+        .and_then(|key| read_iv(iv).map(|iv| (key, iv)))
+        .and_then(|(key, iv)| buffer::decrypt(key, iv, ciphertext))
+        .map_err(js_error)
+}
+
+/// Encrypts a stream a piece at a time.
+#[wasm_bindgen]
+pub struct Encryptor(incremental::Encryptor);
+
+impl Encryptor {
+
+    fn start(key: &[u8], iv: &[u8]) -> Result<Encryptor, Error> {
+        Ok(Encryptor(incremental::Encryptor::new(try!(read_key(key)), try!(read_iv(iv)))))
+    }
+
+}
+
+#[wasm_bindgen]
+impl Encryptor {
+
+    /// Starts encrypting a stream.
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Encryptor, JsError> {
+        Encryptor::start(key, iv).map_err(js_error)
+    }
+
+    /// Encrypts `plaintext`, returning every whole block of ciphertext
+    /// that's ready.  Up to 7 bytes wait for the next call.
+    pub fn update(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; self.0.update_len(plaintext.len())];
+        self.0.update(plaintext, &mut out).ok().unwrap();
+        out
+    }
+
+    /// Pads and encrypts the rest of the stream, returning the last
+    /// block of ciphertext.
+    pub fn finish(mut self) -> Vec<u8> {
+        let mut out = vec![0u8; 8];
+        self.0.finish(&mut out).ok().unwrap();
+        out
+    }
+
+}
+
+/// Decrypts a stream a piece at a time.
+#[wasm_bindgen]
+pub struct Decryptor(incremental::Decryptor);
+
+impl Decryptor {
+
+    fn start(key: &[u8], iv: &[u8]) -> Result<Decryptor, Error> {
+        Ok(Decryptor(incremental::Decryptor::new(try!(read_key(key)), try!(read_iv(iv)))))
+    }
+
+    fn end(mut self) -> Result<Vec<u8>, Error> {
+        let mut out = vec![0u8; 8];
+        let len = try!(self.0.finish(&mut out));
+        out.truncate(len);
+        Ok(out)
+    }
+
+}
+
+#[wasm_bindgen]
+impl Decryptor {
+
+    /// Starts decrypting a stream.
+    #[wasm_bindgen(constructor)]
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Decryptor, JsError> {
+        Decryptor::start(key, iv).map_err(js_error)
+    }
+
+    /// Decrypts `ciphertext`, returning the plaintext that's ready.
+    /// The last block is held back until `finish`, since it holds the
+    /// padding.
+    pub fn update(&mut self, ciphertext: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; self.0.update_len(ciphertext.len())];
+        self.0.update(ciphertext, &mut out).ok().unwrap();
+        out
+    }
+
+    /// Decrypts the last block and strips the padding, throwing if
+    /// the stream was cut short or the padding is bad.
+    pub fn finish(self) -> Result<Vec<u8>, JsError> {
+        self.end().map_err(js_error)
+    }
+
+}
+
+// Only the paths that don't throw can run outside a browser, since
+// making a `JsError` calls into JavaScript.
+#[test]
+fn it_works() {
+    let key = [7u8; 16];
+    let iv = [0u8, 0, 0, 5, 0, 0, 0, 6];
+    let plaintext: Vec<u8> = (0..100).collect();
+    let ciphertext = encrypt(&key, &iv, &plaintext).ok().unwrap();
+    assert_eq!(ciphertext, buffer::encrypt(Key::from_bytes(&key), [5, 6], &plaintext));
+    assert_eq!(decrypt(&key, &iv, &ciphertext).ok().unwrap(), plaintext);
+
+    for &chunk_size in [1, 3, 8, 13, 100].iter() {
+        let mut encryptor = Encryptor::start(&key, &iv).ok().unwrap();
+        let mut encrypted = Vec::new();
+        for chunk in plaintext.chunks(chunk_size) {
+            encrypted.push_all(&encryptor.update(chunk));
+        }
+        encrypted.push_all(&encryptor.finish());
+        assert_eq!(encrypted, ciphertext);
+
+        let mut decryptor = Decryptor::start(&key, &iv).ok().unwrap();
+        let mut decrypted = Vec::new();
+        for chunk in ciphertext.chunks(chunk_size) {
+            decrypted.push_all(&decryptor.update(chunk));
+        }
+        decrypted.push_all(&decryptor.end().ok().unwrap());
+        assert_eq!(decrypted, plaintext);
+    }
+
+    assert_eq!(Encryptor::start(&key[..15], &iv).err(), Some(Error::InvalidKey));
+    assert_eq!(Encryptor::start(&key, &iv[..7]).err(), Some(Error::InvalidIv));
+    let mut decryptor = Decryptor::start(&key, &iv).ok().unwrap();
+    decryptor.update(&ciphertext[..ciphertext.len() - 1]);
+    assert_eq!(decryptor.end(), Err(Error::Truncated));
+}
@@ -0,0 +1,84 @@
+//! Wraps (encrypts) one key under another, key-encryption key, so a
+//! data key can be stored next to the ciphertext it protects while the
+//! key-encryption key lives somewhere safer.
+
+use super::{Key, Block, Error};
+use cipher;
+use ct;
+use mac;
+
+// Splits the key-encryption key into one key for the integrity tag and
+// one for encryption, so neither use can interfere with the other.
+fn wrap_keys(kek: &Key) -> (Key, Key) {
+    (kek.derive_subkey(b"tea key wrap: mac"), kek.derive_subkey(b"tea key wrap: enc"))
+}
+
+// XORs `bytes` with a CTR keystream starting from `tag`.
+fn apply_keystream(key: &Key, tag: Block, bytes: &mut [u8; 16]) {
+    let counter = u64::from(tag);
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        let pad = cipher::encipher(key, &Block::from(counter.wrapping_add(i as u64))).to_be_bytes();
+        for j in 0..8 {
+            chunk[j] ^= pad[j];
+        }
+    }
+}
+
+/// Wraps `key` under the key-encryption key `kek`.  This is
+/// deterministic authenticated encryption in the style of SIV (RFC
+/// 5297): an 8-byte CMAC of the key comes first and doubles as the iv
+/// for encrypting the 16 key bytes in CTR mode, 24 bytes in all.  The
+/// same `kek` and `key` always wrap to the same bytes, which is fine
+/// because keys are random to begin with.
+///
+/// # Example:
+/// ```
+/// use tea::Key;
+///
+/// let kek = Key::new([1, 2, 3, 4]);
+/// let data_key = Key::new([5, 6, 7, 8]);
+/// let wrapped = tea::wrap_key(kek, &data_key);
+/// assert_eq!(tea::unwrap_key(kek, &wrapped), Ok(data_key));
+/// ```
+pub fn wrap_key<K: Into<Key>>(kek: K, key: &Key) -> [u8; 24] {
+    let (mac_key, enc_key) = wrap_keys(&kek.into());
+    let mut bytes = key.to_bytes();
+    let tag = Block::from_be_bytes(&mac::cmac(mac_key, &bytes));
+    apply_keystream(&enc_key, tag, &mut bytes);
+    let mut wrapped = [0u8; 24];
+    wrapped[..8].clone_from_slice(&tag.to_be_bytes());
+    wrapped[8..].clone_from_slice(&bytes);
+    wrapped
+}
+
+/// Unwraps a key wrapped by `wrap_key`, returning `Error::BadTag` if
+/// `wrapped` was tampered with or `kek` is the wrong key.
+pub fn unwrap_key<K: Into<Key>>(kek: K, wrapped: &[u8; 24]) -> Result<Key, Error> {
+    let (mac_key, enc_key) = wrap_keys(&kek.into());
+    let mut tag_bytes = [0u8; 8];
+    tag_bytes.clone_from_slice(&wrapped[..8]);
+    let tag = Block::from_be_bytes(&tag_bytes);
+    let mut bytes = [0u8; 16];
+    bytes.clone_from_slice(&wrapped[8..]);
+    apply_keystream(&enc_key, tag, &mut bytes);
+    if !ct::eq(&mac::cmac(mac_key, &bytes), &tag_bytes) {
+        return Err(Error::BadTag);
+    }
+    Ok(Key::from_bytes(&bytes))
+}
+
+#[test]
+fn it_works() {
+    let kek = Key::new([1, 2, 3, 4]);
+    let key = Key::new([5, 6, 7, 8]);
+    let wrapped = wrap_key(kek, &key);
+    assert_eq!(wrapped, wrap_key(kek, &key));
+    assert!(&wrapped[8..] != &key.to_bytes()[..]);
+    assert_eq!(unwrap_key(kek, &wrapped), Ok(key));
+    assert_eq!(unwrap_key([1, 2, 3, 5], &wrapped), Err(Error::BadTag));
+    for i in 0..24 {
+        let mut tampered = wrapped;
+        tampered[i] ^= 1;
+        assert_eq!(unwrap_key(kek, &tampered), Err(Error::BadTag));
+    }
+}
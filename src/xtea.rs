@@ -0,0 +1,149 @@
+//! XTEA as a RustCrypto block cipher, so it plugs into the crates
+//! built on the `cipher` traits (the generic `cbc` and `ctr` modes,
+//! MACs, test harnesses and so on).  Only built with the `rustcrypto`
+//! feature.
+
+use rustcrypto_cipher::consts::{U1, U16, U8};
+use rustcrypto_cipher::{AlgorithmName, BlockCipherDecBackend, BlockCipherDecClosure, BlockCipherDecrypt,
+                        BlockCipherEncBackend, BlockCipherEncClosure, BlockCipherEncrypt, BlockSizeUser,
+                        InOut, KeyInit, KeySizeUser, ParBlocksSizeUser};
+use std::fmt;
+
+use super::{Block, Key, SecretKey};
+use cipher;
+
+/// XTEA implementing `KeyInit`, `BlockCipherEncrypt` and
+/// `BlockCipherDecrypt` from the `cipher` crate.  Keys and blocks are
+/// read big-endian, like `Key::from_bytes` and `Block::from_be_bytes`,
+/// which matches the reference test vectors.  The key is wiped when
+/// it's dropped.
+///
+/// # Example:
+/// ```.ignore
+/// use cipher::{BlockCipherEncrypt, KeyInit};
+/// use tea::Xtea;
+///
+/// let xtea = Xtea::new(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15].into());
+/// let mut block = (*b"ABCDEFGH").into();
+/// xtea.encrypt_block(&mut block);
+/// assert_eq!(block[..], [0x49, 0x7d, 0xf3, 0xd0, 0x72, 0x61, 0x2c, 0xb5]);
+/// ```
+pub struct Xtea {
+    key: SecretKey,
+}
+
+// Does the work for `Xtea`, kept separate so the backend methods
+// don't clash with the ones users call.
+struct Backend<'a>(&'a Key);
+
+impl<'a> Backend<'a> {
+
+    fn process<F: Fn(&Key, &Block) -> Block>(&self, mut block: InOut<rustcrypto_cipher::Block<Self>>, f: F) {
+        let mut bytes = [0u8; 8];
+        bytes.clone_from_slice(block.get_in());
+        let output = f(self.0, &Block::from_be_bytes(&bytes));
+        block.get_out().clone_from_slice(&output.to_be_bytes());
+    }
+
+}
+
+impl<'a> BlockSizeUser for Backend<'a> {
+
+    type BlockSize = U8;
+
+}
+
+impl<'a> ParBlocksSizeUser for Backend<'a> {
+
+    type ParBlocksSize = U1;
+
+}
+
+impl<'a> BlockCipherEncBackend for Backend<'a> {
+
+    fn encrypt_block(&self, block: InOut<rustcrypto_cipher::Block<Self>>) {
+        self.process(block, cipher::encipher);
+    }
+
+}
+
+impl<'a> BlockCipherDecBackend for Backend<'a> {
+
+    fn decrypt_block(&self, block: InOut<rustcrypto_cipher::Block<Self>>) {
+        self.process(block, cipher::decipher);
+    }
+
+}
+
+impl KeySizeUser for Xtea {
+
+    type KeySize = U16;
+
+}
+
+impl BlockSizeUser for Xtea {
+
+    type BlockSize = U8;
+
+}
+
+impl KeyInit for Xtea {
+
+    fn new(key: &rustcrypto_cipher::Key<Self>) -> Xtea {
+        let mut bytes = [0u8; 16];
+        bytes.clone_from_slice(key);
+        let xtea = Xtea{ key: SecretKey::from_bytes(&bytes) };
+        ::mem::wipe(&mut bytes);
+        xtea
+    }
+
+}
+
+impl BlockCipherEncrypt for Xtea {
+
+    fn encrypt_with_backend(&self, f: impl BlockCipherEncClosure<BlockSize = U8>) {
+        f.call(&Backend(&self.key));
+    }
+
+}
+
+impl BlockCipherDecrypt for Xtea {
+
+    fn decrypt_with_backend(&self, f: impl BlockCipherDecClosure<BlockSize = U8>) {
+        f.call(&Backend(&self.key));
+    }
+
+}
+
+impl AlgorithmName for Xtea {
+
+    fn write_alg_name(f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("XTEA")
+    }
+
+}
+
+impl fmt::Debug for Xtea {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Xtea { .. }")
+    }
+
+}
+
+#[test]
+fn it_works() {
+    let key = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    let xtea = Xtea::new(&key.into());
+    let mut block = (*b"ABCDEFGH").into();
+    xtea.encrypt_block(&mut block);
+    assert_eq!(block[..], [0x49, 0x7d, 0xf3, 0xd0, 0x72, 0x61, 0x2c, 0xb5]);
+    xtea.decrypt_block(&mut block);
+    assert_eq!(&block[..], b"ABCDEFGH");
+
+    let mut blocks = [(*b"01234567").into(), (*b"89abcdef").into()];
+    xtea.encrypt_blocks(&mut blocks);
+    let expected = cipher::encipher(&Key::from_bytes(&key), &Block::from_be_bytes(b"89abcdef"));
+    assert_eq!(blocks[1][..], expected.to_be_bytes());
+    assert_eq!(format!("{:?}", xtea), "Xtea { .. }");
+}